@@ -0,0 +1,188 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Circuit breaker of reporting, enabled by
+//! `skywalking_agent.circuit_breaker_threshold`.
+//!
+//! The worker counts the consecutive report failures, and opens the breaker
+//! when the threshold is reached. The open deadline is written into the
+//! shared memory, which is mapped before forking, so the php processes stop
+//! tracing and sending items until the cool-down period elapsed. Then the
+//! breaker is half-open, the items are sent again as the probe, the breaker
+//! is reopened on the first failure, or closed if no failure in another
+//! cool-down period.
+//!
+//! The gRPC reporter doesn't notify the successful reports, so the failures
+//! separated by more than the cool-down period are not consecutive.
+
+use crate::{
+    module::{CIRCUIT_BREAKER_COOL_DOWN_PERIOD, CIRCUIT_BREAKER_THRESHOLD},
+    profiling::now_millis,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use serde_json::json;
+use std::{
+    io,
+    mem::size_of,
+    ptr::null_mut,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex,
+    },
+};
+use tracing::{error, info, warn};
+
+/// The deadline of the opened breaker in millis, shared with php processes.
+static OPEN_UNTIL: OnceCell<&'static AtomicI64> = OnceCell::new();
+
+/// Only changed in worker.
+static BREAKER_STATE: Lazy<Mutex<BreakerState>> = Lazy::new(Default::default);
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: i64,
+    last_failed_time: i64,
+    /// The deadline of the last opening, kept after the breaker is half-open.
+    open_until: Option<i64>,
+    open_count: u64,
+}
+
+impl BreakerState {
+    fn open(&mut self, shared_open_until: &AtomicI64, open_until: i64) {
+        self.open_until = Some(open_until);
+        self.open_count += 1;
+        shared_open_until.store(open_until, Ordering::Relaxed);
+    }
+
+    /// Close the breaker if the half-open state passed without failure.
+    fn close_if_recovered(&mut self, now: i64) {
+        if let Some(open_until) = self.open_until {
+            if now - open_until > cool_down_millis() {
+                self.open_until = None;
+                self.consecutive_failures = 0;
+                info!("No report failure when circuit breaker is half-open, close it");
+            }
+        }
+    }
+
+    fn state_name(&self, now: i64) -> &'static str {
+        match self.open_until {
+            None => "closed",
+            Some(open_until) if now < open_until => "open",
+            Some(_) => "half_open",
+        }
+    }
+}
+
+fn is_enable() -> bool {
+    *CIRCUIT_BREAKER_THRESHOLD > 0
+}
+
+fn cool_down_millis() -> i64 {
+    *CIRCUIT_BREAKER_COOL_DOWN_PERIOD * 1000
+}
+
+/// Map the shared memory, must be called before forking the worker and the
+/// php processes.
+pub fn init() {
+    if !is_enable() {
+        return;
+    }
+
+    let ptr = unsafe {
+        libc::mmap(
+            null_mut(),
+            size_of::<AtomicI64>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        error!(err = ?io::Error::last_os_error(), "Map circuit breaker shared memory failed");
+        return;
+    }
+
+    let _ = OPEN_UNTIL.set(unsafe { &*(ptr as *const AtomicI64) });
+}
+
+/// Whether the php processes should stop tracing and sending items.
+pub fn is_open() -> bool {
+    OPEN_UNTIL.get().map_or(false, |open_until| {
+        open_until.load(Ordering::Relaxed) > now_millis()
+    })
+}
+
+/// Record the report failure, only called in worker.
+pub fn record_failure() {
+    let Some(shared_open_until) = OPEN_UNTIL.get() else {
+        return;
+    };
+    let Ok(mut state) = BREAKER_STATE.lock() else {
+        return;
+    };
+
+    let now = now_millis();
+    let cool_down = cool_down_millis();
+    state.close_if_recovered(now);
+
+    match state.open_until {
+        // The items sent before opening are still failing.
+        Some(open_until) if now < open_until => {}
+        // The probe failed in half-open state.
+        Some(open_until) if now - open_until <= cool_down => {
+            state.open(shared_open_until, now + cool_down);
+            warn!("Report failed when circuit breaker is half-open, reopen it");
+        }
+        _ => {
+            if now - state.last_failed_time > cool_down {
+                state.consecutive_failures = 0;
+            }
+            state.consecutive_failures += 1;
+
+            if state.consecutive_failures >= *CIRCUIT_BREAKER_THRESHOLD {
+                state.open(shared_open_until, now + cool_down);
+                warn!(
+                    consecutive_failures = state.consecutive_failures,
+                    cool_down_period = *CIRCUIT_BREAKER_COOL_DOWN_PERIOD,
+                    "Too many consecutive report failures, open circuit breaker"
+                );
+            }
+        }
+    }
+
+    state.last_failed_time = now;
+}
+
+/// The breaker state written to the status file.
+pub fn status_json() -> serde_json::Value {
+    if !is_enable() {
+        return json!({ "state": "disabled" });
+    }
+    let Ok(mut state) = BREAKER_STATE.lock() else {
+        return json!(null);
+    };
+
+    let now = now_millis();
+    state.close_if_recovered(now);
+
+    json!({
+        "state": state.state_name(now),
+        "consecutive_failures": state.consecutive_failures,
+        "open_until_timestamp": state.open_until.unwrap_or_default() / 1000,
+        "open_count": state.open_count,
+    })
+}
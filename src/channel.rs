@@ -13,26 +13,115 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::{
+    breaker,
+    context::apply_segment_options,
+    meter::{CustomMeterRecord, EndpointRecord},
+    status::WORKER_STATUS,
+};
 use anyhow::anyhow;
-use once_cell::sync::OnceCell;
-use skywalking::reporter::{CollectItem, Report};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use skywalking::{
+    proto::v3::{Event, ThreadSnapshot},
+    reporter::{CollectItem, Report},
+};
 use std::{
-    io::Write,
+    io::{self, Write},
     mem::size_of,
+    net::{Shutdown, TcpStream},
     ops::DerefMut,
     os::unix::net::UnixStream,
-    path::{Path, PathBuf},
+    path::PathBuf,
+    process,
     sync::Mutex,
+    time::Duration,
 };
-use tokio::{io::AsyncReadExt, sync::mpsc};
-use tracing::error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    select,
+    sync::mpsc::{self, error::TrySendError},
+    time::timeout,
+};
+use tracing::{debug, error};
+
+/// The address of worker, the framing of channel is identical across the
+/// socket types.
+#[derive(Debug, Clone)]
+pub enum WorkerAddr {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    fn connect(addr: &WorkerAddr) -> io::Result<Self> {
+        match addr {
+            WorkerAddr::Unix(path) => UnixStream::connect(path).map(Self::Unix),
+            WorkerAddr::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                Ok(Self::Tcp(stream))
+            }
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Self::Unix(stream) => stream.shutdown(Shutdown::Both),
+            Self::Tcp(stream) => stream.shutdown(Shutdown::Both),
+        }
+    }
+}
 
-fn channel_send<T>(data: CollectItem, mut sender: T) -> anyhow::Result<()>
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(stream) => stream.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Unix(stream) => stream.flush(),
+            Self::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+/// The version of the frame format, prefixed to every frame, bump it when the
+/// framing or the serialized `ChannelItem` changes, so the worker of the other
+/// version closes the connection instead of misparsing the frames during the
+/// rolling upgrade.
+const CHANNEL_PROTOCOL_VERSION: u8 = 3;
+
+/// The max declared size of one frame, the larger size prefix is treated as
+/// corrupted instead of allocating the buffer.
+const MAX_FRAME_SIZE: usize = 128 * 1024 * 1024;
+
+/// The item transferred from php processes to worker.
+#[derive(Serialize, Deserialize)]
+pub enum ChannelItem {
+    Collect(CollectItem),
+    ProfileSnapshot(ThreadSnapshot),
+    EndpointRecord(EndpointRecord),
+    CustomMeterRecord(CustomMeterRecord),
+    Event(Event),
+}
+
+fn channel_send<T>(data: ChannelItem, mut sender: T) -> anyhow::Result<()>
 where
-    T: DerefMut<Target = UnixStream>,
+    T: DerefMut,
+    T::Target: Write,
 {
     let content = bincode::serialize(&data)?;
 
+    sender.write_all(&[CHANNEL_PROTOCOL_VERSION])?;
     sender.write_all(&content.len().to_le_bytes())?;
     sender.write_all(&content)?;
     sender.flush()?;
@@ -40,51 +129,166 @@ where
     Ok(())
 }
 
-pub async fn channel_receive(receiver: &mut tokio::net::UnixStream) -> anyhow::Result<CollectItem> {
+/// Receive the frame `[version: u8][size: usize][content]`. The layout of the
+/// frame of other version is unknown, so the `InvalidData` error is returned
+/// before reading the size, and the stream should be closed.
+///
+/// Waiting for the next frame isn't limited, but once the frame started, the
+/// rest should arrive within `read_timeout`, otherwise the `TimedOut` error is
+/// returned. The stream is out of sync after the `io::Error`, so it should be
+/// closed.
+pub async fn channel_receive(
+    receiver: &mut (impl AsyncRead + Unpin), read_timeout: Option<Duration>,
+) -> anyhow::Result<ChannelItem> {
+    let version = receiver.read_u8().await?;
+    if version != CHANNEL_PROTOCOL_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "channel protocol version mismatch, expected {}, got {}",
+                CHANNEL_PROTOCOL_VERSION, version
+            ),
+        )
+        .into());
+    }
+
+    let content = match read_timeout {
+        Some(read_timeout) => timeout(read_timeout, read_frame_content(receiver))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "read frame timeout"))??,
+        None => read_frame_content(receiver).await?,
+    };
+
+    let item = bincode::deserialize(&content)?;
+    Ok(item)
+}
+
+async fn read_frame_content(receiver: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
     let mut size_buf = [0u8; size_of::<usize>()];
     receiver.read_exact(&mut size_buf).await?;
     let size = usize::from_le_bytes(size_buf);
 
+    if size > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame size {} exceeds the limit {}", size, MAX_FRAME_SIZE),
+        ));
+    }
+
     let mut content = vec![0u8; size];
     receiver.read_exact(&mut content).await?;
+    Ok(content)
+}
 
-    let item = bincode::deserialize(&content)?;
-    Ok(item)
+/// The connection to worker of current process, shared by the reporters. It's
+/// connected on the first report, so the php processes never serving a
+/// request, like the idle children of php-fpm, never connect.
+static STREAM: Mutex<Option<ProcessStream>> = Mutex::new(None);
+
+struct ProcessStream {
+    pid: u32,
+    stream: Stream,
+}
+
+/// Close the connection to worker of current process, called in module
+/// shutdown. Do nothing if never connected.
+pub fn close_stream() {
+    let stream = match STREAM.lock() {
+        Ok(mut stream) => stream.take(),
+        Err(err) => {
+            error!(?err, "Get Lock failed");
+            return;
+        }
+    };
+    let Some(ProcessStream { pid, stream }) = stream else {
+        return;
+    };
+    // The connection inherited from the parent process is still used by it,
+    // so only the descriptor is closed.
+    if pid == process::id() {
+        debug!("Close the channel to worker");
+        if let Err(err) = stream.shutdown() {
+            debug!(?err, "Shutdown the channel to worker failed");
+        }
+    }
 }
 
 pub struct Reporter {
-    worker_addr: PathBuf,
-    stream: OnceCell<Mutex<UnixStream>>,
+    worker_addr: WorkerAddr,
 }
 
 impl Reporter {
-    pub fn new(worker_addr: impl AsRef<Path>) -> Self {
-        Self {
-            worker_addr: worker_addr.as_ref().to_path_buf(),
-            stream: OnceCell::new(),
+    pub fn new(worker_addr: WorkerAddr) -> Self {
+        Self { worker_addr }
+    }
+
+    fn try_report(&self, item: ChannelItem) -> anyhow::Result<()> {
+        let mut guard = STREAM.lock().map_err(|_| anyhow!("Get Lock failed"))?;
+
+        // The connection inherited from the parent process is shared with it,
+        // the frames would interleave, so reconnect after fork.
+        let pid = process::id();
+        if guard.as_ref().map_or(false, |stream| stream.pid != pid) {
+            guard.take();
         }
+        let stream = match &mut *guard {
+            Some(stream) => stream,
+            stream @ None => stream.insert(ProcessStream {
+                pid,
+                stream: Stream::connect(&self.worker_addr)?,
+            }),
+        };
+
+        let result = channel_send(item, &mut stream.stream);
+        // The frame may be partially written, so reconnect on the next report.
+        if result.is_err() {
+            guard.take();
+        }
+        result
     }
 
-    fn try_report(&self, item: CollectItem) -> anyhow::Result<()> {
-        let stream = self
-            .stream
-            .get_or_try_init(|| UnixStream::connect(&self.worker_addr).map(Mutex::new))?
-            .lock()
-            .map_err(|_| anyhow!("Get Lock failed"))?;
+    pub fn report_snapshot(&self, snapshot: ThreadSnapshot) {
+        if let Err(err) = self.try_report(ChannelItem::ProfileSnapshot(snapshot)) {
+            error!(?err, "channel send snapshot failed");
+        }
+    }
+
+    pub fn report_endpoint_record(&self, record: EndpointRecord) {
+        if let Err(err) = self.try_report(ChannelItem::EndpointRecord(record)) {
+            error!(?err, "channel send endpoint record failed");
+        }
+    }
 
-        channel_send(item, stream)
+    pub fn report_custom_meter_record(&self, record: CustomMeterRecord) {
+        if let Err(err) = self.try_report(ChannelItem::CustomMeterRecord(record)) {
+            error!(?err, "channel send custom meter record failed");
+        }
+    }
+
+    pub fn report_event(&self, event: Event) {
+        if let Err(err) = self.try_report(ChannelItem::Event(event)) {
+            error!(?err, "channel send event failed");
+        }
     }
 }
 
 impl Report for Reporter {
-    fn report(&self, item: CollectItem) {
-        if let Err(err) = self.try_report(item) {
+    fn report(&self, mut item: CollectItem) {
+        if !apply_segment_options(&mut item) {
+            debug!("Segment discarded");
+            return;
+        }
+        if breaker::is_open() {
+            debug!("Circuit breaker is open, collect item dropped");
+            return;
+        }
+        if let Err(err) = self.try_report(ChannelItem::Collect(item)) {
             error!(?err, "channel send failed");
         }
     }
 }
 
-pub struct TxReporter(pub mpsc::Sender<CollectItem>);
+pub struct TxReporter(pub PrioritySender);
 
 impl Report for TxReporter {
     fn report(&self, item: CollectItem) {
@@ -93,3 +297,197 @@ impl Report for TxReporter {
         }
     }
 }
+
+/// The segment larger than it, likely carrying the bodies or the backtraces,
+/// is queued as low priority.
+const LOW_PRIORITY_SEGMENT_BYTES: usize = 64 * 1024;
+
+/// The priority of the collect items queued in worker. The core items are
+/// drained first, so when the reporting falls behind, the low priority items
+/// pile up and are dropped first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// The segments, the instance properties and the keep alive.
+    Core,
+    /// The large segments, the logs and the meters.
+    Low,
+}
+
+impl Priority {
+    pub fn of(item: &CollectItem) -> Self {
+        match item {
+            CollectItem::Trace(segment) if segment.encoded_len() > LOW_PRIORITY_SEGMENT_BYTES => {
+                Self::Low
+            }
+            CollectItem::Trace(_) | CollectItem::Instance(_) | CollectItem::Ping(_) => Self::Core,
+            _ => Self::Low,
+        }
+    }
+}
+
+/// Create the queue of worker, a channel per priority, each with `buffer`
+/// capacity, so the low priority items never take the room of the core ones.
+pub fn priority_channel(buffer: usize) -> (PrioritySender, PriorityReceiver) {
+    let (core_tx, core_rx) = mpsc::channel(buffer);
+    let (low_tx, low_rx) = mpsc::channel(buffer);
+    (
+        PrioritySender {
+            core: core_tx,
+            low: low_tx,
+        },
+        PriorityReceiver {
+            core: core_rx,
+            low: low_rx,
+        },
+    )
+}
+
+#[derive(Clone)]
+pub struct PrioritySender {
+    core: mpsc::Sender<CollectItem>,
+    low: mpsc::Sender<CollectItem>,
+}
+
+impl PrioritySender {
+    /// Send the item to the channel of its priority without waiting, the
+    /// dropped item is counted by its priority.
+    pub fn try_send(&self, item: CollectItem) -> Result<(), TrySendError<CollectItem>> {
+        let priority = Priority::of(&item);
+        let tx = match priority {
+            Priority::Core => &self.core,
+            Priority::Low => &self.low,
+        };
+        let result = tx.try_send(item);
+        if result.is_err() {
+            WORKER_STATUS.incr_dropped(priority);
+        }
+        result
+    }
+
+    /// The count of the items not taken by the receiver yet, the cloned
+    /// senders take no capacity.
+    pub fn pending(&self) -> usize {
+        [&self.core, &self.low]
+            .iter()
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum()
+    }
+}
+
+pub struct PriorityReceiver {
+    core: mpsc::Receiver<CollectItem>,
+    low: mpsc::Receiver<CollectItem>,
+}
+
+impl PriorityReceiver {
+    /// Receive the core item first, returns `None` when both channels are
+    /// closed and drained.
+    pub async fn recv(&mut self) -> Option<CollectItem> {
+        select! {
+            biased;
+            Some(item) = self.core.recv() => Some(item),
+            Some(item) = self.low.recv() => Some(item),
+            else => None,
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Option<CollectItem> {
+        self.core.try_recv().or_else(|_| self.low.try_recv()).ok()
+    }
+
+    /// Close both channels, the pending items can still be received.
+    pub fn close(&mut self) {
+        self.core.close();
+        self.low.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn error_kind(err: &anyhow::Error) -> Option<io::ErrorKind> {
+        err.downcast_ref::<io::Error>().map(io::Error::kind)
+    }
+
+    #[tokio::test]
+    async fn receive_frame() {
+        let event = Event {
+            name: "test".to_owned(),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        channel_send(ChannelItem::Event(event.clone()), &mut buf).unwrap();
+
+        let item = channel_receive(&mut buf.as_slice(), Some(READ_TIMEOUT))
+            .await
+            .unwrap();
+        assert!(matches!(item, ChannelItem::Event(e) if e == event));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn receive_stalled_frame() {
+        let (mut client, mut server) = duplex(64);
+        // The frame started, but the size is incomplete.
+        client
+            .write_all(&[CHANNEL_PROTOCOL_VERSION, 1, 2])
+            .await
+            .unwrap();
+
+        let err = channel_receive(&mut server, Some(READ_TIMEOUT))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error_kind(&err), Some(io::ErrorKind::TimedOut));
+        drop(client);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_next_frame_unlimited() {
+        let (client, mut server) = duplex(64);
+
+        let result = timeout(
+            READ_TIMEOUT * 10,
+            channel_receive(&mut server, Some(READ_TIMEOUT)),
+        )
+        .await;
+        assert!(result.is_err());
+        drop(client);
+    }
+
+    #[tokio::test]
+    async fn receive_oversized_frame() {
+        let mut buf = vec![CHANNEL_PROTOCOL_VERSION];
+        buf.extend_from_slice(&(MAX_FRAME_SIZE + 1).to_le_bytes());
+
+        let err = channel_receive(&mut buf.as_slice(), Some(READ_TIMEOUT))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error_kind(&err), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[tokio::test]
+    async fn receive_version_mismatch() {
+        // The size isn't read, which may be in the other layout.
+        let buf = [CHANNEL_PROTOCOL_VERSION.wrapping_add(1), 0xff];
+
+        let err = channel_receive(&mut buf.as_slice(), Some(READ_TIMEOUT))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error_kind(&err), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[tokio::test]
+    async fn receive_eof() {
+        let err = channel_receive(&mut [].as_slice(), Some(READ_TIMEOUT))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error_kind(&err), Some(io::ErrorKind::UnexpectedEof));
+    }
+}
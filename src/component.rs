@@ -17,12 +17,146 @@
 //!
 //! <https://github.com/apache/skywalking/blob/014861535015745ae3f7b99acd7d14500b3b3927/oap-server/server-starter/src/main/resources/component-libraries.yml>
 
+use crate::module::{
+    COMPONENT_OVERRIDES as COMPONENT_OVERRIDES_INI,
+    EXIT_SPAN_SAMPLE_RATE as EXIT_SPAN_SAMPLE_RATE_INI,
+};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use tracing::warn;
+
 pub const COMPONENT_PHP_ID: i32 = 8001;
 pub const COMPONENT_PHP_CURL_ID: i32 = 8002;
 pub const COMPONENT_PHP_PDO_ID: i32 = 8003;
 pub const COMPONENT_PHP_MYSQLI_ID: i32 = 8004;
+pub const COMPONENT_PHP_YAR_ID: i32 = 8005;
 pub const COMPONENT_PHP_PREDIS_ID: i32 = 8006;
 pub const COMPONENT_PHP_MEMCACHED_ID: i32 = 20;
 pub const COMPONENT_PHP_REDIS_ID: i32 = 7;
 pub const COMPONENT_AMQP_PRODUCER_ID: i32 = 144;
+pub const COMPONENT_AMQP_CONSUMER_ID: i32 = 145;
 pub const COMPONENT_MONGODB_ID: i32 = 9;
+pub const COMPONENT_ELASTICSEARCH_ID: i32 = 47;
+pub const COMPONENT_KAFKA_PRODUCER_ID: i32 = 40;
+pub const COMPONENT_KAFKA_CONSUMER_ID: i32 = 41;
+pub const COMPONENT_GRPC_ID: i32 = 23;
+pub const COMPONENT_THRIFT_CLIENT_ID: i32 = 62;
+
+/// The plugin names accepted by `skywalking_agent.component_overrides`, with
+/// the default component ids.
+pub const PLUGIN_COMPONENTS: &[(&str, i32)] = &[
+    ("curl", COMPONENT_PHP_CURL_ID),
+    ("pdo", COMPONENT_PHP_PDO_ID),
+    ("mysqli", COMPONENT_PHP_MYSQLI_ID),
+    ("predis", COMPONENT_PHP_PREDIS_ID),
+    ("memcached", COMPONENT_PHP_MEMCACHED_ID),
+    ("memcache", COMPONENT_PHP_MEMCACHED_ID),
+    ("redis", COMPONENT_PHP_REDIS_ID),
+    ("amqplib", COMPONENT_AMQP_PRODUCER_ID),
+    ("amqp_producer", COMPONENT_AMQP_PRODUCER_ID),
+    ("amqp_consumer", COMPONENT_AMQP_CONSUMER_ID),
+    ("mongodb", COMPONENT_MONGODB_ID),
+    ("yar", COMPONENT_PHP_YAR_ID),
+    ("elasticsearch", COMPONENT_ELASTICSEARCH_ID),
+    ("swoole_http_client", COMPONENT_PHP_ID),
+    ("swoole_redis", COMPONENT_PHP_REDIS_ID),
+    ("swoole_mysql", COMPONENT_PHP_MYSQLI_ID),
+    ("rdkafka_producer", COMPONENT_KAFKA_PRODUCER_ID),
+    ("rdkafka_consumer", COMPONENT_KAFKA_CONSUMER_ID),
+    ("http_stream", COMPONENT_PHP_ID),
+    ("grpc", COMPONENT_GRPC_ID),
+    ("thrift", COMPONENT_THRIFT_CLIENT_ID),
+    ("session", COMPONENT_PHP_ID),
+    ("messenger", COMPONENT_PHP_ID),
+    ("laravel_queue", COMPONENT_PHP_ID),
+    ("subprocess", COMPONENT_PHP_ID),
+];
+
+/// Parsed from `skywalking_agent.component_overrides`, the invalid items are
+/// logged and ignored, so it should be forced after the logger initialized.
+pub static COMPONENT_OVERRIDES: Lazy<HashMap<&'static str, i32>> = Lazy::new(|| {
+    let mut overrides = HashMap::new();
+
+    for item in COMPONENT_OVERRIDES_INI
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let Some((plugin, id)) = item.split_once('=') else {
+            warn!(
+                item,
+                "Component override should be `plugin=componentId`, ignored"
+            );
+            continue;
+        };
+        let Some((plugin, _)) = find_plugin(plugin) else {
+            warn!(item, "Unknown plugin name of component override, ignored");
+            continue;
+        };
+        match id.trim().parse::<i32>() {
+            Ok(id) if id > 0 => {
+                overrides.insert(plugin, id);
+            }
+            _ => {
+                warn!(item, "Component id must be positive integer, ignored");
+            }
+        }
+    }
+
+    overrides
+});
+
+/// Parsed from `skywalking_agent.exit_span_sample_rate`, the sample rates of
+/// the exit spans keyed by the component id, so the plugins sharing the
+/// component id share the rate too.
+pub static EXIT_SPAN_SAMPLE_RATES: Lazy<HashMap<i32, f64>> = Lazy::new(|| {
+    let mut rates = HashMap::new();
+
+    for item in EXIT_SPAN_SAMPLE_RATE_INI
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let Some((plugin, rate)) = item.split_once('=') else {
+            warn!(
+                item,
+                "Exit span sample rate should be `plugin=rate`, ignored"
+            );
+            continue;
+        };
+        let Some((plugin, default_id)) = find_plugin(plugin) else {
+            warn!(
+                item,
+                "Unknown plugin name of exit span sample rate, ignored"
+            );
+            continue;
+        };
+        match rate.trim().parse::<f64>() {
+            Ok(rate) if (0.0..=1.0).contains(&rate) => {
+                rates.insert(component_id(plugin, default_id), rate);
+            }
+            _ => {
+                warn!(item, "Exit span sample rate must be in [0, 1], ignored");
+            }
+        }
+    }
+
+    rates
+});
+
+fn find_plugin(name: &str) -> Option<(&'static str, i32)> {
+    let name = name.trim();
+    PLUGIN_COMPONENTS
+        .iter()
+        .copied()
+        .find(|(plugin, _)| *plugin == name)
+}
+
+/// Get the component id reported by the plugin, overridden by
+/// `skywalking_agent.component_overrides`.
+pub fn component_id(plugin: &str, default_id: i32) -> i32 {
+    COMPONENT_OVERRIDES
+        .get(plugin)
+        .copied()
+        .unwrap_or(default_id)
+}
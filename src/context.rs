@@ -13,19 +13,118 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::{
+    component::{COMPONENT_PHP_ID, EXIT_SPAN_SAMPLE_RATES},
+    dynamic_config, meter,
+    module::{INJECT_W3C_TRACEPARENT, MAX_SEGMENT_BYTES, PROPAGATE_EXIT_ERRORS},
+    profiling::now_millis,
+    tag::{suppressed_count_tag, TAG_PROPAGATED, TAG_SEGMENT_TRIMMED, TAG_TAIL_SAMPLED},
+    util::hash_to_u64,
+};
 use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use skywalking::trace::{
-    propagation::encoder::encode_propagation,
-    span::{HandleSpanObject, Span},
-    trace_context::TracingContext,
+use prost::Message;
+use skywalking::{
+    proto::v3::{KeyStringValuePair, SpanType},
+    reporter::CollectItem,
+    trace::{
+        propagation::{decoder::decode_propagation, encoder::encode_propagation},
+        span::{HandleSpanObject, Span},
+        trace_context::TracingContext,
+        tracer,
+    },
 };
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, warn};
 
 pub const SW_HEADER: &str = "sw8";
 
 static REQUEST_CONTEXT: Lazy<DashMap<Option<i64>, RequestContext>> = Lazy::new(DashMap::new);
 
+/// The requests not traced, like ignored or sampled out.
+static SKIPPED_REQUESTS: Lazy<DashMap<Option<i64>, ()>> = Lazy::new(DashMap::new);
+
+/// The outer contexts suspended by `skywalking_continue`, the last one is
+/// restored when the continued context finished.
+static SUSPENDED_CONTEXTS: Lazy<DashMap<Option<i64>, Vec<RequestContext>>> =
+    Lazy::new(DashMap::new);
+
+/// The cross process extension header, carrying the tracing mode.
+pub const SW_X_HEADER: &str = "sw8-x";
+
+/// The cross process correlation header, carrying the custom key-values.
+pub const SW_CORRELATION_HEADER: &str = "sw8-correlation";
+
+/// The response header carrying the service and instance of the traced
+/// server, enabled by `skywalking_agent.response_service_header`, so the
+/// caller names the peer of its exit span by the service.
+pub const SW_SERVICE_HEADER: &str = "sw8-service";
+
+/// The W3C trace context header, injected along with `sw8` and read if no
+/// `sw8` present, enabled by `skywalking_agent.inject_w3c_traceparent`.
+pub const W3C_TRACEPARENT_HEADER: &str = "traceparent";
+
+/// The limits of the correlation context, same as the other agents, the
+/// elements beyond them are dropped.
+const MAX_CORRELATION_ELEMENTS: usize = 3;
+const MAX_CORRELATION_VALUE_LENGTH: usize = 128;
+
+/// The limit of the common tags of a segment, the tags beyond it are dropped.
+const MAX_COMMON_TAGS: usize = 8;
+
+/// The limit of the manual spans not ended in a segment, so the span leaked by
+/// the missing `skywalking_end_span` in a loop doesn't pile up.
+const MAX_MANUAL_SPANS: usize = 64;
+
+/// The spans started by `skywalking_start_span` and not ended yet, keyed by
+/// the segment id, ended in reverse order before the entry span.
+static MANUAL_SPANS: Lazy<DashMap<String, Vec<Span>>> = Lazy::new(DashMap::new);
+
+/// The options of segments set during the request, keyed by the segment id,
+/// applied when the segment is reported, or propagated to downstream.
+static SEGMENT_OPTIONS: Lazy<DashMap<String, SegmentOptions>> = Lazy::new(DashMap::new);
+
+#[derive(Default)]
+struct SegmentOptions {
+    /// Overridden by `skywalking_set_service_name`.
+    service_name: Option<String>,
+    /// Set by the `sw8-x` header from upstream or `skywalking_skip_analysis`.
+    skip_analysis: bool,
+    /// The correlation context, from the `sw8-correlation` header of upstream
+    /// or generated, in insertion order.
+    correlation: Vec<(String, String)>,
+    /// Sampled out by the head sampling, but traced to be retained if slow or
+    /// errored, by `skywalking_agent.tail_sample_latency_ms`.
+    tail_sampling: bool,
+    /// Not retained by the tail sampling, or ignored by
+    /// `skywalking_ignore_trace`, so not reported.
+    discarded: bool,
+    /// Set by `skywalking_set_common_tag`, kept local rather than propagated
+    /// like the correlation, in insertion order.
+    common_tags: Vec<CommonTag>,
+    /// The ids of the spans propagated to downstream by the `sw8` header, which
+    /// are referenced by the downstream segments.
+    propagated_span_ids: Vec<i32>,
+}
+
+#[derive(Clone)]
+struct CommonTag {
+    key: String,
+    value: String,
+    /// The spans started before it are not tagged, except the entry span if
+    /// `include_entry_span`.
+    since_millis: i64,
+    include_entry_span: bool,
+}
+
+/// The request isn't traced, so the hooks needing the tracing context are
+/// skipped silently.
+#[derive(Debug, thiserror::Error)]
+#[error("request isn't traced")]
+pub struct NotTracedError;
+
 pub struct RequestContext {
     pub tracing_context: TracingContext,
     pub entry_span: Span,
@@ -36,8 +135,158 @@ impl RequestContext {
         REQUEST_CONTEXT.insert(request_id, ctx);
     }
 
+    /// The continued contexts not finished by user are finished first.
     pub fn remove_global(request_id: Option<i64>) -> Option<Self> {
-        REQUEST_CONTEXT.remove(&request_id).map(|(_, ctx)| ctx)
+        while Self::finish_continued(request_id) {}
+        REQUEST_CONTEXT
+            .remove(&request_id)
+            .map(|(_, ctx)| ctx.end_manual_spans())
+    }
+
+    /// Capture the snapshot of current context, to be continued by
+    /// [RequestContext::continue_snapshot], in the same format as `sw8`
+    /// header.
+    pub fn try_capture(request_id: Option<i64>) -> crate::Result<String> {
+        Self::try_get_sw_header(request_id, "")
+    }
+
+    /// Start a new segment referencing the captured snapshot, the outer
+    /// context is suspended, so the spans created later belong to the new
+    /// segment, until [RequestContext::finish_continued] is called.
+    pub fn continue_snapshot(
+        request_id: Option<i64>, snapshot: &str, operation_name: &str,
+    ) -> crate::Result<()> {
+        if !REQUEST_CONTEXT.contains_key(&request_id) {
+            return Err(anyhow!("global tracing context not exists").into());
+        }
+
+        let propagation =
+            decode_propagation(snapshot).map_err(|e| anyhow!("decode snapshot failed: {}", e))?;
+
+        let common_tags = Self::try_with_global(request_id, |ctx| Ok(ctx.get_common_tags()))?;
+
+        let mut tracing_context = tracer::create_trace_context();
+        let mut entry_span =
+            tracing_context.create_entry_span_with_propagation(operation_name, &propagation);
+        entry_span.span_object_mut().component_id = COMPONENT_PHP_ID;
+
+        let ctx = RequestContext {
+            tracing_context,
+            entry_span,
+        };
+        // The common tags of the outer context are inherited, all the spans of
+        // the new segment are started after them.
+        if !common_tags.is_empty() {
+            ctx.with_segment_options(|options| options.common_tags = common_tags);
+        }
+
+        Self::start_standalone(request_id, ctx);
+        Ok(())
+    }
+
+    /// Finish the last continued context and restore the outer one, returns
+    /// false if there is no continued context.
+    pub fn finish_continued(request_id: Option<i64>) -> bool {
+        match Self::restore_suspended(request_id) {
+            Some(continued) => {
+                continued.finish();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the standalone context of a new segment, the current one is
+    /// suspended if exists, returns whether there is a suspended one.
+    pub fn start_standalone(request_id: Option<i64>, ctx: RequestContext) -> bool {
+        match REQUEST_CONTEXT.insert(request_id, ctx) {
+            Some(outer) => {
+                SUSPENDED_CONTEXTS
+                    .entry(request_id)
+                    .or_default()
+                    .push(outer);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the standalone context set by [RequestContext::start_standalone],
+    /// and restore the suspended one if `has_suspended`.
+    pub fn finish_standalone(request_id: Option<i64>, has_suspended: bool) -> Option<Self> {
+        if has_suspended {
+            Self::restore_suspended(request_id)
+        } else {
+            REQUEST_CONTEXT
+                .remove(&request_id)
+                .map(|(_, ctx)| ctx.end_manual_spans())
+        }
+    }
+
+    /// Restore the last suspended context, returns the replaced one.
+    fn restore_suspended(request_id: Option<i64>) -> Option<Self> {
+        let Some(outer) = SUSPENDED_CONTEXTS
+            .get_mut(&request_id)
+            .and_then(|mut contexts| contexts.pop())
+        else {
+            SUSPENDED_CONTEXTS.remove(&request_id);
+            return None;
+        };
+
+        REQUEST_CONTEXT
+            .insert(request_id, outer)
+            .map(Self::end_manual_spans)
+    }
+
+    /// Finish the entry span before the tracing context, so the segment is
+    /// reported with it.
+    pub fn finish(self) {
+        let RequestContext {
+            tracing_context,
+            entry_span,
+        } = self.end_manual_spans();
+        drop(entry_span);
+        drop(tracing_context);
+    }
+
+    /// Mark the request not traced, no context is created for it.
+    pub fn set_skipped(request_id: Option<i64>) {
+        SKIPPED_REQUESTS.insert(request_id, ());
+    }
+
+    /// Return true if the request was marked not traced.
+    pub fn remove_skipped(request_id: Option<i64>) -> bool {
+        SKIPPED_REQUESTS.remove(&request_id).is_some()
+    }
+
+    /// Discard the context of the request and the continued ones instead of
+    /// reporting them, then mark the request not traced, so the later hooks
+    /// are skipped silently. Returns false if there is no traced request.
+    pub fn ignore(request_id: Option<i64>) -> bool {
+        let mut is_ignored = false;
+        while let Some((_, ctx)) = REQUEST_CONTEXT.remove(&request_id) {
+            ctx.with_segment_options(|options| options.discarded = true);
+            ctx.finish();
+            is_ignored = true;
+            // Put the suspended one back, to be discarded in the next round.
+            Self::restore_suspended(request_id);
+        }
+        if is_ignored {
+            Self::set_skipped(request_id);
+        }
+        is_ignored
+    }
+
+    /// Rename the entry span of the request, and the endpoint of its meters,
+    /// returns false if the request isn't traced.
+    pub fn rename_entry_span(request_id: Option<i64>, operation_name: String) -> bool {
+        meter::rename_request(request_id, &operation_name);
+        Self::try_with_global(request_id, |ctx| {
+            debug!(%operation_name, "Rename entry span");
+            ctx.entry_span.span_object_mut().operation_name = operation_name;
+            Ok(())
+        })
+        .is_ok()
     }
 
     pub fn try_with_global<T>(
@@ -47,7 +296,13 @@ impl RequestContext {
             .get_mut(&request_id)
             .map(|mut ctx| f(ctx.value_mut()))
             .transpose()?
-            .ok_or_else(|| anyhow!("global tracing context not exists"))
+            .ok_or_else(|| {
+                if SKIPPED_REQUESTS.contains_key(&request_id) {
+                    NotTracedError.into()
+                } else {
+                    anyhow!("global tracing context not exists")
+                }
+            })
     }
 
     pub fn try_with_global_ctx<T>(
@@ -56,19 +311,682 @@ impl RequestContext {
         Self::try_with_global(request_id, |ctx| f(&mut ctx.tracing_context))
     }
 
+    /// The sample flag of header is `0` if the context is tail sampling, so the
+    /// downstream samples by itself, rather than always traces.
     pub fn try_get_sw_header(request_id: Option<i64>, peer: &str) -> crate::Result<String> {
         Ok(Self::try_with_global(request_id, |req_ctx| {
-            Ok(encode_propagation(
+            if let Some(span_id) = req_ctx.tracing_context.peek_active_span_id() {
+                req_ctx.with_segment_options(|options| {
+                    if !options.propagated_span_ids.contains(&span_id) {
+                        options.propagated_span_ids.push(span_id);
+                    }
+                });
+            }
+            let header = encode_propagation(
                 &req_ctx.tracing_context,
                 &req_ctx.get_primary_span().span_object().operation_name,
                 peer,
-            ))
+            );
+            if req_ctx.is_tail_sampling() {
+                if let Some(rest) = header.strip_prefix("1-") {
+                    return Ok(format!("0-{}", rest));
+                }
+            }
+            Ok(header)
+        })?)
+    }
+
+    /// Override the service of the segment of this context.
+    pub fn set_service_name(&self, service_name: String) {
+        self.with_segment_options(|options| options.service_name = Some(service_name));
+    }
+
+    /// Mark the segment of this context skipping the analysis of server, and
+    /// propagate it to downstream by the `sw8-x` header.
+    pub fn set_skip_analysis(&self) {
+        self.with_segment_options(|options| options.skip_analysis = true);
+    }
+
+    /// Mark the context sampled out by the head sampling, the decision is made
+    /// by `retain_tail_sampling` when the request finished.
+    pub fn set_tail_sampling(&self) {
+        self.with_segment_options(|options| options.tail_sampling = true);
+    }
+
+    pub fn is_tail_sampling(&self) -> bool {
+        SEGMENT_OPTIONS
+            .get(self.tracing_context.trace_segment_id())
+            .map_or(false, |options| options.tail_sampling)
+    }
+
+    /// Retain the tail sampling context if the entry span is errored or its
+    /// duration reaches `latency_ms`, otherwise the segment is discarded
+    /// instead of reported. Called before the entry span finished.
+    pub fn retain_tail_sampling(&mut self, latency_ms: i64) {
+        if !self.is_tail_sampling() {
+            return;
+        }
+        let span_object = self.entry_span.span_object();
+        let duration = now_millis() - span_object.start_time;
+        if span_object.is_error || duration >= latency_ms {
+            debug!(duration, "tail sampling retained");
+            self.entry_span.add_tag(TAG_TAIL_SAMPLED, "true");
+        } else {
+            self.with_segment_options(|options| options.discarded = true);
+        }
+    }
+
+    fn is_skip_analysis(&self) -> bool {
+        SEGMENT_OPTIONS
+            .get(self.tracing_context.trace_segment_id())
+            .map_or(false, |options| options.skip_analysis)
+    }
+
+    fn with_segment_options(&self, f: impl FnOnce(&mut SegmentOptions)) {
+        let segment_id = self.tracing_context.trace_segment_id().to_owned();
+        f(&mut SEGMENT_OPTIONS.entry(segment_id).or_default());
+    }
+
+    /// The `sw8-x` header for the outgoing request, only the tracing mode is
+    /// set, `None` means the default tracing mode, so no header is needed.
+    pub fn try_get_sw_x_header(request_id: Option<i64>) -> crate::Result<Option<String>> {
+        Ok(Self::try_with_global(request_id, |req_ctx| {
+            Ok(req_ctx.is_skip_analysis().then(|| "1".to_owned()))
         })?)
     }
 
+    /// Put the key-value into the correlation context, propagated to
+    /// downstream by the `sw8-correlation` header. Returns false if the
+    /// context is full or the value is too long.
+    pub fn put_correlation(&self, key: String, value: String) -> bool {
+        if key.is_empty() || value.len() > MAX_CORRELATION_VALUE_LENGTH {
+            return false;
+        }
+        let mut is_put = false;
+        self.with_segment_options(|options| {
+            let correlation = &mut options.correlation;
+            if let Some(element) = correlation.iter_mut().find(|(k, _)| *k == key) {
+                element.1 = value;
+                is_put = true;
+            } else if correlation.len() < MAX_CORRELATION_ELEMENTS {
+                correlation.push((key, value));
+                is_put = true;
+            }
+        });
+        is_put
+    }
+
+    pub fn get_correlation(&self, key: &str) -> Option<String> {
+        SEGMENT_OPTIONS
+            .get(self.tracing_context.trace_segment_id())?
+            .correlation
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Put the elements of the `sw8-correlation` header from upstream, the
+    /// invalid elements are ignored.
+    pub fn put_correlation_by_header(&self, correlation_header: &str) {
+        for element in correlation_header.split(',') {
+            let mut kv = element.trim().splitn(2, ':');
+            let (Some(key), Some(value)) = (
+                kv.next().and_then(decode_base64),
+                kv.next().and_then(decode_base64),
+            ) else {
+                debug!(element, "Invalid correlation element, ignored");
+                continue;
+            };
+            self.put_correlation(key, value);
+        }
+    }
+
+    /// The `sw8-correlation` header for the outgoing request, `None` means the
+    /// correlation context is empty, so no header is needed.
+    pub fn try_get_sw_correlation_header(request_id: Option<i64>) -> crate::Result<Option<String>> {
+        Ok(Self::try_with_global(request_id, |req_ctx| {
+            let header = SEGMENT_OPTIONS
+                .get(req_ctx.tracing_context.trace_segment_id())
+                .map(|options| {
+                    options
+                        .correlation
+                        .iter()
+                        .map(|(key, value)| {
+                            format!("{}:{}", STANDARD.encode(key), STANDARD.encode(value))
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .filter(|header| !header.is_empty());
+            Ok(header)
+        })?)
+    }
+
+    /// Put the tag applied to the spans of the segment started since now, and
+    /// the entry span if `include_entry_span`, the tags set by the plugins
+    /// aren't overridden. Returns false if the common tags are full.
+    pub fn put_common_tag(&self, key: String, value: String, include_entry_span: bool) -> bool {
+        if key.is_empty() {
+            return false;
+        }
+        let tag = CommonTag {
+            key,
+            value,
+            since_millis: now_millis(),
+            include_entry_span,
+        };
+        let mut is_put = false;
+        self.with_segment_options(|options| {
+            let common_tags = &mut options.common_tags;
+            if let Some(element) = common_tags.iter_mut().find(|t| t.key == tag.key) {
+                *element = tag;
+                is_put = true;
+            } else if common_tags.len() < MAX_COMMON_TAGS {
+                common_tags.push(tag);
+                is_put = true;
+            }
+        });
+        is_put
+    }
+
+    fn get_common_tags(&self) -> Vec<CommonTag> {
+        SEGMENT_OPTIONS
+            .get(self.tracing_context.trace_segment_id())
+            .map(|options| options.common_tags.clone())
+            .unwrap_or_default()
+    }
+
+    /// Start the local span, or the exit span if `peer` is set, as the child of
+    /// the active span, so the spans created by the plugins before it ended
+    /// are its children. Returns false if too many spans not ended.
+    pub fn start_manual_span(&mut self, operation_name: &str, peer: Option<&str>) -> bool {
+        let segment_id = self.tracing_context.trace_segment_id().to_owned();
+        let mut spans = MANUAL_SPANS.entry(segment_id).or_default();
+        if spans.len() >= MAX_MANUAL_SPANS {
+            return false;
+        }
+        let mut span = match peer {
+            Some(peer) => self.tracing_context.create_exit_span(operation_name, peer),
+            None => self.tracing_context.create_local_span(operation_name),
+        };
+        span.span_object_mut().component_id = COMPONENT_PHP_ID;
+        spans.push(span);
+        true
+    }
+
+    /// End the last manual span, returns false if there is none.
+    pub fn end_manual_span(&self, is_error: bool) -> bool {
+        let span = MANUAL_SPANS
+            .get_mut(self.tracing_context.trace_segment_id())
+            .and_then(|mut spans| spans.pop());
+        match span {
+            Some(mut span) => {
+                if is_error {
+                    span.span_object_mut().is_error = true;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The last manual span not ended, otherwise the entry span, since the
+    /// spans of the plugins are finished before the hooked calls return.
+    pub fn with_active_span<T>(&mut self, f: impl FnOnce(&mut Span) -> T) -> T {
+        match MANUAL_SPANS
+            .get_mut(self.tracing_context.trace_segment_id())
+            .as_deref_mut()
+            .and_then(|spans| spans.last_mut())
+        {
+            Some(span) => f(span),
+            None => f(&mut self.entry_span),
+        }
+    }
+
+    /// End the manual spans not ended by user, before the entry span.
+    fn end_manual_spans(self) -> Self {
+        if let Some((_, mut spans)) = MANUAL_SPANS.remove(self.tracing_context.trace_segment_id()) {
+            while spans.pop().is_some() {}
+        }
+        self
+    }
+
     /// Primary endpoint name is used for endpoint dependency.
     #[inline]
     fn get_primary_span(&self) -> &Span {
         &self.entry_span
     }
 }
+
+/// The first field of `sw8-x` is the tracing mode, `1` means skipping the
+/// analysis.
+pub fn is_skip_analysis_header(sw_x_header: &str) -> bool {
+    sw_x_header.split('-').next().map(str::trim) == Some("1")
+}
+
+/// The value of `sw8-service` is the base64 encoded service and instance
+/// separated by `-`, like the fields of `sw8`.
+pub fn encode_service_header(service: &str, service_instance: &str) -> String {
+    format!(
+        "{}-{}",
+        STANDARD.encode(service),
+        STANDARD.encode(service_instance)
+    )
+}
+
+pub fn decode_service_header(header: &str) -> Option<(String, String)> {
+    let (service, service_instance) = header.trim().split_once('-')?;
+    let service = decode_base64(service).filter(|service| !service.is_empty())?;
+    Some((service, decode_base64(service_instance)?))
+}
+
+/// Map the `sw8` header to the `traceparent` header, returns `None` if
+/// `skywalking_agent.inject_w3c_traceparent` is disabled.
+///
+/// The trace id is kept if it's already 32 lowercase hex digits, otherwise
+/// hashed to them, the parent id is hashed from the segment id and span id,
+/// so the same span always maps to the same `traceparent`.
+pub fn traceparent_header(sw_header: &str) -> Option<String> {
+    if !*INJECT_W3C_TRACEPARENT {
+        return None;
+    }
+
+    let mut fields = sw_header.split('-');
+    let sample = fields.next()?;
+    let trace_id = decode_base64(fields.next()?)?;
+    let segment_id = decode_base64(fields.next()?)?;
+    let span_id = fields.next()?;
+
+    let trace_id = if is_w3c_id(&trace_id, 32) {
+        trace_id
+    } else {
+        format!(
+            "{:016x}{:016x}",
+            hash_to_u64(&trace_id, 0),
+            hash_to_u64(&trace_id, 1)
+        )
+    };
+    // The all zero id is invalid.
+    let parent_id = hash_to_u64(&format!("{}.{}", segment_id, span_id), 0).max(1);
+    let flags = if sample == "1" { "01" } else { "00" };
+
+    Some(format!("00-{}-{:016x}-{}", trace_id, parent_id, flags))
+}
+
+/// Map the `traceparent` header to the `sw8` header, returns `None` if it's
+/// invalid.
+///
+/// The trace id is kept, the parent id becomes the parent segment id, with the
+/// parent span id `0`. The parent service, instance, endpoint and address are
+/// unknown, left empty rather than filled with a made-up name, which would be
+/// turned into a fake upstream service in the topology. The `tracestate` is
+/// ignored.
+pub fn decode_traceparent_header(header: &str) -> Option<String> {
+    let mut fields = header.trim().split('-');
+    let version = fields.next()?;
+    let trace_id = fields.next()?;
+    let parent_id = fields.next()?;
+    let flags = fields.next()?;
+    // The later versions may append fields, but the version `00` mustn't.
+    if !is_w3c_id(version, 2) || version == "ff" || (version == "00" && fields.next().is_some()) {
+        return None;
+    }
+    if !is_w3c_id(trace_id, 32) || !is_w3c_id(parent_id, 16) {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16)
+        .ok()
+        .filter(|_| flags.len() == 2)?;
+
+    Some(format!(
+        "{}-{}-{}-0----",
+        flags & 1,
+        STANDARD.encode(trace_id),
+        STANDARD.encode(parent_id),
+    ))
+}
+
+/// The ids of `traceparent` are lowercase hex digits of the fixed length, and
+/// not all zero.
+fn is_w3c_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && id.bytes().any(|b| b != b'0')
+}
+
+fn decode_base64(s: &str) -> Option<String> {
+    String::from_utf8(STANDARD.decode(s).ok()?).ok()
+}
+
+/// Apply the segment options set during the request, called in php processes
+/// before the segment is sent to worker. Returns false if the segment is
+/// discarded by the tail sampling or ignored, which shouldn't be reported.
+pub fn apply_segment_options(item: &mut CollectItem) -> bool {
+    let CollectItem::Trace(segment) = item else {
+        return true;
+    };
+    let Some((_, options)) = SEGMENT_OPTIONS.remove(&segment.trace_segment_id) else {
+        return true;
+    };
+    if options.discarded {
+        return false;
+    }
+    if let Some(service_name) = options.service_name {
+        segment.service = service_name;
+    }
+    if options.skip_analysis {
+        for span in &mut segment.spans {
+            span.skip_analysis = true;
+        }
+    }
+    for span in &mut segment.spans {
+        if options.propagated_span_ids.contains(&span.span_id) {
+            span.tags.push(KeyStringValuePair {
+                key: TAG_PROPAGATED.to_owned(),
+                value: "true".to_owned(),
+            });
+        }
+    }
+    for tag in &options.common_tags {
+        for span in &mut segment.spans {
+            let is_applied = span.start_time >= tag.since_millis
+                || (tag.include_entry_span && span.span_id == 0);
+            if is_applied && !span.tags.iter().any(|t| t.key == tag.key) {
+                span.tags.push(KeyStringValuePair {
+                    key: tag.key.clone(),
+                    value: tag.value.clone(),
+                });
+            }
+        }
+    }
+    true
+}
+
+/// Mark the enclosing entry span errored if any errored exit span in it,
+/// enabled by `skywalking_agent.propagate_exit_errors`.
+pub fn propagate_exit_errors(item: &mut CollectItem) {
+    propagate_exit_errors_if(item, *PROPAGATE_EXIT_ERRORS);
+}
+
+/// Without `enabled`, only the exit spans are errored.
+fn propagate_exit_errors_if(item: &mut CollectItem, enabled: bool) {
+    if !enabled {
+        return;
+    }
+
+    let CollectItem::Trace(segment) = item else {
+        return;
+    };
+
+    let indexes = segment
+        .spans
+        .iter()
+        .enumerate()
+        .map(|(index, span)| (span.span_id, index))
+        .collect::<HashMap<_, _>>();
+
+    let errored_exits = segment
+        .spans
+        .iter()
+        .filter(|span| span.is_error && span.span_type() == SpanType::Exit)
+        .map(|span| span.parent_span_id)
+        .collect::<Vec<_>>();
+
+    for parent_span_id in errored_exits {
+        // Walk up to the nearest entry span, the span id is less than the
+        // child's, so the loop is finite.
+        let mut span_id = parent_span_id;
+        while let Some(&index) = indexes.get(&span_id) {
+            let span = &mut segment.spans[index];
+            if span.span_type() == SpanType::Entry {
+                span.is_error = true;
+                break;
+            }
+            if span.parent_span_id >= span_id {
+                break;
+            }
+            span_id = span.parent_span_id;
+        }
+    }
+}
+
+/// Drop the spans exceeding the dynamic `span_limit_per_segment`, the span id
+/// of parent is always less than the child's, so the kept spans are complete.
+pub fn limit_segment_spans(item: &mut CollectItem) {
+    let CollectItem::Trace(segment) = item else {
+        return;
+    };
+
+    let limit = dynamic_config::get().span_limit_per_segment;
+    if limit <= 0 || segment.spans.len() as i64 <= limit {
+        return;
+    }
+
+    let count = segment.spans.len();
+    segment.spans.retain(|span| (span.span_id as i64) < limit);
+    debug!(
+        trace_segment_id = %segment.trace_segment_id,
+        dropped = count - segment.spans.len(),
+        "Spans exceed the limit per segment, dropped"
+    );
+}
+
+/// Suppress the repetitive exit spans by
+/// `skywalking_agent.exit_span_sample_rate` of the component, the suppressed
+/// count is tagged on the parent span.
+///
+/// Among the exit spans of the same component under the same parent, the
+/// first one is always kept and the rest are kept evenly by the rate. The
+/// errored spans and the spans having children are never suppressed, so the
+/// kept spans are complete, neither are the spans propagated to downstream by
+/// the `sw8` header, which are referenced by the downstream segments.
+pub fn sample_exit_spans(item: &mut CollectItem) {
+    let CollectItem::Trace(segment) = item else {
+        return;
+    };
+
+    // The propagated mark is removed even if no suppression.
+    let mut propagated_span_ids = HashSet::new();
+    for span in &mut segment.spans {
+        let count = span.tags.len();
+        span.tags.retain(|tag| tag.key != TAG_PROPAGATED);
+        if span.tags.len() != count {
+            propagated_span_ids.insert(span.span_id);
+        }
+    }
+
+    if EXIT_SPAN_SAMPLE_RATES.is_empty() {
+        return;
+    }
+
+    let parent_span_ids = segment
+        .spans
+        .iter()
+        .map(|span| span.parent_span_id)
+        .collect::<HashSet<_>>();
+
+    let mut indexes = (0..segment.spans.len()).collect::<Vec<_>>();
+    indexes.sort_unstable_by_key(|index| segment.spans[*index].span_id);
+
+    let mut seen = HashMap::<(i32, i32), usize>::new();
+    let mut suppressed = HashMap::<(i32, i32), usize>::new();
+    let mut keeps = vec![true; segment.spans.len()];
+    for index in indexes {
+        let span = &segment.spans[index];
+        if span.span_type() != SpanType::Exit
+            || span.is_error
+            || parent_span_ids.contains(&span.span_id)
+            || propagated_span_ids.contains(&span.span_id)
+        {
+            continue;
+        }
+        let Some(&rate) = EXIT_SPAN_SAMPLE_RATES.get(&span.component_id) else {
+            continue;
+        };
+
+        let key = (span.parent_span_id, span.component_id);
+        let n = seen.entry(key).or_default();
+        let keep = *n == 0 || (*n as f64 * rate).floor() > ((*n - 1) as f64 * rate).floor();
+        *n += 1;
+        if !keep {
+            keeps[index] = false;
+            *suppressed.entry(key).or_default() += 1;
+        }
+    }
+
+    if suppressed.is_empty() {
+        return;
+    }
+
+    let mut keeps = keeps.into_iter();
+    segment.spans.retain(|_| keeps.next().unwrap_or(true));
+
+    for ((parent_span_id, component_id), count) in suppressed {
+        if let Some(parent) = segment
+            .spans
+            .iter_mut()
+            .find(|span| span.span_id == parent_span_id)
+        {
+            parent.tags.push(KeyStringValuePair {
+                key: suppressed_count_tag(component_id),
+                value: count.to_string(),
+            });
+        }
+    }
+}
+
+/// Drop the spans until the encoded segment fits in
+/// `skywalking_agent.max_segment_bytes`, to not be rejected whole by the
+/// message size limit of the collector.
+///
+/// The spans with larger span id are dropped first, like
+/// `limit_segment_spans`, so the kept spans are complete and the entry span is
+/// always kept, which is tagged with the count of dropped spans.
+pub fn limit_segment_bytes(item: &mut CollectItem) {
+    let Ok(max_bytes) = usize::try_from(*MAX_SEGMENT_BYTES) else {
+        return;
+    };
+    if max_bytes == 0 {
+        return;
+    }
+
+    let CollectItem::Trace(segment) = item else {
+        return;
+    };
+
+    let encoded_len = segment.encoded_len();
+    if encoded_len <= max_bytes {
+        return;
+    }
+
+    let mut spans = segment
+        .spans
+        .iter()
+        .filter(|span| span.span_id > 0)
+        .map(|span| (span.span_id, span.encoded_len()))
+        .collect::<Vec<_>>();
+    spans.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+    // The length delimiter and tag of the repeated field is ignored, the
+    // result is a bit smaller than the limit.
+    let mut len = encoded_len;
+    let mut min_dropped_span_id = None;
+    for (span_id, span_len) in spans {
+        if len <= max_bytes {
+            break;
+        }
+        len = len.saturating_sub(span_len);
+        min_dropped_span_id = Some(span_id);
+    }
+    let Some(min_dropped_span_id) = min_dropped_span_id else {
+        return;
+    };
+
+    let count = segment.spans.len();
+    segment
+        .spans
+        .retain(|span| span.span_id < min_dropped_span_id);
+    let dropped = count - segment.spans.len();
+
+    if let Some(span) = segment.spans.iter_mut().find(|span| span.span_id == 0) {
+        span.tags.push(KeyStringValuePair {
+            key: TAG_SEGMENT_TRIMMED.to_owned(),
+            value: dropped.to_string(),
+        });
+    }
+
+    warn!(
+        trace_segment_id = %segment.trace_segment_id,
+        encoded_len,
+        max_bytes,
+        dropped,
+        "Segment exceeds the max bytes, spans dropped"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skywalking::proto::v3::{SegmentObject, SpanObject};
+
+    fn new_span(
+        span_id: i32, parent_span_id: i32, span_type: SpanType, is_error: bool,
+    ) -> SpanObject {
+        SpanObject {
+            span_id,
+            parent_span_id,
+            span_type: span_type as i32,
+            is_error,
+            ..Default::default()
+        }
+    }
+
+    /// The entry span, the local span in it, and the errored exit span in the
+    /// local span.
+    fn new_errored_exit_item() -> CollectItem {
+        CollectItem::Trace(Box::new(SegmentObject {
+            spans: vec![
+                new_span(0, -1, SpanType::Entry, false),
+                new_span(1, 0, SpanType::Local, false),
+                new_span(2, 1, SpanType::Exit, true),
+            ],
+            ..Default::default()
+        }))
+    }
+
+    fn span_errors(item: &CollectItem) -> Vec<bool> {
+        let CollectItem::Trace(segment) = item else {
+            unreachable!();
+        };
+        segment.spans.iter().map(|span| span.is_error).collect()
+    }
+
+    #[test]
+    fn propagate_exit_errors_enabled() {
+        let mut item = new_errored_exit_item();
+        propagate_exit_errors_if(&mut item, true);
+        assert_eq!(span_errors(&item), [true, false, true]);
+    }
+
+    #[test]
+    fn propagate_exit_errors_disabled() {
+        let mut item = new_errored_exit_item();
+        propagate_exit_errors_if(&mut item, false);
+        assert_eq!(span_errors(&item), [false, false, true]);
+    }
+
+    #[test]
+    fn propagate_exit_errors_to_nearest_entry() {
+        let mut item = CollectItem::Trace(Box::new(SegmentObject {
+            spans: vec![
+                new_span(0, -1, SpanType::Entry, false),
+                new_span(1, 0, SpanType::Entry, false),
+                new_span(2, 1, SpanType::Exit, true),
+                new_span(3, 0, SpanType::Exit, false),
+            ],
+            ..Default::default()
+        }));
+        propagate_exit_errors_if(&mut item, true);
+        assert_eq!(span_errors(&item), [false, true, true, false]);
+    }
+}
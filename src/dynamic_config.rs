@@ -0,0 +1,369 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dynamic configuration, compatible with the Configuration Discovery Service
+//! of skywalking.
+//!
+//! The worker fetches the configuration and writes it into the shared memory,
+//! which is mapped before forking, so the php processes read the latest
+//! values in `request::init` without restart. The ini values are used until
+//! the configuration is pushed by the server.
+
+use crate::module::{
+    ENDPOINT_SAMPLE_RULES, SAMPLE_RATE, SPAN_LIMIT_PER_SEGMENT, TRACE_IGNORE_PATHS,
+};
+use once_cell::sync::OnceCell;
+use std::{
+    cell::{RefCell, UnsafeCell},
+    io,
+    mem::size_of,
+    ptr::{self, null_mut},
+    rc::Rc,
+    sync::atomic::{fence, AtomicU64, Ordering},
+    thread,
+};
+use tracing::{error, warn};
+
+/// The max length of the ignored paths joined by comma.
+const MAX_TRACE_IGNORE_PATH_LEN: usize = 4096;
+
+static SHARED_STATE: OnceCell<&'static SharedState> = OnceCell::new();
+
+thread_local! {
+    /// The config parsed from the shared memory, with the version.
+    static CACHED_CONFIG: RefCell<Option<(u64, Rc<DynamicConfig>)>> = RefCell::new(None);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicConfig {
+    /// The rate of requests traced, in `[0, 1]`.
+    pub sample_rate: f64,
+    /// The request paths not traced, in the Ant-style patterns.
+    pub trace_ignore_paths: Vec<String>,
+    /// The max count of spans in one segment, the exceeded spans are dropped.
+    pub span_limit_per_segment: i64,
+}
+
+impl DynamicConfig {
+    /// The config of ini, used when not pushed by server.
+    pub fn from_ini() -> Self {
+        Self {
+            sample_rate: *SAMPLE_RATE,
+            trace_ignore_paths: TRACE_IGNORE_PATHS.clone(),
+            span_limit_per_segment: *SPAN_LIMIT_PER_SEGMENT,
+        }
+    }
+
+    pub fn is_path_ignored(&self, path: &str) -> bool {
+        self.trace_ignore_paths
+            .iter()
+            .any(|pattern| is_path_matched(pattern, path))
+    }
+
+    /// The rate of the first matched endpoint sample rule, fallback to the
+    /// global sample rate.
+    pub fn sample_rate_of(&self, path: Option<&str>) -> f64 {
+        path.and_then(|path| {
+            ENDPOINT_SAMPLE_RULES
+                .iter()
+                .find(|(pattern, _)| is_path_matched(pattern, path))
+        })
+        .map_or(self.sample_rate, |(_, rate)| *rate)
+    }
+}
+
+#[repr(C)]
+struct SharedState {
+    /// Odd when the config is being written.
+    version: AtomicU64,
+    config: UnsafeCell<RawConfig>,
+}
+
+/// The config is guarded by the version, like seqlock.
+unsafe impl Sync for SharedState {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawConfig {
+    /// Zero filled means the config isn't pushed by server.
+    is_set: bool,
+    sample_rate: f64,
+    span_limit_per_segment: i64,
+    trace_ignore_path: [u8; MAX_TRACE_IGNORE_PATH_LEN],
+    trace_ignore_path_len: usize,
+}
+
+impl RawConfig {
+    fn to_config(self) -> DynamicConfig {
+        if !self.is_set {
+            return DynamicConfig::from_ini();
+        }
+        DynamicConfig {
+            sample_rate: self.sample_rate,
+            trace_ignore_paths: parse_trace_ignore_paths(&String::from_utf8_lossy(
+                &self.trace_ignore_path[..self.trace_ignore_path_len],
+            )),
+            span_limit_per_segment: self.span_limit_per_segment,
+        }
+    }
+}
+
+/// The Ant-style pattern, like `/static/**` and `/api/*/status`. The legacy
+/// pattern with only the trailing `*`, like `/static/*`, still matches the
+/// prefix, across the segments.
+pub fn is_path_matched(pattern: &str, path: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix('*') {
+        if !prefix.contains(|c| c == '*' || c == '?') {
+            return path.starts_with(prefix);
+        }
+    }
+    ant_match(pattern.as_bytes(), path.as_bytes())
+}
+
+/// `?` matches one char, `*` matches zero or more chars within the segment,
+/// and `**` matches zero or more segments. The paths are percent-encoded, so
+/// matching by bytes is fine.
+fn ant_match(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        // The whole segment `**` also matches no segment.
+        [b'/', b'*', b'*', rest @ ..] if rest.first().map_or(true, |c| *c == b'/') => {
+            if ant_match(rest, path) {
+                return true;
+            }
+            if path.first() != Some(&b'/') {
+                return false;
+            }
+            rest.is_empty()
+                || (1..path.len()).any(|i| path[i] == b'/' && ant_match(rest, &path[i..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| ant_match(rest, &path[i..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|i| *i == 0 || path[i - 1] != b'/')
+            .any(|i| ant_match(rest, &path[i..])),
+        [b'?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != b'/') && ant_match(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && ant_match(rest, &path[1..]),
+    }
+}
+
+/// Parse the `pattern=rate` rules separated by comma, the invalid rules are
+/// skipped.
+pub fn parse_endpoint_sample_rules(rules: &str) -> Vec<(String, f64)> {
+    rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| {
+            let parsed = rule.rsplit_once('=').and_then(|(pattern, rate)| {
+                let rate = rate.trim().parse::<f64>().ok()?;
+                Some((pattern.trim(), rate))
+            });
+            match parsed {
+                Some((pattern, rate)) if !pattern.is_empty() && (0. ..=1.).contains(&rate) => {
+                    Some((pattern.to_owned(), rate))
+                }
+                _ => {
+                    warn!(rule, "Invalid endpoint sample rule, skipped");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Parse the paths separated by comma.
+pub fn parse_trace_ignore_paths(paths: &str) -> Vec<String> {
+    paths
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// Map the shared memory, must be called before forking the worker and the
+/// php processes.
+pub fn init() {
+    let ptr = unsafe {
+        libc::mmap(
+            null_mut(),
+            size_of::<SharedState>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        error!(err = ?io::Error::last_os_error(), "Map dynamic config shared memory failed");
+        return;
+    }
+
+    let _ = SHARED_STATE.set(unsafe { &*(ptr as *const SharedState) });
+}
+
+/// Write the config into shared memory, only called by worker, `None` means
+/// restoring the ini config.
+pub fn set(config: Option<&DynamicConfig>) {
+    let Some(state) = SHARED_STATE.get() else {
+        return;
+    };
+
+    let mut raw: RawConfig = unsafe { std::mem::zeroed() };
+    if let Some(config) = config {
+        let trace_ignore_path = config.trace_ignore_paths.join(",");
+        if trace_ignore_path.len() > MAX_TRACE_IGNORE_PATH_LEN {
+            warn!(
+                trace_ignore_path,
+                "Dynamic trace ignore path is too long, ignored"
+            );
+            return;
+        }
+        raw.is_set = true;
+        raw.sample_rate = config.sample_rate;
+        raw.span_limit_per_segment = config.span_limit_per_segment;
+        raw.trace_ignore_path[..trace_ignore_path.len()]
+            .copy_from_slice(trace_ignore_path.as_bytes());
+        raw.trace_ignore_path_len = trace_ignore_path.len();
+    }
+
+    state.version.fetch_add(1, Ordering::AcqRel);
+    unsafe {
+        ptr::write_volatile(state.config.get(), raw);
+    }
+    state.version.fetch_add(1, Ordering::Release);
+}
+
+/// Read the latest config, parsed only when the version changed.
+pub fn get() -> Rc<DynamicConfig> {
+    let Some(state) = SHARED_STATE.get() else {
+        return CACHED_CONFIG.with(|cached| {
+            cached
+                .borrow_mut()
+                .get_or_insert_with(|| (0, Rc::new(DynamicConfig::from_ini())))
+                .1
+                .clone()
+        });
+    };
+
+    loop {
+        let version = state.version.load(Ordering::Acquire);
+        if version % 2 == 1 {
+            thread::yield_now();
+            continue;
+        }
+
+        let cached = CACHED_CONFIG.with(|cached| {
+            cached
+                .borrow()
+                .as_ref()
+                .filter(|(cached_version, _)| *cached_version == version)
+                .map(|(_, config)| config.clone())
+        });
+        if let Some(config) = cached {
+            return config;
+        }
+
+        let raw = unsafe { ptr::read_volatile(state.config.get()) };
+        fence(Ordering::Acquire);
+        if state.version.load(Ordering::Relaxed) == version {
+            let config = Rc::new(raw.to_config());
+            CACHED_CONFIG.with(|cached| *cached.borrow_mut() = Some((version, config.clone())));
+            return config;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_exact() {
+        assert!(is_path_matched("/health", "/health"));
+        assert!(!is_path_matched("/health", "/health/"));
+        assert!(!is_path_matched("/health", "/healthz"));
+    }
+
+    #[test]
+    fn match_double_star() {
+        assert!(is_path_matched("/static/**", "/static"));
+        assert!(is_path_matched("/static/**", "/static/"));
+        assert!(is_path_matched("/static/**", "/static/a/b"));
+        assert!(!is_path_matched("/static/**", "/statics/a"));
+        assert!(is_path_matched("/api/**/status", "/api/status"));
+        assert!(is_path_matched("/api/**/status", "/api/a/b/status"));
+        assert!(!is_path_matched("/api/**/status", "/api/a/b/statuses"));
+    }
+
+    #[test]
+    fn match_star_in_segment() {
+        assert!(is_path_matched("/api/*/status", "/api/a/status"));
+        assert!(is_path_matched("/api/*/status", "/api//status"));
+        assert!(!is_path_matched("/api/*/status", "/api/a/b/status"));
+        assert!(is_path_matched("/api/v*/users", "/api/v2/users"));
+        assert!(!is_path_matched("/api/v*/users", "/api/v2/x/users"));
+    }
+
+    #[test]
+    fn match_question_mark() {
+        assert!(is_path_matched("/v?/users", "/v1/users"));
+        assert!(!is_path_matched("/v?/users", "/v/users"));
+        assert!(!is_path_matched("/v?/users", "/v12/users"));
+        assert!(!is_path_matched("/a?b", "/a/b"));
+    }
+
+    #[test]
+    fn match_legacy_prefix() {
+        assert!(is_path_matched("/static/*", "/static/"));
+        assert!(is_path_matched("/static/*", "/static/a/b"));
+        assert!(!is_path_matched("/static/*", "/static"));
+        assert!(is_path_matched("/api*", "/api/v1/users"));
+        // The pattern with other wildcards isn't legacy.
+        assert!(!is_path_matched("/api/*/*", "/api/a/b/c"));
+    }
+
+    #[test]
+    fn parse_sample_rules() {
+        assert_eq!(
+            parse_endpoint_sample_rules(" /checkout=1, /api/*=0.1 ,,"),
+            vec![("/checkout".to_owned(), 1.), ("/api/*".to_owned(), 0.1)]
+        );
+        // The `=` in pattern is kept, the rate is after the last one.
+        assert_eq!(
+            parse_endpoint_sample_rules("/a=b=0.5"),
+            vec![("/a=b".to_owned(), 0.5)]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_sample_rules() {
+        assert!(parse_endpoint_sample_rules("").is_empty());
+        assert_eq!(
+            parse_endpoint_sample_rules("/a,=0.5,/b=x,/c=1.5,/d=-0.1,/e=0"),
+            vec![("/e".to_owned(), 0.)]
+        );
+    }
+
+    #[test]
+    fn parse_ignore_paths() {
+        assert_eq!(
+            parse_trace_ignore_paths(" /health , ,/static/**"),
+            vec!["/health".to_owned(), "/static/**".to_owned()]
+        );
+    }
+}
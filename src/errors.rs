@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::context::NotTracedError;
 use anyhow::anyhow;
 use std::{result, str::Utf8Error};
 
@@ -27,6 +28,17 @@ pub enum Error {
     Anyhow(#[from] anyhow::Error),
 }
 
+impl Error {
+    /// Whether the error is caused by the request isn't traced, which
+    /// shouldn't be logged as error.
+    pub fn is_not_traced(&self) -> bool {
+        match self {
+            Self::Anyhow(err) => err.is::<NotTracedError>(),
+            _ => false,
+        }
+    }
+}
+
 impl From<Utf8Error> for Error {
     fn from(e: Utf8Error) -> Self {
         Self::Anyhow(e.into())
@@ -0,0 +1,94 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Events reporting, enabled by `skywalking_agent.enable_event`.
+//!
+//! The events reported by `skywalking_report_event()`, like the deployments
+//! and the config changes, are sent to worker, and uploaded to the event
+//! service of the OAP server, see [`crate::reporter::event`]. The worker
+//! reports the `Start` and `Shutdown` events of itself, like the java agent.
+
+use crate::{
+    channel::Reporter,
+    context::RequestContext,
+    module::{is_thread_worker_mode, SERVICE_INSTANCE, SERVICE_NAME, WORKER_ADDR},
+    profiling::now_millis,
+    util::truncate_str,
+    worker::ThreadReporter,
+};
+use once_cell::sync::Lazy;
+use skywalking::{
+    common::random_generator::RandomGenerator,
+    proto::v3::{Event, Source, Type},
+    trace::span::HandleSpanObject,
+};
+use std::collections::HashMap;
+
+/// The longer message is truncated, like the logs.
+const MAX_EVENT_MESSAGE_LENGTH: usize = 8 * 1024;
+
+/// The layer of the php services.
+const EVENT_LAYER: &str = "GENERAL";
+
+/// The events are sent by standalone connection, like the logs.
+static EVENT_REPORTER: Lazy<Reporter> = Lazy::new(|| Reporter::new(WORKER_ADDR.clone()));
+
+/// Create the event of current service instance, the times are timestamps in
+/// milliseconds.
+pub fn new_event(
+    name: String, message: &str, parameters: HashMap<String, String>, is_error: bool,
+    endpoint: String, start_time: i64, end_time: i64,
+) -> Event {
+    Event {
+        uuid: RandomGenerator::generate(),
+        source: Some(Source {
+            service: SERVICE_NAME.clone(),
+            service_instance: SERVICE_INSTANCE.clone(),
+            endpoint,
+        }),
+        name,
+        r#type: (if is_error { Type::Error } else { Type::Normal }) as i32,
+        message: truncate_str(message, MAX_EVENT_MESSAGE_LENGTH).to_owned(),
+        parameters,
+        start_time,
+        end_time,
+        layer: EVENT_LAYER.to_owned(),
+    }
+}
+
+/// Report the event, the source endpoint is the entry span of the request if
+/// it's traced. The start time defaults to now, and the end time defaults to
+/// the start time.
+pub fn report_event(
+    request_id: Option<i64>, name: String, message: &str, parameters: HashMap<String, String>,
+    is_error: bool, start_time: Option<i64>, end_time: Option<i64>,
+) {
+    let endpoint = RequestContext::try_with_global(request_id, |ctx| {
+        Ok(ctx.entry_span.span_object().operation_name.clone())
+    })
+    .unwrap_or_default();
+
+    let start_time = start_time.unwrap_or_else(now_millis);
+    let end_time = end_time.unwrap_or(start_time).max(start_time);
+    let event = new_event(
+        name, message, parameters, is_error, endpoint, start_time, end_time,
+    );
+
+    if is_thread_worker_mode() {
+        ThreadReporter::report_event(event);
+    } else {
+        EVENT_REPORTER.report_event(event);
+    }
+}
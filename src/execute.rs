@@ -14,10 +14,14 @@
 // limitations under the License.
 
 use crate::{
-    module::{ENABLE_ZEND_OBSERVER, IS_ZEND_OBSERVER_CALLED_FOR_INTERNAL},
+    module::{
+        CAPTURE_STACK_ON_SLOW_SPAN, ENABLE_ZEND_OBSERVER, IS_ZEND_OBSERVER_CALLED_FOR_INTERNAL,
+        SLOW_SPAN_THRESHOLD_MS,
+    },
     plugin::select_plugin_hook,
-    request::{HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME, IS_SWOOLE},
-    util::catch_unwind_result,
+    profiling::now_millis,
+    request::{get_swoole_request_id, is_swoole_hack_function, IS_SWOOLE},
+    util::{capture_backtrace, catch_unwind_result},
 };
 use anyhow::{bail, Context};
 use phper::{
@@ -26,8 +30,33 @@ use phper::{
     sys,
     values::{ExecuteData, ZVal},
 };
-use std::{any::Any, panic::AssertUnwindSafe, ptr::null_mut, sync::atomic::Ordering};
-use tracing::{error, trace};
+use skywalking::{
+    proto::v3::SpanType,
+    trace::span::{HandleSpanObject, Span},
+};
+use std::{
+    any::Any, cell::RefCell, panic::AssertUnwindSafe, ptr::null_mut, sync::atomic::Ordering,
+};
+use tracing::{debug, error, trace};
+
+/// The max depth of the stack attached to the slow span.
+const MAX_SLOW_SPAN_STACK_DEPTH: usize = 50;
+
+const TAG_STACKTRACE: &str = "sw.stacktrace";
+
+thread_local! {
+    /// The results of the before hooks whose after hooks haven't run, in the
+    /// order of calling. The result is kept out of the stack frame, which is
+    /// skipped if the hooked function bailed out (like fatal error), then the
+    /// result is left here and closed by [close_pending_results].
+    static PENDING_RESULTS: RefCell<Vec<PendingResult>> = Default::default();
+}
+
+struct PendingResult {
+    execute_data_ptr: *const sys::zend_execute_data,
+    request_id: Option<i64>,
+    result: Box<dyn Any>,
+}
 
 pub type BeforeExecuteHook = dyn Fn(Option<i64>, &mut ExecuteData) -> crate::Result<Box<dyn Any>>;
 
@@ -104,7 +133,7 @@ unsafe extern "C" fn execute_internal(
         }
     };
 
-    if function_name == HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME {
+    if is_swoole_hack_function(function_name.as_bytes()) {
         ori_execute_internal(Some(execute_data), Some(return_value));
         return;
     }
@@ -123,20 +152,38 @@ unsafe extern "C" fn execute_internal(
     );
 
     let result = catch_unwind_result(AssertUnwindSafe(|| before(request_id, execute_data)));
-    if let Err(err) = &result {
-        error!(
-            ?request_id,
-            ?function_name,
-            ?class_name,
-            ?err,
-            "before execute internal"
-        );
+    match &result {
+        Err(err) if err.is_not_traced() => {
+            trace!(
+                ?request_id,
+                ?function_name,
+                ?class_name,
+                "request isn't traced"
+            );
+        }
+        Err(err) => {
+            error!(
+                ?request_id,
+                ?function_name,
+                ?class_name,
+                ?err,
+                "before execute internal"
+            );
+        }
+        Ok(_) => {}
     }
 
+    let execute_data_ptr = execute_data.as_ptr();
+    let result = result.map(|data| push_pending_result(execute_data_ptr, request_id, data));
+
     ori_execute_internal(Some(execute_data), Some(return_value));
 
     // If before hook return error, don't execute the after hook.
-    if let Ok(data) = result {
+    if let Some(mut data) = result
+        .ok()
+        .and_then(|_| take_pending_result(execute_data_ptr))
+    {
+        attach_slow_span_stack(&mut data);
         if let Err(err) = catch_unwind_result(AssertUnwindSafe(|| {
             after(request_id, data, execute_data, return_value)
         })) {
@@ -197,20 +244,38 @@ unsafe extern "C" fn execute_ex(execute_data: *mut sys::zend_execute_data) {
     );
 
     let result = catch_unwind_result(AssertUnwindSafe(|| before(request_id, execute_data)));
-    if let Err(err) = &result {
-        error!(
-            ?request_id,
-            ?function_name,
-            ?class_name,
-            ?err,
-            "before execute ex"
-        );
+    match &result {
+        Err(err) if err.is_not_traced() => {
+            trace!(
+                ?request_id,
+                ?function_name,
+                ?class_name,
+                "request isn't traced"
+            );
+        }
+        Err(err) => {
+            error!(
+                ?request_id,
+                ?function_name,
+                ?class_name,
+                ?err,
+                "before execute ex"
+            );
+        }
+        Ok(_) => {}
     }
 
+    let execute_data_ptr = execute_data.as_ptr();
+    let result = result.map(|data| push_pending_result(execute_data_ptr, request_id, data));
+
     ori_execute_ex(Some(execute_data));
 
     // If before hook return error, don't execute the after hook.
-    if let Ok(data) = result {
+    if let Some(mut data) = result
+        .ok()
+        .and_then(|_| take_pending_result(execute_data_ptr))
+    {
+        attach_slow_span_stack(&mut data);
         let mut null = ZVal::from(());
         let return_value = match ZVal::try_from_mut_ptr((*execute_data.as_mut_ptr()).return_value) {
             Some(return_value) => return_value,
@@ -269,6 +334,88 @@ pub fn register_execute_functions() {
     }
 }
 
+fn push_pending_result(
+    execute_data_ptr: *const sys::zend_execute_data, request_id: Option<i64>, result: Box<dyn Any>,
+) {
+    // The pointer of the bailed out frame is reused.
+    if let Some(stale) = take_pending_result(execute_data_ptr) {
+        close_pending_result(stale);
+    }
+    PENDING_RESULTS.with(|results| {
+        results.borrow_mut().push(PendingResult {
+            execute_data_ptr,
+            request_id,
+            result,
+        })
+    });
+}
+
+fn take_pending_result(execute_data_ptr: *const sys::zend_execute_data) -> Option<Box<dyn Any>> {
+    PENDING_RESULTS.with(|results| {
+        let mut results = results.borrow_mut();
+        let index = results
+            .iter()
+            .rposition(|result| result.execute_data_ptr == execute_data_ptr)?;
+        Some(results.remove(index).result)
+    })
+}
+
+/// Close the results left by the hooked functions bailed out during the
+/// request, called before the request context is finished, so the spans are
+/// closed in the reverse order and marked errored, instead of leaking.
+pub fn close_pending_results(request_id: Option<i64>) {
+    let pending = PENDING_RESULTS.with(|results| {
+        let mut results = results.borrow_mut();
+        let (pending, others) = results
+            .drain(..)
+            .partition::<Vec<_>, _>(|result| result.request_id == request_id);
+        *results = others;
+        pending
+    });
+
+    for result in pending.into_iter().rev() {
+        close_pending_result(result.result);
+    }
+}
+
+fn close_pending_result(mut result: Box<dyn Any>) {
+    if let Some(span) = result.downcast_mut::<Span>() {
+        debug!(
+            operation_name = %span.span_object().operation_name,
+            "Close span of the hooked call not returned"
+        );
+        let span_object = span.span_object_mut();
+        span_object.is_error = true;
+        span_object.add_log([
+            ("event", "error"),
+            ("message", "the hooked call didn't return, like fatal error"),
+        ]);
+    }
+}
+
+/// Attach the stack to the exit span slower than
+/// `skywalking_agent.slow_span_threshold_ms`, before the after hook closes it,
+/// so the fast calls needn't capture the stack.
+fn attach_slow_span_stack(data: &mut Box<dyn Any>) {
+    if !*CAPTURE_STACK_ON_SLOW_SPAN {
+        return;
+    }
+    let Some(span) = data.downcast_mut::<Span>() else {
+        return;
+    };
+    let span_object = span.span_object();
+    if span_object.span_type() != SpanType::Exit
+        || now_millis() - span_object.start_time < *SLOW_SPAN_THRESHOLD_MS
+    {
+        return;
+    }
+
+    match capture_backtrace(MAX_SLOW_SPAN_STACK_DEPTH) {
+        Ok(stack) => span.add_tag(TAG_STACKTRACE, stack.join("\n")),
+        Err(err) => debug!(?err, "Capture stack of slow span failed"),
+    }
+}
+
 pub fn validate_num_args(execute_data: &mut ExecuteData, num: usize) -> anyhow::Result<()> {
     if execute_data.num_args() < num {
         bail!("argument count incorrect");
@@ -312,19 +459,17 @@ fn infer_request_id(execute_data: &mut ExecuteData) -> Option<i64> {
         };
         let func_name = prev_execute_data.func().get_function_name();
         if !func_name
-            .map(|s| s == &HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME.as_bytes())
+            .map(|s| is_swoole_hack_function(s.to_bytes()))
             .unwrap_or_default()
         {
             prev_execute_data_ptr = unsafe { (*prev_execute_data_ptr).prev_execute_data };
             continue;
         }
-        let Some(request) = prev_execute_data.get_mut_parameter(0).as_mut_z_obj() else {
-            return None;
-        };
-        match request.get_mut_property("fd").as_long() {
-            Some(fd) => return Some(fd),
-            None => {
-                error!("infer request id failed");
+        // The stack is of the current coroutine, so the callback runs in it.
+        match get_swoole_request_id() {
+            Ok(request_id) => return Some(request_id),
+            Err(err) => {
+                error!(?err, "infer request id failed");
                 return None;
             }
         }
@@ -344,15 +489,8 @@ pub fn register_observer_handlers() {
 #[cfg(phper_major_version = "8")]
 pub mod observer {
     use super::*;
-    use dashmap::DashMap;
-    use once_cell::sync::Lazy;
     use phper::sys;
 
-    /// Key is the pointer address of execute_data, value is the result of
-    /// before hook.
-    static mut RESULT_MAP: Lazy<DashMap<*const sys::zend_execute_data, Box<dyn Any>>> =
-        Lazy::new(DashMap::new);
-
     pub unsafe extern "C" fn observer_handler(
         execute_data: *mut sys::zend_execute_data,
     ) -> sys::zend_observer_fcall_handlers {
@@ -378,7 +516,7 @@ pub mod observer {
             return Default::default();
         };
 
-        if function_name == HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME {
+        if is_swoole_hack_function(function_name.as_bytes()) {
             return Default::default();
         }
 
@@ -427,6 +565,15 @@ pub mod observer {
         let result =
             match catch_unwind_result(AssertUnwindSafe(|| before(request_id, execute_data))) {
                 Ok(result) => result,
+                Err(err) if err.is_not_traced() => {
+                    trace!(
+                        ?request_id,
+                        ?function_name,
+                        ?class_name,
+                        "request isn't traced"
+                    );
+                    return;
+                }
                 Err(err) => {
                     error!(
                         ?request_id,
@@ -439,7 +586,7 @@ pub mod observer {
                 }
             };
 
-        RESULT_MAP.insert(execute_data.as_ptr(), result);
+        push_pending_result(execute_data.as_ptr(), request_id, result);
     }
 
     unsafe extern "C" fn observer_end(
@@ -450,9 +597,10 @@ pub mod observer {
         };
         trace!(execute_data_ptr=?execute_data.as_ptr(), "start observer_end");
 
-        let Some((_, result)) = RESULT_MAP.remove(&execute_data.as_ptr()) else {
+        let Some(mut result) = take_pending_result(execute_data.as_ptr()) else {
             return;
         };
+        attach_slow_span_stack(&mut result);
 
         let mut null = ZVal::from(());
         let ret = match ZVal::try_from_mut_ptr(retval) {
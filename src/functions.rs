@@ -0,0 +1,587 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The functions exported to PHP userland, they are always registered, and
+//! do nothing if the agent isn't enabled, so the user code needn't check the
+//! existence.
+
+use crate::{
+    context::RequestContext,
+    event,
+    meter::{self, default_histogram_buckets, CustomMeterValue},
+    module::{
+        is_enable, is_thread_worker_mode, ENABLE_EVENT, ENABLE_METER, ENABLE_TLS, REPORTER_TYPE,
+        SERVER_ADDR, SERVICE_NAME, WORKER_MODE,
+    },
+    worker::{is_worker_running, ThreadReporter},
+};
+use phper::{
+    arrays::{IterKey, ZArr, ZArray},
+    values::ZVal,
+};
+use skywalking::trace::span::HandleSpanObject;
+use std::time::Duration;
+use tracing::debug;
+
+const DEFAULT_CONTINUED_OPERATION_NAME: &str = "PHP/Continued";
+
+const DEFAULT_FLUSH_TIMEOUT_MS: i64 = 1000;
+
+/// `skywalking_set_operation_name(string $operation_name): bool`
+///
+/// Override the operation name of the entry span of current request, returns
+/// false if there is no traced request.
+pub fn skywalking_set_operation_name(args: &mut [ZVal]) -> phper::Result<bool> {
+    let operation_name = args[0].expect_z_str()?.to_str()?.to_owned();
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    Ok(RequestContext::rename_entry_span(None, operation_name))
+}
+
+/// `skywalking_set_service_name(string $service_name): bool`
+///
+/// Override the service of the segment of current request, returns false if
+/// there is no traced request. The service instance and the parent service in
+/// the propagated `sw8` header are unchanged.
+pub fn skywalking_set_service_name(args: &mut [ZVal]) -> phper::Result<bool> {
+    let service_name = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+
+    if !is_enable() || service_name.is_empty() {
+        return Ok(false);
+    }
+
+    let result = RequestContext::try_with_global(None, |ctx| {
+        debug!(%service_name, "Set service name of segment");
+        ctx.set_service_name(service_name);
+        Ok(())
+    });
+
+    Ok(result.is_ok())
+}
+
+/// `skywalking_skip_analysis(): bool`
+///
+/// Mark the segment of current request skipping the analysis of server, and
+/// propagate it to downstream by the `sw8-x` header, returns false if there is
+/// no traced request.
+pub fn skywalking_skip_analysis(_: &mut [ZVal]) -> phper::Result<bool> {
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    let result = RequestContext::try_with_global(None, |ctx| {
+        debug!("Set skip analysis of segment");
+        ctx.set_skip_analysis();
+        Ok(())
+    });
+
+    Ok(result.is_ok())
+}
+
+/// `skywalking_ignore_trace(): bool`
+///
+/// Discard the segment of current request instead of reporting it, and stop
+/// tracing the rest of the request, returns false if there is no traced
+/// request. The downstream already called keeps the trace id propagated, so
+/// its segments are reported without the parent.
+pub fn skywalking_ignore_trace(_: &mut [ZVal]) -> phper::Result<bool> {
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    let is_ignored = RequestContext::ignore(None);
+    if is_ignored {
+        debug!("Ignore trace of current request");
+    }
+
+    Ok(is_ignored)
+}
+
+/// `skywalking_capture(): string`
+///
+/// Capture the snapshot of current context, to be continued later by
+/// `skywalking_continue`, returns empty string if there is no traced request.
+pub fn skywalking_capture(_: &mut [ZVal]) -> phper::Result<String> {
+    if !is_enable() {
+        return Ok(String::new());
+    }
+
+    Ok(RequestContext::try_capture(None).unwrap_or_default())
+}
+
+/// `skywalking_continue(string $snapshot, string $operation_name =
+/// "PHP/Continued"): bool`
+///
+/// Start a new segment referencing the captured snapshot, the spans created
+/// before `skywalking_finish_continued` belong to it. Returns false if there
+/// is no traced request or the snapshot is invalid.
+pub fn skywalking_continue(args: &mut [ZVal]) -> phper::Result<bool> {
+    let snapshot = args[0].expect_z_str()?.to_str()?.to_owned();
+    let operation_name = match args.get(1) {
+        Some(operation_name) => operation_name.expect_z_str()?.to_str()?.to_owned(),
+        None => DEFAULT_CONTINUED_OPERATION_NAME.to_owned(),
+    };
+
+    if !is_enable() || snapshot.is_empty() {
+        return Ok(false);
+    }
+
+    match RequestContext::continue_snapshot(None, &snapshot, &operation_name) {
+        Ok(_) => {
+            debug!(%operation_name, "Continue snapshot");
+            Ok(true)
+        }
+        Err(err) => {
+            debug!(?err, "Continue snapshot failed");
+            Ok(false)
+        }
+    }
+}
+
+/// `skywalking_finish_continued(): bool`
+///
+/// Finish the segment started by the last `skywalking_continue`, returns false
+/// if there is no continued segment.
+pub fn skywalking_finish_continued(_: &mut [ZVal]) -> phper::Result<bool> {
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    Ok(RequestContext::finish_continued(None))
+}
+
+/// `skywalking_get_correlation(string $key): ?string`.
+///
+/// Get the value of the correlation context of current request, from the
+/// `sw8-correlation` header of upstream, or the request id generated by
+/// `skywalking_agent.auto_correlation_request_id`. Returns null if not exists.
+pub fn skywalking_get_correlation(args: &mut [ZVal]) -> phper::Result<Option<String>> {
+    let key = args[0].expect_z_str()?.to_str()?;
+
+    if !is_enable() {
+        return Ok(None);
+    }
+
+    Ok(
+        RequestContext::try_with_global(None, |ctx| Ok(ctx.get_correlation(key)))
+            .ok()
+            .flatten(),
+    )
+}
+
+/// `skywalking_correlation_put(string $key, string $value): bool`
+///
+/// Put the key-value into the correlation context of current request,
+/// propagated to downstream by the `sw8-correlation` header, the existing key
+/// is replaced. Returns false if there is no traced request, the context
+/// already has 3 elements, or the value is longer than 128 bytes.
+pub fn skywalking_correlation_put(args: &mut [ZVal]) -> phper::Result<bool> {
+    let key = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let value = args[1].expect_z_str()?.to_str()?.to_owned();
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    Ok(RequestContext::try_with_global(None, |ctx| {
+        debug!(%key, "Put correlation");
+        Ok(ctx.put_correlation(key, value))
+    })
+    .unwrap_or_default())
+}
+
+/// `skywalking_set_common_tag(string $key, string $value, bool
+/// $include_entry_span = true): bool`
+///
+/// Tag every span of current request started afterward, and the entry span if
+/// `$include_entry_span`, like the tenant id or the build version, without
+/// touching the plugins. Unlike the correlation, it isn't propagated to
+/// downstream. Returns false if there is no traced request or the common tags
+/// are full.
+pub fn skywalking_set_common_tag(args: &mut [ZVal]) -> phper::Result<bool> {
+    let key = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let value = args[1].expect_z_str()?.to_str()?.to_owned();
+    let include_entry_span = match args.get(2) {
+        Some(include_entry_span) => include_entry_span.expect_bool()?,
+        None => true,
+    };
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    Ok(RequestContext::try_with_global(None, |ctx| {
+        debug!(%key, "Set common tag");
+        Ok(ctx.put_common_tag(key, value, include_entry_span))
+    })
+    .unwrap_or_default())
+}
+
+/// `skywalking_add_log(array $key_values): bool`.
+///
+/// Add a log event with current timestamp to the active span, which is the
+/// last span started by `skywalking_start_span` and not ended, or the entry
+/// span of current request or continued segment, since the exit spans are
+/// finished before the hooked calls return. The string, integer, float and
+/// bool values are recorded, the others are skipped. Returns false if there is
+/// no traced request or nothing to log.
+pub fn skywalking_add_log(args: &mut [ZVal]) -> phper::Result<bool> {
+    let key_values = args[0].expect_z_arr()?;
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    let logs = scalar_key_values(key_values);
+
+    if logs.is_empty() {
+        return Ok(false);
+    }
+
+    let result = RequestContext::try_with_global(None, |ctx| {
+        debug!(count = logs.len(), "Add log to active span");
+        ctx.with_active_span(|span| span.span_object_mut().add_log(logs));
+        Ok(())
+    });
+
+    Ok(result.is_ok())
+}
+
+/// `skywalking_start_span(string $operation_name, ?string $peer = null):
+/// bool`
+///
+/// Start a local span, or an exit span to `$peer`, as the child of the active
+/// span, for the business code no plugin covers. The spans created by the
+/// plugins before it ended are its children. Must be ended by
+/// `skywalking_end_span`, otherwise it's ended with the request. Returns false
+/// if there is no traced request, or 64 spans not ended.
+pub fn skywalking_start_span(args: &mut [ZVal]) -> phper::Result<bool> {
+    let operation_name = args[0].expect_z_str()?.to_str()?.to_owned();
+    let peer = match args.get(1) {
+        Some(peer) if !peer.get_type_info().is_null() => {
+            Some(peer.expect_z_str()?.to_str()?.to_owned())
+        }
+        _ => None,
+    };
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    Ok(RequestContext::try_with_global(None, |ctx| {
+        debug!(%operation_name, ?peer, "Start manual span");
+        Ok(ctx.start_manual_span(&operation_name, peer.as_deref()))
+    })
+    .unwrap_or_default())
+}
+
+/// `skywalking_end_span(bool $is_error = false): bool`
+///
+/// End the last span started by `skywalking_start_span`, marked errored if
+/// `$is_error`. Returns false if there is no span to end.
+pub fn skywalking_end_span(args: &mut [ZVal]) -> phper::Result<bool> {
+    let is_error = match args.first() {
+        Some(is_error) => is_error.expect_bool()?,
+        None => false,
+    };
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    Ok(RequestContext::try_with_global(None, |ctx| {
+        debug!(is_error, "End manual span");
+        Ok(ctx.end_manual_span(is_error))
+    })
+    .unwrap_or_default())
+}
+
+/// `skywalking_tag(string $key, string $value): bool`
+///
+/// Tag the active span, the same span as `skywalking_add_log`. Returns false
+/// if there is no traced request.
+pub fn skywalking_tag(args: &mut [ZVal]) -> phper::Result<bool> {
+    let key = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let value = args[1].expect_z_str()?.to_str()?.to_owned();
+
+    if !is_enable() || key.is_empty() {
+        return Ok(false);
+    }
+
+    let result = RequestContext::try_with_global(None, |ctx| {
+        debug!(%key, "Tag active span");
+        ctx.with_active_span(|span| span.add_tag(key, value));
+        Ok(())
+    });
+
+    Ok(result.is_ok())
+}
+
+/// `skywalking_trace_id(): ?string`
+///
+/// The trace id of current request, or the continued segment, returns null if
+/// there is no traced request.
+pub fn skywalking_trace_id(_: &mut [ZVal]) -> phper::Result<Option<String>> {
+    if !is_enable() {
+        return Ok(None);
+    }
+
+    Ok(RequestContext::try_with_global_ctx(None, |ctx| Ok(ctx.trace_id().to_owned())).ok())
+}
+
+/// `skywalking_counter(string $name, float $value = 1, array $labels = []):
+/// bool`
+///
+/// Increase the custom counter, labeled by the string keys and scalar values
+/// of `$labels`. Requires `skywalking_agent.enable_meter`, the meters are
+/// aggregated by the worker and reported periodically. Returns false if the
+/// meter isn't enabled, or the value isn't a non-negative number.
+pub fn skywalking_counter(args: &mut [ZVal]) -> phper::Result<bool> {
+    let name = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let value = match args.get(1) {
+        Some(value) => number(value),
+        None => Some(1.),
+    };
+    let labels = match args.get(2) {
+        Some(labels) => scalar_key_values(labels.expect_z_arr()?),
+        None => Vec::new(),
+    };
+
+    let Some(value) = value.filter(|value| *value >= 0.) else {
+        return Ok(false);
+    };
+    Ok(record_custom_meter(
+        name,
+        labels,
+        CustomMeterValue::Counter(value),
+    ))
+}
+
+/// `skywalking_gauge(string $name, float $value, array $labels = []): bool`
+///
+/// Set the custom gauge, the last value in the report period is reported.
+/// Returns false if the meter isn't enabled, or the value isn't a number.
+pub fn skywalking_gauge(args: &mut [ZVal]) -> phper::Result<bool> {
+    let name = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let value = number(&args[1]);
+    let labels = match args.get(2) {
+        Some(labels) => scalar_key_values(labels.expect_z_arr()?),
+        None => Vec::new(),
+    };
+
+    let Some(value) = value else {
+        return Ok(false);
+    };
+    Ok(record_custom_meter(
+        name,
+        labels,
+        CustomMeterValue::Gauge(value),
+    ))
+}
+
+/// `skywalking_histogram(string $name, float $value, array $labels = [], array
+/// $buckets = []): bool`
+///
+/// Observe the value by the custom histogram. The buckets are the lower bounds,
+/// fixed by the first observation of the meter, and default to the buckets of
+/// `php_endpoint_response_time`. Returns false if the meter isn't enabled, or
+/// the value isn't a number.
+pub fn skywalking_histogram(args: &mut [ZVal]) -> phper::Result<bool> {
+    let name = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let value = number(&args[1]);
+    let labels = match args.get(2) {
+        Some(labels) => scalar_key_values(labels.expect_z_arr()?),
+        None => Vec::new(),
+    };
+    let mut buckets = match args.get(3) {
+        Some(buckets) => buckets
+            .expect_z_arr()?
+            .iter()
+            .filter_map(|(_, bucket)| number(bucket))
+            .filter(|bucket| bucket.is_finite())
+            .collect(),
+        None => Vec::new(),
+    };
+    if buckets.is_empty() {
+        buckets = default_histogram_buckets();
+    }
+    buckets.sort_by(f64::total_cmp);
+    buckets.dedup();
+
+    let Some(value) = value else {
+        return Ok(false);
+    };
+    Ok(record_custom_meter(
+        name,
+        labels,
+        CustomMeterValue::Histogram { value, buckets },
+    ))
+}
+
+fn record_custom_meter(
+    name: String, labels: Vec<(String, String)>, value: CustomMeterValue,
+) -> bool {
+    if !is_enable() || !*ENABLE_METER || name.is_empty() {
+        return false;
+    }
+    debug!(%name, "Record custom meter");
+    meter::record_custom(name, labels, value);
+    true
+}
+
+/// The integer or float, and not NaN.
+fn number(value: &ZVal) -> Option<f64> {
+    value
+        .as_double()
+        .or_else(|| value.as_long().map(|n| n as f64))
+        .filter(|n| !n.is_nan())
+}
+
+/// The pairs of the array with the string, integer, float and bool values, the
+/// others are skipped.
+fn scalar_key_values(arr: &ZArr) -> Vec<(String, String)> {
+    arr.iter()
+        .filter_map(|(key, value)| {
+            let key = match key {
+                IterKey::Index(i) => i.to_string(),
+                IterKey::ZStr(s) => s.to_str().ok()?.to_owned(),
+            };
+            let value = if let Some(s) = value.as_z_str() {
+                s.to_str().ok()?.to_owned()
+            } else if let Some(b) = value.as_bool() {
+                b.to_string()
+            } else if let Some(n) = value.as_long() {
+                n.to_string()
+            } else {
+                value.as_double()?.to_string()
+            };
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// `skywalking_report_event(string $name, string $message = '', array
+/// $parameters = [], bool $is_error = false, ?int $start_time = null, ?int
+/// $end_time = null): bool`
+///
+/// Report the event of current service instance, like the deployment or the
+/// config change, the scalar values of `$parameters` are recorded. The times
+/// are timestamps in milliseconds, the start time defaults to now, and the end
+/// time defaults to the start time. Requires `skywalking_agent.enable_event`,
+/// returns false if it isn't enabled or the name is empty.
+pub fn skywalking_report_event(args: &mut [ZVal]) -> phper::Result<bool> {
+    let name = args[0].expect_z_str()?.to_str()?.trim().to_owned();
+    let message = match args.get(1) {
+        Some(message) => message.expect_z_str()?.to_str()?.to_owned(),
+        None => String::new(),
+    };
+    let parameters = match args.get(2) {
+        Some(parameters) => scalar_key_values(parameters.expect_z_arr()?),
+        None => Vec::new(),
+    };
+    let is_error = match args.get(3) {
+        Some(is_error) => is_error.expect_bool()?,
+        None => false,
+    };
+    let start_time = optional_long(args.get(4))?;
+    let end_time = optional_long(args.get(5))?;
+
+    if !is_enable() || !*ENABLE_EVENT || name.is_empty() {
+        return Ok(false);
+    }
+
+    debug!(%name, "Report event");
+    event::report_event(
+        None,
+        name,
+        &message,
+        parameters.into_iter().collect(),
+        is_error,
+        start_time,
+        end_time,
+    );
+    Ok(true)
+}
+
+/// The optional argument of `?int`, `None` if omitted or null.
+fn optional_long(value: Option<&ZVal>) -> phper::Result<Option<i64>> {
+    match value {
+        Some(value) if !value.get_type_info().is_null() => Ok(Some(value.expect_long()?)),
+        _ => Ok(None),
+    }
+}
+
+/// `skywalking_flush(int $timeout_ms = 1000): bool`
+///
+/// Block until the items reported by current process are handed to the
+/// worker, or the timeout elapses, returns false if timeout. Only the hop from
+/// php to worker is flushed, not the reporting from worker to OAP.
+///
+/// The items are written to the socket synchronously when the worker is a
+/// standalone process, so there is nothing to wait, only the channel of the
+/// thread worker is waited.
+///
+/// The segment of current request is reported when the request ends, after
+/// the script, so it can't be flushed by this function, like the final segment
+/// of the cli script. It's drained by the module shutdown of the thread
+/// worker instead, see
+/// [`shutdown_thread_worker`](crate::worker::shutdown_thread_worker).
+pub fn skywalking_flush(args: &mut [ZVal]) -> phper::Result<bool> {
+    let timeout_ms = match args.first() {
+        Some(timeout_ms) => timeout_ms.expect_long()?.max(0),
+        None => DEFAULT_FLUSH_TIMEOUT_MS,
+    };
+
+    if !is_enable() {
+        return Ok(false);
+    }
+
+    if !is_thread_worker_mode() {
+        return Ok(true);
+    }
+
+    debug!(timeout_ms, "Flush thread worker");
+    Ok(ThreadReporter::flush(Duration::from_millis(
+        timeout_ms as u64,
+    )))
+}
+
+/// `skywalking_agent_version(): string`
+///
+/// The version of the loaded agent.
+pub fn skywalking_agent_version(_: &mut [ZVal]) -> phper::Result<&'static str> {
+    Ok(env!("CARGO_PKG_VERSION"))
+}
+
+/// `skywalking_agent_info(): array`
+///
+/// The version and the resolved settings of the loaded agent, and whether the
+/// worker is running, for the support triage without `phpinfo()`. The settings
+/// are read from the parsed ini values, so it's cheap.
+pub fn skywalking_agent_info(_: &mut [ZVal]) -> phper::Result<ZArray> {
+    let mut info = ZArray::new();
+    info.insert("version", env!("CARGO_PKG_VERSION"));
+    info.insert("enabled", is_enable());
+    info.insert("worker_running", is_enable() && is_worker_running());
+    info.insert("worker_mode", WORKER_MODE.as_str());
+    info.insert("reporter_type", REPORTER_TYPE.as_str());
+    info.insert("server_addr", SERVER_ADDR.as_str());
+    info.insert("service_name", SERVICE_NAME.as_str());
+    info.insert("enable_tls", *ENABLE_TLS);
+    Ok(info)
+}
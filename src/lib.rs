@@ -17,22 +17,36 @@
 #![warn(rust_2018_idioms)]
 #![warn(clippy::dbg_macro, clippy::print_stdout)]
 
+mod breaker;
 mod channel;
 mod component;
 mod context;
+mod dynamic_config;
 mod errors;
+mod event;
 mod execute;
+mod functions;
+mod log;
+mod meter;
 mod module;
+mod peer;
 mod plugin;
+mod profiling;
 mod reporter;
 mod request;
+mod status;
 mod tag;
+#[cfg(test)]
+mod testing;
 mod util;
 mod worker;
 
-use phper::{ini::Policy, modules::Module, php_get_module};
+use phper::{functions::Argument, ini::Policy, modules::Module, php_get_module};
 
-use crate::request::HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME;
+use crate::request::{
+    HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME, HACK_SWOOLE_ON_OPEN_FUNCTION_NAME,
+    HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME,
+};
 pub use errors::{Error, Result};
 
 /// Enable agent and report or not.
@@ -62,9 +76,27 @@ const SKYWALKING_AGENT_LOG_FILE: &str = "skywalking_agent.log_file";
 /// Skywalking agent runtime directory.
 const SKYWALKING_AGENT_RUNTIME_DIR: &str = "skywalking_agent.runtime_dir";
 
+/// Unix socket file path used by the worker, overrides the default
+/// `agent.sock` under the service directory of runtime directory when not
+/// empty.
+const SKYWALKING_AGENT_SOCKET_FILE_PATH: &str = "skywalking_agent.socket_file_path";
+
+/// Permission mode of the worker unix socket file, in octal, such as `0770`.
+const SKYWALKING_AGENT_SOCKET_FILE_MODE: &str = "skywalking_agent.socket_file_mode";
+
+/// Socket type between php processes and worker, `unix` or `tcp`.
+const SKYWALKING_AGENT_SOCKET_TYPE: &str = "skywalking_agent.socket_type";
+
+/// TCP address of the worker, used when the socket type is `tcp`.
+const SKYWALKING_AGENT_SOCKET_TCP_ADDR: &str = "skywalking_agent.socket_tcp_addr";
+
 /// Skywalking agent authentication token.
 const SKYWALKING_AGENT_AUTHENTICATION: &str = "skywalking_agent.authentication";
 
+/// Extra gRPC metadata attached to every report call, in the format of
+/// `key1:value1,key2:value2`, the `${ENV}` in values is expanded.
+const SKYWALKING_AGENT_GRPC_EXTRA_HEADERS: &str = "skywalking_agent.grpc_extra_headers";
+
 /// Wether to enable tls for gPRC.
 const SKYWALKING_AGENT_ENABLE_TLS: &str = "skywalking_agent.enable_tls";
 
@@ -92,7 +124,8 @@ const SKYWALKING_AGENT_PROPERTIES_REPORT_PERIOD_FACTOR: &str =
 /// PHP8's jit.
 const SKYWALKING_AGENT_ENABLE_ZEND_OBSERVER: &str = "skywalking_agent.enable_zend_observer";
 
-/// Reporter type, optional values are `grpc` and `kafka`, default is `grpc`.
+/// Reporter type, optional values are `grpc`, `kafka` and `http`, default is
+/// `grpc`.
 const SKYWALKING_AGENT_REPORTER_TYPE: &str = "skywalking_agent.reporter_type";
 
 /// A list of host/port pairs to use for establishing the initial connection to
@@ -103,12 +136,273 @@ const SKYWALKING_AGENT_KAFKA_BOOTSTRAP_SERVERS: &str = "skywalking_agent.kafka_b
 /// Only available when the reporter type is `kafka`.
 const SKYWALKING_AGENT_KAFKA_PRODUCER_CONFIG: &str = "skywalking_agent.kafka_producer_config";
 
+/// The namespace prefixed to the Kafka topics, like `ns-skywalking-segments`,
+/// same as the namespace of the kafka fetcher of the OAP server. Only
+/// available when the reporter type is `kafka`.
+const SKYWALKING_AGENT_KAFKA_NAMESPACE: &str = "skywalking_agent.kafka_namespace";
+
 /// Whether to enable automatic injection of skywalking context variables (such
 /// as `SW_TRACE_ID`). For `php-fpm` mode, it will be injected into the
 /// `$_SERVER` variable. For `swoole` mode, it will be injected into the
 /// `$request->server` variable.
 const SKYWALKING_AGENT_INJECT_CONTEXT: &str = "skywalking_agent.inject_context";
 
+/// Whether to correct the span timestamps by the clock skew between the local
+/// host and skywalking server.
+const SKYWALKING_AGENT_CORRECT_CLOCK_SKEW: &str = "skywalking_agent.correct_clock_skew";
+
+/// The file which the worker status is written to periodically in JSON, empty
+/// means disabled.
+const SKYWALKING_AGENT_STATUS_FILE: &str = "skywalking_agent.status_file";
+
+/// The max count of collect items reported in a batch by the worker.
+const SKYWALKING_AGENT_REPORT_BATCH_SIZE: &str = "skywalking_agent.report_batch_size";
+
+/// The max waiting time in milliseconds of accumulating a batch.
+const SKYWALKING_AGENT_REPORT_FLUSH_INTERVAL_MS: &str = "skywalking_agent.report_flush_interval_ms";
+
+/// Whether to report the request body of elasticsearch as `db.statement`.
+const SKYWALKING_AGENT_ES_REPORT_BODY: &str = "skywalking_agent.es_report_body";
+
+/// Whether to replace the literals in SQL statements with `?` before
+/// reporting.
+const SKYWALKING_AGENT_SQL_PARAMETERIZE: &str = "skywalking_agent.sql_parameterize";
+
+/// Whether to trace the cli scripts, the cli under swoole is always traced
+/// unless `skywalking_agent.enable_swoole` is Off.
+const SKYWALKING_AGENT_ENABLE_CLI: &str = "skywalking_agent.enable_cli";
+
+/// How to name the entry span of cli scripts, `script_basename`, `script_path`
+/// or `argv`.
+const SKYWALKING_AGENT_CLI_OPERATION_NAME_MODE: &str = "skywalking_agent.cli_operation_name_mode";
+
+/// Whether to tag the entry span with the php-fpm pool and process id.
+const SKYWALKING_AGENT_REPORT_FPM_INFO: &str = "skywalking_agent.report_fpm_info";
+
+/// The request paths whose body is captured, separated by comma, the path
+/// ending with `*` matches the prefix.
+const SKYWALKING_AGENT_CAPTURE_REQUEST_BODY_PATHS: &str =
+    "skywalking_agent.capture_request_body_paths";
+
+/// The max bytes of the captured request body.
+const SKYWALKING_AGENT_TRACE_MAX_BODY_SIZE: &str = "skywalking_agent.trace_max_body_size";
+
+/// Whether to enable trace profiling, the stack sampling adds overhead.
+const SKYWALKING_AGENT_ENABLE_PROFILING: &str = "skywalking_agent.enable_profiling";
+
+/// How to run the worker, `fork` (standalone process) or `thread` (background
+/// thread of every php process, for environments that forbid fork).
+const SKYWALKING_AGENT_WORKER_MODE: &str = "skywalking_agent.worker_mode";
+
+/// Whether to mark the entry span errored when its exit span is errored.
+const SKYWALKING_AGENT_PROPAGATE_EXIT_ERRORS: &str = "skywalking_agent.propagate_exit_errors";
+
+/// The rate of requests traced, in `[0, 1]`.
+const SKYWALKING_AGENT_SAMPLE_RATE: &str = "skywalking_agent.sample_rate";
+
+/// The request paths not traced, separated by comma, the path ending with `*`
+/// matches the prefix.
+const SKYWALKING_AGENT_TRACE_IGNORE_PATH: &str = "skywalking_agent.trace_ignore_path";
+
+/// The sample rates of the request paths, like `/checkout=1,/api/*=0.1`,
+/// separated by comma, the first matched rule wins, fallback to the
+/// `sample_rate`.
+const SKYWALKING_AGENT_ENDPOINT_SAMPLE_RULES: &str = "skywalking_agent.endpoint_sample_rules";
+
+/// The max count of spans in one segment, non-positive means unlimited.
+const SKYWALKING_AGENT_SPAN_LIMIT_PER_SEGMENT: &str = "skywalking_agent.span_limit_per_segment";
+
+/// The max bytes of every tag value, the value beyond it is truncated.
+const SKYWALKING_AGENT_MAX_TAG_VALUE_LENGTH: &str = "skywalking_agent.max_tag_value_length";
+
+/// Whether to report the endpoint meters, aggregated by the worker.
+const SKYWALKING_AGENT_ENABLE_METER: &str = "skywalking_agent.enable_meter";
+
+/// The count of consecutive report failures which opens the circuit breaker,
+/// non-positive means disabled.
+const SKYWALKING_AGENT_CIRCUIT_BREAKER_THRESHOLD: &str =
+    "skywalking_agent.circuit_breaker_threshold";
+
+/// The seconds of the opened circuit breaker before probing again.
+const SKYWALKING_AGENT_CIRCUIT_BREAKER_COOL_DOWN_PERIOD: &str =
+    "skywalking_agent.circuit_breaker_cool_down_period";
+
+/// Whether to trace the session functions, like `session_start`.
+const SKYWALKING_AGENT_TRACE_SESSIONS: &str = "skywalking_agent.trace_sessions";
+
+/// The exception classes not marking the span errored, separated by comma,
+/// the subclasses are also ignored.
+const SKYWALKING_AGENT_IGNORED_EXCEPTIONS: &str = "skywalking_agent.ignored_exceptions";
+
+/// Whether to attach the stack to the exit span slower than
+/// `slow_span_threshold_ms`.
+const SKYWALKING_AGENT_CAPTURE_STACK_ON_SLOW_SPAN: &str =
+    "skywalking_agent.capture_stack_on_slow_span";
+
+/// The duration in milliseconds of the slow exit span.
+const SKYWALKING_AGENT_SLOW_SPAN_THRESHOLD_MS: &str = "skywalking_agent.slow_span_threshold_ms";
+
+/// The max count of the connections from php processes to the worker, the
+/// connections beyond it are rejected.
+const SKYWALKING_AGENT_MAX_IPC_CONNECTIONS: &str = "skywalking_agent.max_ipc_connections";
+
+/// Whether to tag the first query span of the connection with the connect
+/// time, in the PDO and mysqli plugins.
+const SKYWALKING_AGENT_REPORT_DB_CONNECT_TIME: &str = "skywalking_agent.report_db_connect_time";
+
+/// Whether to put a generated request id into the correlation context of
+/// every traced request.
+const SKYWALKING_AGENT_AUTO_CORRELATION_REQUEST_ID: &str =
+    "skywalking_agent.auto_correlation_request_id";
+
+/// The key of the generated request id in the correlation context.
+const SKYWALKING_AGENT_CORRELATION_REQUEST_ID_KEY: &str =
+    "skywalking_agent.correlation_request_id_key";
+
+/// Whether to report the OS info (like hostname and IPs) in the instance
+/// properties.
+const SKYWALKING_AGENT_REPORT_OS_INFO: &str = "skywalking_agent.report_os_info";
+
+/// Whether to report the query string as the `http.params` tag of the entry
+/// span.
+const SKYWALKING_AGENT_REPORT_QUERY_STRING: &str = "skywalking_agent.report_query_string";
+
+/// The query params whose values are masked, separated by comma, case
+/// insensitive.
+const SKYWALKING_AGENT_QUERY_STRING_REDACT_KEYS: &str = "skywalking_agent.query_string_redact_keys";
+
+/// The rules of custom exit spans, like
+/// `Solarium\Client::execute|8001|$this.options.endpoint`, separated by
+/// semicolon.
+const SKYWALKING_AGENT_CUSTOM_EXIT_SPAN_RULES: &str = "skywalking_agent.custom_exit_span_rules";
+
+/// The component ids overriding the defaults of plugins, like
+/// `curl=8002,redis=7`, separated by comma.
+const SKYWALKING_AGENT_COMPONENT_OVERRIDES: &str = "skywalking_agent.component_overrides";
+
+/// The max delay in milliseconds of reconnecting to the skywalking server.
+const SKYWALKING_AGENT_RECONNECT_MAX_BACKOFF_MS: &str = "skywalking_agent.reconnect_max_backoff_ms";
+
+/// The headers to find the client ip in order, like
+/// `X-Forwarded-For,X-Real-IP`, separated by comma.
+const SKYWALKING_AGENT_CLIENT_IP_HEADERS: &str = "skywalking_agent.client_ip_headers";
+
+/// Whether to create a segment for every message handled by the queue workers
+/// of symfony/messenger and Laravel.
+const SKYWALKING_AGENT_TRACE_QUEUE_WORKERS: &str = "skywalking_agent.trace_queue_workers";
+
+/// The format of the agent logs, `text` or `json`.
+const SKYWALKING_AGENT_LOG_FORMAT: &str = "skywalking_agent.log_format";
+
+/// The max encoded bytes of one segment, the spans beyond it are dropped.
+const SKYWALKING_AGENT_MAX_SEGMENT_BYTES: &str = "skywalking_agent.max_segment_bytes";
+
+/// The namespace to isolate the traces, combined into the service name.
+const SKYWALKING_AGENT_NAMESPACE: &str = "skywalking_agent.namespace";
+
+/// The base url of the OAP REST receiver. Only available when the reporter type
+/// is `http`.
+const SKYWALKING_AGENT_HTTP_RECEIVER_URL: &str = "skywalking_agent.http_receiver_url";
+
+/// The sample rates of the repetitive exit spans per plugin, like
+/// `redis=0.1,memcached=0.1`.
+const SKYWALKING_AGENT_EXIT_SPAN_SAMPLE_RATE: &str = "skywalking_agent.exit_span_sample_rate";
+
+/// The query params whose values are masked in the `url` tag of http exit
+/// spans, separated by comma, case insensitive.
+const SKYWALKING_AGENT_URL_REDACT_PARAMS: &str = "skywalking_agent.url_redact_params";
+
+/// The requests sampled out are still traced, and reported if the duration
+/// reaches it or errored. Zero means disabled.
+const SKYWALKING_AGENT_TAIL_SAMPLE_LATENCY_MS: &str = "skywalking_agent.tail_sample_latency_ms";
+
+/// The logical service names of the peers of exit spans, like
+/// `10.0.0.1:80=order-service,*.example.com=example`.
+const SKYWALKING_AGENT_PEER_SERVICE_MAPPING: &str = "skywalking_agent.peer_service_mapping";
+
+/// The file of extra instance properties in `key=value` lines, the properties
+/// are re-reported at once when it changes.
+const SKYWALKING_AGENT_INSTANCE_PROPERTIES_FILE: &str = "skywalking_agent.instance_properties_file";
+
+/// Whether to trace the subprocess functions, like `proc_open` and `exec`.
+const SKYWALKING_AGENT_TRACE_SUBPROCESS: &str = "skywalking_agent.trace_subprocess";
+
+/// Whether to tag the full command lines of the subprocess spans, which may
+/// contain the secrets.
+const SKYWALKING_AGENT_TRACE_SUBPROCESS_COMMAND: &str = "skywalking_agent.trace_subprocess_command";
+
+/// The component id of the entry spans of the http requests, for the
+/// frameworks not recognized.
+const SKYWALKING_AGENT_DEFAULT_HTTP_SERVER_COMPONENT: &str =
+    "skywalking_agent.default_http_server_component";
+
+/// The max milliseconds of the worker waiting for the rest of a started frame
+/// from the php process, the stalled connection is closed.
+const SKYWALKING_AGENT_IPC_READ_TIMEOUT_MS: &str = "skywalking_agent.ipc_read_timeout_ms";
+
+/// Whether to sample by the hash of the trace id instead of the random draw,
+/// so the services sharing the sample rate decide the same for a trace.
+const SKYWALKING_AGENT_DETERMINISTIC_SAMPLING: &str = "skywalking_agent.deterministic_sampling";
+
+/// Whether to log the summaries of the segments in the worker at info level
+/// instead of reporting them, only for debugging.
+const SKYWALKING_AGENT_DIAGNOSTIC_MODE: &str = "skywalking_agent.diagnostic_mode";
+
+/// The max frames of the captured backtraces, 0 means the built-in limit of
+/// each usage.
+const SKYWALKING_AGENT_BACKTRACE_MAX_DEPTH: &str = "skywalking_agent.backtrace_max_depth";
+
+/// Whether to skip the vendor frames and the absolute paths of the captured
+/// backtraces.
+const SKYWALKING_AGENT_BACKTRACE_STRIP_PATHS: &str = "skywalking_agent.backtrace_strip_paths";
+
+/// Whether to name the entry span by the matched route of Laravel and Symfony
+/// instead of the path.
+const SKYWALKING_AGENT_ROUTE_OPERATION_NAME: &str = "skywalking_agent.route_operation_name";
+
+/// Whether to send the `sw8-service` response header to the traced callers, so
+/// they tag the exit span with the downstream service.
+const SKYWALKING_AGENT_RESPONSE_SERVICE_HEADER: &str = "skywalking_agent.response_service_header";
+
+/// Whether to trace the swoole server, `auto` enables it if the swoole
+/// extension is loaded, `Off` also removes the swoole hack functions.
+const SKYWALKING_AGENT_ENABLE_SWOOLE: &str = "skywalking_agent.enable_swoole";
+
+/// Whether to tag the affected rows of the write statements of PDO and mysqli
+/// as `db.rows_affected`.
+const SKYWALKING_AGENT_REPORT_DB_ROWS: &str = "skywalking_agent.report_db_rows";
+
+/// The proxy to connect to the OAP server through, like `http://proxy:3128`
+/// or `socks5://proxy:1080`, only for the grpc reporter.
+const SKYWALKING_AGENT_GRPC_PROXY: &str = "skywalking_agent.grpc_proxy";
+
+/// Whether to tag the entry span with the peak memory usage and the memory
+/// limit of the request, under php-fpm and cli.
+const SKYWALKING_AGENT_REPORT_MEMORY: &str = "skywalking_agent.report_memory";
+
+/// Whether to inject the W3C `traceparent` header along with `sw8` in the
+/// http exit spans, and continue the trace from `traceparent` if no `sw8`.
+const SKYWALKING_AGENT_INJECT_W3C_TRACEPARENT: &str = "skywalking_agent.inject_w3c_traceparent";
+
+/// Whether to report the messages of `error_log()` and the records of Monolog
+/// to the OAP server as logs, correlated with the trace.
+const SKYWALKING_AGENT_REPORT_LOG: &str = "skywalking_agent.report_log";
+
+/// The minimum level of the Monolog records reported, like `WARNING`.
+const SKYWALKING_AGENT_REPORT_LOG_LEVEL: &str = "skywalking_agent.report_log_level";
+
+/// The seconds between the reports of the aggregated meters by the worker.
+const SKYWALKING_AGENT_METER_REPORT_PERIOD: &str = "skywalking_agent.meter_report_period";
+
+/// The max count of requests sampled by rate per 3 seconds in every php
+/// process, like the agent config of the other agents. Non-positive means no
+/// limit.
+const SKYWALKING_AGENT_SAMPLE_N_PER_3_SECS: &str = "skywalking_agent.sample_n_per_3_secs";
+
+/// Whether to report the events by `skywalking_report_event()`, and the start
+/// and shutdown events of the worker.
+const SKYWALKING_AGENT_ENABLE_EVENT: &str = "skywalking_agent.enable_event";
+
 #[php_get_module]
 pub fn get_module() -> Module {
     let mut module = Module::new(
@@ -151,11 +445,36 @@ pub fn get_module() -> Module {
         "/tmp/skywalking-agent".to_string(),
         Policy::System,
     );
+    module.add_ini(
+        SKYWALKING_AGENT_SOCKET_FILE_PATH,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_SOCKET_FILE_MODE,
+        "0777".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_SOCKET_TYPE,
+        "unix".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_SOCKET_TCP_ADDR,
+        "127.0.0.1:19876".to_string(),
+        Policy::System,
+    );
     module.add_ini(
         SKYWALKING_AGENT_AUTHENTICATION,
         "".to_string(),
         Policy::System,
     );
+    module.add_ini(
+        SKYWALKING_AGENT_GRPC_EXTRA_HEADERS,
+        "".to_string(),
+        Policy::System,
+    );
     module.add_ini(SKYWALKING_AGENT_ENABLE_TLS, false, Policy::System);
     module.add_ini(
         SKYWALKING_AGENT_SSL_TRUSTED_CA_PATH,
@@ -172,10 +491,14 @@ pub fn get_module() -> Module {
         "".to_string(),
         Policy::System,
     );
-    module.add_ini(SKYWALKING_AGENT_HEARTBEAT_PERIOD, 30i64, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_HEARTBEAT_PERIOD,
+        module::DEFAULT_HEARTBEAT_PERIOD,
+        Policy::System,
+    );
     module.add_ini(
         SKYWALKING_AGENT_PROPERTIES_REPORT_PERIOD_FACTOR,
-        10i64,
+        module::DEFAULT_PROPERTIES_REPORT_PERIOD_FACTOR,
         Policy::System,
     );
     module.add_ini(SKYWALKING_AGENT_ENABLE_ZEND_OBSERVER, false, Policy::System);
@@ -194,7 +517,243 @@ pub fn get_module() -> Module {
         "{}".to_string(),
         Policy::System,
     );
+    module.add_ini(
+        SKYWALKING_AGENT_KAFKA_NAMESPACE,
+        "".to_string(),
+        Policy::System,
+    );
     module.add_ini(SKYWALKING_AGENT_INJECT_CONTEXT, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_CORRECT_CLOCK_SKEW, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_STATUS_FILE, "".to_string(), Policy::System);
+    module.add_ini(SKYWALKING_AGENT_REPORT_BATCH_SIZE, 1i64, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_REPORT_FLUSH_INTERVAL_MS,
+        1000i64,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_ES_REPORT_BODY, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_SQL_PARAMETERIZE, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_ENABLE_PROFILING, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_REPORT_FPM_INFO, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_CAPTURE_REQUEST_BODY_PATHS,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_TRACE_MAX_BODY_SIZE,
+        4096i64,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_ENABLE_CLI, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_CLI_OPERATION_NAME_MODE,
+        "script_basename".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_WORKER_MODE,
+        "fork".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_PROPAGATE_EXIT_ERRORS,
+        false,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_SAMPLE_RATE, 1.0f64, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_TRACE_IGNORE_PATH,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_ENDPOINT_SAMPLE_RULES,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_SPAN_LIMIT_PER_SEGMENT,
+        0i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_MAX_TAG_VALUE_LENGTH,
+        2048i64,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_ENABLE_METER, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_CIRCUIT_BREAKER_THRESHOLD,
+        5i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_CIRCUIT_BREAKER_COOL_DOWN_PERIOD,
+        30i64,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_TRACE_SESSIONS, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_IGNORED_EXCEPTIONS,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_CAPTURE_STACK_ON_SLOW_SPAN,
+        false,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_SLOW_SPAN_THRESHOLD_MS,
+        500i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_MAX_IPC_CONNECTIONS,
+        module::DEFAULT_MAX_IPC_CONNECTIONS as i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_REPORT_DB_CONNECT_TIME,
+        false,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_AUTO_CORRELATION_REQUEST_ID,
+        false,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_CORRELATION_REQUEST_ID_KEY,
+        "request_id".to_string(),
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_REPORT_OS_INFO, true, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_REPORT_QUERY_STRING, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_QUERY_STRING_REDACT_KEYS,
+        "password,token,secret".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_CUSTOM_EXIT_SPAN_RULES,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_COMPONENT_OVERRIDES,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_RECONNECT_MAX_BACKOFF_MS,
+        module::DEFAULT_RECONNECT_MAX_BACKOFF_MS as i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_CLIENT_IP_HEADERS,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_TRACE_QUEUE_WORKERS, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_LOG_FORMAT,
+        "text".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_MAX_SEGMENT_BYTES,
+        52428800i64,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_NAMESPACE, "".to_string(), Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_HTTP_RECEIVER_URL,
+        "http://127.0.0.1:12800".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_EXIT_SPAN_SAMPLE_RATE,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_URL_REDACT_PARAMS,
+        "password,token,secret".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_TAIL_SAMPLE_LATENCY_MS,
+        0i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_PEER_SERVICE_MAPPING,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_INSTANCE_PROPERTIES_FILE,
+        "".to_string(),
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_TRACE_SUBPROCESS, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_TRACE_SUBPROCESS_COMMAND,
+        false,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_DEFAULT_HTTP_SERVER_COMPONENT,
+        component::COMPONENT_PHP_ID as i64,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_IPC_READ_TIMEOUT_MS,
+        module::DEFAULT_IPC_READ_TIMEOUT_MS,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_DETERMINISTIC_SAMPLING,
+        false,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_DIAGNOSTIC_MODE, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_BACKTRACE_MAX_DEPTH, 0i64, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_BACKTRACE_STRIP_PATHS,
+        false,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_ROUTE_OPERATION_NAME, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_RESPONSE_SERVICE_HEADER,
+        false,
+        Policy::System,
+    );
+    module.add_ini(
+        SKYWALKING_AGENT_ENABLE_SWOOLE,
+        module::ENABLE_SWOOLE_AUTO.to_string(),
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_REPORT_DB_ROWS, false, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_GRPC_PROXY, "".to_string(), Policy::System);
+    module.add_ini(SKYWALKING_AGENT_REPORT_MEMORY, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_INJECT_W3C_TRACEPARENT,
+        false,
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_REPORT_LOG, false, Policy::System);
+    module.add_ini(
+        SKYWALKING_AGENT_REPORT_LOG_LEVEL,
+        "INFO".to_string(),
+        Policy::System,
+    );
+    module.add_ini(SKYWALKING_AGENT_METER_REPORT_PERIOD, 20i64, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_SAMPLE_N_PER_3_SECS, 0i64, Policy::System);
+    module.add_ini(SKYWALKING_AGENT_ENABLE_EVENT, false, Policy::System);
 
     // Hooks.
     module.on_module_init(module::init);
@@ -203,11 +762,163 @@ pub fn get_module() -> Module {
     module.on_request_shutdown(request::shutdown);
 
     // The function is used by swoole plugin, to surround the callback of on
-    // request.
+    // request. The swoole hack functions are removed in module init if swoole
+    // isn't enabled.
     module.add_function(
         HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME,
         request::skywalking_hack_swoole_on_request,
     );
 
+    // The functions are used by swoole plugin, to surround the callbacks of
+    // websocket server.
+    module.add_function(
+        HACK_SWOOLE_ON_OPEN_FUNCTION_NAME,
+        request::skywalking_hack_swoole_on_open,
+    );
+    module.add_function(
+        HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME,
+        request::skywalking_hack_swoole_on_message,
+    );
+
+    // The function is used by amqp plugin, to surround the callback of
+    // `AMQPQueue::consume`.
+    module.add_function(
+        plugin::HACK_AMQP_CONSUME_CALLBACK_FUNCTION_NAME,
+        plugin::skywalking_hack_amqp_consume_callback,
+    );
+
+    // The functions are used by yar plugin, to surround the callbacks of
+    // `Yar_Concurrent_Client`.
+    module.add_function(
+        plugin::HACK_YAR_CALLBACK_FUNCTION_NAME,
+        plugin::skywalking_hack_yar_callback,
+    );
+    module.add_function(
+        plugin::HACK_YAR_ERROR_CALLBACK_FUNCTION_NAME,
+        plugin::skywalking_hack_yar_error_callback,
+    );
+
+    module
+        .add_function(
+            "skywalking_set_operation_name",
+            functions::skywalking_set_operation_name,
+        )
+        .argument(Argument::by_val("operation_name"));
+
+    module
+        .add_function(
+            "skywalking_set_service_name",
+            functions::skywalking_set_service_name,
+        )
+        .argument(Argument::by_val("service_name"));
+
+    module.add_function(
+        "skywalking_skip_analysis",
+        functions::skywalking_skip_analysis,
+    );
+
+    module.add_function(
+        "skywalking_ignore_trace",
+        functions::skywalking_ignore_trace,
+    );
+
+    module.add_function("skywalking_capture", functions::skywalking_capture);
+
+    module
+        .add_function("skywalking_continue", functions::skywalking_continue)
+        .argument(Argument::by_val("snapshot"))
+        .argument(Argument::by_val_optional("operation_name"));
+
+    module.add_function(
+        "skywalking_finish_continued",
+        functions::skywalking_finish_continued,
+    );
+
+    module
+        .add_function(
+            "skywalking_get_correlation",
+            functions::skywalking_get_correlation,
+        )
+        .argument(Argument::by_val("key"));
+
+    module
+        .add_function(
+            "skywalking_correlation_put",
+            functions::skywalking_correlation_put,
+        )
+        .argument(Argument::by_val("key"))
+        .argument(Argument::by_val("value"));
+
+    module
+        .add_function(
+            "skywalking_set_common_tag",
+            functions::skywalking_set_common_tag,
+        )
+        .argument(Argument::by_val("key"))
+        .argument(Argument::by_val("value"))
+        .argument(Argument::by_val_optional("include_entry_span"));
+
+    module
+        .add_function("skywalking_add_log", functions::skywalking_add_log)
+        .argument(Argument::by_val("key_values"));
+
+    module
+        .add_function("skywalking_start_span", functions::skywalking_start_span)
+        .argument(Argument::by_val("operation_name"))
+        .argument(Argument::by_val_optional("peer"));
+
+    module
+        .add_function("skywalking_end_span", functions::skywalking_end_span)
+        .argument(Argument::by_val_optional("is_error"));
+
+    module
+        .add_function("skywalking_tag", functions::skywalking_tag)
+        .argument(Argument::by_val("key"))
+        .argument(Argument::by_val("value"));
+
+    module.add_function("skywalking_trace_id", functions::skywalking_trace_id);
+
+    module
+        .add_function("skywalking_counter", functions::skywalking_counter)
+        .argument(Argument::by_val("name"))
+        .argument(Argument::by_val_optional("value"))
+        .argument(Argument::by_val_optional("labels"));
+
+    module
+        .add_function("skywalking_gauge", functions::skywalking_gauge)
+        .argument(Argument::by_val("name"))
+        .argument(Argument::by_val("value"))
+        .argument(Argument::by_val_optional("labels"));
+
+    module
+        .add_function("skywalking_histogram", functions::skywalking_histogram)
+        .argument(Argument::by_val("name"))
+        .argument(Argument::by_val("value"))
+        .argument(Argument::by_val_optional("labels"))
+        .argument(Argument::by_val_optional("buckets"));
+
+    module
+        .add_function(
+            "skywalking_report_event",
+            functions::skywalking_report_event,
+        )
+        .argument(Argument::by_val("name"))
+        .argument(Argument::by_val_optional("message"))
+        .argument(Argument::by_val_optional("parameters"))
+        .argument(Argument::by_val_optional("is_error"))
+        .argument(Argument::by_val_optional("start_time"))
+        .argument(Argument::by_val_optional("end_time"));
+
+    module
+        .add_function("skywalking_flush", functions::skywalking_flush)
+        .argument(Argument::by_val_optional("timeout_ms"));
+
+    module.add_function(
+        "skywalking_agent_version",
+        functions::skywalking_agent_version,
+    );
+
+    module.add_function("skywalking_agent_info", functions::skywalking_agent_info);
+
     module
 }
@@ -0,0 +1,143 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Logs reporting, enabled by `skywalking_agent.report_log`.
+//!
+//! The messages of `error_log()` and the records of Monolog are collected by
+//! the plugins, and sent to worker as the `LogData`, correlated with the trace
+//! id, segment id and active span id of the request if it's traced. The
+//! worker reports them like the segments, only the grpc and kafka reporters
+//! support the logs.
+
+use crate::{
+    channel::Reporter,
+    context::RequestContext,
+    module::{
+        is_thread_worker_mode, REPORT_LOG_LEVEL, SERVICE_INSTANCE, SERVICE_NAME, WORKER_ADDR,
+    },
+    profiling::now_millis,
+    util::truncate_str,
+    worker::ThreadReporter,
+};
+use once_cell::sync::Lazy;
+use skywalking::{
+    proto::v3::{
+        log_data_body::Content, KeyStringValuePair, LogData, LogDataBody, LogTags, TextLog,
+        TraceContext,
+    },
+    reporter::{CollectItem, Report},
+    trace::span::HandleSpanObject,
+};
+use tracing::warn;
+
+/// The longer message is truncated, the logs are queued with the segments in
+/// worker, so they shouldn't be too large.
+const MAX_LOG_MESSAGE_LENGTH: usize = 8 * 1024;
+
+const TAG_LEVEL: &str = "level";
+const TAG_LOGGER: &str = "logger";
+
+/// The logs are sent by standalone connection, like the meters.
+static LOG_REPORTER: Lazy<Reporter> = Lazy::new(|| Reporter::new(WORKER_ADDR.clone()));
+
+/// The levels of Monolog, same as RFC 5424 but the value is ascending by
+/// severity.
+const LEVELS: &[(&str, i64)] = &[
+    ("DEBUG", 100),
+    ("INFO", 200),
+    ("NOTICE", 250),
+    ("WARNING", 300),
+    ("ERROR", 400),
+    ("CRITICAL", 500),
+    ("ALERT", 550),
+    ("EMERGENCY", 600),
+];
+
+/// Fallback to `INFO` if `skywalking_agent.report_log_level` is unknown.
+static MIN_LEVEL: Lazy<i64> = Lazy::new(|| {
+    LEVELS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(REPORT_LOG_LEVEL.trim()))
+        .map(|(_, value)| *value)
+        .unwrap_or_else(|| {
+            warn!(level = %*REPORT_LOG_LEVEL, "Unknown report log level, fallback to INFO");
+            200
+        })
+});
+
+/// The name of the Monolog level value, `None` if it's below
+/// `skywalking_agent.report_log_level`, which shouldn't be reported.
+pub fn reported_level_name(level: i64) -> Option<&'static str> {
+    if level < *MIN_LEVEL {
+        return None;
+    }
+    LEVELS
+        .iter()
+        .rev()
+        .find(|(_, value)| level >= *value)
+        .map(|(name, _)| *name)
+}
+
+/// Report the log message, `level` is `None` for `error_log()`, which has no
+/// level.
+pub fn report_log(request_id: Option<i64>, logger: &str, level: Option<&str>, message: &str) {
+    // The request isn't traced, like ignored or sampled out, the log is
+    // reported without the trace context.
+    let (endpoint, trace_context) = RequestContext::try_with_global(request_id, |ctx| {
+        let tracing_context = &ctx.tracing_context;
+        Ok((
+            ctx.entry_span.span_object().operation_name.clone(),
+            Some(TraceContext {
+                trace_id: tracing_context.trace_id().to_owned(),
+                trace_segment_id: tracing_context.trace_segment_id().to_owned(),
+                span_id: tracing_context.peek_active_span_id().unwrap_or_default(),
+            }),
+        ))
+    })
+    .unwrap_or_default();
+
+    let mut tags = vec![KeyStringValuePair {
+        key: TAG_LOGGER.to_owned(),
+        value: logger.to_owned(),
+    }];
+    if let Some(level) = level {
+        tags.push(KeyStringValuePair {
+            key: TAG_LEVEL.to_owned(),
+            value: level.to_owned(),
+        });
+    }
+
+    let item = CollectItem::Log(Box::new(LogData {
+        timestamp: now_millis(),
+        service: SERVICE_NAME.clone(),
+        service_instance: SERVICE_INSTANCE.clone(),
+        endpoint,
+        body: Some(LogDataBody {
+            r#type: "text".to_owned(),
+            content: Some(Content::Text(TextLog {
+                text: truncate_str(message, MAX_LOG_MESSAGE_LENGTH).to_owned(),
+            })),
+        }),
+        trace_context,
+        tags: Some(LogTags { data: tags }),
+        ..Default::default()
+    }));
+
+    if is_thread_worker_mode() {
+        ThreadReporter.report(item);
+    } else {
+        LOG_REPORTER.report(item);
+    }
+}
@@ -0,0 +1,356 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Endpoint meters, enabled by `skywalking_agent.enable_meter`.
+//!
+//! The php processes record the endpoint and duration of every request, even
+//! it isn't traced, and send the records to the worker. The worker aggregates
+//! them and reports the meters periodically, so there is no meter call per
+//! request.
+//!
+//! The custom meters recorded by `skywalking_counter()`, `skywalking_gauge()`
+//! and `skywalking_histogram()` are aggregated and reported the same way.
+
+use crate::{
+    channel::Reporter,
+    module::{
+        is_thread_worker_mode, ENABLE_METER, METER_REPORT_PERIOD, SERVICE_INSTANCE, SERVICE_NAME,
+        WORKER_ADDR,
+    },
+    profiling::now_millis,
+    worker::ThreadReporter,
+};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use skywalking::{
+    proto::v3::{
+        meter_data::Metric, Label, MeterBucketValue, MeterData, MeterHistogram, MeterSingleValue,
+    },
+    reporter::{CollectItem, Report},
+};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+use tokio::time::interval;
+use tracing::{debug, error, warn};
+
+/// The max count of endpoints aggregated, the records of new endpoints beyond
+/// it are dropped, to avoid the unbounded memory.
+const MAX_ENDPOINTS: usize = 1000;
+
+const METER_REQUEST_COUNT: &str = "php_endpoint_request_count";
+const METER_REQUEST_ERROR_COUNT: &str = "php_endpoint_request_error_count";
+const METER_RESPONSE_TIME: &str = "php_endpoint_response_time";
+
+const LABEL_ENDPOINT: &str = "endpoint";
+
+/// The buckets of response time in milliseconds, also the default buckets of
+/// the custom histograms.
+const RESPONSE_TIME_BUCKETS: &[i64] = &[0, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// The max count of custom meters aggregated, same as the endpoints, a meter
+/// is identified by the name and the labels.
+const MAX_CUSTOM_METERS: usize = 1000;
+
+/// The meters are sent by standalone connection, to avoid blocking the
+/// reporting of segments.
+static METER_REPORTER: Lazy<Reporter> = Lazy::new(|| Reporter::new(WORKER_ADDR.clone()));
+
+/// Key is request id, value is the endpoint and the start time in millis.
+static REQUEST_STARTS: Lazy<DashMap<Option<i64>, (String, i64)>> = Lazy::new(DashMap::new);
+
+/// Aggregated in worker, key is endpoint.
+static ENDPOINT_METERS: Lazy<Mutex<HashMap<String, EndpointMeter>>> = Lazy::new(Default::default);
+
+/// Aggregated in worker, key is the name and the labels sorted by name.
+static CUSTOM_METERS: Lazy<Mutex<HashMap<CustomMeterKey, CustomMeter>>> =
+    Lazy::new(Default::default);
+
+type CustomMeterKey = (String, Vec<(String, String)>);
+
+/// The record of one request, sent from php process to worker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EndpointRecord {
+    endpoint: String,
+    duration: i64,
+    is_error: bool,
+}
+
+/// The counts are accumulated since the worker started.
+#[derive(Default)]
+struct EndpointMeter {
+    count: i64,
+    error_count: i64,
+    buckets: [i64; RESPONSE_TIME_BUCKETS.len()],
+}
+
+/// The record of a custom meter, sent from php process to worker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomMeterRecord {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: CustomMeterValue,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CustomMeterValue {
+    /// Added to the counter.
+    Counter(f64),
+    /// Replaces the gauge.
+    Gauge(f64),
+    /// Observed by the histogram, the buckets are fixed by the first record.
+    Histogram { value: f64, buckets: Vec<f64> },
+}
+
+/// The counter and the histogram are accumulated since the worker started,
+/// like the endpoint meters.
+enum CustomMeter {
+    Counter(f64),
+    Gauge(f64),
+    Histogram { buckets: Vec<f64>, counts: Vec<i64> },
+}
+
+/// Called when the request starts, whether it is traced or not.
+pub fn start_request(request_id: Option<i64>, endpoint: String) {
+    if !*ENABLE_METER {
+        return;
+    }
+    REQUEST_STARTS.insert(request_id, (endpoint, now_millis()));
+}
+
+/// Called when the entry span is renamed, so the meters are aggregated by the
+/// same endpoint as the traces.
+pub fn rename_request(request_id: Option<i64>, endpoint: &str) {
+    if let Some(mut start) = REQUEST_STARTS.get_mut(&request_id) {
+        start.0 = endpoint.to_owned();
+    }
+}
+
+/// Called when the request finishes, send the record to worker.
+pub fn finish_request(request_id: Option<i64>, is_error: bool) {
+    let Some((_, (endpoint, start_time))) = REQUEST_STARTS.remove(&request_id) else {
+        return;
+    };
+
+    let record = EndpointRecord {
+        endpoint,
+        duration: (now_millis() - start_time).max(0),
+        is_error,
+    };
+
+    if is_thread_worker_mode() {
+        ThreadReporter::report_endpoint_record(record);
+    } else {
+        METER_REPORTER.report_endpoint_record(record);
+    }
+}
+
+/// Record the custom meter, send the record to worker. The labels are sorted,
+/// so the same labels in different order are the same meter.
+pub fn record_custom(name: String, mut labels: Vec<(String, String)>, value: CustomMeterValue) {
+    labels.sort();
+    let record = CustomMeterRecord {
+        name,
+        labels,
+        value,
+    };
+
+    if is_thread_worker_mode() {
+        ThreadReporter::report_custom_meter_record(record);
+    } else {
+        METER_REPORTER.report_custom_meter_record(record);
+    }
+}
+
+/// Aggregate the record, only called in worker.
+pub fn aggregate(record: EndpointRecord) {
+    let mut meters = match ENDPOINT_METERS.lock() {
+        Ok(meters) => meters,
+        Err(err) => {
+            error!(?err, "Get endpoint meters lock failed");
+            return;
+        }
+    };
+
+    if !meters.contains_key(&record.endpoint) && meters.len() >= MAX_ENDPOINTS {
+        debug!(endpoint = %record.endpoint, "Too many endpoints, meter record dropped");
+        return;
+    }
+
+    let meter = meters.entry(record.endpoint).or_default();
+    meter.count += 1;
+    if record.is_error {
+        meter.error_count += 1;
+    }
+    let index = RESPONSE_TIME_BUCKETS
+        .iter()
+        .rposition(|bucket| record.duration >= *bucket)
+        .unwrap_or_default();
+    meter.buckets[index] += 1;
+}
+
+/// Aggregate the custom meter record, only called in worker. The record of the
+/// existing meter of the other type is dropped.
+pub fn aggregate_custom(record: CustomMeterRecord) {
+    let mut meters = match CUSTOM_METERS.lock() {
+        Ok(meters) => meters,
+        Err(err) => {
+            error!(?err, "Get custom meters lock failed");
+            return;
+        }
+    };
+
+    let key = (record.name, record.labels);
+    if !meters.contains_key(&key) && meters.len() >= MAX_CUSTOM_METERS {
+        debug!(name = %key.0, "Too many custom meters, meter record dropped");
+        return;
+    }
+
+    let meter = meters.entry(key).or_insert_with(|| match &record.value {
+        CustomMeterValue::Counter(_) => CustomMeter::Counter(0.),
+        CustomMeterValue::Gauge(_) => CustomMeter::Gauge(0.),
+        CustomMeterValue::Histogram { buckets, .. } => CustomMeter::Histogram {
+            buckets: buckets.clone(),
+            counts: vec![0; buckets.len()],
+        },
+    });
+    match (meter, record.value) {
+        (CustomMeter::Counter(count), CustomMeterValue::Counter(value)) => *count += value,
+        (CustomMeter::Gauge(gauge), CustomMeterValue::Gauge(value)) => *gauge = value,
+        (CustomMeter::Histogram { buckets, counts }, CustomMeterValue::Histogram { value, .. }) => {
+            let index = buckets
+                .iter()
+                .rposition(|bucket| value >= *bucket)
+                .unwrap_or_default();
+            counts[index] += 1;
+        }
+        _ => debug!("Type of custom meter mismatched, meter record dropped"),
+    }
+}
+
+/// Report the aggregated meters every `skywalking_agent.meter_report_period`
+/// seconds, only run in worker.
+pub async fn run(reporter: impl Report) {
+    let mut ticker = interval(Duration::from_secs(*METER_REPORT_PERIOD as u64));
+
+    loop {
+        ticker.tick().await;
+
+        let mut items = match ENDPOINT_METERS.lock() {
+            Ok(meters) => meters
+                .iter()
+                .flat_map(|(endpoint, meter)| meter_items(endpoint, meter))
+                .collect::<Vec<_>>(),
+            Err(err) => {
+                warn!(?err, "Get endpoint meters lock failed");
+                continue;
+            }
+        };
+        match CUSTOM_METERS.lock() {
+            Ok(meters) => items.extend(meters.iter().map(custom_meter_item)),
+            Err(err) => warn!(?err, "Get custom meters lock failed"),
+        }
+
+        debug!(count = items.len(), "Report endpoint meters");
+        for item in items {
+            reporter.report(item);
+        }
+    }
+}
+
+fn meter_items(endpoint: &str, meter: &EndpointMeter) -> [CollectItem; 3] {
+    let labels = vec![Label {
+        name: LABEL_ENDPOINT.to_owned(),
+        value: endpoint.to_owned(),
+    }];
+
+    let single_value = |name: &str, value: i64| {
+        new_meter_item(Metric::SingleValue(MeterSingleValue {
+            name: name.to_owned(),
+            labels: labels.clone(),
+            value: value as f64,
+        }))
+    };
+
+    let histogram = new_meter_item(Metric::Histogram(MeterHistogram {
+        name: METER_RESPONSE_TIME.to_owned(),
+        labels: labels.clone(),
+        values: RESPONSE_TIME_BUCKETS
+            .iter()
+            .zip(meter.buckets)
+            .map(|(bucket, count)| MeterBucketValue {
+                bucket: *bucket as f64,
+                count,
+                is_negative_infinity: false,
+            })
+            .collect(),
+    }));
+
+    [
+        single_value(METER_REQUEST_COUNT, meter.count),
+        single_value(METER_REQUEST_ERROR_COUNT, meter.error_count),
+        histogram,
+    ]
+}
+
+fn custom_meter_item(((name, labels), meter): (&CustomMeterKey, &CustomMeter)) -> CollectItem {
+    let name = name.clone();
+    let labels = labels
+        .iter()
+        .map(|(name, value)| Label {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect();
+
+    new_meter_item(match meter {
+        CustomMeter::Counter(value) | CustomMeter::Gauge(value) => {
+            Metric::SingleValue(MeterSingleValue {
+                name,
+                labels,
+                value: *value,
+            })
+        }
+        CustomMeter::Histogram { buckets, counts } => Metric::Histogram(MeterHistogram {
+            name,
+            labels,
+            values: buckets
+                .iter()
+                .zip(counts)
+                .map(|(bucket, count)| MeterBucketValue {
+                    bucket: *bucket,
+                    count: *count,
+                    is_negative_infinity: false,
+                })
+                .collect(),
+        }),
+    })
+}
+
+/// The default buckets of the custom histograms.
+pub fn default_histogram_buckets() -> Vec<f64> {
+    RESPONSE_TIME_BUCKETS
+        .iter()
+        .map(|bucket| *bucket as f64)
+        .collect()
+}
+
+fn new_meter_item(metric: Metric) -> CollectItem {
+    CollectItem::Meter(Box::new(MeterData {
+        metric: Some(metric),
+        service: SERVICE_NAME.clone(),
+        service_instance: SERVICE_INSTANCE.clone(),
+        timestamp: now_millis(),
+    }))
+}
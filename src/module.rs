@@ -14,14 +14,21 @@
 // limitations under the License.
 
 use crate::{
-    channel::Reporter,
+    breaker,
+    channel::{self, Reporter, WorkerAddr},
+    component, dynamic_config,
     execute::{register_execute_functions, register_observer_handlers},
-    util::{get_sapi_module_name, get_str_ini_with_default, IPS},
-    worker::init_worker,
+    peer, profiling,
+    request::disable_swoole_hack_functions,
+    util::{
+        check_writable, fnv1a_32, get_sapi_module_name, get_str_ini_with_default, log_php_error,
+        parse_file_mode, split_host_port, IPS,
+    },
+    worker::{init_worker, shutdown_thread_worker, ThreadReporter},
     *,
 };
 use anyhow::bail;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use phper::{arrays::ZArr, ini::ini_get, sys};
 use skywalking::{
     common::random_generator::RandomGenerator,
@@ -29,13 +36,16 @@ use skywalking::{
 };
 use std::{
     ffi::{CStr, OsStr},
-    fs::{self, OpenOptions},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
     os::unix::prelude::OsStrExt,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Mutex,
+    time::Duration,
     // time::SystemTime,
 };
-use tracing::{debug, error, info, metadata::LevelFilter};
+use tracing::{debug, error, info, metadata::LevelFilter, warn};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 static IS_ENABLE: Lazy<bool> = Lazy::new(|| {
@@ -49,18 +59,51 @@ static IS_ENABLE: Lazy<bool> = Lazy::new(|| {
         return true;
     }
 
-    if sapi == b"cli" && get_module_registry().exists("swoole") {
-        return true;
+    if sapi == b"cli" {
+        return is_swoole_enabled() || ini_get::<bool>(SKYWALKING_AGENT_ENABLE_CLI);
     }
 
     false
 });
 
+pub const CLI_OPERATION_NAME_MODE_SCRIPT_BASENAME: &str = "script_basename";
+pub const CLI_OPERATION_NAME_MODE_SCRIPT_PATH: &str = "script_path";
+pub const CLI_OPERATION_NAME_MODE_ARGV: &str = "argv";
+
+pub const ENABLE_SWOOLE_AUTO: &str = "auto";
+
+/// The bare `On` and `Off` in php.ini are parsed as `1` and the empty string.
+static ENABLE_SWOOLE: Lazy<String> = Lazy::new(|| {
+    get_str_ini_with_default(SKYWALKING_AGENT_ENABLE_SWOOLE)
+        .trim()
+        .to_ascii_lowercase()
+});
+
+/// Swoole is enabled if the extension is loaded, unless
+/// `skywalking_agent.enable_swoole` is Off.
+static IS_SWOOLE_ENABLED: Lazy<bool> = Lazy::new(|| {
+    !matches!(ENABLE_SWOOLE.as_str(), "" | "0" | "off" | "false" | "no") && is_swoole_loaded()
+});
+
+pub const WORKER_MODE_FORK: &str = "fork";
+pub const WORKER_MODE_THREAD: &str = "thread";
+
 pub static SERVER_ADDR: Lazy<String> =
     Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_SERVER_ADDR));
 
-pub static SERVICE_NAME: Lazy<String> =
-    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_SERVICE_NAME));
+/// The service name with the namespace, `<service_name>|<namespace>`, used in
+/// the registration, the segments and the `sw8` header, so the traces of the
+/// different namespaces are isolated.
+pub static SERVICE_NAME: Lazy<String> = Lazy::new(|| {
+    let service_name = get_str_ini_with_default(SKYWALKING_AGENT_SERVICE_NAME);
+    let namespace = get_str_ini_with_default(SKYWALKING_AGENT_NAMESPACE);
+    let namespace = namespace.trim();
+    if namespace.is_empty() {
+        service_name
+    } else {
+        format!("{}|{}", service_name, namespace)
+    }
+});
 
 pub static SERVICE_INSTANCE: Lazy<String> = Lazy::new(|| {
     let rnd_hostname = RandomGenerator::generate() + "@" + &IPS[0];
@@ -77,6 +120,13 @@ pub static SERVICE_INSTANCE: Lazy<String> = Lazy::new(|| {
     service_instance.to_string()
 });
 
+/// The OAP server before 8 doesn't speak the v3 protocol.
+pub const MIN_SKYWALKING_VERSION: i64 = 8;
+
+/// The major versions of OAP server known to speak the v3 protocol, the later
+/// ones likely keep it, so they're only warned.
+pub const KNOWN_SKYWALKING_VERSIONS: [i64; 3] = [8, 9, 10];
+
 pub static SKYWALKING_VERSION: Lazy<i64> =
     Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_SKYWALKING_VERSION));
 
@@ -91,8 +141,24 @@ pub static RUNTIME_DIR: Lazy<PathBuf> = Lazy::new(|| {
     path
 });
 
-pub static SOCKET_FILE_PATH: Lazy<PathBuf> = Lazy::new(|| {
+/// The directory of current service under `runtime_dir`, named by the service
+/// name and its hash, so the php-fpm pools of different services on one host
+/// get their own worker socket and pid file.
+pub static SERVICE_RUNTIME_DIR: Lazy<PathBuf> = Lazy::new(|| {
     let mut dir = RUNTIME_DIR.clone();
+    dir.push(service_dir_name(&SERVICE_NAME));
+    dir
+});
+
+pub static SOCKET_FILE_PATH: Lazy<PathBuf> = Lazy::new(|| {
+    if let Some(path) = ini_get::<Option<&CStr>>(SKYWALKING_AGENT_SOCKET_FILE_PATH) {
+        let path = path.to_bytes();
+        if !path.is_empty() {
+            return PathBuf::from(OsStr::from_bytes(path));
+        }
+    }
+
+    let mut dir = SERVICE_RUNTIME_DIR.clone();
 
     // let dur = SystemTime::now()
     //     .duration_since(SystemTime::UNIX_EPOCH)
@@ -104,8 +170,38 @@ pub static SOCKET_FILE_PATH: Lazy<PathBuf> = Lazy::new(|| {
     dir
 });
 
+pub static SOCKET_TYPE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_SOCKET_TYPE));
+
+pub static SOCKET_TCP_ADDR: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_SOCKET_TCP_ADDR));
+
+/// The address of worker, which php processes connect to.
+pub static WORKER_ADDR: Lazy<WorkerAddr> = Lazy::new(|| match SOCKET_TYPE.as_str() {
+    "tcp" => WorkerAddr::Tcp(SOCKET_TCP_ADDR.clone()),
+    _ => WorkerAddr::Unix(SOCKET_FILE_PATH.clone()),
+});
+
+pub static PROPAGATE_EXIT_ERRORS: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_PROPAGATE_EXIT_ERRORS));
+
+pub static WORKER_MODE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_WORKER_MODE));
+
+/// Whether the worker runs in the background thread of php process, rather
+/// than the forked process.
+#[inline]
+pub fn is_thread_worker_mode() -> bool {
+    WORKER_MODE.as_str() == WORKER_MODE_THREAD
+}
+
+/// The parsed permission mode of socket file, `None` if the ini value is
+/// invalid.
+pub static SOCKET_FILE_MODE: Lazy<Option<libc::mode_t>> =
+    Lazy::new(|| parse_file_mode(&get_str_ini_with_default(SKYWALKING_AGENT_SOCKET_FILE_MODE)));
+
 pub static AGENT_PID_FILE_PATH: Lazy<PathBuf> = Lazy::new(|| {
-    let mut dir = RUNTIME_DIR.clone();
+    let mut dir = SERVICE_RUNTIME_DIR.clone();
 
     dir.push("agent.pid");
     dir
@@ -114,6 +210,9 @@ pub static AGENT_PID_FILE_PATH: Lazy<PathBuf> = Lazy::new(|| {
 pub static AUTHENTICATION: Lazy<String> =
     Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_AUTHENTICATION));
 
+pub static GRPC_EXTRA_HEADERS: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_GRPC_EXTRA_HEADERS));
+
 pub static ENABLE_TLS: Lazy<bool> = Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_ENABLE_TLS));
 
 pub static SSL_TRUSTED_CA_PATH: Lazy<String> =
@@ -125,11 +224,25 @@ pub static SSL_KEY_PATH: Lazy<String> =
 pub static SSL_CERT_CHAIN_PATH: Lazy<String> =
     Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_SSL_CERT_CHAIN_PATH));
 
-pub static HEARTBEAT_PERIOD: Lazy<i64> =
-    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_HEARTBEAT_PERIOD));
+pub const DEFAULT_HEARTBEAT_PERIOD: i64 = 30;
+
+pub const DEFAULT_PROPERTIES_REPORT_PERIOD_FACTOR: i64 = 10;
+
+/// Fallback to the default value if the ini isn't positive.
+pub static HEARTBEAT_PERIOD: Lazy<i64> = Lazy::new(|| {
+    Some(ini_get::<i64>(SKYWALKING_AGENT_HEARTBEAT_PERIOD))
+        .filter(|period| *period > 0)
+        .unwrap_or(DEFAULT_HEARTBEAT_PERIOD)
+});
 
-pub static PROPERTIES_REPORT_PERIOD_FACTOR: Lazy<i64> =
-    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_PROPERTIES_REPORT_PERIOD_FACTOR));
+/// Fallback to the default value if the ini isn't positive.
+pub static PROPERTIES_REPORT_PERIOD_FACTOR: Lazy<i64> = Lazy::new(|| {
+    Some(ini_get::<i64>(
+        SKYWALKING_AGENT_PROPERTIES_REPORT_PERIOD_FACTOR,
+    ))
+    .filter(|factor| *factor > 0)
+    .unwrap_or(DEFAULT_PROPERTIES_REPORT_PERIOD_FACTOR)
+});
 
 /// Zend observer is only support in PHP8+.
 pub static ENABLE_ZEND_OBSERVER: Lazy<bool> = Lazy::new(|| {
@@ -148,9 +261,299 @@ pub static KAFKA_BOOTSTRAP_SERVERS: Lazy<String> =
 pub static KAFKA_PRODUCER_CONFIG: Lazy<String> =
     Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_KAFKA_PRODUCER_CONFIG));
 
+pub static KAFKA_NAMESPACE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_KAFKA_NAMESPACE));
+
 pub static INJECT_CONTEXT: Lazy<bool> =
     Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_INJECT_CONTEXT));
 
+pub static CORRECT_CLOCK_SKEW: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_CORRECT_CLOCK_SKEW));
+
+pub static STATUS_FILE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_STATUS_FILE));
+
+pub static REPORT_BATCH_SIZE: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_REPORT_BATCH_SIZE));
+
+pub static REPORT_FLUSH_INTERVAL_MS: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_REPORT_FLUSH_INTERVAL_MS).max(0));
+
+pub static ES_REPORT_BODY: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_ES_REPORT_BODY));
+
+pub static SQL_PARAMETERIZE: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_SQL_PARAMETERIZE));
+
+pub static ENABLE_PROFILING: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_ENABLE_PROFILING));
+
+pub static CLI_OPERATION_NAME_MODE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_CLI_OPERATION_NAME_MODE));
+
+pub static REPORT_FPM_INFO: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_FPM_INFO));
+
+pub static CAPTURE_REQUEST_BODY_PATHS: Lazy<Vec<String>> = Lazy::new(|| {
+    get_str_ini_with_default(SKYWALKING_AGENT_CAPTURE_REQUEST_BODY_PATHS)
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+});
+
+pub static TRACE_MAX_BODY_SIZE: Lazy<usize> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_TRACE_MAX_BODY_SIZE).max(0) as usize);
+
+/// Clamped into `[0, 1]`, may be overridden by the dynamic config.
+pub static SAMPLE_RATE: Lazy<f64> = Lazy::new(|| {
+    let rate = ini_get::<f64>(SKYWALKING_AGENT_SAMPLE_RATE);
+    if rate.is_nan() {
+        1.
+    } else {
+        rate.clamp(0., 1.)
+    }
+});
+
+pub static TRACE_IGNORE_PATHS: Lazy<Vec<String>> = Lazy::new(|| {
+    dynamic_config::parse_trace_ignore_paths(&get_str_ini_with_default(
+        SKYWALKING_AGENT_TRACE_IGNORE_PATH,
+    ))
+});
+
+pub static ENDPOINT_SAMPLE_RULES: Lazy<Vec<(String, f64)>> = Lazy::new(|| {
+    dynamic_config::parse_endpoint_sample_rules(&get_str_ini_with_default(
+        SKYWALKING_AGENT_ENDPOINT_SAMPLE_RULES,
+    ))
+});
+
+pub static SPAN_LIMIT_PER_SEGMENT: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_SPAN_LIMIT_PER_SEGMENT));
+
+/// Non-positive means no limit.
+pub static MAX_TAG_VALUE_LENGTH: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_MAX_TAG_VALUE_LENGTH));
+
+pub static ENABLE_METER: Lazy<bool> = Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_ENABLE_METER));
+
+pub static TRACE_SESSIONS: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_TRACE_SESSIONS));
+
+/// The leading backslashes of the class names are trimmed.
+pub static IGNORED_EXCEPTIONS: Lazy<Vec<String>> = Lazy::new(|| {
+    get_str_ini_with_default(SKYWALKING_AGENT_IGNORED_EXCEPTIONS)
+        .split(',')
+        .map(|class_name| class_name.trim().trim_start_matches('\\'))
+        .filter(|class_name| !class_name.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+});
+
+pub static CAPTURE_STACK_ON_SLOW_SPAN: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_CAPTURE_STACK_ON_SLOW_SPAN));
+
+pub static SLOW_SPAN_THRESHOLD_MS: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_SLOW_SPAN_THRESHOLD_MS).max(0));
+
+pub static REPORT_DB_CONNECT_TIME: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_DB_CONNECT_TIME));
+
+pub static AUTO_CORRELATION_REQUEST_ID: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_AUTO_CORRELATION_REQUEST_ID));
+
+pub static CORRELATION_REQUEST_ID_KEY: Lazy<String> = Lazy::new(|| {
+    let key = get_str_ini_with_default(SKYWALKING_AGENT_CORRELATION_REQUEST_ID_KEY);
+    match key.trim() {
+        "" => "request_id".to_owned(),
+        key => key.to_owned(),
+    }
+});
+
+pub static REPORT_OS_INFO: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_OS_INFO));
+
+pub static REPORT_QUERY_STRING: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_QUERY_STRING));
+
+/// Lowercased for the case insensitive matching.
+pub static QUERY_STRING_REDACT_KEYS: Lazy<Vec<String>> = Lazy::new(|| {
+    get_str_ini_with_default(SKYWALKING_AGENT_QUERY_STRING_REDACT_KEYS)
+        .split(',')
+        .map(|key| key.trim().to_lowercase())
+        .filter(|key| !key.is_empty())
+        .collect()
+});
+
+pub static CUSTOM_EXIT_SPAN_RULES: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_CUSTOM_EXIT_SPAN_RULES));
+
+pub static COMPONENT_OVERRIDES: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_COMPONENT_OVERRIDES));
+
+pub const DEFAULT_RECONNECT_MAX_BACKOFF_MS: u64 = 30000;
+
+/// Fallback to the default value if the ini isn't positive.
+pub static RECONNECT_MAX_BACKOFF_MS: Lazy<u64> = Lazy::new(|| {
+    let max = ini_get::<i64>(SKYWALKING_AGENT_RECONNECT_MAX_BACKOFF_MS);
+    if max > 0 {
+        max as u64
+    } else {
+        DEFAULT_RECONNECT_MAX_BACKOFF_MS
+    }
+});
+
+pub static CLIENT_IP_HEADERS: Lazy<Vec<String>> = Lazy::new(|| {
+    get_str_ini_with_default(SKYWALKING_AGENT_CLIENT_IP_HEADERS)
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+});
+
+pub static TRACE_QUEUE_WORKERS: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_TRACE_QUEUE_WORKERS));
+
+/// Non-positive means no limit.
+pub static MAX_SEGMENT_BYTES: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_MAX_SEGMENT_BYTES));
+
+pub static HTTP_RECEIVER_URL: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_HTTP_RECEIVER_URL));
+
+pub static EXIT_SPAN_SAMPLE_RATE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_EXIT_SPAN_SAMPLE_RATE));
+
+/// Lowercased for the case insensitive matching.
+pub static URL_REDACT_PARAMS: Lazy<Vec<String>> = Lazy::new(|| {
+    get_str_ini_with_default(SKYWALKING_AGENT_URL_REDACT_PARAMS)
+        .split(',')
+        .map(|key| key.trim().to_lowercase())
+        .filter(|key| !key.is_empty())
+        .collect()
+});
+
+/// Non-positive means disabled.
+pub static TAIL_SAMPLE_LATENCY_MS: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_TAIL_SAMPLE_LATENCY_MS));
+
+pub static PEER_SERVICE_MAPPING: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_PEER_SERVICE_MAPPING));
+
+pub static INSTANCE_PROPERTIES_FILE: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_INSTANCE_PROPERTIES_FILE));
+
+pub static TRACE_SUBPROCESS: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_TRACE_SUBPROCESS));
+
+pub static TRACE_SUBPROCESS_COMMAND: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_TRACE_SUBPROCESS_COMMAND));
+
+/// Fallback to the component `PHP` if the ini isn't a positive `i32`.
+pub static DEFAULT_HTTP_SERVER_COMPONENT: Lazy<i32> = Lazy::new(|| {
+    i32::try_from(ini_get::<i64>(
+        SKYWALKING_AGENT_DEFAULT_HTTP_SERVER_COMPONENT,
+    ))
+    .ok()
+    .filter(|id| *id > 0)
+    .unwrap_or(component::COMPONENT_PHP_ID)
+});
+
+pub const DEFAULT_IPC_READ_TIMEOUT_MS: i64 = 5000;
+
+/// Non-positive means no timeout.
+pub static IPC_READ_TIMEOUT: Lazy<Option<Duration>> = Lazy::new(|| {
+    Some(ini_get::<i64>(SKYWALKING_AGENT_IPC_READ_TIMEOUT_MS))
+        .filter(|timeout| *timeout > 0)
+        .map(|timeout| Duration::from_millis(timeout as u64))
+});
+
+pub static DETERMINISTIC_SAMPLING: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_DETERMINISTIC_SAMPLING));
+
+pub static DIAGNOSTIC_MODE: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_DIAGNOSTIC_MODE));
+
+/// Non-positive means the built-in limit of each usage.
+pub static BACKTRACE_MAX_DEPTH: Lazy<usize> = Lazy::new(|| {
+    ini_get::<i64>(SKYWALKING_AGENT_BACKTRACE_MAX_DEPTH)
+        .try_into()
+        .unwrap_or_default()
+});
+
+pub static BACKTRACE_STRIP_PATHS: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_BACKTRACE_STRIP_PATHS));
+
+pub static ROUTE_OPERATION_NAME: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_ROUTE_OPERATION_NAME));
+
+pub static RESPONSE_SERVICE_HEADER: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_RESPONSE_SERVICE_HEADER));
+
+pub static REPORT_DB_ROWS: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_DB_ROWS));
+
+pub static GRPC_PROXY: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_GRPC_PROXY));
+
+pub static REPORT_MEMORY: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_MEMORY));
+
+pub static INJECT_W3C_TRACEPARENT: Lazy<bool> =
+    Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_INJECT_W3C_TRACEPARENT));
+
+pub static REPORT_LOG: Lazy<bool> = Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_REPORT_LOG));
+
+pub static REPORT_LOG_LEVEL: Lazy<String> =
+    Lazy::new(|| get_str_ini_with_default(SKYWALKING_AGENT_REPORT_LOG_LEVEL));
+
+/// Non-positive means no limit.
+pub static SAMPLE_N_PER_3_SECS: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_SAMPLE_N_PER_3_SECS));
+
+pub static ENABLE_EVENT: Lazy<bool> = Lazy::new(|| ini_get::<bool>(SKYWALKING_AGENT_ENABLE_EVENT));
+
+pub const DEFAULT_METER_REPORT_PERIOD: i64 = 20;
+
+/// Fallback to the default value if the ini isn't positive.
+pub static METER_REPORT_PERIOD: Lazy<i64> = Lazy::new(|| {
+    let period = ini_get::<i64>(SKYWALKING_AGENT_METER_REPORT_PERIOD);
+    if period > 0 {
+        period
+    } else {
+        DEFAULT_METER_REPORT_PERIOD
+    }
+});
+
+pub const DEFAULT_MAX_IPC_CONNECTIONS: usize = 1024;
+
+/// Fallback to the default value if the ini isn't positive.
+pub static MAX_IPC_CONNECTIONS: Lazy<usize> = Lazy::new(|| {
+    let max = ini_get::<i64>(SKYWALKING_AGENT_MAX_IPC_CONNECTIONS);
+    if max > 0 {
+        max as usize
+    } else {
+        DEFAULT_MAX_IPC_CONNECTIONS
+    }
+});
+
+/// Non-positive means the circuit breaker is disabled.
+pub static CIRCUIT_BREAKER_THRESHOLD: Lazy<i64> =
+    Lazy::new(|| ini_get::<i64>(SKYWALKING_AGENT_CIRCUIT_BREAKER_THRESHOLD));
+
+pub const DEFAULT_CIRCUIT_BREAKER_COOL_DOWN_PERIOD: i64 = 30;
+
+/// Fallback to the default value if the ini isn't positive.
+pub static CIRCUIT_BREAKER_COOL_DOWN_PERIOD: Lazy<i64> = Lazy::new(|| {
+    let period = ini_get::<i64>(SKYWALKING_AGENT_CIRCUIT_BREAKER_COOL_DOWN_PERIOD);
+    if period > 0 {
+        period
+    } else {
+        DEFAULT_CIRCUIT_BREAKER_COOL_DOWN_PERIOD
+    }
+});
+
 /// For PHP 8.2+, zend observer api are now also called for internal functions.
 ///
 /// Refer to this commit: <https://github.com/php/php-src/commit/625f1649639c2b9a9d76e4d42f88c264ddb8447d>
@@ -159,6 +562,10 @@ pub const IS_ZEND_OBSERVER_CALLED_FOR_INTERNAL: bool =
     sys::PHP_MAJOR_VERSION > 8 || (sys::PHP_MAJOR_VERSION == 8 && sys::PHP_MINOR_VERSION >= 2);
 
 pub fn init() {
+    if !is_enable() || !is_swoole_enabled() {
+        disable_swoole_hack_functions();
+    }
+
     if !is_enable() {
         return;
     }
@@ -169,8 +576,14 @@ pub fn init() {
     Lazy::force(&SERVICE_INSTANCE);
     Lazy::force(&SKYWALKING_VERSION);
     Lazy::force(&RUNTIME_DIR);
+    Lazy::force(&SERVICE_RUNTIME_DIR);
     Lazy::force(&SOCKET_FILE_PATH);
+    Lazy::force(&SOCKET_FILE_MODE);
+    Lazy::force(&SOCKET_TYPE);
+    Lazy::force(&SOCKET_TCP_ADDR);
+    Lazy::force(&WORKER_ADDR);
     Lazy::force(&AUTHENTICATION);
+    Lazy::force(&GRPC_EXTRA_HEADERS);
     Lazy::force(&ENABLE_TLS);
     Lazy::force(&SSL_TRUSTED_CA_PATH);
     Lazy::force(&SSL_KEY_PATH);
@@ -182,7 +595,71 @@ pub fn init() {
     Lazy::force(&REPORTER_TYPE);
     Lazy::force(&KAFKA_BOOTSTRAP_SERVERS);
     Lazy::force(&KAFKA_PRODUCER_CONFIG);
+    Lazy::force(&KAFKA_NAMESPACE);
     Lazy::force(&INJECT_CONTEXT);
+    Lazy::force(&CORRECT_CLOCK_SKEW);
+    Lazy::force(&STATUS_FILE);
+    Lazy::force(&REPORT_BATCH_SIZE);
+    Lazy::force(&REPORT_FLUSH_INTERVAL_MS);
+    Lazy::force(&ES_REPORT_BODY);
+    Lazy::force(&SQL_PARAMETERIZE);
+    Lazy::force(&ENABLE_PROFILING);
+    Lazy::force(&CLI_OPERATION_NAME_MODE);
+    Lazy::force(&REPORT_FPM_INFO);
+    Lazy::force(&CAPTURE_REQUEST_BODY_PATHS);
+    Lazy::force(&TRACE_MAX_BODY_SIZE);
+    Lazy::force(&WORKER_MODE);
+    Lazy::force(&PROPAGATE_EXIT_ERRORS);
+    Lazy::force(&SAMPLE_RATE);
+    Lazy::force(&TRACE_IGNORE_PATHS);
+    Lazy::force(&ENDPOINT_SAMPLE_RULES);
+    Lazy::force(&SPAN_LIMIT_PER_SEGMENT);
+    Lazy::force(&MAX_TAG_VALUE_LENGTH);
+    Lazy::force(&ENABLE_METER);
+    Lazy::force(&CIRCUIT_BREAKER_THRESHOLD);
+    Lazy::force(&CIRCUIT_BREAKER_COOL_DOWN_PERIOD);
+    Lazy::force(&TRACE_SESSIONS);
+    Lazy::force(&IGNORED_EXCEPTIONS);
+    Lazy::force(&CAPTURE_STACK_ON_SLOW_SPAN);
+    Lazy::force(&SLOW_SPAN_THRESHOLD_MS);
+    Lazy::force(&MAX_IPC_CONNECTIONS);
+    Lazy::force(&REPORT_DB_CONNECT_TIME);
+    Lazy::force(&AUTO_CORRELATION_REQUEST_ID);
+    Lazy::force(&CORRELATION_REQUEST_ID_KEY);
+    Lazy::force(&REPORT_OS_INFO);
+    Lazy::force(&REPORT_QUERY_STRING);
+    Lazy::force(&QUERY_STRING_REDACT_KEYS);
+    Lazy::force(&CUSTOM_EXIT_SPAN_RULES);
+    Lazy::force(&COMPONENT_OVERRIDES);
+    Lazy::force(&RECONNECT_MAX_BACKOFF_MS);
+    Lazy::force(&CLIENT_IP_HEADERS);
+    Lazy::force(&TRACE_QUEUE_WORKERS);
+    Lazy::force(&MAX_SEGMENT_BYTES);
+    Lazy::force(&HTTP_RECEIVER_URL);
+    Lazy::force(&EXIT_SPAN_SAMPLE_RATE);
+    Lazy::force(&URL_REDACT_PARAMS);
+    Lazy::force(&TAIL_SAMPLE_LATENCY_MS);
+    Lazy::force(&PEER_SERVICE_MAPPING);
+    Lazy::force(&INSTANCE_PROPERTIES_FILE);
+    Lazy::force(&TRACE_SUBPROCESS);
+    Lazy::force(&TRACE_SUBPROCESS_COMMAND);
+    Lazy::force(&DEFAULT_HTTP_SERVER_COMPONENT);
+    Lazy::force(&IPC_READ_TIMEOUT);
+    Lazy::force(&DETERMINISTIC_SAMPLING);
+    Lazy::force(&DIAGNOSTIC_MODE);
+    Lazy::force(&BACKTRACE_MAX_DEPTH);
+    Lazy::force(&BACKTRACE_STRIP_PATHS);
+    Lazy::force(&ROUTE_OPERATION_NAME);
+    Lazy::force(&RESPONSE_SERVICE_HEADER);
+    Lazy::force(&REPORT_DB_ROWS);
+    Lazy::force(&GRPC_PROXY);
+    Lazy::force(&REPORT_MEMORY);
+    Lazy::force(&INJECT_W3C_TRACEPARENT);
+    Lazy::force(&REPORT_LOG);
+    Lazy::force(&REPORT_LOG_LEVEL);
+    Lazy::force(&METER_REPORT_PERIOD);
+    Lazy::force(&SAMPLE_N_PER_3_SECS);
+    Lazy::force(&ENABLE_EVENT);
 
     if let Err(err) = try_init_logger() {
         eprintln!("skywalking_agent: initialize logger failed: {}", err);
@@ -200,32 +677,182 @@ pub fn init() {
 
     // Skywalking version check.
     let skywalking_version = *SKYWALKING_VERSION;
-    if skywalking_version < 8 {
-        error!(
-            skywalking_version,
-            "The skywalking agent only supports versions after skywalking 8"
+    if skywalking_version < MIN_SKYWALKING_VERSION {
+        let message = format!(
+            "The skywalking agent doesn't support `skywalking_agent.skywalking_version` {}, the \
+             agent is disabled, set it to the major version of OAP server, at least {}",
+            skywalking_version, MIN_SKYWALKING_VERSION
         );
+        error!("{}", message);
+        log_php_error(&message);
         return;
     }
+    if !KNOWN_SKYWALKING_VERSIONS.contains(&skywalking_version) {
+        warn!(
+            skywalking_version,
+            "The `skywalking_agent.skywalking_version` isn't one of the known versions {:?}, the \
+             v3 protocol is assumed",
+            KNOWN_SKYWALKING_VERSIONS
+        );
+    }
 
     // Initialize runtime directory.
     if RUNTIME_DIR.as_os_str().is_empty() {
         error!("The skywalking agent runtime directory must not be empty");
         return;
     }
-    if let Err(err) = fs::create_dir_all(&*RUNTIME_DIR) {
-        error!(?err, "Create runtime directory failed");
+    if let Err(err) =
+        fs::create_dir_all(&*SERVICE_RUNTIME_DIR).and_then(|_| check_writable(&SERVICE_RUNTIME_DIR))
+    {
+        let message = format!(
+            "The skywalking agent runtime directory {:?} isn't writable: {}, the agent is \
+             disabled, set `skywalking_agent.runtime_dir` to a writable directory",
+            &*SERVICE_RUNTIME_DIR, err
+        );
+        error!("{}", message);
+        log_php_error(&message);
         return;
     }
+    log_legacy_runtime_files();
+
+    if ![
+        CLI_OPERATION_NAME_MODE_SCRIPT_BASENAME,
+        CLI_OPERATION_NAME_MODE_SCRIPT_PATH,
+        CLI_OPERATION_NAME_MODE_ARGV,
+    ]
+    .contains(&CLI_OPERATION_NAME_MODE.as_str())
+    {
+        warn!(
+            cli_operation_name_mode = &*CLI_OPERATION_NAME_MODE,
+            "Unknown cli operation name mode, fallback to script_basename"
+        );
+    }
+
+    match ENABLE_SWOOLE.as_str() {
+        ENABLE_SWOOLE_AUTO | "" | "0" | "off" | "false" | "no" => {}
+        "1" | "on" | "true" | "yes" => {
+            if !is_swoole_loaded() {
+                warn!("The swoole is enabled, but the swoole extension isn't loaded");
+            }
+        }
+        enable_swoole => {
+            warn!(enable_swoole, "Unknown enable swoole, fallback to auto");
+        }
+    }
+
+    // Parse after the logger initialized, to log the invalid items.
+    Lazy::force(&component::COMPONENT_OVERRIDES);
+    Lazy::force(&component::EXIT_SPAN_SAMPLE_RATES);
+    Lazy::force(&peer::PEER_SERVICE_MAPPING);
 
-    // Initialize Agent worker.
-    init_worker();
+    for (name, default) in [
+        (SKYWALKING_AGENT_HEARTBEAT_PERIOD, DEFAULT_HEARTBEAT_PERIOD),
+        (
+            SKYWALKING_AGENT_PROPERTIES_REPORT_PERIOD_FACTOR,
+            DEFAULT_PROPERTIES_REPORT_PERIOD_FACTOR,
+        ),
+    ] {
+        let value = ini_get::<i64>(name);
+        if value <= 0 {
+            warn!(
+                name,
+                value, default, "The ini must be positive, fallback to default"
+            );
+        }
+    }
 
-    tracer::set_global_tracer(Tracer::new(
-        &*SERVICE_NAME,
-        &*SERVICE_INSTANCE,
-        Reporter::new(&*SOCKET_FILE_PATH),
-    ));
+    if ![WORKER_MODE_FORK, WORKER_MODE_THREAD].contains(&WORKER_MODE.as_str()) {
+        error!(
+            worker_mode = &*WORKER_MODE,
+            "The worker mode must be fork or thread"
+        );
+        return;
+    }
+
+    // Validate the socket configuration.
+    if !["unix", "tcp"].contains(&SOCKET_TYPE.as_str()) {
+        error!(
+            socket_type = &*SOCKET_TYPE,
+            "The socket type must be unix or tcp"
+        );
+        return;
+    }
+    if SOCKET_TYPE.as_str() == "tcp" && SOCKET_TCP_ADDR.is_empty() {
+        error!("The socket tcp address must not be empty");
+        return;
+    }
+    if SOCKET_TYPE.as_str() == "tcp" {
+        if let Err(err) = split_host_port(&SOCKET_TCP_ADDR) {
+            error!(
+                socket_tcp_addr = &*SOCKET_TCP_ADDR,
+                ?err,
+                "The socket tcp address is invalid"
+            );
+            return;
+        }
+    }
+
+    // Validate the server address.
+    if REPORTER_TYPE.as_str() == "grpc" {
+        if let Err(err) = split_host_port(&SERVER_ADDR) {
+            error!(
+                server_addr = &*SERVER_ADDR,
+                ?err,
+                "The server address is invalid"
+            );
+            return;
+        }
+    }
+    if SOCKET_FILE_MODE.is_none() {
+        error!(
+            socket_file_mode = %get_str_ini_with_default(SKYWALKING_AGENT_SOCKET_FILE_MODE),
+            "The socket file mode must be an octal number no more than 0777"
+        );
+        return;
+    }
+    // The socket isn't used in thread mode.
+    match &*WORKER_ADDR {
+        WorkerAddr::Unix(socket_file) if !is_thread_worker_mode() => {
+            if let Some(parent) = socket_file.parent() {
+                if let Err(err) = fs::create_dir_all(parent) {
+                    error!(?err, "Create socket file directory failed");
+                    return;
+                }
+            }
+        }
+        _ => {}
+    }
+
+    // Initialize the shared memory of profiling, dynamic config and circuit
+    // breaker, before forking.
+    profiling::init();
+    dynamic_config::init();
+    breaker::init();
+
+    if is_thread_worker_mode() {
+        // The worker is started lazily in every php process, because the threads
+        // don't survive the fork of php-fpm.
+        warn!(
+            "The worker runs in thread mode, every php process starts its own reporter, so there \
+             are more connections to the backend, and the pending items may be lost when the php \
+             process exits abnormally"
+        );
+
+        tracer::set_global_tracer(Tracer::new(
+            &*SERVICE_NAME,
+            &*SERVICE_INSTANCE,
+            ThreadReporter,
+        ));
+    } else {
+        // Initialize Agent worker.
+        init_worker();
+
+        tracer::set_global_tracer(Tracer::new(
+            &*SERVICE_NAME,
+            &*SERVICE_INSTANCE,
+            Reporter::new(WORKER_ADDR.clone()),
+        ));
+    }
 
     // Hook functions.
     register_execute_functions();
@@ -238,6 +865,88 @@ pub fn shutdown() {
     }
 
     debug!("skywalking agent shutdown hook called");
+
+    if is_thread_worker_mode() {
+        shutdown_thread_worker();
+    } else {
+        channel::close_stream();
+    }
+}
+
+/// The log file and its path, reopened by [reopen_log_file].
+static LOG_FILE: OnceCell<(PathBuf, Mutex<File>)> = OnceCell::new();
+
+/// Write to the current [LOG_FILE].
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.get().map(|(_, file)| file.lock()) {
+            Some(Ok(mut file)) => file.write(buf),
+            _ => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.get().map(|(_, file)| file.lock()) {
+            Some(Ok(mut file)) => file.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn open_log_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+/// Reopen the log file by path, after it's moved by logrotate, called by
+/// worker on `SIGUSR1`. Do nothing if the log isn't enabled.
+pub fn reopen_log_file() -> io::Result<()> {
+    let Some((path, file)) = LOG_FILE.get() else {
+        return Ok(());
+    };
+    let new_file = open_log_file(path)?;
+    if let Ok(mut file) = file.lock() {
+        *file = new_file;
+    }
+    Ok(())
+}
+
+/// The directory name of the service, the unsafe chars of the service name are
+/// replaced and the length is limited, for the length limit of unix socket
+/// path, and the hash of the full name keeps the names distinct.
+fn service_dir_name(service_name: &str) -> String {
+    const MAX_NAME_LEN: usize = 32;
+
+    let name = service_name
+        .chars()
+        .take(MAX_NAME_LEN)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+
+    format!("{}-{:08x}", name, fnv1a_32(service_name.as_bytes()))
+}
+
+/// The socket and pid file were directly under `runtime_dir` in the former
+/// versions, which may be left by the worker not upgraded yet.
+fn log_legacy_runtime_files() {
+    for name in ["agent.sock", "agent.pid"] {
+        let path = RUNTIME_DIR.join(name);
+        if path.exists() {
+            info!(
+                ?path,
+                service_runtime_dir = ?&*SERVICE_RUNTIME_DIR,
+                "The legacy runtime file is detected, the worker files are moved into the \
+                 service directory, remove it after all the php processes upgraded"
+            );
+        }
+    }
 }
 
 fn try_init_logger() -> anyhow::Result<()> {
@@ -265,20 +974,32 @@ fn try_init_logger() -> anyhow::Result<()> {
         fs::create_dir_all(parent)?;
     }
 
-    let mut open_options = OpenOptions::new();
-    open_options.append(true).create(true);
+    let file = open_log_file(path)?;
+    if LOG_FILE.set((path.to_owned(), Mutex::new(file))).is_err() {
+        bail!("log file already opened");
+    }
 
-    let file = open_options.open(path)?;
+    let log_format = ini_get::<Option<&CStr>>(SKYWALKING_AGENT_LOG_FORMAT)
+        .and_then(|s| s.to_str().ok())
+        .unwrap_or("text");
+    let log_format = log_format.trim();
 
     let filter = EnvFilter::new(format!("info,skywalking_agent={}", log_level));
 
-    let subscriber = FmtSubscriber::builder()
+    let builder = FmtSubscriber::builder()
         .with_env_filter(filter)
         .with_ansi(false)
-        .with_writer(file)
-        .finish();
+        .with_writer(|| LogWriter);
 
-    tracing::subscriber::set_global_default(subscriber)?;
+    match log_format {
+        "text" => tracing::subscriber::set_global_default(builder.finish())?,
+        // The fields of event are flattened into the top level object, along
+        // with the timestamp, level and target.
+        "json" => {
+            tracing::subscriber::set_global_default(builder.json().flatten_event(true).finish())?
+        }
+        _ => bail!("log format must be text or json, got {:?}", log_format),
+    }
 
     Ok(())
 }
@@ -288,7 +1009,39 @@ fn get_module_registry() -> &'static ZArr {
     unsafe { ZArr::from_ptr(&sys::module_registry) }
 }
 
+#[inline]
+pub fn is_swoole_loaded() -> bool {
+    get_module_registry().exists("swoole")
+}
+
+#[inline]
+pub fn is_swoole_enabled() -> bool {
+    *IS_SWOOLE_ENABLED
+}
+
 #[inline]
 pub fn is_enable() -> bool {
     *IS_ENABLE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_dir_name_stable() {
+        assert_eq!(service_dir_name("hello"), "hello-4f9f2cab");
+        assert_eq!(
+            service_dir_name("my service/\u{4e2d}\u{6587}"),
+            "my_service___-a279abd5"
+        );
+    }
+
+    #[test]
+    fn service_dir_name_limited() {
+        let dir_name = service_dir_name(&"a".repeat(100));
+        assert!(dir_name.starts_with(&"a".repeat(32)));
+        assert_eq!(dir_name.len(), 32 + 9);
+        assert_ne!(dir_name, service_dir_name(&"a".repeat(101)));
+    }
+}
@@ -0,0 +1,163 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Map the observed peers of exit spans to the logical service names by
+//! `skywalking_agent.peer_service_mapping`, for the backends behind the load
+//! balancers, which are collapsed into the address of load balancer.
+//!
+//! The exit span tagged by the `sw8-service` response header of the downstream
+//! service isn't mapped, since the OAP server already links it to the
+//! downstream service by the address in `sw8`, the mapping is the fallback
+//! when the downstream identity is unknown.
+
+use crate::{
+    module::PEER_SERVICE_MAPPING as PEER_SERVICE_MAPPING_INI,
+    tag::{TAG_PEER_ADDRESS, TAG_PEER_INSTANCE, TAG_PEER_SERVICE},
+    util::split_host_port,
+};
+use once_cell::sync::Lazy;
+use skywalking::{
+    proto::v3::{KeyStringValuePair, SpanObject, SpanType},
+    reporter::CollectItem,
+};
+use tracing::warn;
+
+/// Parsed from `skywalking_agent.peer_service_mapping`, sorted from the most
+/// specific, the invalid items are logged and ignored, so it should be forced
+/// after the logger initialized.
+pub static PEER_SERVICE_MAPPING: Lazy<Vec<PeerMapping>> = Lazy::new(|| {
+    let mut mappings = Vec::new();
+
+    for item in PEER_SERVICE_MAPPING_INI
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+    {
+        let Some((peer, service)) = item.rsplit_once('=') else {
+            warn!(
+                item,
+                "Peer service mapping should be `peer=service`, ignored"
+            );
+            continue;
+        };
+        let service = service.trim();
+        if service.is_empty() {
+            warn!(item, "Service of peer service mapping is empty, ignored");
+            continue;
+        }
+        match PeerMapping::parse(peer.trim(), service) {
+            Ok(mapping) => mappings.push(mapping),
+            Err(err) => {
+                warn!(item, ?err, "Invalid peer of peer service mapping, ignored");
+            }
+        }
+    }
+
+    mappings.sort_by_key(|mapping| std::cmp::Reverse(mapping.specificity()));
+    mappings
+});
+
+/// The peer is `host[:port]`, the host can be `*.<domain>` matching the
+/// subdomains, and the mapping without port matches any port.
+pub struct PeerMapping {
+    host: String,
+    is_wildcard: bool,
+    port: Option<u16>,
+    service: String,
+}
+
+impl PeerMapping {
+    fn parse(peer: &str, service: &str) -> anyhow::Result<Self> {
+        let (host, port) = split_host_port(peer)?;
+        let host = host.to_ascii_lowercase();
+        let (host, is_wildcard) = match host.strip_prefix("*.") {
+            Some(domain) => (format!(".{}", domain), true),
+            None => (host, false),
+        };
+        Ok(Self {
+            host,
+            is_wildcard,
+            port,
+            service: service.to_owned(),
+        })
+    }
+
+    /// The exact host is more specific than the wildcard, then the longer host,
+    /// then the one with port.
+    fn specificity(&self) -> (bool, usize, bool) {
+        (!self.is_wildcard, self.host.len(), self.port.is_some())
+    }
+
+    fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        if self.port.is_some() && self.port != port {
+            return false;
+        }
+        if self.is_wildcard {
+            host.ends_with(&self.host)
+        } else {
+            host == self.host
+        }
+    }
+}
+
+/// Replace the peers of exit spans with the mapped service names, the original
+/// peer is kept in the `peer.address` tag.
+pub fn map_peer_services(item: &mut CollectItem) {
+    if PEER_SERVICE_MAPPING.is_empty() {
+        return;
+    }
+
+    let CollectItem::Trace(segment) = item else {
+        return;
+    };
+
+    for span in &mut segment.spans {
+        if span.span_type() != SpanType::Exit
+            || span.peer.is_empty()
+            || span
+                .tags
+                .iter()
+                .any(|tag| tag.key == TAG_PEER_ADDRESS || tag.key == TAG_PEER_SERVICE)
+        {
+            continue;
+        }
+        let Some(service) = find_service(&span.peer) else {
+            continue;
+        };
+        let peer = std::mem::replace(&mut span.peer, service.to_owned());
+        span.tags.push(KeyStringValuePair {
+            key: TAG_PEER_ADDRESS.to_owned(),
+            value: peer,
+        });
+    }
+}
+
+/// Tag the exit span with the service and instance of the `sw8-service`
+/// response header. The peer is kept as the address, which the OAP server
+/// registers as the alias of the downstream service by the `sw8` header, the
+/// service name as peer would be drawn as another conjectured service.
+pub fn tag_peer_service(span_object: &mut SpanObject, service: String, service_instance: String) {
+    span_object.add_tag(TAG_PEER_SERVICE, service);
+    span_object.add_tag(TAG_PEER_INSTANCE, service_instance);
+}
+
+fn find_service(peer: &str) -> Option<&'static str> {
+    let (host, port) = split_host_port(peer).ok()?;
+    let host = host.to_ascii_lowercase();
+    PEER_SERVICE_MAPPING
+        .iter()
+        .find(|mapping| mapping.matches(&host, port))
+        .map(|mapping| mapping.service.as_str())
+}
@@ -0,0 +1,169 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The entry spans of the messages received by the consumers polling in a
+//! loop, like `RdKafka\KafkaConsumer::consume`. The handling of the message
+//! isn't a hooked call, so the span is started when the message is received,
+//! and kept until the handling ends, that is the next receiving of the same
+//! consumer, or the end of the request.
+
+use crate::context::RequestContext;
+use skywalking::trace::{span::Span, trace_context::TracingContext, tracer};
+use std::{cell::RefCell, collections::HashMap};
+use tracing::debug;
+
+thread_local! {
+    /// The spans of the messages in handling, keyed by the handle of consumer
+    /// object.
+    static HANDLING_SPANS: RefCell<HashMap<u32, HandlingSpan>> = Default::default();
+}
+
+struct HandlingSpan {
+    request_id: Option<i64>,
+    span: Span,
+    /// The standalone context created for the message if the request isn't
+    /// traced, finished with the span.
+    tracing_context: Option<TracingContext>,
+}
+
+/// Start the entry span of the message received by the consumer, in the
+/// context of request, or the standalone one if the request isn't traced (such
+/// as the consumer running in cli). The span of the last message received by
+/// the consumer is finished first.
+pub fn start_handling_span(
+    request_id: Option<i64>, handle: u32, create_span: impl Fn(&mut TracingContext) -> Span,
+) {
+    finish_handling_span(handle);
+
+    let handling = match RequestContext::try_with_global_ctx(request_id, |ctx| Ok(create_span(ctx)))
+    {
+        Ok(span) => HandlingSpan {
+            request_id,
+            span,
+            tracing_context: None,
+        },
+        Err(_) => {
+            let mut tracing_context = tracer::create_trace_context();
+            let span = create_span(&mut tracing_context);
+            HandlingSpan {
+                request_id,
+                span,
+                tracing_context: Some(tracing_context),
+            }
+        }
+    };
+
+    debug!(handle, "start handling span of consumer");
+    HANDLING_SPANS.with(|spans| spans.borrow_mut().insert(handle, handling));
+}
+
+/// Finish the span of the last message received by the consumer, called
+/// before the consumer receives the next one.
+pub fn finish_handling_span(handle: u32) {
+    let handling = HANDLING_SPANS.with(|spans| spans.borrow_mut().remove(&handle));
+    if let Some(handling) = handling {
+        debug!(handle, "finish handling span of consumer");
+        finish(handling);
+    }
+}
+
+/// Finish the spans of the messages received in the request, called before
+/// the request context is finished.
+pub fn finish_request_handling_spans(request_id: Option<i64>) {
+    let handlings = HANDLING_SPANS.with(|spans| {
+        let mut spans = spans.borrow_mut();
+        let handles = spans
+            .iter()
+            .filter(|(_, handling)| handling.request_id == request_id)
+            .map(|(handle, _)| *handle)
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .filter_map(|handle| spans.remove(&handle))
+            .collect::<Vec<_>>()
+    });
+    for handling in handlings {
+        finish(handling);
+    }
+}
+
+/// The span is finished before its standalone context, so it's reported with
+/// the context.
+fn finish(handling: HandlingSpan) {
+    let HandlingSpan {
+        span,
+        tracing_context,
+        ..
+    } = handling;
+    drop(span);
+    drop(tracing_context);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{find_span, finish_request, start_request, RecordingReporter};
+
+    #[test]
+    fn finish_last_handling_span() {
+        let (tracer, reporter) = RecordingReporter::tracer();
+        let request_id = start_request(&tracer, "consume");
+
+        start_handling_span(request_id, 1, |ctx| ctx.create_local_span("message-1"));
+        start_handling_span(request_id, 1, |ctx| ctx.create_local_span("message-2"));
+        start_handling_span(request_id, 2, |ctx| ctx.create_local_span("message-3"));
+        finish_handling_span(1);
+        finish_request_handling_spans(request_id);
+        finish_request(request_id);
+
+        let segments = reporter.segments();
+        assert_eq!(segments.len(), 1);
+        let entry = find_span(&segments[0], "consume").unwrap();
+        let message_1 = find_span(&segments[0], "message-1").unwrap();
+        let message_2 = find_span(&segments[0], "message-2").unwrap();
+        let message_3 = find_span(&segments[0], "message-3").unwrap();
+        // The span of the last message is finished before the next one starts.
+        assert_eq!(message_1.parent_span_id, entry.span_id);
+        assert_eq!(message_2.parent_span_id, entry.span_id);
+        // The span of another consumer is started in the active one.
+        assert_eq!(message_3.parent_span_id, message_2.span_id);
+        assert!(HANDLING_SPANS.with(|spans| spans.borrow().is_empty()));
+    }
+
+    #[test]
+    fn finish_request_handling_spans_only() {
+        let (tracer, reporter) = RecordingReporter::tracer();
+        let request_id = start_request(&tracer, "consume");
+        let other_request_id = start_request(&tracer, "other");
+
+        start_handling_span(request_id, 1, |ctx| ctx.create_local_span("message"));
+        start_handling_span(other_request_id, 2, |ctx| {
+            ctx.create_local_span("other-message")
+        });
+        finish_request_handling_spans(request_id);
+        finish_request(request_id);
+
+        let segments = reporter.segments();
+        assert_eq!(segments.len(), 1);
+        assert!(find_span(&segments[0], "message").is_some());
+        assert!(HANDLING_SPANS.with(|spans| spans.borrow().contains_key(&2)));
+
+        finish_handling_span(2);
+        finish_request(other_request_id);
+        let segments = reporter.segments();
+        assert_eq!(segments.len(), 2);
+        assert!(find_span(&segments[1], "other-message").is_some());
+    }
+}
@@ -13,21 +13,50 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod consumer;
+mod plugin_amqp;
 mod plugin_amqplib;
 mod plugin_curl;
+mod plugin_custom;
+mod plugin_elasticsearch;
+mod plugin_grpc;
+mod plugin_http_stream;
+mod plugin_log;
 mod plugin_memcache;
 mod plugin_memcached;
 mod plugin_mongodb;
 mod plugin_mysqli;
 mod plugin_pdo;
 mod plugin_predis;
+mod plugin_queue_worker;
+mod plugin_rdkafka;
 mod plugin_redis;
+mod plugin_route;
+mod plugin_session;
+mod plugin_subprocess;
 mod plugin_swoole;
+mod plugin_swoole_coroutine;
+mod plugin_swoole_http_client;
+mod plugin_thrift;
+mod plugin_yar;
+mod sql;
 mod style;
 
-use crate::execute::{AfterExecuteHook, BeforeExecuteHook};
+pub use plugin_amqp::{
+    skywalking_hack_amqp_consume_callback, HACK_AMQP_CONSUME_CALLBACK_FUNCTION_NAME,
+};
+pub use plugin_yar::{
+    skywalking_hack_yar_callback, skywalking_hack_yar_error_callback,
+    HACK_YAR_CALLBACK_FUNCTION_NAME, HACK_YAR_ERROR_CALLBACK_FUNCTION_NAME,
+};
+
+use crate::{
+    execute::{AfterExecuteHook, BeforeExecuteHook},
+    module::IGNORED_EXCEPTIONS,
+    util::exception_backtrace,
+};
 use once_cell::sync::Lazy;
-use phper::{eg, objects::ZObj};
+use phper::{eg, functions::call, objects::ZObj, values::ZVal};
 use skywalking::trace::span::HandleSpanObject;
 use std::{collections::HashMap, ops::Deref, sync::Mutex};
 use tracing::error;
@@ -46,6 +75,25 @@ static PLUGINS: Lazy<Vec<Box<DynPlugin>>> = Lazy::new(|| {
         Box::<plugin_amqplib::AmqplibPlugin>::default(),
         Box::<plugin_mongodb::MongodbPlugin>::default(),
         Box::<plugin_memcache::MemcachePlugin>::default(),
+        Box::<plugin_yar::YarPlugin>::default(),
+        Box::<plugin_elasticsearch::ElasticsearchPlugin>::default(),
+        Box::<plugin_swoole_http_client::SwooleHttpClientPlugin>::default(),
+        Box::<plugin_swoole_coroutine::SwooleRedisPlugin>::default(),
+        Box::<plugin_swoole_coroutine::SwooleMysqlPlugin>::default(),
+        Box::<plugin_rdkafka::RdKafkaPlugin>::default(),
+        Box::<plugin_http_stream::HttpStreamPlugin>::default(),
+        Box::<plugin_grpc::GrpcPlugin>::default(),
+        Box::<plugin_amqp::AmqpPlugin>::default(),
+        Box::<plugin_thrift::ThriftSendPlugin>::default(),
+        Box::<plugin_thrift::ThriftRecvPlugin>::default(),
+        Box::<plugin_session::SessionPlugin>::default(),
+        Box::<plugin_queue_worker::QueueWorkerPlugin>::default(),
+        Box::<plugin_route::LaravelRoutePlugin>::default(),
+        Box::<plugin_route::SymfonyRoutePlugin>::default(),
+        Box::<plugin_custom::CustomExitSpanPlugin>::default(),
+        Box::<plugin_log::ErrorLogPlugin>::default(),
+        Box::<plugin_log::MonologPlugin>::default(),
+        Box::<plugin_subprocess::SubprocessPlugin>::default(),
     ]
 });
 
@@ -56,6 +104,13 @@ pub trait Plugin {
 
     fn function_name_prefix(&self) -> Option<&'static str>;
 
+    /// The exact names of the functions, not the methods, for the plugin
+    /// hooking a few functions without a common prefix.
+    #[inline]
+    fn function_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
     fn hook(
         &self, class_name: Option<&str>, function_name: &str,
     ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)>;
@@ -80,43 +135,75 @@ pub fn select_plugin_hook(
     unsafe {
         HOOK_MAP
             .entry((class_name.map(ToOwned::to_owned), function_name.to_owned()))
-            .or_insert_with(|| {
-                select_plugin(class_name, function_name)
-                    .and_then(|plugin| plugin.hook(class_name, function_name))
-            })
+            .or_insert_with(|| select_plugin(class_name, function_name))
             .as_ref()
             .map(|(before, after)| (before.deref(), after.deref()))
     }
 }
 
-fn select_plugin(class_name: Option<&str>, function_name: &str) -> Option<&'static DynPlugin> {
-    let mut selected_plugin = None;
+/// Close the spans and clean the states kept by the plugins across the hooked
+/// calls of the request, called when the request ends, even if it isn't
+/// traced, and before the request context is finished, like
+/// [`close_pending_results`](crate::execute::close_pending_results).
+pub fn close_request_states(request_id: Option<i64>) {
+    plugin_mysqli::close_async_query_spans(request_id);
+    consumer::finish_request_handling_spans(request_id);
+    plugin_rdkafka::remove_request_handles(request_id);
+}
 
-    for plugin in &*PLUGINS {
-        if let Some(class_name) = class_name {
-            if let Some(plugin_class_names) = plugin.class_names() {
-                if plugin_class_names.contains(&class_name) {
-                    selected_plugin = Some(plugin);
-                    break;
-                }
-            }
+/// Select the hook of the first plugin which matches the function, and hooks
+/// it. The plugins matching the class name are tried before the ones matching
+/// the function name prefix or the function names, so the classes, including
+/// the ones of the custom rules, aren't shadowed by the prefixes of the other
+/// methods. The plugin matched but not hooking it falls through to the next
+/// plugins.
+fn select_plugin(
+    class_name: Option<&str>, function_name: &str,
+) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+    let by_class = PLUGINS
+        .iter()
+        .filter(|plugin| is_class_matched(plugin.as_ref(), class_name));
+    let by_function = PLUGINS
+        .iter()
+        .filter(|plugin| is_function_matched(plugin.as_ref(), class_name, function_name));
+    by_class
+        .chain(by_function)
+        .find_map(|plugin| plugin.hook(class_name, function_name))
+}
+
+fn is_class_matched(plugin: &DynPlugin, class_name: Option<&str>) -> bool {
+    match (class_name, plugin.class_names()) {
+        (Some(class_name), Some(plugin_class_names)) => plugin_class_names.contains(&class_name),
+        _ => false,
+    }
+}
+
+fn is_function_matched(plugin: &DynPlugin, class_name: Option<&str>, function_name: &str) -> bool {
+    if let Some(function_name_prefix) = plugin.function_name_prefix() {
+        if function_name.starts_with(function_name_prefix) {
+            return true;
         }
-        if let Some(function_name_prefix) = plugin.function_name_prefix() {
-            if function_name.starts_with(function_name_prefix) {
-                selected_plugin = Some(plugin);
-                break;
+    }
+    if class_name.is_none() {
+        if let Some(function_names) = plugin.function_names() {
+            if function_names.contains(&function_name) {
+                return true;
             }
         }
     }
-
-    selected_plugin.map(AsRef::as_ref)
+    false
 }
 
+/// Log the thrown exception to the span, and mark the span errored unless the
+/// exception is an instance of `skywalking_agent.ignored_exceptions`.
 fn log_exception(span: &mut impl HandleSpanObject) -> Option<&mut ZObj> {
     let mut ex = unsafe { ZObj::try_from_mut_ptr(eg!(exception)) };
     if let Some(ex) = ex.as_mut() {
+        let is_ignored = is_exception_ignored(ex);
         let span_object = span.span_object_mut();
-        span_object.is_error = true;
+        if !is_ignored {
+            span_object.is_error = true;
+        }
 
         let mut logs = Vec::new();
         if let Ok(class_name) = ex.get_class().get_name().to_str() {
@@ -127,10 +214,10 @@ fn log_exception(span: &mut impl HandleSpanObject) -> Option<&mut ZObj> {
                 logs.push(("message", message.to_owned()));
             }
         }
-        if let Ok(stack) = ex.call("getTraceAsString", []) {
-            if let Some(stack) = stack.as_z_str().and_then(|s| s.to_str().ok()) {
-                logs.push(("stack", stack.to_owned()));
-            }
+        // The stack of the exception is only limited by
+        // `skywalking_agent.backtrace_max_depth`.
+        if let Some(stack) = exception_backtrace(ex, usize::MAX) {
+            logs.push(("stack", stack));
         }
         if !logs.is_empty() {
             span_object.add_log(logs);
@@ -138,3 +225,18 @@ fn log_exception(span: &mut impl HandleSpanObject) -> Option<&mut ZObj> {
     }
     ex
 }
+
+fn is_exception_ignored(ex: &mut ZObj) -> bool {
+    IGNORED_EXCEPTIONS.iter().any(|class_name| {
+        call(
+            "is_a",
+            [
+                ZVal::from(ex.to_ref_owned()),
+                ZVal::from(class_name.as_str()),
+            ],
+        )
+        .ok()
+        .and_then(|result| result.as_bool())
+        .unwrap_or_default()
+    })
+}
@@ -0,0 +1,375 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin of the C extension [amqp](https://github.com/php-amqp/php-amqp).
+//!
+//! The `sw8` header is only injected by `AMQPExchange::publish` with the
+//! attributes argument passed, because the arguments of internal function
+//! can't be appended.
+
+use super::{
+    consumer::{finish_handling_span, start_handling_span},
+    log_exception, Plugin,
+};
+use crate::{
+    component::{component_id, COMPONENT_AMQP_CONSUMER_ID, COMPONENT_AMQP_PRODUCER_ID},
+    context::{RequestContext, SW_HEADER},
+    execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook},
+    tag::{TAG_MQ_BROKER, TAG_MQ_QUEUE, TAG_MQ_TOPIC},
+    util::join_host_port,
+};
+use phper::{
+    arrays::ZArray,
+    objects::ZObj,
+    strings::ZString,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::{
+        propagation::decoder::decode_propagation,
+        span::{HandleSpanObject, Span},
+        trace_context::TracingContext,
+        tracer,
+    },
+};
+use std::{cell::RefCell, mem::replace};
+use tracing::{debug, error};
+
+const EXCHANGE_CLASS_NAME: &str = "AMQPExchange";
+const QUEUE_CLASS_NAME: &str = "AMQPQueue";
+
+pub const HACK_AMQP_CONSUME_CALLBACK_FUNCTION_NAME: &str =
+    "skywalking_hack_amqp_consume_callback_please_do_not_use";
+
+thread_local! {
+    /// The original callbacks of the running `AMQPQueue::consume`, the last
+    /// one is the innermost.
+    static CONSUME_CALLBACKS: RefCell<Vec<ConsumeCallback>> = Default::default();
+}
+
+struct ConsumeCallback {
+    callback: ZVal,
+    queue: Queue,
+}
+
+#[derive(Clone)]
+struct Queue {
+    name: String,
+    peer: String,
+}
+
+#[derive(Default, Clone)]
+pub struct AmqpPlugin;
+
+impl Plugin for AmqpPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(&[EXCHANGE_CLASS_NAME, QUEUE_CLASS_NAME])
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (Some(EXCHANGE_CLASS_NAME), "publish") => Some(self.hook_exchange_publish()),
+            (Some(QUEUE_CLASS_NAME), "get") => Some(self.hook_queue_get()),
+            (Some(QUEUE_CLASS_NAME), "consume") => Some(self.hook_queue_consume()),
+            _ => None,
+        }
+    }
+}
+
+impl AmqpPlugin {
+    /// `AMQPExchange::publish(string $message, ?string $routingKey = null,
+    /// ?int $flags = null, array $attributes = [])`.
+    fn hook_exchange_publish(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                let routing_key = execute_data
+                    .get_parameter(1)
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .map(ToOwned::to_owned)
+                    .unwrap_or_default();
+
+                let this = get_this_mut(execute_data)?;
+                let exchange = call_string(this, "getName").unwrap_or_default();
+                let peer = get_peer(this);
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(
+                        &format!("{}->{}", EXCHANGE_CLASS_NAME, "publish"),
+                        &peer,
+                    ))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.set_span_layer(SpanLayer::Mq);
+                span_object.component_id =
+                    component_id("amqp_producer", COMPONENT_AMQP_PRODUCER_ID);
+                span_object.add_tag(TAG_MQ_BROKER, &peer);
+                span_object.add_tag(TAG_MQ_TOPIC, &exchange);
+                span_object.add_tag(TAG_MQ_QUEUE, &routing_key);
+
+                if execute_data.num_args() >= 4 {
+                    let sw_header = RequestContext::try_get_sw_header(request_id, &peer)?;
+                    inject_sw_header(execute_data, sw_header);
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(|_, span, _, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+
+    /// `AMQPQueue::get(int $flags = AMQP_NOPARAM): ?AMQPEnvelope`.
+    ///
+    /// The entry span is created after the message is received, to extract
+    /// the `sw8` header, and finished when the queue gets the next one, or the
+    /// request ends, so it covers the handling of the message.
+    fn hook_queue_get(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, execute_data| {
+                finish_handling_span(get_this_mut(execute_data)?.handle());
+                Ok(Box::new(()))
+            }),
+            Box::new(|request_id, _, execute_data, return_value| {
+                let Some(envelope) = return_value.as_mut_z_obj() else {
+                    return Ok(());
+                };
+
+                let this = get_this_mut(execute_data)?;
+                let handle = this.handle();
+                let queue = get_queue(this);
+                let sw_header = get_sw_header(envelope)?;
+                let operation_name = format!("{}->{}", QUEUE_CLASS_NAME, "get");
+
+                let create_span = |ctx: &mut TracingContext| {
+                    create_entry_span(ctx, &operation_name, sw_header.as_deref(), &queue)
+                };
+
+                start_handling_span(request_id, handle, create_span);
+
+                Ok(())
+            }),
+        )
+    }
+
+    /// `AMQPQueue::consume(?callable $callback = null, int $flags =
+    /// AMQP_NOPARAM, ?string $consumerTag = null)`.
+    ///
+    /// The callback is replaced by
+    /// [`skywalking_hack_amqp_consume_callback`], which surrounds every
+    /// message handling with an entry span.
+    fn hook_queue_consume(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, execute_data| {
+                if execute_data.num_args() < 1
+                    || execute_data.get_parameter(0).get_type_info().is_null()
+                {
+                    return Ok(Box::new(false));
+                }
+
+                let queue = get_queue(get_this_mut(execute_data)?);
+
+                let callback = execute_data.get_mut_parameter(0);
+                let callback = replace(
+                    callback,
+                    ZVal::from(ZString::new(HACK_AMQP_CONSUME_CALLBACK_FUNCTION_NAME)),
+                );
+                CONSUME_CALLBACKS.with(|callbacks| {
+                    callbacks
+                        .borrow_mut()
+                        .push(ConsumeCallback { callback, queue })
+                });
+
+                Ok(Box::new(true))
+            }),
+            Box::new(|_, is_hacked, _, _| {
+                if *is_hacked.downcast::<bool>().unwrap() {
+                    CONSUME_CALLBACKS.with(|callbacks| callbacks.borrow_mut().pop());
+                }
+                Ok(())
+            }),
+        )
+    }
+}
+
+/// The function is used by amqp plugin, to surround the callback of
+/// `AMQPQueue::consume`, the arguments are `(AMQPEnvelope $envelope,
+/// AMQPQueue $queue)`.
+///
+/// If there is no request context (such as the consumer running in cli), the
+/// standalone one is created for every message, so the spans created by the
+/// callback are in the same segment.
+pub fn skywalking_hack_amqp_consume_callback(args: &mut [ZVal]) -> phper::Result<ZVal> {
+    let Some((mut f, queue)) = CONSUME_CALLBACKS.with(|callbacks| {
+        callbacks
+            .borrow()
+            .last()
+            .map(|callback| (callback.callback.clone(), callback.queue.clone()))
+    }) else {
+        error!("Origin amqp consume callback is missing");
+        return Ok(ZVal::from(()));
+    };
+
+    let sw_header = match args.first_mut().and_then(ZVal::as_mut_z_obj) {
+        Some(envelope) => get_sw_header(envelope).unwrap_or_else(|err| {
+            debug!(?err, "get amqp envelope header failed");
+            None
+        }),
+        None => None,
+    };
+    let operation_name = format!("{}->{}", QUEUE_CLASS_NAME, "consume");
+
+    let create_span = |ctx: &mut TracingContext| {
+        create_entry_span(ctx, &operation_name, sw_header.as_deref(), &queue)
+    };
+
+    let (span, standalone) =
+        match RequestContext::try_with_global_ctx(None, |ctx| Ok(create_span(ctx))) {
+            Ok(span) => (Some(span), false),
+            Err(_) => {
+                let mut tracing_context = tracer::create_trace_context();
+                let entry_span = create_span(&mut tracing_context);
+                RequestContext::set_global(
+                    None,
+                    RequestContext {
+                        tracing_context,
+                        entry_span,
+                    },
+                );
+                (None, true)
+            }
+        };
+
+    let return_value = f.call(&mut *args);
+
+    let finish = |span: &mut Span| {
+        log_exception(span);
+        if return_value.is_err() {
+            span.span_object_mut().is_error = true;
+        }
+    };
+    if let Some(mut span) = span {
+        finish(&mut span);
+    }
+    if standalone {
+        if let Some(RequestContext {
+            tracing_context,
+            mut entry_span,
+        }) = RequestContext::remove_global(None)
+        {
+            finish(&mut entry_span);
+            drop(entry_span);
+            drop(tracing_context);
+        }
+    }
+
+    return_value
+}
+
+/// The invalid `sw8` header is ignored, to not lose the message handling.
+fn create_entry_span(
+    ctx: &mut TracingContext, operation_name: &str, sw_header: Option<&str>, queue: &Queue,
+) -> Span {
+    let propagation = sw_header.and_then(|header| match decode_propagation(header) {
+        Ok(propagation) => Some(propagation),
+        Err(err) => {
+            debug!(?err, "decode propagation failed");
+            None
+        }
+    });
+    let mut span = match &propagation {
+        Some(propagation) => ctx.create_entry_span_with_propagation(operation_name, propagation),
+        None => ctx.create_entry_span(operation_name),
+    };
+
+    let span_object = span.span_object_mut();
+    span_object.set_span_layer(SpanLayer::Mq);
+    span_object.component_id = component_id("amqp_consumer", COMPONENT_AMQP_CONSUMER_ID);
+    span_object.peer = queue.peer.clone();
+    span_object.add_tag(TAG_MQ_BROKER, &queue.peer);
+    span_object.add_tag(TAG_MQ_QUEUE, &queue.name);
+
+    span
+}
+
+fn get_queue(this: &mut ZObj) -> Queue {
+    Queue {
+        name: call_string(this, "getName").unwrap_or_default(),
+        peer: get_peer(this),
+    }
+}
+
+/// Get the `sw8` header of `AMQPEnvelope`.
+fn get_sw_header(envelope: &mut ZObj) -> crate::Result<Option<String>> {
+    let headers = envelope.call("getHeaders", [])?;
+    Ok(headers
+        .as_z_arr()
+        .and_then(|headers| headers.get(SW_HEADER))
+        .and_then(|header| header.as_z_str())
+        .and_then(|header| header.to_str().ok())
+        .map(ToOwned::to_owned))
+}
+
+/// Both `AMQPExchange` and `AMQPQueue` have `getConnection`.
+fn get_peer(this: &mut ZObj) -> String {
+    let Ok(mut connection) = this.call("getConnection", []) else {
+        return "unknown:0".to_owned();
+    };
+    let Some(connection) = connection.as_mut_z_obj() else {
+        return "unknown:0".to_owned();
+    };
+    let host = call_string(connection, "getHost").unwrap_or_else(|_| "unknown".to_owned());
+    let port = connection
+        .call("getPort", [])
+        .ok()
+        .and_then(|port| port.as_long())
+        .unwrap_or_default();
+    join_host_port(&host, port)
+}
+
+fn call_string(obj: &mut ZObj, method: &str) -> crate::Result<String> {
+    Ok(obj.call(method, [])?.expect_z_str()?.to_str()?.to_owned())
+}
+
+/// Inject the header into the `headers` of `$attributes` argument of
+/// `publish`.
+fn inject_sw_header(execute_data: &mut ExecuteData, sw_header: String) {
+    let attributes = execute_data.get_mut_parameter(3);
+    if attributes.get_type_info().is_null() {
+        *attributes = ZVal::from(ZArray::new());
+    }
+    let Some(attributes) = attributes.as_mut_z_arr() else {
+        return;
+    };
+    if attributes.get("headers").and_then(ZVal::as_z_arr).is_none() {
+        attributes.insert("headers", ZArray::new());
+    }
+    if let Some(headers) = attributes.get_mut("headers").and_then(ZVal::as_mut_z_arr) {
+        headers.insert(SW_HEADER, sw_header);
+    }
+}
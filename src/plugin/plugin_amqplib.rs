@@ -15,10 +15,11 @@
 
 use super::{log_exception, Plugin};
 use crate::{
-    component::COMPONENT_AMQP_PRODUCER_ID,
+    component::{component_id, COMPONENT_AMQP_PRODUCER_ID},
     context::{RequestContext, SW_HEADER},
     execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook},
     tag::{TAG_MQ_BROKER, TAG_MQ_QUEUE, TAG_MQ_TOPIC},
+    util::join_host_port,
 };
 use anyhow::Context;
 use phper::{
@@ -124,7 +125,7 @@ impl AmqplibPlugin {
             .and_then(|s| s.to_str().ok())
             .unwrap_or("unknown");
         let port = io.get_property("port").as_long().unwrap_or_default();
-        format!("{}:{}", host, port)
+        join_host_port(&host, port)
     }
 
     fn create_exit_span(
@@ -137,7 +138,7 @@ impl AmqplibPlugin {
 
         let span_object = span.span_object_mut();
         span_object.set_span_layer(SpanLayer::Mq);
-        span_object.component_id = COMPONENT_AMQP_PRODUCER_ID;
+        span_object.component_id = component_id("amqplib", COMPONENT_AMQP_PRODUCER_ID);
         span_object.add_tag(TAG_MQ_BROKER, peer);
         span_object.add_tag(TAG_MQ_TOPIC, exchange);
         span_object.add_tag(TAG_MQ_QUEUE, routing_key);
@@ -15,9 +15,14 @@
 
 use super::{log_exception, Plugin};
 use crate::{
-    component::COMPONENT_PHP_CURL_ID,
-    context::{RequestContext, SW_HEADER},
+    component::{component_id, COMPONENT_PHP_CURL_ID},
+    context::{
+        traceparent_header, RequestContext, SW_CORRELATION_HEADER, SW_HEADER, SW_X_HEADER,
+        W3C_TRACEPARENT_HEADER,
+    },
     execute::{validate_num_args, AfterExecuteHook, BeforeExecuteHook, Noop},
+    tag::sanitize_url,
+    util::z_val_to_string,
 };
 use anyhow::Context;
 use phper::{
@@ -37,6 +42,15 @@ const CURLM_OK: i64 = 0;
 
 const CURLOPT_HTTPHEADER: c_long = 10023;
 
+const CURLOPT_PROXY: c_long = 10004;
+
+const CURLOPT_PROXYPORT: c_long = 59;
+
+const CURLOPT_UNIX_SOCKET_PATH: c_long = 10231;
+
+/// The default port of proxy used by libcurl.
+const DEFAULT_PROXY_PORT: u16 = 1080;
+
 /// Prevent calling `curl_setopt` inside this plugin sets headers, the hook of
 /// `curl_setopt` is repeatedly called.
 const SKY_CURLOPT_HTTPHEADER: c_long = 9923;
@@ -44,6 +58,7 @@ const SKY_CURLOPT_HTTPHEADER: c_long = 9923;
 thread_local! {
     static CURL_HEADERS: RefCell<HashMap<i64, ZVal>> = Default::default();
     static CURL_MULTI_INFO_MAP: RefCell<HashMap<i64, CurlMultiInfo>> = Default::default();
+    static CURL_PEER_OPTIONS: RefCell<HashMap<i64, CurlPeerOptions>> = Default::default();
 }
 
 struct CurlInfo {
@@ -51,9 +66,75 @@ struct CurlInfo {
     raw_url: String,
     url: Url,
     peer: String,
+    proxy: Option<String>,
     is_http: bool,
 }
 
+/// The options of curl handle which change the real network hop.
+#[derive(Default)]
+struct CurlPeerOptions {
+    proxy: Option<String>,
+    proxy_port: Option<u16>,
+    unix_socket_path: Option<String>,
+}
+
+impl CurlPeerOptions {
+    fn set(&mut self, option: c_long, value: &ZVal) {
+        match option {
+            CURLOPT_PROXY => {
+                self.proxy = z_val_to_string(value).filter(|proxy| !proxy.is_empty());
+            }
+            CURLOPT_PROXYPORT => {
+                self.proxy_port = value
+                    .as_long()
+                    .and_then(|port| u16::try_from(port).ok())
+                    .filter(|port| *port != 0);
+            }
+            CURLOPT_UNIX_SOCKET_PATH => {
+                self.unix_socket_path = z_val_to_string(value).filter(|path| !path.is_empty());
+            }
+            _ => {}
+        }
+    }
+
+    /// The real network hop is the unix socket or the proxy rather than the
+    /// target host, return the peer and the proxy.
+    fn resolve_peer(&self, target_peer: String) -> (String, Option<String>) {
+        let peer = self
+            .unix_socket_path
+            .clone()
+            .or_else(|| self.proxy_peer())
+            .unwrap_or(target_peer);
+        (peer, self.proxy.clone())
+    }
+
+    /// Get the peer of proxy, like `host:port`.
+    fn proxy_peer(&self) -> Option<String> {
+        let proxy = self.proxy.as_deref()?;
+        let url = if proxy.contains("://") {
+            proxy.parse::<Url>()
+        } else {
+            format!("http://{}", proxy).parse::<Url>()
+        };
+        let url = match url {
+            Ok(url) => url,
+            Err(err) => {
+                warn!(?err, proxy, "parse curl proxy failed");
+                return None;
+            }
+        };
+        let host = url.host_str()?;
+        let port = url
+            .port()
+            .or(self.proxy_port)
+            .unwrap_or(match url.scheme() {
+                "https" => 443,
+                _ => DEFAULT_PROXY_PORT,
+            });
+        Some(format!("{host}:{port}"))
+    }
+}
+
 #[derive(Default)]
 struct CurlMultiInfo {
     exec_spans: Option<Vec<(i64, AsyncSpan)>>,
@@ -112,14 +193,30 @@ impl CurlPlugin {
                 let cid = Self::get_resource_id(execute_data)?;
                 let options = execute_data.get_parameter(1).as_long();
 
-                if options == Some(SKY_CURLOPT_HTTPHEADER) {
-                    *execute_data.get_mut_parameter(1) = CURLOPT_HTTPHEADER.into();
-                } else if options == Some(CURLOPT_HTTPHEADER) {
-                    let value = execute_data.get_parameter(2);
-                    if value.get_type_info().is_array() {
-                        CURL_HEADERS
-                            .with(|headers| headers.borrow_mut().insert(cid, value.clone()));
+                match options {
+                    Some(SKY_CURLOPT_HTTPHEADER) => {
+                        *execute_data.get_mut_parameter(1) = CURLOPT_HTTPHEADER.into();
+                    }
+                    Some(CURLOPT_HTTPHEADER) => {
+                        let value = execute_data.get_parameter(2);
+                        if value.get_type_info().is_array() {
+                            CURL_HEADERS
+                                .with(|headers| headers.borrow_mut().insert(cid, value.clone()));
+                        }
+                    }
+                    Some(
+                        option @ (CURLOPT_PROXY | CURLOPT_PROXYPORT | CURLOPT_UNIX_SOCKET_PATH),
+                    ) => {
+                        let value = execute_data.get_parameter(2);
+                        CURL_PEER_OPTIONS.with(|options| {
+                            options
+                                .borrow_mut()
+                                .entry(cid)
+                                .or_default()
+                                .set(option, value)
+                        });
                     }
+                    _ => {}
                 }
 
                 Ok(Box::new(()))
@@ -140,6 +237,17 @@ impl CurlPlugin {
                         CURL_HEADERS
                             .with(|headers| headers.borrow_mut().insert(cid, value.clone()));
                     }
+                    for option in [CURLOPT_PROXY, CURLOPT_PROXYPORT, CURLOPT_UNIX_SOCKET_PATH] {
+                        if let Some(value) = opts.get(option as u64) {
+                            CURL_PEER_OPTIONS.with(|options| {
+                                options
+                                    .borrow_mut()
+                                    .entry(cid)
+                                    .or_default()
+                                    .set(option, value)
+                            });
+                        }
+                    }
                 }
 
                 Ok(Box::new(()))
@@ -185,6 +293,7 @@ impl CurlPlugin {
                 let cid = Self::get_resource_id(execute_data)?;
 
                 CURL_HEADERS.with(|headers| headers.borrow_mut().remove(&cid));
+                CURL_PEER_OPTIONS.with(|options| options.borrow_mut().remove(&cid));
 
                 Ok(Box::new(()))
             }),
@@ -392,17 +501,28 @@ impl CurlPlugin {
         };
         let peer = format!("{host}:{port}");
 
+        let (peer, proxy) = CURL_PEER_OPTIONS.with(|options| match options.borrow().get(&cid) {
+            Some(options) => options.resolve_peer(peer),
+            None => (peer, None),
+        });
+
+        debug!(%peer, "curl peer resolved");
+
         Ok(CurlInfo {
             cid,
             raw_url: raw_url.to_string(),
             url,
             peer,
+            proxy,
             is_http,
         })
     }
 
     fn inject_sw_header(request_id: Option<i64>, ch: ZVal, info: &CurlInfo) -> crate::Result<()> {
         let sw_header = RequestContext::try_get_sw_header(request_id, &info.peer)?;
+        let sw_x_header = RequestContext::try_get_sw_x_header(request_id)?;
+        let sw_correlation_header = RequestContext::try_get_sw_correlation_header(request_id)?;
+        let traceparent = traceparent_header(&sw_header);
         let mut val = CURL_HEADERS
             .with(|headers| headers.borrow_mut().remove(&info.cid))
             .unwrap_or_else(|| ZVal::from(ZArray::new()));
@@ -411,6 +531,27 @@ impl CurlPlugin {
                 InsertKey::NextIndex,
                 ZVal::from(format!("{}: {}", SW_HEADER, sw_header)),
             );
+            if let Some(traceparent) = traceparent {
+                arr.insert(
+                    InsertKey::NextIndex,
+                    ZVal::from(format!("{}: {}", W3C_TRACEPARENT_HEADER, traceparent)),
+                );
+            }
+            if let Some(sw_x_header) = sw_x_header {
+                arr.insert(
+                    InsertKey::NextIndex,
+                    ZVal::from(format!("{}: {}", SW_X_HEADER, sw_x_header)),
+                );
+            }
+            if let Some(sw_correlation_header) = sw_correlation_header {
+                arr.insert(
+                    InsertKey::NextIndex,
+                    ZVal::from(format!(
+                        "{}: {}",
+                        SW_CORRELATION_HEADER, sw_correlation_header
+                    )),
+                );
+            }
             call(
                 "curl_setopt",
                 &mut [ch, ZVal::from(SKY_CURLOPT_HTTPHEADER), val],
@@ -426,8 +567,11 @@ impl CurlPlugin {
 
         let span_object = span.span_object_mut();
         span_object.set_span_layer(SpanLayer::Http);
-        span_object.component_id = COMPONENT_PHP_CURL_ID;
-        span_object.add_tag("url", &info.raw_url);
+        span_object.component_id = component_id("curl", COMPONENT_PHP_CURL_ID);
+        span_object.add_tag("url", sanitize_url(&info.raw_url));
+        if let Some(proxy) = &info.proxy {
+            span_object.add_tag("http.proxy", proxy);
+        }
 
         Ok(span)
     }
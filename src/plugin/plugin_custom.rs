@@ -0,0 +1,254 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generic plugin of the exit spans configured by
+//! `skywalking_agent.custom_exit_span_rules`, for the clients without the
+//! dedicated plugin, like Solr, Sphinx and the in-house SDKs.
+//!
+//! The rule is `<class>::<method>|<component id>|<peer>[|<operation>]`, the
+//! peer and operation are the expressions like `$this.options.host` and
+//! `$0.handler`, which start from `$this` or the argument index, and read the
+//! object properties or array keys by the path.
+//!
+//! The classes hooked by the other plugins are ignored.
+
+use super::{log_exception, Plugin};
+use crate::{
+    context::RequestContext,
+    execute::{AfterExecuteHook, BeforeExecuteHook},
+    module::CUSTOM_EXIT_SPAN_RULES,
+};
+use anyhow::{anyhow, bail, Context};
+use once_cell::sync::Lazy;
+use phper::values::{ExecuteData, ZVal};
+use skywalking::trace::span::{HandleSpanObject, Span};
+use tracing::{debug, warn};
+
+const UNKNOWN_PEER: &str = "unknown:0";
+
+static RULES: Lazy<Vec<Rule>> = Lazy::new(|| {
+    CUSTOM_EXIT_SPAN_RULES
+        .split(';')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .filter_map(|rule| match Rule::parse(rule) {
+            Ok(rule) => {
+                debug!(?rule, "custom exit span rule loaded");
+                Some(rule)
+            }
+            Err(err) => {
+                warn!(rule, ?err, "invalid custom exit span rule, ignored");
+                None
+            }
+        })
+        .collect()
+});
+
+static CLASS_NAMES: Lazy<Vec<&'static str>> = Lazy::new(|| {
+    let mut class_names = RULES
+        .iter()
+        .map(|rule| rule.class_name.as_str())
+        .collect::<Vec<_>>();
+    class_names.sort_unstable();
+    class_names.dedup();
+    class_names
+});
+
+#[derive(Debug)]
+struct Rule {
+    class_name: String,
+    method_name: String,
+    component_id: i32,
+    peer: Expression,
+    operation: Option<Expression>,
+}
+
+impl Rule {
+    fn parse(rule: &str) -> anyhow::Result<Self> {
+        let mut fields = rule.split('|').map(str::trim);
+
+        let (class_name, method_name) = fields
+            .next()
+            .and_then(|pattern| pattern.split_once("::"))
+            .context("pattern should be `<class>::<method>`")?;
+        let class_name = class_name.trim_start_matches('\\');
+        if class_name.is_empty() || method_name.is_empty() {
+            bail!("class or method is empty");
+        }
+
+        let component_id = fields
+            .next()
+            .context("component id is missing")?
+            .parse::<i32>()
+            .ok()
+            .filter(|id| *id > 0)
+            .context("component id should be positive integer")?;
+
+        let peer = Expression::parse(fields.next().context("peer is missing")?)?;
+        let operation = fields.next().map(Expression::parse).transpose()?;
+
+        if fields.next().is_some() {
+            bail!("too many fields");
+        }
+
+        Ok(Self {
+            class_name: class_name.to_owned(),
+            method_name: method_name.to_owned(),
+            component_id,
+            peer,
+            operation,
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Source {
+    This,
+    Argument(usize),
+}
+
+/// The minimal expression, `$this` or `$<argument index>` followed by the
+/// property names or array keys separated by dot.
+#[derive(Debug)]
+struct Expression {
+    source: Source,
+    path: Vec<String>,
+}
+
+impl Expression {
+    fn parse(expr: &str) -> anyhow::Result<Self> {
+        let mut segments = expr.split('.');
+
+        let source = match segments.next().and_then(|s| s.strip_prefix('$')) {
+            Some("this") => Source::This,
+            Some(index) => Source::Argument(
+                index
+                    .parse()
+                    .map_err(|_| anyhow!("invalid source `{}`", expr))?,
+            ),
+            None => bail!("expression `{}` should start with `$`", expr),
+        };
+
+        let path = segments.map(ToOwned::to_owned).collect::<Vec<_>>();
+        if path.iter().any(String::is_empty) {
+            bail!("expression `{}` has empty segment", expr);
+        }
+        if matches!(source, Source::This) && path.is_empty() {
+            bail!("expression `{}` can't be `$this` only", expr);
+        }
+
+        Ok(Self { source, path })
+    }
+
+    fn evaluate(&self, execute_data: &mut ExecuteData) -> Option<String> {
+        let (mut value, path) = match self.source {
+            Source::This => {
+                let (first, rest) = self.path.split_first()?;
+                let this = execute_data.get_this_mut()?;
+                (&*this.get_mut_property(first), rest)
+            }
+            Source::Argument(index) => {
+                if execute_data.num_args() <= index {
+                    return None;
+                }
+                (execute_data.get_parameter(index), &*self.path)
+            }
+        };
+
+        for name in path {
+            value = get_field(value, name)?;
+        }
+
+        if let Some(s) = value.as_z_str() {
+            s.to_str().ok().map(ToOwned::to_owned)
+        } else {
+            value.as_long().map(|n| n.to_string())
+        }
+    }
+}
+
+fn get_field<'a>(value: &'a ZVal, name: &str) -> Option<&'a ZVal> {
+    if let Some(obj) = value.as_z_obj() {
+        Some(obj.get_property(name))
+    } else if let Some(arr) = value.as_z_arr() {
+        match name.parse::<u64>() {
+            Ok(index) => arr.get(index),
+            Err(_) => arr.get(name),
+        }
+    } else {
+        None
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct CustomExitSpanPlugin;
+
+impl Plugin for CustomExitSpanPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        if CLASS_NAMES.is_empty() {
+            None
+        } else {
+            Some(CLASS_NAMES.as_slice())
+        }
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        let class_name = class_name?;
+        let rule = RULES.iter().find(|rule| {
+            rule.class_name == class_name && rule.method_name.eq_ignore_ascii_case(function_name)
+        })?;
+        Some(self.hook_rule(rule))
+    }
+}
+
+impl CustomExitSpanPlugin {
+    fn hook_rule(&self, rule: &'static Rule) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let default_operation_name = format!("{}->{}", rule.class_name, rule.method_name);
+
+        (
+            Box::new(move |request_id, execute_data| {
+                let peer = rule
+                    .peer
+                    .evaluate(execute_data)
+                    .unwrap_or_else(|| UNKNOWN_PEER.to_owned());
+                let operation_name = rule
+                    .operation
+                    .as_ref()
+                    .and_then(|operation| operation.evaluate(execute_data))
+                    .unwrap_or_else(|| default_operation_name.clone());
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(&operation_name, &peer))
+                })?;
+                span.span_object_mut().component_id = rule.component_id;
+
+                Ok(Box::new(span))
+            }),
+            Box::new(move |_, span, _, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+}
@@ -0,0 +1,223 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin of [elasticsearch-php](https://github.com/elastic/elasticsearch-php).
+//!
+//! The client retries the request internally, so the whole request including
+//! retries is traced as a single logical span. The retry count is tagged for
+//! 7.x only, the `retries` of 8.x is the configured max retries, and the
+//! attempts made aren't exposed.
+//!
+//! There is no span per physical attempt. The attempts of 7.x are the futures
+//! of the ring handler, resolved and retried outside the hooked calls, and
+//! the attempts of 8.x are sent by the PSR-18 client chosen by user, so
+//! neither can be timed by hooking a known method.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_ELASTICSEARCH_ID},
+    context::RequestContext,
+    execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook},
+    module::ES_REPORT_BODY,
+    tag::{TAG_DB_STATEMENT, TAG_DB_TYPE, TAG_ES_RETRIES},
+    util::join_host_port,
+};
+use anyhow::Context;
+use phper::{functions::call, objects::ZObj, values::ZVal};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+use tracing::debug;
+use url::Url;
+
+/// The client of elasticsearch-php 7.x.
+const CLIENT_V7_CLASS_NAME: &str = r"Elasticsearch\Client";
+
+/// The transport of elasticsearch-php 8.x.
+const TRANSPORT_V8_CLASS_NAME: &str = r"Elastic\Transport\Transport";
+
+#[derive(Default, Clone)]
+pub struct ElasticsearchPlugin;
+
+impl Plugin for ElasticsearchPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(&[CLIENT_V7_CLASS_NAME, TRANSPORT_V8_CLASS_NAME])
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (Some(CLIENT_V7_CLASS_NAME), "performRequest") => Some(self.hook_v7_perform_request()),
+            (Some(TRANSPORT_V8_CLASS_NAME), "sendRequest") => Some(self.hook_v8_send_request()),
+            _ => None,
+        }
+    }
+}
+
+impl ElasticsearchPlugin {
+    /// `Elasticsearch\Client::performRequest(AbstractEndpoint $endpoint)`.
+    fn hook_v7_perform_request(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 1)?;
+
+                let endpoint = execute_data
+                    .get_mut_parameter(0)
+                    .as_mut_z_obj()
+                    .context("endpoint isn't object")?;
+
+                let method = call_string(endpoint, "getMethod")?;
+                let uri = call_string(endpoint, "getURI")?;
+
+                let mut span = create_exit_span(request_id, &method, &uri)?;
+
+                if *ES_REPORT_BODY {
+                    let body = endpoint.call("getBody", [])?;
+                    if !body.get_type_info().is_null() {
+                        let body = if body.get_type_info().is_string() {
+                            body
+                        } else {
+                            call("json_encode", [body])?
+                        };
+                        if let Some(body) = body.as_z_str().and_then(|s| s.to_str().ok()) {
+                            span.add_tag(TAG_DB_STATEMENT, body);
+                        }
+                    }
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(|_, span, execute_data, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+
+                let transport = get_this_mut(execute_data)?
+                    .get_mut_property("transport")
+                    .as_mut_z_obj()
+                    .context("transport isn't object")?;
+
+                if let Some(retries) = transport.get_property("retryAttempts").as_long() {
+                    span.add_tag(TAG_ES_RETRIES, retries.to_string());
+                }
+
+                if let Some(connection) =
+                    transport.get_mut_property("lastConnection").as_mut_z_obj()
+                {
+                    match call_string(connection, "getHost") {
+                        Ok(host) => span.span_object_mut().peer = get_peer(&host),
+                        Err(err) => debug!(?err, "get elasticsearch host failed"),
+                    }
+                }
+
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+
+    /// `Elastic\Transport\Transport::sendRequest(RequestInterface $request)`.
+    fn hook_v8_send_request(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 1)?;
+
+                let request = execute_data
+                    .get_mut_parameter(0)
+                    .as_mut_z_obj()
+                    .context("request isn't object")?;
+
+                let method = call_string(request, "getMethod")?;
+                let mut uri = request.call("getUri", [])?;
+                let path = call_string(uri.expect_mut_z_obj()?, "getPath")?;
+
+                let mut span = create_exit_span(request_id, &method, &path)?;
+
+                if *ES_REPORT_BODY {
+                    let mut body = request.call("getBody", [])?;
+                    let body = call_string(body.expect_mut_z_obj()?, "__toString")?;
+                    if !body.is_empty() {
+                        span.add_tag(TAG_DB_STATEMENT, body);
+                    }
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(|_, span, execute_data, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+
+                let this = get_this_mut(execute_data)?;
+
+                let mut request = this.call("getLastRequest", [])?;
+                if let Some(request) = request.as_mut_z_obj() {
+                    let mut uri = request.call("getUri", [])?;
+                    let uri = uri.expect_mut_z_obj()?;
+                    let host = call_string(uri, "getHost")?;
+                    let port = uri.call("getPort", [])?.as_long();
+                    let port =
+                        port.unwrap_or_else(|| match call_string(uri, "getScheme").as_deref() {
+                            Ok("https") => 443,
+                            _ => 9200,
+                        });
+                    span.span_object_mut().peer = join_host_port(&host, port);
+                }
+
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+}
+
+fn call_string(obj: &mut ZObj, method: &str) -> crate::Result<String> {
+    Ok(obj.call(method, [])?.expect_z_str()?.to_str()?.to_owned())
+}
+
+/// The host of connection is like `http://127.0.0.1:9200`.
+fn get_peer(host: &str) -> String {
+    let url = if host.contains("://") {
+        Url::parse(host)
+    } else {
+        Url::parse(&format!("http://{}", host))
+    };
+    match url {
+        Ok(url) => format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port().unwrap_or(9200)
+        ),
+        Err(_) => host.to_owned(),
+    }
+}
+
+fn create_exit_span(request_id: Option<i64>, method: &str, path: &str) -> crate::Result<Span> {
+    let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+        // The node is selected inside the client, so peer is reset in after hook.
+        Ok(ctx.create_exit_span(&format!("{} {}", method, path), ""))
+    })?;
+
+    let span_object = span.span_object_mut();
+    span_object.set_span_layer(SpanLayer::Database);
+    span_object.component_id = component_id("elasticsearch", COMPONENT_ELASTICSEARCH_ID);
+    span_object.add_tag(TAG_DB_TYPE, "elasticsearch");
+
+    Ok(span)
+}
@@ -0,0 +1,266 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin for the client of [grpc-php](https://github.com/grpc/grpc/tree/master/src/php).
+//!
+//! The call is started by `Grpc\BaseStub::_simpleRequest` and the other
+//! request methods, and completed when the status is received, so the span is
+//! held by the call object until `wait`, `getStatus` or `cancel` is called.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_GRPC_ID},
+    context::{RequestContext, SW_CORRELATION_HEADER, SW_HEADER},
+    execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook},
+};
+use anyhow::Context;
+use phper::{
+    arrays::{InsertKey, ZArray},
+    objects::ZObj,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+use std::{cell::RefCell, collections::HashMap};
+use tracing::debug;
+
+const BASE_STUB_CLASS_NAME: &str = "Grpc\\BaseStub";
+const ABSTRACT_CALL_CLASS_NAME: &str = "Grpc\\AbstractCall";
+const UNARY_CALL_CLASS_NAME: &str = "Grpc\\UnaryCall";
+const CLIENT_STREAMING_CALL_CLASS_NAME: &str = "Grpc\\ClientStreamingCall";
+const SERVER_STREAMING_CALL_CLASS_NAME: &str = "Grpc\\ServerStreamingCall";
+const BIDI_STREAMING_CALL_CLASS_NAME: &str = "Grpc\\BidiStreamingCall";
+
+const TAG_RPC_STATUS_CODE: &str = "rpc.status_code";
+
+/// `Grpc\STATUS_OK`.
+const STATUS_OK: i64 = 0;
+
+thread_local! {
+    /// The spans of the started calls, keyed by the handle of call object.
+    static GRPC_CALL_SPANS: RefCell<HashMap<u32, Span>> = Default::default();
+}
+
+#[derive(Default, Clone)]
+pub struct GrpcPlugin;
+
+impl Plugin for GrpcPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(&[
+            BASE_STUB_CLASS_NAME,
+            ABSTRACT_CALL_CLASS_NAME,
+            UNARY_CALL_CLASS_NAME,
+            CLIENT_STREAMING_CALL_CLASS_NAME,
+            SERVER_STREAMING_CALL_CLASS_NAME,
+            BIDI_STREAMING_CALL_CLASS_NAME,
+        ])
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (Some(BASE_STUB_CLASS_NAME), "_simpleRequest" | "_serverStreamRequest") => {
+                Some(self.hook_base_stub_request(3))
+            }
+            (Some(BASE_STUB_CLASS_NAME), "_clientStreamRequest" | "_bidiRequest") => {
+                Some(self.hook_base_stub_request(2))
+            }
+            (Some(UNARY_CALL_CLASS_NAME | CLIENT_STREAMING_CALL_CLASS_NAME), "wait") => {
+                Some(self.hook_call_finish(true))
+            }
+            (
+                Some(SERVER_STREAMING_CALL_CLASS_NAME | BIDI_STREAMING_CALL_CLASS_NAME),
+                "getStatus",
+            ) => Some(self.hook_call_finish(false)),
+            (Some(ABSTRACT_CALL_CLASS_NAME), "cancel") => Some(self.hook_call_cancel()),
+            _ => None,
+        }
+    }
+}
+
+impl GrpcPlugin {
+    /// The `metadata_index` is the index of parameter `$metadata`.
+    fn hook_base_stub_request(
+        &self, metadata_index: usize,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(move |request_id, execute_data| {
+                validate_num_args(execute_data, 1)?;
+
+                let method = execute_data
+                    .get_parameter(0)
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .context("grpc method isn't string")?
+                    .to_owned();
+
+                let this = get_this_mut(execute_data)?;
+                let peer = Self::get_peer(this);
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(
+                        &format!("grpc/{}", method.trim_start_matches('/')),
+                        &peer,
+                    ))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.set_span_layer(SpanLayer::RpcFramework);
+                span_object.component_id = component_id("grpc", COMPONENT_GRPC_ID);
+                span_object.add_tag("url", format!("{}{}", peer, method));
+
+                Self::inject_sw_header(request_id, execute_data, metadata_index, &peer)?;
+
+                Ok(Box::new(span))
+            }),
+            Box::new(move |_, span, _, return_value| {
+                let mut span = span.downcast::<Span>().unwrap();
+
+                if log_exception(&mut *span).is_some() {
+                    return Ok(());
+                }
+
+                let Some(call) = return_value.as_z_obj() else {
+                    return Ok(());
+                };
+                let handle = call.handle();
+                debug!(handle, "grpc call started");
+
+                GRPC_CALL_SPANS.with(|spans| spans.borrow_mut().insert(handle, *span));
+
+                Ok(())
+            }),
+        )
+    }
+
+    /// Finish the span when the status received, the `wait` returns
+    /// `[$response, $status]`, and the `getStatus` returns `$status`.
+    ///
+    /// The deadline exceeded is also returned as the status, so the span is
+    /// closed on timeout.
+    fn hook_call_finish(&self, is_wait: bool) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, _| Ok(Box::new(()))),
+            Box::new(move |_, _, execute_data, return_value| {
+                let Some(mut span) = Self::remove_call_span(execute_data)? else {
+                    return Ok(());
+                };
+
+                if log_exception(&mut span).is_some() {
+                    return Ok(());
+                }
+
+                let status = if is_wait {
+                    return_value.as_z_arr().and_then(|result| result.get(1))
+                } else {
+                    Some(&*return_value)
+                };
+                let Some(status) = status.and_then(ZVal::as_z_obj) else {
+                    return Ok(());
+                };
+
+                let code = status.get_property("code").as_long().unwrap_or(STATUS_OK);
+                span.add_tag(TAG_RPC_STATUS_CODE, code.to_string());
+                if code != STATUS_OK {
+                    let span_object = span.span_object_mut();
+                    span_object.is_error = true;
+                    if let Some(details) = status
+                        .get_property("details")
+                        .as_z_str()
+                        .and_then(|s| s.to_str().ok())
+                    {
+                        span_object.add_log(vec![("GRPC_ERROR", details)]);
+                    }
+                }
+
+                Ok(())
+            }),
+        )
+    }
+
+    fn hook_call_cancel(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, _| Ok(Box::new(()))),
+            Box::new(|_, _, execute_data, _| {
+                let Some(mut span) = Self::remove_call_span(execute_data)? else {
+                    return Ok(());
+                };
+
+                let span_object = span.span_object_mut();
+                span_object.is_error = true;
+                span_object.add_log(vec![("GRPC_ERROR", "call cancelled")]);
+
+                Ok(())
+            }),
+        )
+    }
+
+    fn remove_call_span(execute_data: &mut ExecuteData) -> crate::Result<Option<Span>> {
+        let handle = get_this_mut(execute_data)?.handle();
+        Ok(GRPC_CALL_SPANS.with(|spans| spans.borrow_mut().remove(&handle)))
+    }
+
+    /// The target of channel, like `localhost:50051`.
+    fn get_peer(this: &mut ZObj) -> String {
+        this.call("getTarget", [])
+            .ok()
+            .and_then(|target| {
+                target
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .map(ToOwned::to_owned)
+            })
+            .unwrap_or_else(|| "unknown:0".to_owned())
+    }
+
+    /// The metadata value is array of strings, like `['sw8' => ['...']]`, the
+    /// `sw8-correlation` is injected if the correlation context isn't empty.
+    fn inject_sw_header(
+        request_id: Option<i64>, execute_data: &mut ExecuteData, metadata_index: usize, peer: &str,
+    ) -> crate::Result<()> {
+        if execute_data.num_args() <= metadata_index {
+            debug!("grpc metadata isn't passed, skip injecting sw header");
+            return Ok(());
+        }
+
+        let sw_header = RequestContext::try_get_sw_header(request_id, peer)?;
+        let sw_correlation_header = RequestContext::try_get_sw_correlation_header(request_id)?;
+
+        let metadata = execute_data
+            .get_mut_parameter(metadata_index)
+            .as_mut_z_arr()
+            .context("grpc metadata isn't array")?;
+
+        let mut values = ZArray::new();
+        values.insert(InsertKey::NextIndex, ZVal::from(sw_header));
+        metadata.insert(SW_HEADER, values);
+        if let Some(sw_correlation_header) = sw_correlation_header {
+            let mut values = ZArray::new();
+            values.insert(InsertKey::NextIndex, ZVal::from(sw_correlation_header));
+            metadata.insert(SW_CORRELATION_HEADER, values);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin for the http(s) stream wrapper, like
+//! `file_get_contents('http://...')` and `fopen('http://...')`.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_PHP_ID},
+    context::{
+        decode_service_header, traceparent_header, RequestContext, SW_CORRELATION_HEADER,
+        SW_HEADER, SW_SERVICE_HEADER, SW_X_HEADER, W3C_TRACEPARENT_HEADER,
+    },
+    execute::{AfterExecuteHook, BeforeExecuteHook},
+    peer::tag_peer_service,
+    tag::sanitize_url,
+    util::z_val_to_string,
+};
+use phper::{
+    arrays::{InsertKey, ZArr, ZArray},
+    functions::call,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+use tracing::debug;
+use url::Url;
+
+/// The stream context option wrapper, used by both http and https.
+const HTTP_WRAPPER: &str = "http";
+
+const FUNCTION_NAMES: &[&str] = &["file_get_contents", "fopen"];
+
+#[derive(Default, Clone)]
+pub struct HttpStreamPlugin;
+
+impl Plugin for HttpStreamPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    #[inline]
+    fn function_names(&self) -> Option<&'static [&'static str]> {
+        Some(FUNCTION_NAMES)
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (None, "file_get_contents") => Some(self.hook_open(2, false)),
+            (None, "fopen") => Some(self.hook_open(3, true)),
+            _ => None,
+        }
+    }
+}
+
+/// The span and the stream context modified, the header of context should be
+/// restored after called.
+struct StreamSpan {
+    span: Span,
+    context: ZVal,
+    origin_header: Option<ZVal>,
+}
+
+impl HttpStreamPlugin {
+    /// The `context_index` is the index of parameter `$context`, and the
+    /// `is_stream` means the return value is stream resource.
+    fn hook_open(
+        &self, context_index: usize, is_stream: bool,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(move |request_id, execute_data| {
+                let Some(url) = get_http_url(execute_data) else {
+                    return Ok(Box::new(()));
+                };
+
+                let context = get_stream_context(execute_data, context_index)?;
+                let options = call("stream_context_get_options", [context.clone()])?;
+                let http_options = options
+                    .as_z_arr()
+                    .and_then(|options| options.get(HTTP_WRAPPER))
+                    .and_then(ZVal::as_z_arr);
+                let origin_header = http_options
+                    .and_then(|options| options.get("header"))
+                    .cloned();
+                let method = http_options
+                    .and_then(|options| options.get("method"))
+                    .and_then(z_val_to_string)
+                    .unwrap_or_else(|| "GET".to_owned());
+
+                let host = url.host_str().unwrap_or_default();
+                let port = url.port_or_known_default().unwrap_or_default();
+                let peer = format!("{host}:{port}");
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(url.path(), &peer))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.set_span_layer(SpanLayer::Http);
+                span_object.component_id = component_id("http_stream", COMPONENT_PHP_ID);
+                span_object.add_tag("url", sanitize_url(url.as_str()));
+                span_object.add_tag("http.method", method);
+
+                let sw_header = RequestContext::try_get_sw_header(request_id, &peer)?;
+                let mut line = format!("{}: {}", SW_HEADER, sw_header);
+                if let Some(traceparent) = traceparent_header(&sw_header) {
+                    line.push_str(&format!("\r\n{}: {}", W3C_TRACEPARENT_HEADER, traceparent));
+                }
+                if let Some(sw_x_header) = RequestContext::try_get_sw_x_header(request_id)? {
+                    line.push_str(&format!("\r\n{}: {}", SW_X_HEADER, sw_x_header));
+                }
+                if let Some(sw_correlation_header) =
+                    RequestContext::try_get_sw_correlation_header(request_id)?
+                {
+                    line.push_str(&format!(
+                        "\r\n{}: {}",
+                        SW_CORRELATION_HEADER, sw_correlation_header
+                    ));
+                }
+                let header = append_header(origin_header.as_ref(), line);
+                set_context_header(&context, header)?;
+
+                Ok(Box::new(StreamSpan {
+                    span,
+                    context,
+                    origin_header,
+                }))
+            }),
+            Box::new(move |_, stream_span, _, return_value| {
+                let Ok(stream_span) = stream_span.downcast::<StreamSpan>() else {
+                    return Ok(());
+                };
+                let StreamSpan {
+                    mut span,
+                    context,
+                    origin_header,
+                } = *stream_span;
+
+                set_context_header(
+                    &context,
+                    origin_header.unwrap_or_else(|| ZArray::new().into()),
+                )?;
+
+                let response_headers = if is_stream {
+                    get_stream_response_headers(return_value)
+                } else {
+                    get_last_response_headers()
+                };
+                let response_headers = response_headers.as_ref().and_then(ZVal::as_z_arr);
+                if let Some(status_code) = response_headers.and_then(parse_status_code) {
+                    span.add_tag("status_code", status_code.to_string());
+                    span.span_object_mut().is_error = status_code >= 400;
+                }
+                if let Some((service, service_instance)) =
+                    response_headers.and_then(parse_service_header)
+                {
+                    tag_peer_service(span.span_object_mut(), service, service_instance);
+                }
+
+                if return_value.as_bool() == Some(false) {
+                    let span_object = span.span_object_mut();
+                    span_object.is_error = true;
+                    if let Some(message) = get_last_error_message() {
+                        span_object.add_log(vec![("HTTP_STREAM_ERROR", message)]);
+                    }
+                }
+
+                log_exception(&mut span);
+
+                Ok(())
+            }),
+        )
+    }
+}
+
+/// Only the http(s) url is traced, the local files and other wrappers are
+/// ignored.
+fn get_http_url(execute_data: &mut ExecuteData) -> Option<Url> {
+    if execute_data.num_args() < 1 {
+        return None;
+    }
+    let path = execute_data.get_parameter(0).as_z_str()?.to_bytes();
+    let is_http = [&b"http://"[..], &b"https://"[..]].iter().any(|prefix| {
+        path.len() >= prefix.len() && path[..prefix.len()].eq_ignore_ascii_case(prefix)
+    });
+    if !is_http {
+        return None;
+    }
+    let url = std::str::from_utf8(path).ok()?.parse::<Url>().ok()?;
+    debug!(%url, "http stream url");
+    Some(url)
+}
+
+/// Get the context passed by user, fallback to the default context.
+fn get_stream_context(execute_data: &mut ExecuteData, context_index: usize) -> crate::Result<ZVal> {
+    if execute_data.num_args() > context_index {
+        let context = execute_data.get_parameter(context_index);
+        if context.as_z_res().is_some() {
+            return Ok(context.clone());
+        }
+    }
+    Ok(call("stream_context_get_default", [])?)
+}
+
+fn set_context_header(context: &ZVal, header: ZVal) -> crate::Result<()> {
+    call(
+        "stream_context_set_option",
+        [
+            context.clone(),
+            ZVal::from(HTTP_WRAPPER),
+            ZVal::from("header"),
+            header,
+        ],
+    )?;
+    Ok(())
+}
+
+/// The header option can be string separated by `\r\n` or array.
+fn append_header(origin_header: Option<&ZVal>, line: String) -> ZVal {
+    if let Some(header) = origin_header {
+        if let Some(headers) = header.as_z_arr() {
+            let mut headers = headers.to_owned();
+            headers.insert(InsertKey::NextIndex, ZVal::from(line));
+            return headers.into();
+        }
+        if let Some(header) = z_val_to_string(header) {
+            let header = header.trim_end_matches("\r\n");
+            if !header.is_empty() {
+                return ZVal::from(format!("{}\r\n{}", header, line));
+            }
+        }
+    }
+    let mut headers = ZArray::new();
+    headers.insert(InsertKey::NextIndex, ZVal::from(line));
+    headers.into()
+}
+
+/// The response headers of stream are in the `wrapper_data` of
+/// `stream_get_meta_data`.
+fn get_stream_response_headers(stream: &ZVal) -> Option<ZVal> {
+    stream.as_z_res()?;
+    let meta_data = call("stream_get_meta_data", [stream.clone()]).ok()?;
+    meta_data.as_z_arr()?.get("wrapper_data").cloned()
+}
+
+/// The `$http_response_header` is set in the scope of caller, so only get it
+/// by `http_get_last_response_headers` (since PHP 8.4).
+fn get_last_response_headers() -> Option<ZVal> {
+    let exists = call(
+        "function_exists",
+        [ZVal::from("http_get_last_response_headers")],
+    )
+    .ok()
+    .and_then(|exists| exists.as_bool())
+    .unwrap_or_default();
+    if !exists {
+        return None;
+    }
+    call("http_get_last_response_headers", []).ok()
+}
+
+/// Get the status code from the last status line, because of the redirects.
+fn parse_status_code(headers: &ZArr) -> Option<i64> {
+    headers
+        .iter()
+        .filter_map(|(_, header)| z_val_to_string(header))
+        .filter(|header| header.starts_with("HTTP/"))
+        .last()
+        .and_then(|status_line| {
+            status_line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|code| code.parse().ok())
+        })
+}
+
+/// The `sw8-service` header of the last response, responded by the traced
+/// downstream service.
+fn parse_service_header(headers: &ZArr) -> Option<(String, String)> {
+    headers
+        .iter()
+        .filter_map(|(_, header)| z_val_to_string(header))
+        .filter_map(|header| {
+            let (name, value) = header.split_once(':')?;
+            name.trim()
+                .eq_ignore_ascii_case(SW_SERVICE_HEADER)
+                .then(|| value.to_owned())
+        })
+        .last()
+        .and_then(|value| decode_service_header(&value))
+}
+
+fn get_last_error_message() -> Option<String> {
+    let error = call("error_get_last", []).ok()?;
+    error.as_z_arr()?.get("message").and_then(z_val_to_string)
+}
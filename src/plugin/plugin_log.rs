@@ -0,0 +1,149 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugins collecting the logs of `error_log()` and Monolog, enabled by
+//! `skywalking_agent.report_log`, see [`crate::log`]. No span is created.
+//!
+//! `Logger::addRecord` is hooked instead of the handlers, so the records are
+//! reported once whatever the handlers are. The `error_log()` called inside
+//! it, like by the `ErrorLogHandler`, isn't reported again.
+
+use super::Plugin;
+use crate::{
+    execute::{validate_num_args, AfterExecuteHook, BeforeExecuteHook},
+    log::{report_log, reported_level_name},
+    module::REPORT_LOG,
+    util::z_val_to_string,
+};
+use phper::values::ZVal;
+use std::cell::Cell;
+
+const LOGGER_ERROR_LOG: &str = "error_log";
+
+const MONOLOG_LOGGER_CLASS_NAMES: &[&str] = &[r"Monolog\Logger"];
+
+thread_local! {
+    /// The depth of the `Logger::addRecord` calls in progress.
+    static MONOLOG_DEPTH: Cell<usize> = Cell::new(0);
+}
+
+#[derive(Default, Clone)]
+pub struct ErrorLogPlugin;
+
+impl Plugin for ErrorLogPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        Some("error_log")
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (None, "error_log") if *REPORT_LOG => Some(self.hook_error_log()),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorLogPlugin {
+    /// `error_log(string $message, int $message_type = 0, ...): bool`.
+    fn hook_error_log(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                if MONOLOG_DEPTH.with(Cell::get) > 0 {
+                    return Ok(Box::new(()));
+                }
+                validate_num_args(execute_data, 1)?;
+                if let Some(message) = z_val_to_string(execute_data.get_parameter(0)) {
+                    report_log(request_id, LOGGER_ERROR_LOG, None, &message);
+                }
+                Ok(Box::new(()))
+            }),
+            Box::new(|_, _, _, _| Ok(())),
+        )
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct MonologPlugin;
+
+impl Plugin for MonologPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(MONOLOG_LOGGER_CLASS_NAMES)
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, _class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match function_name {
+            "addRecord" if *REPORT_LOG => Some(self.hook_add_record()),
+            _ => None,
+        }
+    }
+}
+
+impl MonologPlugin {
+    /// `addRecord(int|Level $level, string $message, array $context = [],
+    /// ...): bool`, the level is the `Level` enum since Monolog 3.
+    fn hook_add_record(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 2)?;
+                // Decreased in the after hook, which is skipped if the before
+                // hook fails, so nothing fails below.
+                MONOLOG_DEPTH.with(|depth| depth.set(depth.get() + 1));
+
+                let this = execute_data.get_this_mut();
+                let channel = this
+                    .as_ref()
+                    .and_then(|this| z_val_to_string(this.get_property("name")));
+
+                let level = execute_data.get_mut_parameter(0);
+                let level = match level.as_mut_z_obj() {
+                    Some(level) => level.get_property("value").as_long(),
+                    None => level.as_long(),
+                };
+                let Some(level) = level.and_then(reported_level_name) else {
+                    return Ok(Box::new(()));
+                };
+
+                if let Some(message) = z_val_to_string(execute_data.get_parameter(1)) {
+                    let logger = match channel {
+                        Some(channel) => format!("monolog.{}", channel),
+                        None => "monolog".to_owned(),
+                    };
+                    report_log(request_id, &logger, Some(level), &message);
+                }
+                Ok(Box::new(()))
+            }),
+            Box::new(|_, _, _, _| {
+                MONOLOG_DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+                Ok(())
+            }),
+        )
+    }
+}
@@ -15,7 +15,7 @@
 
 use super::{log_exception, style::ApiStyle, Plugin};
 use crate::{
-    component::COMPONENT_PHP_MEMCACHED_ID,
+    component::{component_id, COMPONENT_PHP_MEMCACHED_ID},
     context::RequestContext,
     execute::{AfterExecuteHook, BeforeExecuteHook, Noop},
     tag::{CacheOp, TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE},
@@ -255,7 +255,7 @@ fn create_exit_span(
 
         let span_object = span.span_object_mut();
         span_object.set_span_layer(SpanLayer::Cache);
-        span_object.component_id = COMPONENT_PHP_MEMCACHED_ID;
+        span_object.component_id = component_id("memcache", COMPONENT_PHP_MEMCACHED_ID);
         span_object.add_tag(TAG_CACHE_TYPE, "memcache");
         if let Some(cmd) = tag_info.cmd {
             span_object.add_tag(TAG_CACHE_CMD, cmd);
@@ -17,10 +17,11 @@ use std::{any::Any, collections::HashMap};
 
 use super::{log_exception, Plugin};
 use crate::{
-    component::COMPONENT_PHP_MEMCACHED_ID,
+    component::{component_id, COMPONENT_PHP_MEMCACHED_ID},
     context::RequestContext,
     execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook},
     tag::{CacheOp, TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE},
+    util::join_host_port,
 };
 use anyhow::Context;
 use once_cell::sync::Lazy;
@@ -350,7 +351,7 @@ fn create_exit_span(
 
         let span_object = span.span_object_mut();
         span_object.set_span_layer(SpanLayer::Cache);
-        span_object.component_id = COMPONENT_PHP_MEMCACHED_ID;
+        span_object.component_id = component_id("memcached", COMPONENT_PHP_MEMCACHED_ID);
         span_object.add_tag(TAG_CACHE_TYPE, "memcache");
         if let Some(cmd) = tag_info.cmd {
             span_object.add_tag(TAG_CACHE_CMD, cmd);
@@ -381,7 +382,7 @@ fn get_peer(this: &mut ZObj, key: ZVal) -> String {
             .context("Server port not exists")?
             .as_long()
             .context("Server port isn't long")?;
-        Ok::<_, crate::Error>(format!("{}:{}", host, port))
+        Ok::<_, crate::Error>(join_host_port(&host, port))
     };
     f().unwrap_or_else(|err| {
         warn!(?err, "Get peer failed");
@@ -15,10 +15,11 @@
 
 use super::{log_exception, Plugin};
 use crate::{
-    component::COMPONENT_MONGODB_ID,
+    component::{component_id, COMPONENT_MONGODB_ID},
     context::RequestContext,
     execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook},
     tag::TAG_DB_TYPE,
+    util::join_host_port,
 };
 use phper::{
     objects::ZObj,
@@ -123,7 +124,7 @@ fn before_manager_crud_hook(
 
     let span_object = span.span_object_mut();
     span_object.set_span_layer(SpanLayer::Database);
-    span_object.component_id = COMPONENT_MONGODB_ID;
+    span_object.component_id = component_id("mongodb", COMPONENT_MONGODB_ID);
     span_object.add_tag(TAG_DB_TYPE, "MongoDB");
 
     if let Some(id) = execute_data
@@ -184,7 +185,7 @@ fn get_peer(this: &mut ZObj) -> phper::Result<String> {
 
         let port = server.call("getPort", [])?.expect_long()?;
 
-        addr.push(format!("{}:{}", host, port));
+        addr.push(join_host_port(&host, port));
     }
 
     Ok(addr.join(";"))
@@ -13,31 +13,63 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{log_exception, style::ApiStyle, Plugin};
+use super::{
+    log_exception,
+    sql::{
+        format_statement, record_connect_time, remove_connect_time, tag_connect_time,
+        tag_rows_affected, SqlDialect,
+    },
+    style::ApiStyle,
+    Plugin,
+};
 use crate::{
-    component::COMPONENT_PHP_MYSQLI_ID,
+    component::{component_id, COMPONENT_PHP_MYSQLI_ID},
     context::RequestContext,
-    execute::{AfterExecuteHook, BeforeExecuteHook},
+    execute::{AfterExecuteHook, BeforeExecuteHook, Noop},
+    util::{host_to_peer, join_host_port},
 };
+use once_cell::sync::OnceCell;
 use phper::{
     alloc::ToRefOwned,
     functions::call,
     objects::ZObj,
+    sys,
     values::{ExecuteData, ZVal},
 };
 use skywalking::{
     proto::v3::SpanLayer,
     trace::span::{HandleSpanObject, Span},
 };
+use std::{cell::RefCell, collections::HashMap, sync::Once};
 use tracing::{debug, error};
 
+/// The flag of `resultmode`, the result is fetched by `reap_async_query`.
+const MYSQLI_ASYNC: i64 = 8;
+
+/// The handlers are shared by all the mysqli objects, so the dtor is hacked
+/// once, and the original one is kept for all of them.
+static HACK_DTOR: Once = Once::new();
+
+static ORI_DTOR: OnceCell<sys::zend_object_dtor_obj_t> = OnceCell::new();
+
+thread_local! {
+    /// The spans of the async queries not reaped and the request id, keyed by
+    /// the handle of mysqli object, there is at most one async query per
+    /// connection.
+    static ASYNC_QUERY_SPANS: RefCell<HashMap<u32, (Option<i64>, Span)>> = Default::default();
+
+    /// The peer and the statement of the prepared statements, keyed by the
+    /// handle of mysqli_stmt object, overwritten when the handle is reused.
+    static PREPARED_STATEMENTS: RefCell<HashMap<u32, (String, String)>> = Default::default();
+}
+
 #[derive(Default, Clone)]
 pub struct MySQLImprovedPlugin;
 
 impl Plugin for MySQLImprovedPlugin {
     #[inline]
     fn class_names(&self) -> Option<&'static [&'static str]> {
-        Some(&["mysqli"])
+        Some(&["mysqli", "mysqli_stmt"])
     }
 
     #[inline]
@@ -79,6 +111,16 @@ impl Plugin for MySQLImprovedPlugin {
             {
                 Some(self.hook_mysqli_methods(class_name, function_name, ApiStyle::Procedural))
             }
+            (Some("mysqli"), "close") => Some(self.hook_mysqli_close(ApiStyle::OO)),
+            (None, "mysqli_close") => Some(self.hook_mysqli_close(ApiStyle::Procedural)),
+            (Some("mysqli"), "reap_async_query") => Some(self.hook_mysqli_reap(ApiStyle::OO)),
+            (None, "mysqli_reap_async_query") => Some(self.hook_mysqli_reap(ApiStyle::Procedural)),
+            (Some("mysqli_stmt"), "execute") => {
+                Some(self.hook_mysqli_stmt_execute(class_name, function_name, ApiStyle::OO))
+            }
+            (None, "mysqli_stmt_execute") => {
+                Some(self.hook_mysqli_stmt_execute(class_name, function_name, ApiStyle::Procedural))
+            }
             _ => None,
         }
     }
@@ -109,6 +151,9 @@ impl MySQLImprovedPlugin {
             Box::new(move |_, span, execute_data, return_value| {
                 let mut span = span.downcast::<Span>().unwrap();
 
+                let is_connected =
+                    log_exception(&mut *span).is_none() && return_value.as_bool() != Some(false);
+
                 // Reset the peer here, it should be more precise.
                 if let Some(b) = return_value.as_bool() {
                     if !b {
@@ -119,12 +164,18 @@ impl MySQLImprovedPlugin {
                     if let Some(peer) = get_peer_by_this(this) {
                         span.span_object_mut().peer = peer;
                     }
+                    record_connect_time(this.handle(), &span);
+                    hack_dtor(this);
                 } else {
                     match style.get_this_mut(execute_data) {
                         Ok(this) => {
                             if let Some(peer) = get_peer_by_this(this) {
                                 span.span_object_mut().peer = peer;
                             }
+                            if is_connected {
+                                record_connect_time(this.handle(), &span);
+                                hack_dtor(this);
+                            }
                         }
                         Err(err) => {
                             error!(?err, "reset peer failed");
@@ -132,7 +183,6 @@ impl MySQLImprovedPlugin {
                     }
                 }
 
-                log_exception(&mut *span);
                 Ok(())
             }),
         )
@@ -143,6 +193,18 @@ impl MySQLImprovedPlugin {
     ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
         let class_name = class_name.map(ToOwned::to_owned);
         let function_name = function_name.to_owned();
+        let is_query = matches!(&*function_name, "query" | "mysqli_query");
+        let is_prepare = matches!(&*function_name, "prepare" | "mysqli_prepare");
+        // The `affected_rows` of `multi_query` is of the first statement only.
+        let is_rows_affected = matches!(
+            &*function_name,
+            "query"
+                | "mysqli_query"
+                | "real_query"
+                | "mysqli_real_query"
+                | "execute_query"
+                | "mysqli_execute_query"
+        );
         (
             Box::new(move |request_id, execute_data| {
                 let this = style.get_this_mut(execute_data)?;
@@ -159,28 +221,211 @@ impl MySQLImprovedPlugin {
                     style,
                 )?;
 
-                if execute_data.num_args() >= 1 {
-                    if let Some(statement) = execute_data.get_parameter(0).as_z_str() {
-                        span.add_tag("db.statement", statement.to_str()?);
+                tag_connect_time(handle, &mut span);
+
+                if style.validate_num_args(execute_data, 1).is_ok() {
+                    if let Some(statement) = style.get_mut_parameter(execute_data, 0).as_z_str() {
+                        span.add_tag(
+                            "db.statement",
+                            format_statement(statement.to_str()?, SqlDialect::Mysql),
+                        );
                     }
                 }
 
                 Ok(Box::new(span) as _)
             }),
-            Box::new(move |_, span, _, return_value| {
+            Box::new(move |request_id, span, execute_data, return_value| {
                 let mut span = span.downcast::<Span>().unwrap();
                 if let Some(b) = return_value.as_bool() {
                     if !b {
                         span.span_object_mut().is_error = true;
                     }
                 }
-                log_exception(&mut *span);
+                if log_exception(&mut *span).is_some() {
+                    return Ok(());
+                }
+
+                // The span is finished when the result is reaped.
+                if is_query
+                    && return_value.as_bool() == Some(true)
+                    && is_async_query(execute_data, style)
+                {
+                    let handle = style.get_this_mut(execute_data)?.handle();
+                    debug!(handle, "mysqli async query started");
+                    ASYNC_QUERY_SPANS
+                        .with(|spans| spans.borrow_mut().insert(handle, (request_id, *span)));
+                } else if is_prepare {
+                    if let Some(stmt) = return_value.as_z_obj() {
+                        let span_object = span.span_object();
+                        let statement = span_object
+                            .tags
+                            .iter()
+                            .find(|tag| tag.key == "db.statement")
+                            .map(|tag| tag.value.clone())
+                            .unwrap_or_default();
+                        let peer = span_object.peer.clone();
+                        PREPARED_STATEMENTS.with(|statements| {
+                            statements
+                                .borrow_mut()
+                                .insert(stmt.handle(), (peer, statement))
+                        });
+                    }
+                } else if is_rows_affected && return_value.as_bool() != Some(false) {
+                    let this = style.get_this_mut(execute_data)?;
+                    tag_rows_affected(&mut span, || {
+                        Ok(this.get_property("affected_rows").as_long())
+                    })?;
+                }
+                Ok(())
+            }),
+        )
+    }
+
+    fn hook_mysqli_close(
+        &self, style: ApiStyle,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Noop::noop(),
+            Box::new(move |_, _, execute_data, _| {
+                let handle = style.get_this_mut(execute_data)?.handle();
+                debug!(handle, "mysqli closed");
+                remove_connect_time(handle);
+                Ok(())
+            }),
+        )
+    }
+
+    fn hook_mysqli_reap(&self, style: ApiStyle) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Noop::noop(),
+            Box::new(move |_, _, execute_data, return_value| {
+                let handle = style.get_this_mut(execute_data)?.handle();
+                let Some((_, mut span)) =
+                    ASYNC_QUERY_SPANS.with(|spans| spans.borrow_mut().remove(&handle))
+                else {
+                    return Ok(());
+                };
+                debug!(handle, "mysqli async query reaped");
+
+                if return_value.as_bool() == Some(false) {
+                    span.span_object_mut().is_error = true;
+                }
+                log_exception(&mut span);
+                Ok(())
+            }),
+        )
+    }
+
+    fn hook_mysqli_stmt_execute(
+        &self, class_name: Option<&str>, function_name: &str, style: ApiStyle,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let class_name = class_name.map(ToOwned::to_owned);
+        let function_name = function_name.to_owned();
+        (
+            Box::new(move |request_id, execute_data| {
+                let handle = style.get_this_mut(execute_data)?.handle();
+
+                debug!(handle, class_name, function_name, "call mysqli_stmt method");
+
+                let (peer, statement) = PREPARED_STATEMENTS
+                    .with(|statements| statements.borrow().get(&handle).cloned())
+                    .unwrap_or_default();
+                let mut span = create_mysqli_exit_span(
+                    request_id,
+                    class_name.as_deref(),
+                    &function_name,
+                    &peer,
+                    style,
+                )?;
+                if !statement.is_empty() {
+                    span.add_tag("db.statement", statement);
+                }
+
+                Ok(Box::new(span) as _)
+            }),
+            Box::new(move |_, span, execute_data, return_value| {
+                let mut span = span.downcast::<Span>().unwrap();
+                if return_value.as_bool() == Some(false) {
+                    span.span_object_mut().is_error = true;
+                }
+                if log_exception(&mut *span).is_none() && return_value.as_bool() == Some(true) {
+                    let this = style.get_this_mut(execute_data)?;
+                    tag_rows_affected(&mut span, || {
+                        Ok(this.get_property("affected_rows").as_long())
+                    })?;
+                }
                 Ok(())
             }),
         )
     }
 }
 
+/// Hack the dtor of mysqli objects, for removing the connect time of the
+/// connections never closed explicitly, like the PDO plugin.
+fn hack_dtor(this: &mut ZObj) {
+    HACK_DTOR.call_once(|| unsafe {
+        let handlers = (*this.as_mut_ptr()).handlers as *mut sys::zend_object_handlers;
+        let _ = ORI_DTOR.set((*handlers).dtor_obj);
+        (*handlers).dtor_obj = Some(mysqli_dtor);
+    });
+}
+
+unsafe extern "C" fn mysqli_dtor(object: *mut sys::zend_object) {
+    let handle = ZObj::from_ptr(object).handle();
+    debug!(handle, "call mysqli dtor");
+
+    remove_connect_time(handle);
+    if let Some(Some(dtor)) = ORI_DTOR.get() {
+        dtor(object);
+    }
+}
+
+/// Close the spans of the async queries never reaped by the request, before
+/// the request context is finished, otherwise they are left open into the next
+/// request, and become the parent of its spans.
+pub fn close_async_query_spans(request_id: Option<i64>) {
+    let spans = ASYNC_QUERY_SPANS.with(|spans| {
+        let mut spans = spans.borrow_mut();
+        let handles = spans
+            .iter()
+            .filter(|(_, (id, _))| *id == request_id)
+            .map(|(handle, _)| *handle)
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .filter_map(|handle| spans.remove(&handle))
+            .map(|(_, span)| span)
+            .collect::<Vec<_>>()
+    });
+
+    for mut span in spans {
+        debug!(
+            operation_name = %span.span_object().operation_name,
+            "Close span of the mysqli async query not reaped"
+        );
+        let span_object = span.span_object_mut();
+        span_object.is_error = true;
+        span_object.add_log([
+            ("event", "error"),
+            (
+                "message",
+                "the async query isn't reaped before the request ends",
+            ),
+        ]);
+    }
+}
+
+/// The `resultmode` is the second parameter of `mysqli::query`.
+fn is_async_query(execute_data: &mut ExecuteData, style: ApiStyle) -> bool {
+    if style.validate_num_args(execute_data, 2).is_err() {
+        return false;
+    }
+    style
+        .get_mut_parameter(execute_data, 1)
+        .as_long()
+        .map_or(false, |result_mode| result_mode & MYSQLI_ASYNC != 0)
+}
+
 fn create_mysqli_exit_span(
     request_id: Option<i64>, class_name: Option<&str>, function_name: &str, peer: &str,
     style: ApiStyle,
@@ -193,7 +438,7 @@ fn create_mysqli_exit_span(
 
         let span_object = span.span_object_mut();
         span_object.set_span_layer(SpanLayer::Database);
-        span_object.component_id = COMPONENT_PHP_MYSQLI_ID;
+        span_object.component_id = component_id("mysqli", COMPONENT_PHP_MYSQLI_ID);
         span_object.add_tag("db.type", "mysql");
 
         Ok(span)
@@ -217,13 +462,7 @@ fn get_peer_by_this(this: &mut ZObj) -> Option<String> {
         .as_z_str()
         .and_then(|info| info.to_str().ok())
         .and_then(|info| info.split(' ').next())
-        .map(ToOwned::to_owned)
-        .map(|mut info| {
-            if !info.contains(':') {
-                info.push_str(":3306");
-            }
-            info
-        })
+        .map(|info| host_to_peer(info, 3306))
 }
 
 fn get_peer_by_parameters(execute_data: &mut ExecuteData, style: ApiStyle) -> String {
@@ -251,8 +490,7 @@ fn get_peer_by_parameters(execute_data: &mut ExecuteData, style: ApiStyle) -> St
             None
         };
 
-        peer.push(':');
-        peer.push_str(port.as_deref().unwrap_or("3306"));
+        peer = join_host_port(&peer, port.as_deref().unwrap_or("3306"));
     }
 
     peer
@@ -13,12 +13,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{log_exception, Plugin};
+use super::{
+    log_exception,
+    sql::{
+        format_statement, record_connect_time, remove_connect_time, tag_connect_time,
+        tag_rows_affected, SqlDialect,
+    },
+    Plugin,
+};
 use crate::{
-    component::COMPONENT_PHP_PDO_ID,
+    component::{component_id, COMPONENT_PHP_PDO_ID},
     context::RequestContext,
     execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook},
     tag::{TAG_DB_STATEMENT, TAG_DB_TYPE},
+    util::host_to_peer,
 };
 use anyhow::Context;
 use dashmap::DashMap;
@@ -106,9 +114,11 @@ impl PdoPlugin {
 
                 Ok(Box::new(span))
             }),
-            Box::new(move |_, span, _, _| {
+            Box::new(move |_, span, execute_data, _| {
                 let mut span = span.downcast::<Span>().unwrap();
-                log_exception(&mut *span);
+                if log_exception(&mut *span).is_none() {
+                    record_connect_time(get_this_mut(execute_data)?.handle(), &span);
+                }
                 Ok(())
             }),
         )
@@ -124,13 +134,19 @@ impl PdoPlugin {
 
                 debug!(handle, function_name, "call PDO method");
 
-                let mut span = with_dsn(handle, |dsn| {
-                    create_exit_span_with_dsn(request_id, "PDO", &function_name, dsn)
+                let (mut span, dialect) = with_dsn(handle, |dsn| {
+                    let span = create_exit_span_with_dsn(request_id, "PDO", &function_name, dsn)?;
+                    Ok((span, dsn.sql_dialect()))
                 })?;
 
+                tag_connect_time(handle, &mut span);
+
                 if execute_data.num_args() >= 1 {
                     if let Some(statement) = execute_data.get_parameter(0).as_z_str() {
-                        span.add_tag(TAG_DB_STATEMENT, statement.to_str()?);
+                        span.add_tag(
+                            TAG_DB_STATEMENT,
+                            format_statement(statement.to_str()?, dialect),
+                        );
                     }
                 }
 
@@ -144,6 +160,7 @@ impl PdoPlugin {
         &self, function_name: &str,
     ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
         let function_name = function_name.to_owned();
+        let is_execute = function_name == "execute";
         (
             Box::new(move |request_id, execute_data| {
                 let this = get_this_mut(execute_data)?;
@@ -151,19 +168,31 @@ impl PdoPlugin {
 
                 debug!(handle, function_name, "call PDOStatement method");
 
-                let mut span = with_dsn(handle, |dsn| {
-                    create_exit_span_with_dsn(request_id, "PDOStatement", &function_name, dsn)
+                let (mut span, dialect) = with_dsn(handle, |dsn| {
+                    let span =
+                        create_exit_span_with_dsn(request_id, "PDOStatement", &function_name, dsn)?;
+                    Ok((span, dsn.sql_dialect()))
                 })?;
 
                 if let Some(query) = this.get_property("queryString").as_z_str() {
-                    span.add_tag(TAG_DB_STATEMENT, query.to_str()?);
+                    span.add_tag(TAG_DB_STATEMENT, format_statement(query.to_str()?, dialect));
                 } else {
                     warn!("PDOStatement queryString is empty");
                 }
 
                 Ok(Box::new(span) as _)
             }),
-            Box::new(after_hook),
+            Box::new(move |request_id, mut span, execute_data, return_value| {
+                // The `rowCount()` returns the count kept by the driver after
+                // executed.
+                if is_execute && return_value.as_bool() == Some(true) {
+                    if let Some(span) = span.downcast_mut::<Span>() {
+                        let this = get_this_mut(execute_data)?;
+                        tag_rows_affected(span, || Ok(this.call("rowCount", [])?.as_long()))?;
+                    }
+                }
+                after_hook(request_id, span, execute_data, return_value)
+            }),
         )
     }
 }
@@ -192,6 +221,7 @@ unsafe extern "C" fn dtor(object: *mut sys::zend_object) {
     let handle = ZObj::from_ptr(object).handle();
 
     DSN_MAP.remove(&handle);
+    remove_connect_time(handle);
     if let Some((_, Some(dtor))) = DTOR_MAP.remove(&handle) {
         dtor(object);
     }
@@ -219,6 +249,9 @@ fn after_hook(
             let cls = cls.get_name().to_str()?;
             debug!(cls, "not a subclass of PDOStatement");
         }
+    } else if let Some(rows) = return_value.as_long() {
+        // `PDO::exec` returns the affected rows.
+        tag_rows_affected(&mut span, || Ok(Some(rows)))?;
     }
 
     Ok(())
@@ -274,7 +307,7 @@ fn create_exit_span_with_dsn(
 
         let span_object = span.span_object_mut();
         span_object.set_span_layer(SpanLayer::Database);
-        span_object.component_id = COMPONENT_PHP_PDO_ID;
+        span_object.component_id = component_id("pdo", COMPONENT_PHP_PDO_ID);
         span_object.add_tag(TAG_DB_TYPE, &dsn.db_type);
         span_object.add_tag("db.data_source", &dsn.data_source);
 
@@ -296,6 +329,17 @@ struct Dsn {
     peer: String,
 }
 
+impl Dsn {
+    #[inline]
+    fn sql_dialect(&self) -> SqlDialect {
+        if self.db_type == "mysql" {
+            SqlDialect::Mysql
+        } else {
+            SqlDialect::Ansi
+        }
+    }
+}
+
 impl FromStr for Dsn {
     type Err = anyhow::Error;
 
@@ -335,11 +379,7 @@ impl FromStr for Dsn {
             }
         }
 
-        let peer = if host.contains(':') {
-            host.to_string()
-        } else {
-            host.to_string() + ":" + port
-        };
+        let peer = host_to_peer(host, port);
 
         Ok(Dsn {
             db_type,
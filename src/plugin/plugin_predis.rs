@@ -15,11 +15,15 @@
 
 use super::Plugin;
 use crate::{
-    component::COMPONENT_PHP_PREDIS_ID,
+    component::{component_id, COMPONENT_PHP_PREDIS_ID},
     context::RequestContext,
     execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook},
     plugin::log_exception,
-    tag::{TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE},
+    tag::{
+        TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE, TAG_REDIS_CLUSTER,
+        TAG_REDIS_SENTINEL,
+    },
+    util::join_host_port,
 };
 use once_cell::sync::Lazy;
 use phper::{eg, functions::call, values::ZVal};
@@ -174,9 +178,23 @@ impl Plugin for PredisPlugin {
 
 enum ConnectionType {
     AbstractConnection,
+    /// `PredisCluster` or `RedisCluster`, the node is selected by key.
+    Cluster,
+    /// `MasterSlaveReplication` or `SentinelReplication`, the node is
+    /// selected when executing command.
+    Replication {
+        is_sentinel: bool,
+    },
     Unknown,
 }
 
+/// The command span, the peer of replication is updated after the command
+/// executed, since the node is selected then.
+struct CommandSpan {
+    span: Span,
+    is_replication: bool,
+}
+
 impl PredisPlugin {
     fn hook_predis_execute_command(
         &self, class_name: &str, _function_name: &str,
@@ -199,12 +217,6 @@ impl PredisPlugin {
                     return Ok(Box::new(()));
                 }
 
-                let this = get_this_mut(execute_data)?;
-                let handle = this.handle();
-                let connection = this.call("getConnection", [])?;
-
-                let peer = Self::get_peer(connection)?;
-
                 let op = if REDIS_READ_COMMANDS.contains(&*cmd) {
                     Some("read")
                 } else if REDIS_WRITE_COMMANDS.contains(&*cmd) {
@@ -217,7 +229,15 @@ impl PredisPlugin {
                     .and_then(|_| execute_data.get_parameter(1).as_z_arr())
                     .and_then(|params| params.get(0))
                     .and_then(|key| key.as_z_str())
-                    .and_then(|s| s.to_str().ok());
+                    .and_then(|s| s.to_str().ok())
+                    .map(ToOwned::to_owned);
+
+                let this = get_this_mut(execute_data)?;
+                let handle = this.handle();
+                let connection = this.call("getConnection", [])?;
+
+                let connection_type = Self::infer_connection_type(connection.clone())?;
+                let peer = Self::get_peer(connection, &connection_type, key.as_deref())?;
 
                 debug!(handle, cmd, key, op, "call redis command");
 
@@ -231,7 +251,7 @@ impl PredisPlugin {
 
                 let span_object = span.span_object_mut();
                 span_object.set_span_layer(SpanLayer::Cache);
-                span_object.component_id = COMPONENT_PHP_PREDIS_ID;
+                span_object.component_id = component_id("predis", COMPONENT_PHP_PREDIS_ID);
                 span_object.add_tag(TAG_CACHE_TYPE, "redis");
                 span_object.add_tag(TAG_CACHE_CMD, cmd);
                 if let Some(op) = op {
@@ -240,70 +260,131 @@ impl PredisPlugin {
                 if let Some(key) = key {
                     span_object.add_tag(TAG_CACHE_KEY, key)
                 }
+                match connection_type {
+                    ConnectionType::Cluster => span_object.add_tag(TAG_REDIS_CLUSTER, "true"),
+                    ConnectionType::Replication { is_sentinel: true } => {
+                        span_object.add_tag(TAG_REDIS_SENTINEL, "true")
+                    }
+                    _ => {}
+                }
 
-                Ok(Box::new(span))
+                Ok(Box::new(CommandSpan {
+                    span,
+                    is_replication: matches!(connection_type, ConnectionType::Replication { .. }),
+                }))
             }),
-            Box::new(move |_, span, _, return_value| {
+            Box::new(move |_, span, execute_data, return_value| {
                 if span.downcast_ref::<()>().is_some() {
                     return Ok(());
                 }
 
-                let mut span = span.downcast::<Span>().unwrap();
+                let CommandSpan {
+                    mut span,
+                    is_replication,
+                } = *span.downcast::<CommandSpan>().unwrap();
+
+                if is_replication {
+                    let this = get_this_mut(execute_data)?;
+                    let connection = this.call("getConnection", [])?;
+                    match Self::get_replication_peer(connection) {
+                        Ok(peer) => span.span_object_mut().peer = peer,
+                        Err(err) => debug!(?err, "get predis replication peer failed"),
+                    }
+                }
 
                 let exception = unsafe { eg!(exception) };
 
                 debug!(?return_value, ?exception, "predis after execute command");
 
-                let typ = return_value.get_type_info();
-                if !exception.is_null() || typ.is_false() {
+                // The exception is marked errored by `log_exception`, unless it is ignored.
+                if log_exception(&mut span).is_none() && return_value.get_type_info().is_false() {
                     span.span_object_mut().is_error = true;
                 }
 
-                log_exception(&mut *span);
-
                 Ok(())
             }),
         )
     }
 
-    fn get_peer(mut connection: ZVal) -> crate::Result<String> {
-        let connection_type = Self::infer_connection_type(connection.clone())?;
+    fn get_peer(
+        connection: ZVal, connection_type: &ConnectionType, key: Option<&str>,
+    ) -> crate::Result<String> {
         match connection_type {
-            ConnectionType::AbstractConnection => {
-                let connection = connection.expect_mut_z_obj()?;
+            ConnectionType::AbstractConnection => Self::get_node_peer(connection),
+            ConnectionType::Cluster => {
+                Ok(Self::get_cluster_peer(connection, key)
+                    .unwrap_or_else(|_| "unknown:0".to_owned()))
+            }
+            // The node selected by the last command, updated after this one executed.
+            ConnectionType::Replication { .. } => {
+                Ok(Self::get_replication_peer(connection)
+                    .unwrap_or_else(|_| "unknown:0".to_owned()))
+            }
+            ConnectionType::Unknown => Ok("unknown:0".to_owned()),
+        }
+    }
 
-                let mut parameters = connection.call("getParameters", [])?;
-                let parameters = parameters.expect_mut_z_obj()?;
+    /// The node of key, or the first node in the pool (the seed) if no key.
+    ///
+    /// The pool is read directly rather than iterating the connection, which
+    /// may ask the slot map (`CLUSTER SLOTS`) and connect every node.
+    fn get_cluster_peer(mut connection: ZVal, key: Option<&str>) -> crate::Result<String> {
+        let connection = connection.expect_mut_z_obj()?;
+        let node = match key {
+            Some(key) => connection.call("getConnectionByKey", [ZVal::from(key)])?,
+            None => connection
+                .get_property("pool")
+                .as_z_arr()
+                .and_then(|pool| pool.iter().next())
+                .map(|(_, node)| node.clone())
+                .unwrap_or_else(|| ZVal::from(())),
+        };
+        Self::get_node_peer(node)
+    }
 
-                let host = parameters.call("__get", [ZVal::from("host")])?;
-                let host = host.expect_z_str()?.to_str()?;
+    /// The current node of replication, which the last command is sent to.
+    fn get_replication_peer(mut connection: ZVal) -> crate::Result<String> {
+        let connection = connection.expect_mut_z_obj()?;
+        Self::get_node_peer(connection.call("getCurrent", [])?)
+    }
 
-                let port = parameters.call("__get", [ZVal::from("port")])?;
-                let port = if let Some(port) = port.as_long() {
-                    port.to_string()
-                } else if let Some(port) = port.as_z_str() {
-                    port.to_str().unwrap_or("0").to_string()
-                } else {
-                    "0".to_string()
-                };
+    fn get_node_peer(mut connection: ZVal) -> crate::Result<String> {
+        let connection = connection.expect_mut_z_obj()?;
 
-                Ok(format!("{}:{}", host, port))
-            }
-            ConnectionType::Unknown => Ok("unknown:0".to_owned()),
-        }
+        let mut parameters = connection.call("getParameters", [])?;
+        let parameters = parameters.expect_mut_z_obj()?;
+
+        let host = parameters.call("__get", [ZVal::from("host")])?;
+        let host = host.expect_z_str()?.to_str()?;
+
+        let port = parameters.call("__get", [ZVal::from("port")])?;
+        let port = if let Some(port) = port.as_long() {
+            port.to_string()
+        } else if let Some(port) = port.as_z_str() {
+            port.to_str().unwrap_or("0").to_string()
+        } else {
+            "0".to_string()
+        };
+
+        Ok(join_host_port(&host, port))
     }
 
     fn infer_connection_type(connection: ZVal) -> crate::Result<ConnectionType> {
-        let is_abstract_connection = call(
-            "is_a",
-            [
-                connection,
-                ZVal::from("Predis\\Connection\\AbstractConnection"),
-            ],
-        )?;
-        if is_abstract_connection.as_bool() == Some(true) {
+        let is_a = |class_name: &str| {
+            call("is_a", [connection.clone(), ZVal::from(class_name)])
+                .map(|result| result.as_bool() == Some(true))
+        };
+        if is_a("Predis\\Connection\\AbstractConnection")? {
             return Ok(ConnectionType::AbstractConnection);
         }
+        if is_a("Predis\\Connection\\Cluster\\ClusterInterface")? {
+            return Ok(ConnectionType::Cluster);
+        }
+        if is_a("Predis\\Connection\\Replication\\ReplicationInterface")? {
+            return Ok(ConnectionType::Replication {
+                is_sentinel: is_a("Predis\\Connection\\Replication\\SentinelReplication")?,
+            });
+        }
         Ok(ConnectionType::Unknown)
     }
 }
@@ -0,0 +1,274 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin for the queue workers of
+//! [symfony/messenger](https://github.com/symfony/messenger) and
+//! [Laravel](https://github.com/laravel/framework/tree/10.x/src/Illuminate/Queue),
+//! enabled by `skywalking_agent.trace_queue_workers`.
+//!
+//! The long-running worker handles messages in a loop, so a standalone segment
+//! is created for every message, instead of putting the spans into the segment
+//! of the cli script, which is suspended until the message handled.
+//!
+//! The `sw8` header is extracted from the `AmqpReceivedStamp` of messenger
+//! envelope, or the `sw8` key of Laravel job payload if exists.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_PHP_ID},
+    context::{RequestContext, SW_HEADER},
+    execute::{validate_num_args, AfterExecuteHook, BeforeExecuteHook},
+    module::TRACE_QUEUE_WORKERS,
+    tag::TAG_MQ_QUEUE,
+};
+use anyhow::Context;
+use phper::{
+    functions::call,
+    objects::ZObj,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::{propagation::decoder::decode_propagation, span::HandleSpanObject, tracer},
+};
+use std::any::Any;
+use tracing::debug;
+
+const MESSENGER_WORKER_CLASS_NAME: &str = "Symfony\\Component\\Messenger\\Worker";
+const LARAVEL_WORKER_CLASS_NAME: &str = "Illuminate\\Queue\\Worker";
+
+const TAG_QUEUE_CONNECTION: &str = "queue.connection";
+
+#[derive(Default, Clone)]
+pub struct QueueWorkerPlugin;
+
+impl Plugin for QueueWorkerPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(&[MESSENGER_WORKER_CLASS_NAME, LARAVEL_WORKER_CLASS_NAME])
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        if !*TRACE_QUEUE_WORKERS {
+            return None;
+        }
+        match (class_name, function_name) {
+            (Some(MESSENGER_WORKER_CLASS_NAME), "handleMessage") => {
+                Some(self.hook_messenger_handle_message())
+            }
+            (Some(LARAVEL_WORKER_CLASS_NAME), "process") => Some(self.hook_laravel_process()),
+            _ => None,
+        }
+    }
+}
+
+impl QueueWorkerPlugin {
+    /// `Worker::handleMessage(Envelope $envelope, string $transportName)`.
+    fn hook_messenger_handle_message(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 2)?;
+
+                let transport_name = execute_data
+                    .get_parameter(1)
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned();
+                let envelope = execute_data
+                    .get_mut_parameter(0)
+                    .as_mut_z_obj()
+                    .context("envelope isn't object")?;
+
+                let message_class = envelope
+                    .call("getMessage", [])?
+                    .as_z_obj()
+                    .and_then(|message| message.get_class().get_name().to_str().ok())
+                    .unwrap_or("unknown")
+                    .to_owned();
+                let sw_header = get_messenger_sw_header(envelope);
+
+                let has_suspended = start_message_segment(
+                    request_id,
+                    "messenger",
+                    &format!("Messenger/{}", message_class),
+                    sw_header.as_deref(),
+                    &[(TAG_MQ_QUEUE, transport_name)],
+                );
+                Ok(Box::new(has_suspended))
+            }),
+            Box::new(finish_message_segment),
+        )
+    }
+
+    /// `Worker::process(string $connectionName, Job $job, WorkerOptions
+    /// $options)`.
+    fn hook_laravel_process(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 2)?;
+
+                let connection_name = execute_data
+                    .get_parameter(0)
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned();
+                let job = execute_data
+                    .get_mut_parameter(1)
+                    .as_mut_z_obj()
+                    .context("job isn't object")?;
+
+                let job_name = call_string(job, "resolveName").unwrap_or_else(|| "unknown".into());
+                let queue = call_string(job, "getQueue").unwrap_or_default();
+                let sw_header = job.call("payload", []).ok().and_then(|payload| {
+                    payload
+                        .as_z_arr()
+                        .and_then(|payload| payload.get(SW_HEADER))
+                        .and_then(|header| header.as_z_str())
+                        .and_then(|header| header.to_str().ok())
+                        .map(ToOwned::to_owned)
+                });
+
+                let has_suspended = start_message_segment(
+                    request_id,
+                    "laravel_queue",
+                    &format!("Laravel/Queue/{}", job_name),
+                    sw_header.as_deref(),
+                    &[
+                        (TAG_MQ_QUEUE, queue),
+                        (TAG_QUEUE_CONNECTION, connection_name),
+                    ],
+                );
+                Ok(Box::new(has_suspended))
+            }),
+            Box::new(finish_message_segment),
+        )
+    }
+}
+
+/// Start the standalone segment of the message, the invalid `sw8` header is
+/// ignored, to not lose the message handling.
+fn start_message_segment(
+    request_id: Option<i64>, plugin: &str, operation_name: &str, sw_header: Option<&str>,
+    tags: &[(&str, String)],
+) -> bool {
+    let propagation = sw_header.and_then(|header| match decode_propagation(header) {
+        Ok(propagation) => Some(propagation),
+        Err(err) => {
+            debug!(?err, "decode propagation failed");
+            None
+        }
+    });
+
+    let mut tracing_context = tracer::create_trace_context();
+    let mut entry_span = match &propagation {
+        Some(propagation) => {
+            tracing_context.create_entry_span_with_propagation(operation_name, propagation)
+        }
+        None => tracing_context.create_entry_span(operation_name),
+    };
+
+    let span_object = entry_span.span_object_mut();
+    span_object.set_span_layer(SpanLayer::Mq);
+    span_object.component_id = component_id(plugin, COMPONENT_PHP_ID);
+    for (key, value) in tags {
+        if !value.is_empty() {
+            span_object.add_tag(*key, value);
+        }
+    }
+
+    debug!(operation_name, "start queue message segment");
+    RequestContext::start_standalone(
+        request_id,
+        RequestContext {
+            tracing_context,
+            entry_span,
+        },
+    )
+}
+
+fn finish_message_segment(
+    request_id: Option<i64>, has_suspended: Box<dyn Any>, _: &mut ExecuteData, _: &mut ZVal,
+) -> crate::Result<()> {
+    let has_suspended = *has_suspended.downcast::<bool>().unwrap();
+    if let Some(mut ctx) = RequestContext::finish_standalone(request_id, has_suspended) {
+        log_exception(&mut ctx.entry_span);
+        ctx.finish();
+    }
+    Ok(())
+}
+
+/// Get the `sw8` header of the `AMQPEnvelope` in `AmqpReceivedStamp`, the
+/// other transports don't keep the headers in stamps.
+fn get_messenger_sw_header(envelope: &mut ZObj) -> Option<String> {
+    let mut stamps = envelope.call("all", []).ok()?;
+    let stamps = stamps.as_mut_z_arr()?;
+
+    for (_, stamps) in stamps.iter_mut() {
+        let Some(stamps) = stamps.as_mut_z_arr() else {
+            continue;
+        };
+        for (_, stamp) in stamps.iter_mut() {
+            let Some(stamp) = stamp.as_mut_z_obj() else {
+                continue;
+            };
+            if !method_exists(stamp, "getAmqpEnvelope") {
+                continue;
+            }
+            let header = stamp
+                .call("getAmqpEnvelope", [])
+                .ok()
+                .and_then(|mut amqp_envelope| {
+                    amqp_envelope
+                        .as_mut_z_obj()?
+                        .call("getHeader", [ZVal::from(SW_HEADER)])
+                        .ok()
+                })
+                .and_then(|header| header.as_z_str()?.to_str().ok().map(ToOwned::to_owned));
+            if header.is_some() {
+                return header;
+            }
+        }
+    }
+
+    None
+}
+
+fn method_exists(obj: &mut ZObj, method: &str) -> bool {
+    call(
+        "method_exists",
+        [ZVal::from(obj.to_ref_owned()), ZVal::from(method)],
+    )
+    .ok()
+    .and_then(|result| result.as_bool())
+    .unwrap_or_default()
+}
+
+fn call_string(obj: &mut ZObj, method: &str) -> Option<String> {
+    obj.call(method, [])
+        .ok()?
+        .as_z_str()?
+        .to_str()
+        .ok()
+        .map(ToOwned::to_owned)
+}
@@ -0,0 +1,386 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin of [php-rdkafka](https://github.com/arnaud-lb/php-rdkafka).
+//!
+//! The `sw8` header is only injected by `ProducerTopic::producev` with the
+//! headers argument passed (can be null), because `ProducerTopic::produce`
+//! doesn't support message headers.
+
+use super::{
+    consumer::{finish_handling_span, start_handling_span},
+    log_exception, Plugin,
+};
+use crate::{
+    component::{component_id, COMPONENT_KAFKA_CONSUMER_ID, COMPONENT_KAFKA_PRODUCER_ID},
+    context::{RequestContext, SW_HEADER},
+    execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook, Noop},
+    tag::{TAG_MQ_BROKER, TAG_MQ_TOPIC},
+};
+use anyhow::{anyhow, Context};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use phper::{
+    arrays::{ZArr, ZArray},
+    objects::ZObj,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::{
+        propagation::decoder::decode_propagation,
+        span::{HandleSpanObject, Span},
+        trace_context::TracingContext,
+    },
+};
+use tracing::debug;
+
+const PRODUCER_CLASS_NAME: &str = r"RdKafka\Producer";
+const PRODUCER_TOPIC_CLASS_NAME: &str = r"RdKafka\ProducerTopic";
+const KAFKA_CONSUMER_CLASS_NAME: &str = r"RdKafka\KafkaConsumer";
+
+/// `RD_KAFKA_RESP_ERR_NO_ERROR`, other errors of consume mean no message,
+/// such as timeout or partition EOF.
+const RD_KAFKA_RESP_ERR_NO_ERROR: i64 = 0;
+
+/// The broker list and its alias in `RdKafka\Conf`.
+const BROKERS_CONF_NAMES: &[&str] = &["metadata.broker.list", "bootstrap.servers"];
+
+/// Key is the handle of `RdKafka\Producer`, value is the broker list.
+static PRODUCER_BROKERS: Lazy<DashMap<u32, String>> = Lazy::new(Default::default);
+
+/// Key is the handle of `RdKafka\ProducerTopic`, value is the broker list.
+static TOPIC_BROKERS: Lazy<DashMap<u32, String>> = Lazy::new(Default::default);
+
+/// Key is the handle of `RdKafka\KafkaConsumer`.
+static CONSUMER_INFOS: Lazy<DashMap<u32, ConsumerInfo>> = Lazy::new(Default::default);
+
+/// The request creating the objects of the maps above, keyed by the handle,
+/// the entries are removed when the request ends.
+static HANDLE_REQUESTS: Lazy<DashMap<u32, Option<i64>>> = Lazy::new(Default::default);
+
+#[derive(Default, Clone)]
+pub struct RdKafkaPlugin;
+
+impl Plugin for RdKafkaPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(&[
+            PRODUCER_CLASS_NAME,
+            PRODUCER_TOPIC_CLASS_NAME,
+            KAFKA_CONSUMER_CLASS_NAME,
+        ])
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (Some(PRODUCER_CLASS_NAME), "__construct") => Some(self.hook_producer_construct()),
+            (Some(PRODUCER_CLASS_NAME), "addBrokers") => Some(self.hook_producer_add_brokers()),
+            (Some(PRODUCER_CLASS_NAME), "newTopic") => Some(self.hook_producer_new_topic()),
+            (Some(PRODUCER_TOPIC_CLASS_NAME), f @ ("produce" | "producev")) => {
+                Some(self.hook_producer_topic_produce(f))
+            }
+            (Some(KAFKA_CONSUMER_CLASS_NAME), "__construct") => {
+                Some(self.hook_kafka_consumer_construct())
+            }
+            (Some(KAFKA_CONSUMER_CLASS_NAME), "consume") => {
+                Some(self.hook_kafka_consumer_consume())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl RdKafkaPlugin {
+    /// `RdKafka\Producer::__construct(?RdKafka\Conf $conf = null)`.
+    fn hook_producer_construct(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                let brokers = match get_conf(execute_data) {
+                    Some(conf) => {
+                        let dump = dump_conf(conf)?;
+                        get_conf_value(&dump, BROKERS_CONF_NAMES)
+                    }
+                    None => Default::default(),
+                };
+
+                let handle = get_this_mut(execute_data)?.handle();
+                debug!(handle, brokers, "construct rdkafka producer");
+                PRODUCER_BROKERS.insert(handle, brokers);
+                HANDLE_REQUESTS.insert(handle, request_id);
+
+                Ok(Box::new(()))
+            }),
+            Noop::noop(),
+        )
+    }
+
+    /// `RdKafka\Producer::addBrokers(string $broker_list)`.
+    fn hook_producer_add_brokers(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, execute_data| {
+                let Some(broker_list) = execute_data
+                    .get_parameter(0)
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .map(ToOwned::to_owned)
+                else {
+                    return Ok(Box::new(()));
+                };
+
+                let handle = get_this_mut(execute_data)?.handle();
+                let mut brokers = PRODUCER_BROKERS.entry(handle).or_default();
+                if !brokers.is_empty() {
+                    brokers.push(',');
+                }
+                brokers.push_str(&broker_list);
+
+                Ok(Box::new(()))
+            }),
+            Noop::noop(),
+        )
+    }
+
+    /// `RdKafka\Producer::newTopic(string $topic_name, ?RdKafka\TopicConf
+    /// $topic_conf = null)`.
+    fn hook_producer_new_topic(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Noop::noop(),
+            Box::new(|request_id, _, execute_data, return_value| {
+                let Some(topic) = return_value.as_z_obj() else {
+                    return Ok(());
+                };
+                let topic_handle = topic.handle();
+
+                let handle = get_this_mut(execute_data)?.handle();
+                if let Some(brokers) = PRODUCER_BROKERS.get(&handle) {
+                    TOPIC_BROKERS.insert(topic_handle, brokers.value().clone());
+                    HANDLE_REQUESTS.insert(topic_handle, request_id);
+                }
+
+                Ok(())
+            }),
+        )
+    }
+
+    /// `RdKafka\ProducerTopic::produce(int $partition, int $msgflags, ?string
+    /// $payload = null, ?string $key = null)` and
+    /// `RdKafka\ProducerTopic::producev(int $partition, int $msgflags, ?string
+    /// $payload = null, ?string $key = null, ?array $headers = null, ...)`.
+    fn hook_producer_topic_produce(
+        &self, function_name: &str,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let is_producev = function_name == "producev";
+        (
+            Box::new(move |request_id, execute_data| {
+                let this = get_this_mut(execute_data)?;
+                let topic = call_string(this, "getName")?;
+                let brokers = TOPIC_BROKERS
+                    .get(&this.handle())
+                    .map(|brokers| brokers.value().clone())
+                    .unwrap_or_default();
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(&format!("Kafka/{}/Producer", topic), &brokers))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.set_span_layer(SpanLayer::Mq);
+                span_object.component_id =
+                    component_id("rdkafka_producer", COMPONENT_KAFKA_PRODUCER_ID);
+                span_object.add_tag(TAG_MQ_BROKER, &brokers);
+                span_object.add_tag(TAG_MQ_TOPIC, &topic);
+
+                if is_producev && execute_data.num_args() >= 5 {
+                    let sw_header = RequestContext::try_get_sw_header(request_id, &brokers)?;
+                    inject_sw_header(execute_data, sw_header);
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(|_, span, _, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+
+    /// `RdKafka\KafkaConsumer::__construct(RdKafka\Conf $conf)`.
+    fn hook_kafka_consumer_construct(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                let info = match get_conf(execute_data) {
+                    Some(conf) => {
+                        let dump = dump_conf(conf)?;
+                        ConsumerInfo {
+                            brokers: get_conf_value(&dump, BROKERS_CONF_NAMES),
+                            group_id: get_conf_value(&dump, &["group.id"]),
+                        }
+                    }
+                    None => Default::default(),
+                };
+
+                let handle = get_this_mut(execute_data)?.handle();
+                debug!(handle, ?info, "construct rdkafka consumer");
+                CONSUMER_INFOS.insert(handle, info);
+                HANDLE_REQUESTS.insert(handle, request_id);
+
+                Ok(Box::new(()))
+            }),
+            Noop::noop(),
+        )
+    }
+
+    /// `RdKafka\KafkaConsumer::consume(int $timeout_ms): RdKafka\Message`.
+    ///
+    /// The entry span is created after the message is received, to extract
+    /// the `sw8` header, and finished when the consumer consumes the next one,
+    /// or the request ends, so it covers the handling of the message. If there
+    /// is no request context (such as the consumer running in cli), a
+    /// standalone segment is created.
+    fn hook_kafka_consumer_consume(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, execute_data| {
+                finish_handling_span(get_this_mut(execute_data)?.handle());
+                Ok(Box::new(()))
+            }),
+            Box::new(|request_id, _, execute_data, return_value| {
+                let Some(message) = return_value.as_z_obj() else {
+                    return Ok(());
+                };
+                if message.get_property("err").as_long() != Some(RD_KAFKA_RESP_ERR_NO_ERROR) {
+                    return Ok(());
+                }
+
+                let topic = message
+                    .get_property("topic_name")
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned();
+                let sw_header = message
+                    .get_property("headers")
+                    .as_z_arr()
+                    .and_then(|headers| headers.get(SW_HEADER))
+                    .and_then(|header| header.as_z_str())
+                    .and_then(|header| header.to_str().ok())
+                    .map(ToOwned::to_owned);
+
+                let handle = get_this_mut(execute_data)?.handle();
+                let info = CONSUMER_INFOS
+                    .get(&handle)
+                    .map(|info| info.value().clone())
+                    .unwrap_or_default();
+
+                let propagation = sw_header
+                    .as_deref()
+                    .map(decode_propagation)
+                    .transpose()
+                    .map_err(|err| anyhow!("decode propagation failed: {}", err))?;
+
+                let operation_name = format!("Kafka/{}/Consumer/{}", topic, info.group_id);
+
+                let create_span = |ctx: &mut TracingContext| {
+                    let mut span = match &propagation {
+                        Some(propagation) => {
+                            ctx.create_entry_span_with_propagation(&operation_name, propagation)
+                        }
+                        None => ctx.create_entry_span(&operation_name),
+                    };
+
+                    let span_object = span.span_object_mut();
+                    span_object.set_span_layer(SpanLayer::Mq);
+                    span_object.component_id =
+                        component_id("rdkafka_consumer", COMPONENT_KAFKA_CONSUMER_ID);
+                    span_object.peer = info.brokers.clone();
+                    span_object.add_tag(TAG_MQ_BROKER, &info.brokers);
+                    span_object.add_tag(TAG_MQ_TOPIC, &topic);
+
+                    span
+                };
+
+                start_handling_span(request_id, handle, create_span);
+
+                Ok(())
+            }),
+        )
+    }
+}
+
+/// Remove the entries of the objects created in the request, called before
+/// the request context is finished.
+pub fn remove_request_handles(request_id: Option<i64>) {
+    HANDLE_REQUESTS.retain(|handle, id| {
+        if *id != request_id {
+            return true;
+        }
+        PRODUCER_BROKERS.remove(handle);
+        TOPIC_BROKERS.remove(handle);
+        CONSUMER_INFOS.remove(handle);
+        false
+    });
+}
+
+#[derive(Debug, Default, Clone)]
+struct ConsumerInfo {
+    brokers: String,
+    group_id: String,
+}
+
+fn get_conf(execute_data: &mut ExecuteData) -> Option<&mut ZObj> {
+    if execute_data.num_args() < 1 {
+        return None;
+    }
+    execute_data.get_mut_parameter(0).as_mut_z_obj()
+}
+
+fn dump_conf(conf: &mut ZObj) -> crate::Result<ZArray> {
+    let dump = conf.call("dump", [])?;
+    Ok(dump.as_z_arr().context("conf dump isn't array")?.to_owned())
+}
+
+/// Get the value by the first existing name, the names contain the aliases.
+fn get_conf_value(dump: &ZArr, names: &[&str]) -> String {
+    names
+        .iter()
+        .find_map(|name| dump.get(*name))
+        .and_then(|value| value.as_z_str())
+        .and_then(|value| value.to_str().ok())
+        .map(ToOwned::to_owned)
+        .unwrap_or_default()
+}
+
+fn call_string(obj: &mut ZObj, method: &str) -> crate::Result<String> {
+    Ok(obj.call(method, [])?.expect_z_str()?.to_str()?.to_owned())
+}
+
+/// Inject the header into the `$headers` argument of `producev`.
+fn inject_sw_header(execute_data: &mut ExecuteData, sw_header: String) {
+    let headers = execute_data.get_mut_parameter(4);
+    if headers.get_type_info().is_null() {
+        *headers = ZVal::from(ZArray::new());
+    }
+    if let Some(headers) = headers.as_mut_z_arr() {
+        headers.insert(SW_HEADER, sw_header);
+    }
+}
@@ -15,10 +15,11 @@
 
 use super::{log_exception, Plugin};
 use crate::{
-    component::COMPONENT_PHP_REDIS_ID,
+    component::{component_id, COMPONENT_PHP_REDIS_ID},
     context::RequestContext,
     execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook, Noop},
-    tag::{TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE},
+    tag::{TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE, TAG_REDIS_CLUSTER},
+    util::join_host_port,
 };
 use anyhow::Context;
 use dashmap::DashMap;
@@ -157,13 +158,29 @@ static REDIS_ALL_MAPPING: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     commands
 });
 
+/// Get the command and the operation (`read` or `write`) of the method in
+/// lower case, shared by the clients with the same methods as phpredis.
+pub(super) fn redis_command(
+    function_name_key: &str,
+) -> Option<(&'static str, Option<&'static str>)> {
+    let cmd = *REDIS_ALL_MAPPING.get(function_name_key)?;
+    let op = if REDIS_READ_MAPPING.contains_key(function_name_key) {
+        Some("read")
+    } else if REDIS_WRITE_MAPPING.contains_key(function_name_key) {
+        Some("write")
+    } else {
+        None
+    };
+    Some((cmd, op))
+}
+
 #[derive(Default, Clone)]
 pub struct RedisPlugin;
 
 impl Plugin for RedisPlugin {
     #[inline]
     fn class_names(&self) -> Option<&'static [&'static str]> {
-        Some(&["Redis"])
+        Some(&["Redis", "RedisCluster"])
     }
 
     #[inline]
@@ -176,12 +193,13 @@ impl Plugin for RedisPlugin {
     ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
         match (class_name, function_name) {
             (Some("Redis"), "__construct") => Some(self.hook_redis_construct()),
+            (Some("RedisCluster"), "__construct") => Some(self.hook_redis_cluster_construct()),
             (Some(class_name @ "Redis"), f)
                 if ["connect", "open", "pconnect", "popen"].contains(&f) =>
             {
                 Some(self.hook_redis_connect(class_name, function_name))
             }
-            (Some(class_name @ "Redis"), f)
+            (Some(class_name @ ("Redis" | "RedisCluster")), f)
                 if REDIS_ALL_MAPPING.contains_key(&*f.to_ascii_lowercase()) =>
             {
                 Some(self.hook_redis_methods(class_name, function_name))
@@ -206,6 +224,46 @@ impl RedisPlugin {
         )
     }
 
+    /// `RedisCluster::__construct(?string $name, ?array $seeds = null, ...)`,
+    /// the seeds of the cluster loaded from `redis.clusters.seeds` by name
+    /// aren't supported.
+    ///
+    /// The node handling the command isn't exposed by phpredis, so the first
+    /// seed is used as peer.
+    fn hook_redis_cluster_construct(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, execute_data| {
+                let seed = if execute_data.num_args() >= 2 {
+                    execute_data
+                        .get_parameter(1)
+                        .as_z_arr()
+                        .and_then(|seeds| seeds.iter().next())
+                        .and_then(|(_, seed)| seed.as_z_str())
+                        .and_then(|seed| seed.to_str().ok())
+                        .map(ToOwned::to_owned)
+                } else {
+                    None
+                };
+
+                let this = get_this_mut(execute_data)?;
+                hack_free(this, Some(redis_dtor));
+
+                let addr = seed.unwrap_or_default();
+                debug!(addr, "Get redis cluster peer");
+                PEER_MAP.insert(
+                    this.handle(),
+                    Peer {
+                        addr,
+                        is_cluster: true,
+                    },
+                );
+
+                Ok(Box::new(()))
+            }),
+            Noop::noop(),
+        )
+    }
+
     fn hook_redis_connect(
         &self, class_name: &str, function_name: &str,
     ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
@@ -251,9 +309,15 @@ impl RedisPlugin {
                 };
 
                 let this = get_this_mut(execute_data)?;
-                let addr = format!("{}:{}", host, port);
+                let addr = join_host_port(&host, port);
                 debug!(addr, "Get redis peer");
-                PEER_MAP.insert(this.handle(), Peer { addr: addr.clone() });
+                PEER_MAP.insert(
+                    this.handle(),
+                    Peer {
+                        addr: addr.clone(),
+                        is_cluster: false,
+                    },
+                );
 
                 let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
                     Ok(ctx.create_exit_span(&format!("{}->{}", class_name, function_name), &addr))
@@ -261,7 +325,7 @@ impl RedisPlugin {
 
                 let span_object = span.span_object_mut();
                 span_object.set_span_layer(SpanLayer::Cache);
-                span_object.component_id = COMPONENT_PHP_REDIS_ID;
+                span_object.component_id = component_id("redis", COMPONENT_PHP_REDIS_ID);
                 span_object.add_tag(TAG_CACHE_TYPE, "redis");
 
                 Ok(Box::new(span))
@@ -279,20 +343,13 @@ impl RedisPlugin {
             Box::new(move |request_id, execute_data| {
                 let handle = get_this_mut(execute_data)?.handle();
                 debug!(handle, function_name, "call redis method");
-                let peer = PEER_MAP
+                let (peer, is_cluster) = PEER_MAP
                     .get(&handle)
-                    .map(|r| r.value().addr.clone())
+                    .map(|r| (r.value().addr.clone(), r.value().is_cluster))
                     .unwrap_or_default();
 
-                let function_name_key = &*function_name.to_ascii_lowercase();
-
-                let op = if REDIS_READ_MAPPING.contains_key(function_name_key) {
-                    Some("read")
-                } else if REDIS_WRITE_MAPPING.contains_key(function_name_key) {
-                    Some("write")
-                } else {
-                    None
-                };
+                let (cmd, op) = redis_command(&function_name.to_ascii_lowercase())
+                    .context("unknown redis command")?;
 
                 let key = op
                     .and_then(|_| execute_data.get_parameter(0).as_z_str())
@@ -306,18 +363,18 @@ impl RedisPlugin {
 
                 let span_object = span.span_object_mut();
                 span_object.set_span_layer(SpanLayer::Cache);
-                span_object.component_id = COMPONENT_PHP_REDIS_ID;
+                span_object.component_id = component_id("redis", COMPONENT_PHP_REDIS_ID);
                 span_object.add_tag(TAG_CACHE_TYPE, "redis");
-                span_object.add_tag(
-                    TAG_CACHE_CMD,
-                    *REDIS_ALL_MAPPING.get(function_name_key).unwrap(),
-                );
+                span_object.add_tag(TAG_CACHE_CMD, cmd);
                 if let Some(op) = op {
                     span_object.add_tag(TAG_CACHE_OP, op);
                 }
                 if let Some(key) = key {
                     span_object.add_tag(TAG_CACHE_KEY, key)
                 }
+                if is_cluster {
+                    span_object.add_tag(TAG_REDIS_CLUSTER, "true");
+                }
 
                 Ok(Box::new(span))
             }),
@@ -328,6 +385,7 @@ impl RedisPlugin {
 
 struct Peer {
     addr: String,
+    is_cluster: bool,
 }
 
 fn hack_free(this: &mut ZObj, new_free: sys::zend_object_free_obj_t) {
@@ -0,0 +1,162 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugins naming the entry span by the matched route instead of the path,
+//! enabled by `skywalking_agent.route_operation_name`, so the requests of the
+//! same route are aggregated into one endpoint.
+//!
+//! Laravel names it by the route uri like `GET:/orders/{id}`, Symfony by the
+//! route name like `GET:app_order_show`, because only the name is kept in the
+//! request attributes. No span is created.
+
+use super::Plugin;
+use crate::{
+    context::RequestContext,
+    execute::{AfterExecuteHook, BeforeExecuteHook},
+    module::ROUTE_OPERATION_NAME,
+    util::z_val_to_string,
+};
+use anyhow::Context;
+use phper::values::ZVal;
+
+const LARAVEL_ROUTER_CLASS_NAMES: &[&str] = &[r"Illuminate\Routing\Router"];
+
+const SYMFONY_ROUTER_LISTENER_CLASS_NAMES: &[&str] =
+    &[r"Symfony\Component\HttpKernel\EventListener\RouterListener"];
+
+/// `HttpKernelInterface::MAIN_REQUEST`, the sub requests aren't renamed.
+const SYMFONY_MAIN_REQUEST: i64 = 1;
+
+#[derive(Default, Clone)]
+pub struct LaravelRoutePlugin;
+
+impl Plugin for LaravelRoutePlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(LARAVEL_ROUTER_CLASS_NAMES)
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, _class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match function_name {
+            "findRoute" if *ROUTE_OPERATION_NAME => Some(self.hook_find_route()),
+            _ => None,
+        }
+    }
+}
+
+impl LaravelRoutePlugin {
+    /// `findRoute(Request $request): Route`.
+    fn hook_find_route(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, _| Ok(Box::new(()))),
+            Box::new(|request_id, _, execute_data, return_value| {
+                // Not found throws exception, and returns nothing.
+                let Some(route) = return_value.as_mut_z_obj() else {
+                    return Ok(());
+                };
+                let uri = route.call("uri", [])?;
+
+                let method = execute_data
+                    .get_mut_parameter(0)
+                    .as_mut_z_obj()
+                    .context("request isn't object")?
+                    .call("getMethod", [])?;
+
+                rename_by_route(request_id, &method, &uri, true);
+
+                Ok(())
+            }),
+        )
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct SymfonyRoutePlugin;
+
+impl Plugin for SymfonyRoutePlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(SYMFONY_ROUTER_LISTENER_CLASS_NAMES)
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, _class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match function_name {
+            "onKernelRequest" if *ROUTE_OPERATION_NAME => Some(self.hook_on_kernel_request()),
+            _ => None,
+        }
+    }
+}
+
+impl SymfonyRoutePlugin {
+    /// `onKernelRequest(RequestEvent $event)`, the matched route is set to the
+    /// `_route` attribute of the request.
+    fn hook_on_kernel_request(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|_, _| Ok(Box::new(()))),
+            Box::new(|request_id, _, execute_data, _| {
+                let event = execute_data
+                    .get_mut_parameter(0)
+                    .as_mut_z_obj()
+                    .context("event isn't object")?;
+                if event.call("getRequestType", [])?.as_long() != Some(SYMFONY_MAIN_REQUEST) {
+                    return Ok(());
+                }
+
+                let mut request = event.call("getRequest", [])?;
+                let request = request.expect_mut_z_obj()?;
+                let method = request.call("getMethod", [])?;
+                let route = request
+                    .get_mut_property("attributes")
+                    .expect_mut_z_obj()?
+                    .call("get", [ZVal::from("_route")])?;
+
+                rename_by_route(request_id, &method, &route, false);
+
+                Ok(())
+            }),
+        )
+    }
+}
+
+/// Named as the same format of the path, like `GET:/orders/{id}`, the route uri
+/// of Laravel has no leading slash.
+fn rename_by_route(request_id: Option<i64>, method: &ZVal, route: &ZVal, is_uri: bool) {
+    let (Some(method), Some(route)) = (z_val_to_string(method), z_val_to_string(route)) else {
+        return;
+    };
+    if route.is_empty() {
+        return;
+    }
+    let operation_name = if is_uri {
+        format!("{}:/{}", method, route.trim_start_matches('/'))
+    } else {
+        format!("{}:{}", method, route)
+    };
+    RequestContext::rename_entry_span(request_id, operation_name);
+}
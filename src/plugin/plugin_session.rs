@@ -0,0 +1,143 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin for the [session](https://www.php.net/manual/en/book.session.php)
+//! functions, enabled by `skywalking_agent.trace_sessions`.
+//!
+//! The save handler is called inside the session functions, so the
+//! functions are hooked instead of the handler, which covers both the native
+//! handlers (like `redis` and `memcached`) and the user handlers. The implicit
+//! write at the end of request isn't traced.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{
+        component_id, COMPONENT_PHP_ID, COMPONENT_PHP_MEMCACHED_ID, COMPONENT_PHP_REDIS_ID,
+    },
+    context::RequestContext,
+    execute::{AfterExecuteHook, BeforeExecuteHook},
+    module::TRACE_SESSIONS,
+    util::{get_str_ini_with_default, host_to_peer, join_host_port},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+use url::Url;
+
+const TAG_SESSION_HANDLER: &str = "session.handler";
+
+#[derive(Default, Clone)]
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        Some("session_")
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        if !*TRACE_SESSIONS {
+            return None;
+        }
+        let operation = match (class_name, function_name) {
+            (None, "session_start") => "read",
+            (None, "session_write_close" | "session_commit") => "write",
+            (None, "session_destroy") => "destroy",
+            (None, "session_gc") => "gc",
+            _ => return None,
+        };
+        Some(self.hook_session_operation(operation))
+    }
+}
+
+impl SessionPlugin {
+    fn hook_session_operation(
+        &self, operation: &'static str,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(move |request_id, _| {
+                let handler = get_str_ini_with_default("session.save_handler");
+                let operation_name = format!("Session/{}", operation);
+
+                let mut span = match get_peer(&handler) {
+                    Some(peer) => {
+                        let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                            Ok(ctx.create_exit_span(&operation_name, &peer))
+                        })?;
+                        let span_object = span.span_object_mut();
+                        span_object.set_span_layer(SpanLayer::Cache);
+                        let default_id = match &*handler {
+                            "redis" | "rediscluster" => COMPONENT_PHP_REDIS_ID,
+                            _ => COMPONENT_PHP_MEMCACHED_ID,
+                        };
+                        span_object.component_id = component_id("session", default_id);
+                        span
+                    }
+                    None => {
+                        let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                            Ok(ctx.create_local_span(&operation_name))
+                        })?;
+                        span.span_object_mut().component_id =
+                            component_id("session", COMPONENT_PHP_ID);
+                        span
+                    }
+                };
+                span.add_tag(TAG_SESSION_HANDLER, handler);
+
+                Ok(Box::new(span))
+            }),
+            Box::new(move |_, span, _, return_value| {
+                let mut span = span.downcast::<Span>().unwrap();
+                if log_exception(&mut *span).is_none() && return_value.as_bool() == Some(false) {
+                    span.span_object_mut().is_error = true;
+                }
+                Ok(())
+            }),
+        )
+    }
+}
+
+/// The peer of the remote save handlers, parsed from the first server of
+/// `session.save_path`, like `tcp://127.0.0.1:6379?auth=x` of redis and
+/// `127.0.0.1:11211` of memcached.
+fn get_peer(handler: &str) -> Option<String> {
+    let default_port = match handler {
+        "redis" | "rediscluster" => 6379,
+        "memcached" | "memcache" => 11211,
+        _ => return None,
+    };
+
+    let save_path = get_str_ini_with_default("session.save_path");
+    let server = save_path.split(',').next()?.trim();
+    if server.is_empty() {
+        return None;
+    }
+
+    if server.contains("://") {
+        let url = Url::parse(server).ok()?;
+        let host = url.host_str().filter(|host| !host.is_empty())?;
+        Some(join_host_port(host, url.port().unwrap_or(default_port)))
+    } else {
+        Some(host_to_peer(server, default_port))
+    }
+}
@@ -0,0 +1,158 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin for the [program execution](https://www.php.net/manual/en/book.exec.php)
+//! functions, enabled by `skywalking_agent.trace_subprocess`.
+//!
+//! The span is named after the binary only, because the arguments may contain
+//! the secrets, the full command line is tagged only when
+//! `skywalking_agent.trace_subprocess_command` is enabled. The span of
+//! `proc_open` ends when the process is opened, not when it exits.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_PHP_ID},
+    context::RequestContext,
+    execute::{AfterExecuteHook, BeforeExecuteHook},
+    module::{TRACE_SUBPROCESS, TRACE_SUBPROCESS_COMMAND},
+    util::z_val_to_string,
+};
+use phper::values::{ExecuteData, ZVal};
+use skywalking::trace::span::{HandleSpanObject, Span};
+
+const TAG_SUBPROCESS_FUNCTION: &str = "subprocess.function";
+const TAG_SUBPROCESS_COMMAND: &str = "subprocess.command";
+const TAG_SUBPROCESS_EXIT_CODE: &str = "subprocess.exit_code";
+
+const FUNCTION_NAMES: &[&str] = &["exec", "system", "shell_exec", "proc_open"];
+
+#[derive(Default, Clone)]
+pub struct SubprocessPlugin;
+
+impl Plugin for SubprocessPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    #[inline]
+    fn function_names(&self) -> Option<&'static [&'static str]> {
+        Some(FUNCTION_NAMES)
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        if !*TRACE_SUBPROCESS {
+            return None;
+        }
+        // The index of the `$result_code` parameter.
+        let result_code_index = match (class_name, function_name) {
+            (None, "exec") => Some(2),
+            (None, "system") => Some(1),
+            (None, "proc_open" | "shell_exec") => None,
+            _ => return None,
+        };
+        Some(self.hook_execute(function_name.to_owned(), result_code_index))
+    }
+}
+
+impl SubprocessPlugin {
+    fn hook_execute(
+        &self, function_name: String, result_code_index: Option<usize>,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(move |request_id, execute_data| {
+                let Some(command) = get_command(execute_data) else {
+                    return Ok(Box::new(()));
+                };
+                let binary = binary_name(&command).unwrap_or("unknown");
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_local_span(&format!("Subprocess/{}", binary)))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.component_id = component_id("subprocess", COMPONENT_PHP_ID);
+                span_object.add_tag(TAG_SUBPROCESS_FUNCTION, &function_name);
+                if *TRACE_SUBPROCESS_COMMAND {
+                    span_object.add_tag(TAG_SUBPROCESS_COMMAND, command);
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(move |_, span, execute_data, return_value| {
+                let Ok(mut span) = span.downcast::<Span>() else {
+                    return Ok(());
+                };
+
+                if log_exception(&mut *span).is_some() {
+                    return Ok(());
+                }
+
+                let exit_code = result_code_index
+                    .filter(|index| execute_data.num_args() > *index)
+                    .and_then(|index| get_result_code(execute_data.get_parameter(index)));
+                if let Some(exit_code) = exit_code {
+                    span.add_tag(TAG_SUBPROCESS_EXIT_CODE, exit_code.to_string());
+                }
+
+                if return_value.as_bool() == Some(false)
+                    || matches!(exit_code, Some(code) if code != 0)
+                {
+                    span.span_object_mut().is_error = true;
+                }
+
+                Ok(())
+            }),
+        )
+    }
+}
+
+/// The command is the string, or the array of the binary and arguments
+/// accepted by `proc_open`.
+fn get_command(execute_data: &mut ExecuteData) -> Option<String> {
+    if execute_data.num_args() < 1 {
+        return None;
+    }
+    let command = execute_data.get_parameter(0);
+    if let Some(args) = command.as_z_arr() {
+        let args = args.iter().filter_map(|(_, arg)| z_val_to_string(arg));
+        return Some(args.collect::<Vec<_>>().join(" "));
+    }
+    z_val_to_string(command)
+}
+
+/// The `$result_code` is passed by reference.
+fn get_result_code(result_code: &ZVal) -> Option<i64> {
+    result_code
+        .as_long()
+        .or_else(|| result_code.as_z_ref()?.val().as_long())
+}
+
+/// The base name of the first token, skipping the leading environment
+/// assignments like `LANG=C convert ...`.
+fn binary_name(command: &str) -> Option<&str> {
+    let token = command
+        .split_whitespace()
+        .find(|token| !token.contains('='))?
+        .trim_matches(|c| c == '\'' || c == '"');
+    token.rsplit('/').next().filter(|name| !name.is_empty())
+}
@@ -15,10 +15,12 @@
 
 use crate::{
     execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook, Noop},
+    module::is_swoole_enabled,
     plugin::Plugin,
     request::{
-        HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME, IS_SWOOLE, ORI_SWOOLE_ON_REQUEST,
-        SWOOLE_RESPONSE_STATUS_MAP,
+        HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME, HACK_SWOOLE_ON_OPEN_FUNCTION_NAME,
+        HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME, IS_SWOOLE, ORI_SWOOLE_ON_MESSAGE, ORI_SWOOLE_ON_OPEN,
+        ORI_SWOOLE_ON_REQUEST, SWOOLE_RESPONSE_STATUS_MAP,
     },
 };
 use phper::{strings::ZString, values::ZVal};
@@ -42,7 +44,7 @@ impl Plugin for SwooleServerPlugin {
         &self, _class_name: Option<&str>, function_name: &str,
     ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
         match function_name {
-            "on" => Some(self.hook_on()),
+            "on" if is_swoole_enabled() => Some(self.hook_on()),
             _ => None,
         }
     }
@@ -54,25 +56,27 @@ impl SwooleServerPlugin {
             Box::new(|_, execute_data| {
                 validate_num_args(execute_data, 2)?;
 
-                let on = execute_data.get_parameter(0);
-                if !on
+                let on = execute_data
+                    .get_parameter(0)
                     .as_z_str()
                     .and_then(|s| s.to_str().ok())
-                    .map(|s| s.to_lowercase() == "request")
-                    .unwrap_or_default()
-                {
-                    return Ok(Box::new(()));
-                }
+                    .map(|s| s.to_lowercase())
+                    .unwrap_or_default();
+
+                let (hack_function_name, ori_callback) = match on.as_str() {
+                    "request" => (HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME, &ORI_SWOOLE_ON_REQUEST),
+                    "open" => (HACK_SWOOLE_ON_OPEN_FUNCTION_NAME, &ORI_SWOOLE_ON_OPEN),
+                    "message" => (HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME, &ORI_SWOOLE_ON_MESSAGE),
+                    _ => return Ok(Box::new(())),
+                };
 
                 // Hack the closure with the
-                // [`crate::request::skywalking_hack_swoole_on_request`].
+                // [`crate::request::skywalking_hack_swoole_on_request`] or the
+                // websocket ones.
                 let closure = execute_data.get_mut_parameter(1);
-                let ori_closure = replace(
-                    closure,
-                    ZVal::from(ZString::new(HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME)),
-                );
+                let ori_closure = replace(closure, ZVal::from(ZString::new(hack_function_name)));
 
-                ORI_SWOOLE_ON_REQUEST.store(
+                ori_callback.store(
                     Box::into_raw(Box::new(ori_closure)).cast(),
                     Ordering::Relaxed,
                 );
@@ -103,7 +107,7 @@ impl Plugin for SwooleHttpResponsePlugin {
         &self, _class_name: Option<&str>, function_name: &str,
     ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
         match function_name {
-            "status" => Some(self.hook_status()),
+            "status" if is_swoole_enabled() => Some(self.hook_status()),
             _ => None,
         }
     }
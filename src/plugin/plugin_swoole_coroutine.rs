@@ -0,0 +1,344 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugins for the coroutine clients of swoole, `Swoole\Coroutine\Redis` and
+//! `Swoole\Coroutine\MySQL`, the spans are the same as the `redis` and
+//! `mysqli` plugins.
+//!
+//! The peer is read from the properties set by swoole when connected, so no
+//! state is kept by the plugins. The statement of
+//! `Swoole\Coroutine\MySQL\Statement::execute` isn't exposed, so only
+//! `prepare` is traced with the SQL.
+
+use super::{
+    log_exception,
+    plugin_redis::redis_command,
+    sql::{format_statement, SqlDialect},
+    Plugin,
+};
+use crate::{
+    component::{component_id, COMPONENT_PHP_MYSQLI_ID, COMPONENT_PHP_REDIS_ID},
+    context::RequestContext,
+    execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook},
+    tag::{
+        TAG_CACHE_CMD, TAG_CACHE_KEY, TAG_CACHE_OP, TAG_CACHE_TYPE, TAG_DB_STATEMENT, TAG_DB_TYPE,
+    },
+    util::{join_host_port, z_val_to_string},
+};
+use phper::{
+    arrays::ZArr,
+    objects::ZObj,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+use std::any::Any;
+
+const REDIS_CLASS_NAMES: &[&str] = &[r"Swoole\Coroutine\Redis", r"Co\Redis"];
+
+const MYSQL_CLASS_NAMES: &[&str] = &[r"Swoole\Coroutine\MySQL", r"Co\MySQL"];
+
+const DEFAULT_REDIS_PORT: i64 = 6379;
+
+const DEFAULT_MYSQL_PORT: i64 = 3306;
+
+#[derive(Default, Clone)]
+pub struct SwooleRedisPlugin;
+
+impl Plugin for SwooleRedisPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(REDIS_CLASS_NAMES)
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        let class_name = class_name?;
+        match function_name {
+            "connect" => Some(self.hook_connect(class_name)),
+            f => {
+                let (cmd, op) = redis_command(&f.to_ascii_lowercase())?;
+                Some(self.hook_command(class_name, function_name, cmd, op))
+            }
+        }
+    }
+}
+
+impl SwooleRedisPlugin {
+    /// `connect(string $host, int $port = 6379, bool $serialize = false)`.
+    fn hook_connect(&self, class_name: &str) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let operation_name = format!("{}->connect", class_name);
+        (
+            Box::new(move |request_id, execute_data| {
+                let Some(host) = get_str_parameter(execute_data, 0) else {
+                    return Ok(Box::new(()));
+                };
+                let port = get_long_parameter(execute_data, 1).unwrap_or(DEFAULT_REDIS_PORT);
+                let span =
+                    create_redis_span(request_id, &operation_name, &join_host_port(&host, port))?;
+                Ok(Box::new(span))
+            }),
+            Box::new(after_redis_hook),
+        )
+    }
+
+    fn hook_command(
+        &self, class_name: &str, function_name: &str, cmd: &'static str, op: Option<&'static str>,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let operation_name = format!("{}->{}", class_name, function_name);
+        (
+            Box::new(move |request_id, execute_data| {
+                let peer = get_redis_peer(get_this_mut(execute_data)?);
+                let key = op.and_then(|_| get_str_parameter(execute_data, 0));
+
+                let mut span = create_redis_span(request_id, &operation_name, &peer)?;
+                let span_object = span.span_object_mut();
+                span_object.add_tag(TAG_CACHE_CMD, cmd);
+                if let Some(op) = op {
+                    span_object.add_tag(TAG_CACHE_OP, op);
+                }
+                if let Some(key) = key {
+                    span_object.add_tag(TAG_CACHE_KEY, key);
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(after_redis_hook),
+        )
+    }
+}
+
+fn create_redis_span(
+    request_id: Option<i64>, operation_name: &str, peer: &str,
+) -> anyhow::Result<Span> {
+    RequestContext::try_with_global_ctx(request_id, |ctx| {
+        let mut span = ctx.create_exit_span(operation_name, peer);
+
+        let span_object = span.span_object_mut();
+        span_object.set_span_layer(SpanLayer::Cache);
+        span_object.component_id = component_id("swoole_redis", COMPONENT_PHP_REDIS_ID);
+        span_object.add_tag(TAG_CACHE_TYPE, "redis");
+
+        Ok(span)
+    })
+}
+
+/// The `host` and `port` properties are set when connected.
+fn get_redis_peer(this: &ZObj) -> String {
+    let host = z_val_to_string(this.get_property("host")).unwrap_or_default();
+    let port = this
+        .get_property("port")
+        .as_long()
+        .unwrap_or(DEFAULT_REDIS_PORT);
+    join_host_port(&host, port)
+}
+
+/// The failed command returns false, and sets the `errCode` and `errMsg`
+/// properties.
+fn after_redis_hook(
+    _request_id: Option<i64>, span: Box<dyn Any>, execute_data: &mut ExecuteData,
+    return_value: &mut ZVal,
+) -> crate::Result<()> {
+    let Ok(mut span) = span.downcast::<Span>() else {
+        return Ok(());
+    };
+
+    if log_exception(&mut *span).is_some() || return_value.as_bool() != Some(false) {
+        return Ok(());
+    }
+
+    let this = get_this_mut(execute_data)?;
+    let err_code = this.get_property("errCode").as_long().unwrap_or_default();
+    if err_code != 0 {
+        let span_object = span.span_object_mut();
+        span_object.is_error = true;
+        if let Some(err_msg) = z_val_to_string(this.get_property("errMsg")) {
+            span_object.add_log(vec![("SWOOLE_REDIS_ERROR", err_msg)]);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default, Clone)]
+pub struct SwooleMysqlPlugin;
+
+impl Plugin for SwooleMysqlPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(MYSQL_CLASS_NAMES)
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        let class_name = class_name?;
+        match function_name {
+            "connect" => Some(self.hook_connect(class_name)),
+            "query" | "prepare" => Some(self.hook_method(class_name, function_name, true)),
+            "begin" | "commit" | "rollback" => {
+                Some(self.hook_method(class_name, function_name, false))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl SwooleMysqlPlugin {
+    /// `connect(array $server_config)`, the config has the `host` and `port`.
+    fn hook_connect(&self, class_name: &str) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let operation_name = format!("{}->connect", class_name);
+        (
+            Box::new(move |request_id, execute_data| {
+                if execute_data.num_args() < 1 {
+                    return Ok(Box::new(()));
+                }
+                let Some(peer) = execute_data
+                    .get_parameter(0)
+                    .as_z_arr()
+                    .and_then(get_mysql_peer)
+                else {
+                    return Ok(Box::new(()));
+                };
+                let span = create_mysql_span(request_id, &operation_name, &peer)?;
+                Ok(Box::new(span))
+            }),
+            Box::new(after_mysql_hook),
+        )
+    }
+
+    /// The first parameter of `query` and `prepare` is the SQL.
+    fn hook_method(
+        &self, class_name: &str, function_name: &str, has_statement: bool,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let operation_name = format!("{}->{}", class_name, function_name);
+        (
+            Box::new(move |request_id, execute_data| {
+                let peer = get_this_mut(execute_data)?
+                    .get_property("serverInfo")
+                    .as_z_arr()
+                    .and_then(get_mysql_peer)
+                    .unwrap_or_default();
+                let statement = has_statement
+                    .then(|| get_str_parameter(execute_data, 0))
+                    .flatten();
+
+                let mut span = create_mysql_span(request_id, &operation_name, &peer)?;
+                if let Some(statement) = statement {
+                    span.add_tag(
+                        TAG_DB_STATEMENT,
+                        format_statement(&statement, SqlDialect::Mysql),
+                    );
+                }
+
+                Ok(Box::new(span))
+            }),
+            Box::new(after_mysql_hook),
+        )
+    }
+}
+
+fn create_mysql_span(
+    request_id: Option<i64>, operation_name: &str, peer: &str,
+) -> anyhow::Result<Span> {
+    RequestContext::try_with_global_ctx(request_id, |ctx| {
+        let mut span = ctx.create_exit_span(operation_name, peer);
+
+        let span_object = span.span_object_mut();
+        span_object.set_span_layer(SpanLayer::Database);
+        span_object.component_id = component_id("swoole_mysql", COMPONENT_PHP_MYSQLI_ID);
+        span_object.add_tag(TAG_DB_TYPE, "mysql");
+
+        Ok(span)
+    })
+}
+
+/// The server config passed to `connect`, which is also kept in the
+/// `serverInfo` property.
+fn get_mysql_peer(config: &ZArr) -> Option<String> {
+    let host = config.get("host").and_then(z_val_to_string)?;
+    let port = config
+        .get("port")
+        .and_then(|port| {
+            port.as_long()
+                .or_else(|| z_val_to_string(port)?.parse().ok())
+        })
+        .unwrap_or(DEFAULT_MYSQL_PORT);
+    Some(join_host_port(&host, port))
+}
+
+/// The failed call returns false, and sets the `errno` and `error` properties,
+/// or the `connect_errno` and `connect_error` properties when connecting.
+fn after_mysql_hook(
+    _request_id: Option<i64>, span: Box<dyn Any>, execute_data: &mut ExecuteData,
+    return_value: &mut ZVal,
+) -> crate::Result<()> {
+    let Ok(mut span) = span.downcast::<Span>() else {
+        return Ok(());
+    };
+
+    if log_exception(&mut *span).is_some() || return_value.as_bool() != Some(false) {
+        return Ok(());
+    }
+
+    let span_object = span.span_object_mut();
+    span_object.is_error = true;
+
+    let this = get_this_mut(execute_data)?;
+    let (errno, error) = if this.get_property("connected").as_bool() == Some(false) {
+        ("connect_errno", "connect_error")
+    } else {
+        ("errno", "error")
+    };
+    let mut logs = Vec::new();
+    if let Some(errno) = this.get_property(errno).as_long() {
+        logs.push(("SWOOLE_MYSQL_ERRNO", errno.to_string()));
+    }
+    if let Some(error) = z_val_to_string(this.get_property(error)) {
+        logs.push(("SWOOLE_MYSQL_ERROR", error));
+    }
+    if !logs.is_empty() {
+        span_object.add_log(logs);
+    }
+
+    Ok(())
+}
+
+fn get_str_parameter(execute_data: &mut ExecuteData, index: usize) -> Option<String> {
+    if execute_data.num_args() <= index {
+        return None;
+    }
+    z_val_to_string(execute_data.get_parameter(index))
+}
+
+fn get_long_parameter(execute_data: &mut ExecuteData, index: usize) -> Option<i64> {
+    if execute_data.num_args() <= index {
+        return None;
+    }
+    execute_data.get_parameter(index).as_long()
+}
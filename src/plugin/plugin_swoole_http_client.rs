@@ -0,0 +1,174 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_PHP_ID},
+    context::{
+        traceparent_header, RequestContext, SW_CORRELATION_HEADER, SW_HEADER, SW_X_HEADER,
+        W3C_TRACEPARENT_HEADER,
+    },
+    execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook},
+    tag::sanitize_url,
+    util::join_host_port,
+};
+use anyhow::Context;
+use phper::{arrays::ZArray, objects::ZObj, values::ZVal};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+
+const CLIENT_CLASS_NAMES: &[&str] = &[r"Swoole\Coroutine\Http\Client", r"Co\Http\Client"];
+
+#[derive(Default, Clone)]
+pub struct SwooleHttpClientPlugin;
+
+impl Plugin for SwooleHttpClientPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(CLIENT_CLASS_NAMES)
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, _class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match function_name {
+            "get" => Some(self.hook_request(Some("GET"))),
+            "post" => Some(self.hook_request(Some("POST"))),
+            "execute" => Some(self.hook_request(None)),
+            _ => None,
+        }
+    }
+}
+
+impl SwooleHttpClientPlugin {
+    fn hook_request(
+        &self, method: Option<&'static str>,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(move |request_id, execute_data| {
+                validate_num_args(execute_data, 1)?;
+
+                let path = execute_data
+                    .get_parameter(0)
+                    .as_z_str()
+                    .context("path isn't string")?
+                    .to_str()?
+                    .to_owned();
+
+                let this = get_this_mut(execute_data)?;
+
+                let host = this
+                    .get_property("host")
+                    .as_z_str()
+                    .and_then(|s| s.to_str().ok())
+                    .unwrap_or("unknown")
+                    .to_owned();
+                let is_ssl = this.get_property("ssl").as_bool().unwrap_or_default();
+                let port =
+                    this.get_property("port")
+                        .as_long()
+                        .unwrap_or(if is_ssl { 443 } else { 80 });
+                let peer = join_host_port(&host, port);
+
+                let method = match method {
+                    Some(method) => method.to_owned(),
+                    None => this
+                        .get_property("requestMethod")
+                        .as_z_str()
+                        .and_then(|s| s.to_str().ok())
+                        .map(ToOwned::to_owned)
+                        .unwrap_or_else(|| "GET".to_owned()),
+                };
+
+                let scheme = if is_ssl { "https" } else { "http" };
+                let url = format!("{}://{}{}", scheme, peer, path);
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(path.split('?').next().unwrap_or_default(), &peer))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.set_span_layer(SpanLayer::Http);
+                span_object.component_id = component_id("swoole_http_client", COMPONENT_PHP_ID);
+                span_object.add_tag("url", sanitize_url(&url));
+                span_object.add_tag("http.method", method);
+
+                inject_sw_header(request_id, this, &peer)?;
+
+                Ok(Box::new(span))
+            }),
+            Box::new(|_, span, execute_data, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+
+                let this = get_this_mut(execute_data)?;
+
+                // The negative status code means connect failed, timeout or reset by server.
+                if let Some(status_code) = this.get_property("statusCode").as_long() {
+                    span.add_tag("status_code", status_code.to_string());
+                    if status_code < 0 || status_code >= 400 {
+                        span.span_object_mut().is_error = true;
+                    }
+                    if status_code < 0 {
+                        if let Some(err_msg) = this
+                            .get_property("errMsg")
+                            .as_z_str()
+                            .and_then(|s| s.to_str().ok())
+                        {
+                            span.span_object_mut()
+                                .add_log(vec![("SWOOLE_HTTP_CLIENT_ERROR", err_msg)]);
+                        }
+                    }
+                }
+
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+}
+
+fn inject_sw_header(request_id: Option<i64>, this: &mut ZObj, peer: &str) -> crate::Result<()> {
+    let sw_header = RequestContext::try_get_sw_header(request_id, peer)?;
+    let sw_x_header = RequestContext::try_get_sw_x_header(request_id)?;
+    let sw_correlation_header = RequestContext::try_get_sw_correlation_header(request_id)?;
+    let traceparent = traceparent_header(&sw_header);
+
+    let mut headers = this.get_property("requestHeaders").clone();
+    if !headers.get_type_info().is_array() {
+        headers = ZVal::from(ZArray::new());
+    }
+    if let Some(headers) = headers.as_mut_z_arr() {
+        headers.insert(SW_HEADER, sw_header);
+        if let Some(traceparent) = traceparent {
+            headers.insert(W3C_TRACEPARENT_HEADER, traceparent);
+        }
+        if let Some(sw_x_header) = sw_x_header {
+            headers.insert(SW_X_HEADER, sw_x_header);
+        }
+        if let Some(sw_correlation_header) = sw_correlation_header {
+            headers.insert(SW_CORRELATION_HEADER, sw_correlation_header);
+        }
+    }
+    this.call("setHeaders", [headers])?;
+
+    Ok(())
+}
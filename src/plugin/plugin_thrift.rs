@@ -0,0 +1,244 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugin for the clients generated by [thrift](https://github.com/apache/thrift/tree/master/lib/php).
+//!
+//! The generated client calls `send_<method>` and then `recv_<method>`, the
+//! class names are arbitrary, so the methods are hooked by the prefix, and the
+//! class is treated as thrift client only if it has the protocol property
+//! `output_`.
+//!
+//! The span is held by the client object from `send_` to `recv_`, the oneway
+//! method has no `recv_`, so its span is finished by the next call of the
+//! client, or lost if there is no next call in the request.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_THRIFT_CLIENT_ID},
+    context::{RequestContext, SW_HEADER},
+    execute::{get_this_mut, AfterExecuteHook, BeforeExecuteHook, Noop},
+    util::join_host_port,
+};
+use phper::{eg, functions::call, objects::ZObj, values::ZVal};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{HandleSpanObject, Span},
+};
+use std::{cell::RefCell, collections::HashMap};
+use tracing::debug;
+
+const SEND_PREFIX: &str = "send_";
+const RECV_PREFIX: &str = "recv_";
+
+const TAG_PROPAGATION: &str = "sw.propagation";
+
+/// The max depth of the wrapped transports, like `TFramedTransport` wrapping
+/// `TSocket`.
+const MAX_TRANSPORT_DEPTH: usize = 4;
+
+thread_local! {
+    /// The spans of the sent calls, keyed by the handle of client object.
+    static THRIFT_CALL_SPANS: RefCell<HashMap<u32, Span>> = Default::default();
+}
+
+#[derive(Default, Clone)]
+pub struct ThriftSendPlugin;
+
+impl Plugin for ThriftSendPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        Some(SEND_PREFIX)
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        let method = function_name.strip_prefix(SEND_PREFIX)?;
+        match class_name {
+            Some(class_name) if !method.is_empty() => Some(self.hook_send(class_name, method)),
+            _ => None,
+        }
+    }
+}
+
+impl ThriftSendPlugin {
+    fn hook_send(
+        &self, class_name: &str, method: &str,
+    ) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        let operation_name = format!("Thrift/{}/{}", get_service_name(class_name), method);
+
+        (
+            Box::new(move |request_id, execute_data| {
+                let Some(this) = execute_data.get_this_mut() else {
+                    return Ok(Box::new(()));
+                };
+                let handle = this.handle();
+                let Some(protocol) = this.get_mut_property("output_").as_mut_z_obj() else {
+                    return Ok(Box::new(()));
+                };
+
+                // The span of the last oneway call.
+                remove_call_span(handle);
+
+                let peer = get_peer(protocol);
+
+                let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                    Ok(ctx.create_exit_span(&operation_name, &peer))
+                })?;
+
+                let span_object = span.span_object_mut();
+                span_object.set_span_layer(SpanLayer::RpcFramework);
+                span_object.component_id = component_id("thrift", COMPONENT_THRIFT_CLIENT_ID);
+
+                let sw_header = RequestContext::try_get_sw_header(request_id, &peer)?;
+                if !inject_sw_header(protocol, &sw_header) {
+                    span.add_tag(TAG_PROPAGATION, "unsupported");
+                }
+
+                debug!(handle, operation_name, "thrift call started");
+                THRIFT_CALL_SPANS.with(|spans| spans.borrow_mut().insert(handle, span));
+
+                Ok(Box::new(()))
+            }),
+            Box::new(|_, _, execute_data, _| {
+                // The call failed before `recv_`.
+                if has_exception() {
+                    let handle = get_this_mut(execute_data)?.handle();
+                    if let Some(mut span) = remove_call_span(handle) {
+                        log_exception(&mut span);
+                    }
+                }
+                Ok(())
+            }),
+        )
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct ThriftRecvPlugin;
+
+impl Plugin for ThriftRecvPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        Some(RECV_PREFIX)
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match class_name {
+            Some(_) if function_name.len() > RECV_PREFIX.len() => Some(self.hook_recv()),
+            _ => None,
+        }
+    }
+}
+
+impl ThriftRecvPlugin {
+    fn hook_recv(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Noop::noop(),
+            Box::new(|_, _, execute_data, _| {
+                let Some(this) = execute_data.get_this_mut() else {
+                    return Ok(());
+                };
+                if let Some(mut span) = remove_call_span(this.handle()) {
+                    log_exception(&mut span);
+                }
+                Ok(())
+            }),
+        )
+    }
+}
+
+fn remove_call_span(handle: u32) -> Option<Span> {
+    THRIFT_CALL_SPANS.with(|spans| spans.borrow_mut().remove(&handle))
+}
+
+fn has_exception() -> bool {
+    unsafe { !eg!(exception).is_null() }
+}
+
+/// The generated client class is named `<service>Client`.
+fn get_service_name(class_name: &str) -> &str {
+    let class_name = class_name.rsplit('\\').next().unwrap_or(class_name);
+    class_name.strip_suffix("Client").unwrap_or(class_name)
+}
+
+/// Find the `host_` and `port_` of the transport, like `TSocket` and
+/// `THttpClient`, through the wrapping transports.
+fn get_peer(protocol: &mut ZObj) -> String {
+    let Ok(mut transport) = protocol.call("getTransport", []) else {
+        return "unknown:0".to_owned();
+    };
+
+    for _ in 0..MAX_TRANSPORT_DEPTH {
+        let Some(obj) = transport.as_z_obj() else {
+            break;
+        };
+        if let Some(host) = obj
+            .get_property("host_")
+            .as_z_str()
+            .and_then(|s| s.to_str().ok())
+        {
+            let port = obj.get_property("port_").as_long().unwrap_or_default();
+            return join_host_port(host, port);
+        }
+        let inner = obj.get_property("transport_").clone();
+        transport = inner;
+    }
+
+    "unknown:0".to_owned()
+}
+
+/// Only the header protocol supports headers, `setHeader` is called on the
+/// protocol or its transport if exists.
+fn inject_sw_header(protocol: &mut ZObj, sw_header: &str) -> bool {
+    if call_set_header(protocol, sw_header) {
+        return true;
+    }
+    match protocol.call("getTransport", []) {
+        Ok(mut transport) => transport
+            .as_mut_z_obj()
+            .map_or(false, |transport| call_set_header(transport, sw_header)),
+        Err(_) => false,
+    }
+}
+
+fn call_set_header(obj: &mut ZObj, sw_header: &str) -> bool {
+    let has_method = call(
+        "method_exists",
+        [ZVal::from(obj.to_ref_owned()), ZVal::from("setHeader")],
+    )
+    .ok()
+    .and_then(|result| result.as_bool())
+    .unwrap_or_default();
+    if !has_method {
+        return false;
+    }
+
+    debug!(sw_header, "inject sw header into thrift headers");
+    obj.call("setHeader", [ZVal::from(SW_HEADER), ZVal::from(sw_header)])
+        .is_ok()
+}
@@ -0,0 +1,431 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{log_exception, Plugin};
+use crate::{
+    component::{component_id, COMPONENT_PHP_YAR_ID},
+    context::{RequestContext, SW_HEADER},
+    execute::{get_this_mut, validate_num_args, AfterExecuteHook, BeforeExecuteHook, Noop},
+    tag::sanitize_url,
+    util::z_val_to_string,
+};
+use anyhow::Context;
+use phper::{
+    arrays::{InsertKey, ZArray},
+    functions::call,
+    strings::ZString,
+    values::{ExecuteData, ZVal},
+};
+use skywalking::{
+    proto::v3::SpanLayer,
+    trace::span::{AsyncSpan, HandleSpanObject, Span},
+};
+use std::{cell::RefCell, collections::HashMap, mem::replace};
+use tracing::{debug, error};
+use url::Url;
+
+const CLIENT_CLASS_NAME: &str = "Yar_Client";
+const CONCURRENT_CLIENT_CLASS_NAME: &str = "Yar_Concurrent_Client";
+
+pub const HACK_YAR_CALLBACK_FUNCTION_NAME: &str = "skywalking_hack_yar_callback_please_do_not_use";
+
+pub const HACK_YAR_ERROR_CALLBACK_FUNCTION_NAME: &str =
+    "skywalking_hack_yar_error_callback_please_do_not_use";
+
+thread_local! {
+    /// The concurrent calls, key is request id and the sequence returned by
+    /// `Yar_Concurrent_Client::call`.
+    static CONCURRENT_CALLS: RefCell<HashMap<(Option<i64>, i64), ConcurrentCall>> =
+        Default::default();
+
+    /// The original callbacks of the running `Yar_Concurrent_Client::loop`,
+    /// the last one is the innermost.
+    static LOOP_CALLBACKS: RefCell<Vec<LoopCallbacks>> = Default::default();
+}
+
+/// The span of concurrent call, and the original callbacks passed to
+/// `Yar_Concurrent_Client::call`, which are replaced by the hack functions.
+struct ConcurrentCall {
+    span: AsyncSpan,
+    callback: Option<ZVal>,
+    error_callback: Option<ZVal>,
+}
+
+/// The original callbacks passed to `Yar_Concurrent_Client::loop`, which are
+/// called for the concurrent calls without their own callbacks.
+struct LoopCallbacks {
+    request_id: Option<i64>,
+    callback: Option<ZVal>,
+    error_callback: Option<ZVal>,
+}
+
+#[derive(Default, Clone)]
+pub struct YarPlugin;
+
+impl Plugin for YarPlugin {
+    #[inline]
+    fn class_names(&self) -> Option<&'static [&'static str]> {
+        Some(&[CLIENT_CLASS_NAME, CONCURRENT_CLIENT_CLASS_NAME])
+    }
+
+    #[inline]
+    fn function_name_prefix(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn hook(
+        &self, class_name: Option<&str>, function_name: &str,
+    ) -> Option<(Box<BeforeExecuteHook>, Box<AfterExecuteHook>)> {
+        match (class_name, function_name) {
+            (Some(CLIENT_CLASS_NAME), "__call" | "call") => Some(self.hook_client_call()),
+            (Some(CONCURRENT_CLIENT_CLASS_NAME), "call") => {
+                Some(self.hook_concurrent_client_call())
+            }
+            (Some(CONCURRENT_CLIENT_CLASS_NAME), "loop") => {
+                Some(self.hook_concurrent_client_loop())
+            }
+            (Some(CONCURRENT_CLIENT_CLASS_NAME), "reset") => {
+                Some(self.hook_concurrent_client_reset())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl YarPlugin {
+    fn hook_client_call(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 1)?;
+
+                let method = execute_data
+                    .get_parameter(0)
+                    .as_z_str()
+                    .context("method isn't string")?
+                    .to_str()?
+                    .to_owned();
+
+                let this = get_this_mut(execute_data)?;
+                let uri = this
+                    .get_property("_uri")
+                    .as_z_str()
+                    .context("uri isn't string")?
+                    .to_str()?
+                    .to_owned();
+                let peer = get_peer(&uri);
+
+                let span = create_exit_span(request_id, &method, &uri, &peer)?;
+
+                // Merge the sw8 header into the headers set by user.
+                let header_opt = get_header_opt()?;
+                let mut headers = this
+                    .get_property("_options")
+                    .as_z_arr()
+                    .and_then(|options| options.get(header_opt as u64))
+                    .filter(|headers| headers.get_type_info().is_array())
+                    .cloned()
+                    .unwrap_or_else(|| ZVal::from(ZArray::new()));
+                let sw_header = RequestContext::try_get_sw_header(request_id, &peer)?;
+                if let Some(headers) = headers.as_mut_z_arr() {
+                    headers.insert(
+                        InsertKey::NextIndex,
+                        ZVal::from(format!("{}: {}", SW_HEADER, sw_header)),
+                    );
+                }
+                this.call("setOpt", [ZVal::from(header_opt), headers])?;
+
+                Ok(Box::new(span))
+            }),
+            Box::new(|_, span, _, _| {
+                let mut span = span.downcast::<Span>().unwrap();
+                log_exception(&mut *span);
+                Ok(())
+            }),
+        )
+    }
+
+    /// `Yar_Concurrent_Client::call(string $uri, string $method, ?array
+    /// $parameters = null, ?callable $callback = null, ?callable
+    /// $error_callback = null, ?array $options = null)`.
+    fn hook_concurrent_client_call(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                validate_num_args(execute_data, 2)?;
+
+                let uri = execute_data
+                    .get_parameter(0)
+                    .as_z_str()
+                    .context("uri isn't string")?
+                    .to_str()?
+                    .to_owned();
+                let method = execute_data
+                    .get_parameter(1)
+                    .as_z_str()
+                    .context("method isn't string")?
+                    .to_str()?
+                    .to_owned();
+                let peer = get_peer(&uri);
+
+                let span = create_exit_span(request_id, &method, &uri, &peer)?;
+
+                // Only the passed options can be modified, otherwise the header can't be
+                // injected.
+                if execute_data.num_args() >= 6 {
+                    let header_opt = get_header_opt()?;
+                    let sw_header = RequestContext::try_get_sw_header(request_id, &peer)?;
+                    let options = execute_data.get_mut_parameter(5);
+                    if !options.get_type_info().is_array() {
+                        *options = ZVal::from(ZArray::new());
+                    }
+                    if let Some(options) = options.as_mut_z_arr() {
+                        let mut headers = options
+                            .get(header_opt as u64)
+                            .filter(|headers| headers.get_type_info().is_array())
+                            .cloned()
+                            .unwrap_or_else(|| ZVal::from(ZArray::new()));
+                        if let Some(headers) = headers.as_mut_z_arr() {
+                            headers.insert(
+                                InsertKey::NextIndex,
+                                ZVal::from(format!("{}: {}", SW_HEADER, sw_header)),
+                            );
+                        }
+                        options.insert(header_opt as u64, headers);
+                    }
+                } else {
+                    debug!(
+                        uri,
+                        method, "yar concurrent call without options, skip inject"
+                    );
+                }
+
+                let callback = hack_callback(execute_data, 3, HACK_YAR_CALLBACK_FUNCTION_NAME);
+                let error_callback =
+                    hack_callback(execute_data, 4, HACK_YAR_ERROR_CALLBACK_FUNCTION_NAME);
+
+                Ok(Box::new(ConcurrentCall {
+                    span: span.prepare_for_async(),
+                    callback,
+                    error_callback,
+                }))
+            }),
+            Box::new(|request_id, call, _, return_value| {
+                let mut call = call.downcast::<ConcurrentCall>().unwrap();
+
+                match return_value.as_long() {
+                    Some(sequence) => {
+                        CONCURRENT_CALLS
+                            .with(|calls| calls.borrow_mut().insert((request_id, sequence), *call));
+                    }
+                    None => {
+                        call.span.span_object_mut().is_error = true;
+                        log_exception(&mut call.span);
+                    }
+                }
+
+                Ok(())
+            }),
+        )
+    }
+
+    /// `Yar_Concurrent_Client::loop(?callable $callback = null, ?callable
+    /// $error_callback = null)`.
+    ///
+    /// The callbacks of concurrent calls are called inside the loop, and
+    /// surrounded by [`skywalking_hack_yar_callback`] and
+    /// [`skywalking_hack_yar_error_callback`], which finish the span of the
+    /// call when its response arrives. The spans of the calls without any
+    /// callback are finished after loop.
+    fn hook_concurrent_client_loop(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, execute_data| {
+                let callback = hack_callback(execute_data, 0, HACK_YAR_CALLBACK_FUNCTION_NAME);
+                let error_callback =
+                    hack_callback(execute_data, 1, HACK_YAR_ERROR_CALLBACK_FUNCTION_NAME);
+                LOOP_CALLBACKS.with(|callbacks| {
+                    callbacks.borrow_mut().push(LoopCallbacks {
+                        request_id,
+                        callback,
+                        error_callback,
+                    })
+                });
+                Ok(Box::new(()))
+            }),
+            Box::new(|request_id, _, _, return_value| {
+                LOOP_CALLBACKS.with(|callbacks| callbacks.borrow_mut().pop());
+
+                let is_error = return_value.as_bool() == Some(false);
+                for mut call in take_concurrent_calls(request_id) {
+                    if is_error {
+                        call.span.span_object_mut().is_error = true;
+                    }
+                    log_exception(&mut call.span);
+                }
+                Ok(())
+            }),
+        )
+    }
+
+    fn hook_concurrent_client_reset(&self) -> (Box<BeforeExecuteHook>, Box<AfterExecuteHook>) {
+        (
+            Box::new(|request_id, _| {
+                take_concurrent_calls(request_id);
+                Ok(Box::new(()))
+            }),
+            Noop::noop(),
+        )
+    }
+}
+
+fn take_concurrent_calls(request_id: Option<i64>) -> Vec<ConcurrentCall> {
+    CONCURRENT_CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        let keys = calls
+            .keys()
+            .filter(|(id, _)| *id == request_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.into_iter()
+            .filter_map(|key| calls.remove(&key))
+            .collect()
+    })
+}
+
+/// Replace the callback parameter by the hack function, returns the original
+/// one, the parameter not passed or null is kept.
+fn hack_callback(
+    execute_data: &mut ExecuteData, index: usize, hack_function_name: &str,
+) -> Option<ZVal> {
+    if execute_data.num_args() <= index {
+        return None;
+    }
+    let callback = execute_data.get_mut_parameter(index);
+    if callback.get_type_info().is_null() {
+        return None;
+    }
+    Some(replace(
+        callback,
+        ZVal::from(ZString::new(hack_function_name)),
+    ))
+}
+
+/// The function is used by yar plugin, to surround the callback of
+/// `Yar_Concurrent_Client`, the arguments are `($retval, ?array $callinfo)`.
+///
+/// The span of the call is finished when its response arrives, before the
+/// original callback called. The `$callinfo` is null for the call after all
+/// requests sent, which is passed to the callback of loop.
+pub fn skywalking_hack_yar_callback(args: &mut [ZVal]) -> phper::Result<ZVal> {
+    let call = args.get(1).and_then(take_concurrent_call);
+    let callback = match call {
+        Some(ConcurrentCall { span, callback, .. }) => {
+            drop(span);
+            callback.or_else(get_loop_callback)
+        }
+        None => get_loop_callback(),
+    };
+
+    let Some(mut callback) = callback else {
+        error!("Origin yar callback is missing");
+        return Ok(ZVal::from(()));
+    };
+    callback.call(&mut *args)
+}
+
+/// The function is used by yar plugin, to surround the error callback of
+/// `Yar_Concurrent_Client`, the arguments are `(int $type, $error, array
+/// $callinfo)`.
+///
+/// The span of the call is marked errored with the error, and finished before
+/// the original callback called.
+pub fn skywalking_hack_yar_error_callback(args: &mut [ZVal]) -> phper::Result<ZVal> {
+    let call = args.get(2).and_then(take_concurrent_call);
+    let callback = match call {
+        Some(ConcurrentCall {
+            mut span,
+            error_callback,
+            ..
+        }) => {
+            let message = args
+                .get(1)
+                .and_then(z_val_to_string)
+                .unwrap_or_else(|| "yar concurrent call failed".to_owned());
+            let span_object = span.span_object_mut();
+            span_object.is_error = true;
+            span_object.add_log([("event", "error"), ("message", message.as_str())]);
+            drop(span);
+            error_callback.or_else(get_loop_error_callback)
+        }
+        None => get_loop_error_callback(),
+    };
+
+    let Some(mut callback) = callback else {
+        error!("Origin yar error callback is missing");
+        return Ok(ZVal::from(()));
+    };
+    callback.call(&mut *args)
+}
+
+/// Take the concurrent call of the `$callinfo`, in the request of the
+/// innermost loop.
+fn take_concurrent_call(callinfo: &ZVal) -> Option<ConcurrentCall> {
+    let sequence = callinfo.as_z_arr()?.get("sequence")?.as_long()?;
+    let request_id = LOOP_CALLBACKS.with(|callbacks| {
+        callbacks
+            .borrow()
+            .last()
+            .map(|callbacks| callbacks.request_id)
+    })?;
+    CONCURRENT_CALLS.with(|calls| calls.borrow_mut().remove(&(request_id, sequence)))
+}
+
+fn get_loop_callback() -> Option<ZVal> {
+    LOOP_CALLBACKS.with(|callbacks| callbacks.borrow().last()?.callback.clone())
+}
+
+fn get_loop_error_callback() -> Option<ZVal> {
+    LOOP_CALLBACKS.with(|callbacks| callbacks.borrow().last()?.error_callback.clone())
+}
+
+/// Get the value of constant `YAR_OPT_HEADER`, which is different between yar
+/// versions.
+fn get_header_opt() -> crate::Result<i64> {
+    Ok(call("constant", [ZVal::from("YAR_OPT_HEADER")])?
+        .as_long()
+        .context("YAR_OPT_HEADER isn't long")?)
+}
+
+fn get_peer(uri: &str) -> String {
+    let Ok(url) = Url::parse(uri) else {
+        return uri.to_owned();
+    };
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or_default();
+    format!("{}:{}", host, port)
+}
+
+fn create_exit_span(
+    request_id: Option<i64>, method: &str, uri: &str, peer: &str,
+) -> crate::Result<Span> {
+    let mut span = RequestContext::try_with_global_ctx(request_id, |ctx| {
+        Ok(ctx.create_exit_span(method, peer))
+    })?;
+
+    let span_object = span.span_object_mut();
+    span_object.set_span_layer(SpanLayer::RpcFramework);
+    span_object.component_id = component_id("yar", COMPONENT_PHP_YAR_ID);
+    span_object.add_tag("url", sanitize_url(uri));
+
+    Ok(span)
+}
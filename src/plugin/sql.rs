@@ -0,0 +1,305 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    module::{REPORT_DB_CONNECT_TIME, REPORT_DB_ROWS, SQL_PARAMETERIZE},
+    profiling::now_millis,
+    tag::{TAG_DB_CONNECT_TIME_MS, TAG_DB_ROWS_AFFECTED, TAG_DB_STATEMENT},
+};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use skywalking::trace::span::{HandleSpanObject, Span};
+use std::borrow::Cow;
+
+/// The connect time in millis of the connections not queried yet, keyed by
+/// the handle of connection object.
+static CONNECT_TIMES: Lazy<DashMap<u32, i64>> = Lazy::new(Default::default);
+
+/// The dialect of the literals of the SQL driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlDialect {
+    /// The double quoted text is string literal, and the backslash escapes in
+    /// the string literal.
+    Mysql,
+    /// The double quoted text is identifier, and the backslash is the plain
+    /// character, except in the escape string constant of PostgreSQL like
+    /// `E'\n'`.
+    Ansi,
+}
+
+/// Get the statement to be reported as `db.statement`, the literals are
+/// replaced with `?` if `sql_parameterize` is enabled.
+pub fn format_statement(statement: &str, dialect: SqlDialect) -> Cow<'_, str> {
+    if *SQL_PARAMETERIZE {
+        Cow::Owned(parameterize(statement, dialect))
+    } else {
+        Cow::Borrowed(statement)
+    }
+}
+
+/// Record the duration of the connect span, called in the after hook of
+/// connecting, if `report_db_connect_time` is enabled.
+pub fn record_connect_time(handle: u32, connect_span: &Span) {
+    if !*REPORT_DB_CONNECT_TIME {
+        return;
+    }
+    let connect_time = now_millis() - connect_span.span_object().start_time;
+    CONNECT_TIMES.insert(handle, connect_time.max(0));
+}
+
+/// Tag the first query span of the connection with the recorded connect time,
+/// to tell the slow connecting from the slow query.
+pub fn tag_connect_time(handle: u32, span: &mut Span) {
+    if let Some((_, connect_time)) = CONNECT_TIMES.remove(&handle) {
+        span.add_tag(TAG_DB_CONNECT_TIME_MS, connect_time.to_string());
+    }
+}
+
+/// Called when the connection is closed or the connection object is destroyed
+/// without any query.
+pub fn remove_connect_time(handle: u32) {
+    CONNECT_TIMES.remove(&handle);
+}
+
+/// Tag the affected rows of the write statement tagged as `db.statement`,
+/// called after the statement succeeded, if `report_db_rows` is enabled.
+///
+/// The `rows_affected` should only read the count kept by the driver, without
+/// another round trip. The count of the read statements isn't reliable before
+/// the rows fetched, like `rowCount()` of sqlite, so they aren't tagged.
+pub fn tag_rows_affected(
+    span: &mut Span, rows_affected: impl FnOnce() -> crate::Result<Option<i64>>,
+) -> crate::Result<()> {
+    if !*REPORT_DB_ROWS || !is_write_statement(span) {
+        return Ok(());
+    }
+    // The mysqli `affected_rows` is -1 if the query failed.
+    if let Some(rows) = rows_affected()?.filter(|rows| *rows >= 0) {
+        span.add_tag(TAG_DB_ROWS_AFFECTED, rows.to_string());
+    }
+    Ok(())
+}
+
+fn is_write_statement(span: &Span) -> bool {
+    let Some(statement) = span
+        .span_object()
+        .tags
+        .iter()
+        .find(|tag| tag.key == TAG_DB_STATEMENT)
+    else {
+        return false;
+    };
+    let keyword = statement
+        .value
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '(')
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default();
+    ["insert", "update", "delete", "replace", "merge"]
+        .iter()
+        .any(|write| keyword.eq_ignore_ascii_case(write))
+}
+
+/// Replace the string and number literals with `?` in single pass, without
+/// fully parsing the SQL.
+fn parameterize(statement: &str, dialect: SqlDialect) -> String {
+    let mut result = String::with_capacity(statement.len());
+    let mut chars = statement.chars().peekable();
+    let mut prev = None;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' if c == '\'' || dialect == SqlDialect::Mysql => {
+                let backslash_escaped = match dialect {
+                    SqlDialect::Mysql => true,
+                    SqlDialect::Ansi => {
+                        let escape_string = is_escape_string_prefix(&result);
+                        if escape_string {
+                            result.pop();
+                        }
+                        escape_string
+                    }
+                };
+                loop {
+                    match chars.next() {
+                        Some('\\') if backslash_escaped => {
+                            chars.next();
+                        }
+                        // The quote is escaped by doubling it.
+                        Some(q) if q == c => {
+                            if chars.peek() == Some(&c) {
+                                chars.next();
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+                result.push('?');
+            }
+            '`' | '"' => {
+                result.push(c);
+                for ident in chars.by_ref() {
+                    result.push(ident);
+                    if ident == c {
+                        break;
+                    }
+                }
+            }
+            c if c.is_ascii_digit() && !prev.map(is_identifier_char).unwrap_or_default() => {
+                while let Some(n) = chars.peek() {
+                    if n.is_ascii_alphanumeric() || *n == '.' {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                result.push('?');
+            }
+            c => result.push(c),
+        }
+        prev = Some(c);
+    }
+
+    result
+}
+
+#[inline]
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+/// The `E` prefix of the escape string constant, not the end of identifier.
+fn is_escape_string_prefix(parameterized: &str) -> bool {
+    let mut tail = parameterized.chars().rev();
+    matches!(tail.next(), Some('E' | 'e'))
+        && !tail.next().map(is_identifier_char).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::RequestContext,
+        testing::{find_span, finish_request, span_tag, start_request, RecordingReporter},
+    };
+
+    fn with_statement_span(statement: &str, f: impl FnOnce(&mut Span)) {
+        let (tracer, _) = RecordingReporter::tracer();
+        let mut ctx = tracer.create_trace_context();
+        let mut span = ctx.create_exit_span("mysqli->query", "127.0.0.1:3306");
+        span.add_tag(TAG_DB_STATEMENT, statement);
+        f(&mut span);
+    }
+
+    #[test]
+    fn write_statement() {
+        for statement in [
+            "INSERT INTO t VALUES (1)",
+            "  update t SET a = 1",
+            "(DELETE FROM t)",
+            "replace into t values (1)",
+            "MERGE INTO t USING s ON (1)",
+        ] {
+            with_statement_span(statement, |span| assert!(is_write_statement(span)));
+        }
+    }
+
+    #[test]
+    fn read_statement() {
+        for statement in ["SELECT * FROM t", "inserted", "", "SHOW TABLES"] {
+            with_statement_span(statement, |span| assert!(!is_write_statement(span)));
+        }
+    }
+
+    #[test]
+    fn parameterize_literals() {
+        for dialect in [SqlDialect::Mysql, SqlDialect::Ansi] {
+            assert_eq!(
+                parameterize("SELECT * FROM t2 WHERE a = 'x' AND b = 1.5e3", dialect),
+                "SELECT * FROM t2 WHERE a = ? AND b = ?"
+            );
+            assert_eq!(
+                parameterize("SELECT 'it''s' FROM `t`", dialect),
+                "SELECT ? FROM `t`"
+            );
+        }
+    }
+
+    #[test]
+    fn parameterize_double_quoted() {
+        assert_eq!(
+            parameterize(r#"SELECT "a" FROM t"#, SqlDialect::Mysql),
+            "SELECT ? FROM t"
+        );
+        assert_eq!(
+            parameterize(r#"SELECT "a" FROM t"#, SqlDialect::Ansi),
+            r#"SELECT "a" FROM t"#
+        );
+    }
+
+    #[test]
+    fn parameterize_backslash_mysql() {
+        assert_eq!(
+            parameterize(r"SELECT 'a\'b', 'c\\' FROM t", SqlDialect::Mysql),
+            "SELECT ?, ? FROM t"
+        );
+    }
+
+    #[test]
+    fn parameterize_backslash_ansi() {
+        // The backslash is plain, so the literals aren't merged.
+        assert_eq!(
+            parameterize(r"SELECT 'a\', 'b', 1 FROM t", SqlDialect::Ansi),
+            "SELECT ?, ?, ? FROM t"
+        );
+        assert_eq!(
+            parameterize(r"SELECT E'a\'b', e'c' FROM t", SqlDialect::Ansi),
+            "SELECT ?, ? FROM t"
+        );
+        // The `E` ending the identifier isn't the prefix.
+        assert_eq!(
+            parameterize(r"SELECT * FROM tablE'a\' x", SqlDialect::Ansi),
+            "SELECT * FROM tablE? x"
+        );
+    }
+
+    #[test]
+    fn tag_connect_time_once() {
+        let (tracer, reporter) = RecordingReporter::tracer();
+        let request_id = start_request(&tracer, "GET:/");
+        // The handle isn't used by other tests.
+        let handle = u32::MAX;
+        CONNECT_TIMES.insert(handle, 12);
+
+        RequestContext::try_with_global_ctx(request_id, |ctx| {
+            for name in ["first", "second"] {
+                let mut span = ctx.create_exit_span(name, "127.0.0.1:3306");
+                tag_connect_time(handle, &mut span);
+            }
+            Ok(())
+        })
+        .unwrap();
+        finish_request(request_id);
+
+        let segments = reporter.segments();
+        let first = find_span(&segments[0], "first").unwrap();
+        assert_eq!(span_tag(first, TAG_DB_CONNECT_TIME_MS), Some("12"));
+        let second = find_span(&segments[0], "second").unwrap();
+        assert_eq!(span_tag(second, TAG_DB_CONNECT_TIME_MS), None);
+    }
+}
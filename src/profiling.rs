@@ -0,0 +1,394 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Trace profiling, compatible with the profiling task of skywalking.
+//!
+//! The worker polls the profiling task and writes it into the shared memory,
+//! which is mapped before forking, so it is visible to all php processes.
+//!
+//! When a request matches the task, a timer thread sets the `vm_interrupt` of
+//! executor globals periodically, then the stack of the request is captured
+//! by `debug_backtrace()` in the interrupt function, and sent to worker as
+//! the profiling snapshot.
+
+use crate::{
+    channel::Reporter,
+    context::RequestContext,
+    module::{is_thread_worker_mode, ENABLE_PROFILING, WORKER_ADDR},
+    util::{capture_backtrace, catch_unwind_result},
+    worker::ThreadReporter,
+};
+use once_cell::sync::{Lazy, OnceCell};
+use phper::{eg, sys};
+use skywalking::proto::v3::{ThreadSnapshot, ThreadStack};
+use std::{
+    cell::{RefCell, UnsafeCell},
+    io,
+    mem::size_of,
+    ptr::{self, addr_of_mut, null_mut},
+    sync::{
+        atomic::{fence, AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{debug, error, warn};
+
+/// The max depth of the captured stack, the deeper frames are discarded.
+const MAX_STACK_DEPTH: usize = 500;
+
+const MAX_TASK_ID_LEN: usize = 64;
+
+const MAX_ENDPOINT_NAME_LEN: usize = 256;
+
+/// The max count of requests profiled in parallel, across the php processes,
+/// same as the java agent.
+const MAX_PARALLEL: i64 = 5;
+
+static SHARED_STATE: OnceCell<&'static SharedState> = OnceCell::new();
+
+/// The snapshots are sent by standalone connection, to avoid blocking the
+/// reporting of segments.
+static SNAPSHOT_REPORTER: Lazy<Reporter> = Lazy::new(|| Reporter::new(WORKER_ADDR.clone()));
+
+static mut ORI_INTERRUPT_FUNCTION: Option<unsafe extern "C" fn(*mut sys::zend_execute_data)> = None;
+
+thread_local! {
+    static ACTIVE_PROFILE: RefCell<Option<ActiveProfile>> = RefCell::new(None);
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileTask {
+    pub task_id: String,
+    pub endpoint_name: String,
+    /// Timestamp in milliseconds.
+    pub start_time: i64,
+    /// Timestamp in milliseconds.
+    pub end_time: i64,
+    pub min_duration_threshold: i64,
+    pub dump_period: i64,
+    pub max_sampling_count: i64,
+}
+
+impl ProfileTask {
+    #[inline]
+    pub fn is_running(&self, now: i64) -> bool {
+        self.start_time <= now && now < self.end_time
+    }
+}
+
+#[repr(C)]
+struct SharedState {
+    /// Odd when the task is being written.
+    version: AtomicU64,
+    task: UnsafeCell<RawTask>,
+    /// The count of requests being profiled, across the php processes.
+    profiling_count: AtomicI64,
+    /// The count of requests profiled by the current task, limited by the
+    /// `max_sampling_count` of the task.
+    started_count: AtomicI64,
+}
+
+/// The task is guarded by the version, like seqlock.
+unsafe impl Sync for SharedState {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawTask {
+    task_id: [u8; MAX_TASK_ID_LEN],
+    task_id_len: usize,
+    endpoint_name: [u8; MAX_ENDPOINT_NAME_LEN],
+    endpoint_name_len: usize,
+    start_time: i64,
+    end_time: i64,
+    min_duration_threshold: i64,
+    dump_period: i64,
+    max_sampling_count: i64,
+}
+
+impl RawTask {
+    fn to_task(self) -> Option<ProfileTask> {
+        if self.task_id_len == 0 {
+            return None;
+        }
+        Some(ProfileTask {
+            task_id: String::from_utf8_lossy(&self.task_id[..self.task_id_len]).into_owned(),
+            endpoint_name: String::from_utf8_lossy(&self.endpoint_name[..self.endpoint_name_len])
+                .into_owned(),
+            start_time: self.start_time,
+            end_time: self.end_time,
+            min_duration_threshold: self.min_duration_threshold,
+            dump_period: self.dump_period,
+            max_sampling_count: self.max_sampling_count,
+        })
+    }
+}
+
+/// Map the shared memory, must be called before forking the worker and the
+/// php processes.
+pub fn init() {
+    if !*ENABLE_PROFILING {
+        return;
+    }
+
+    let ptr = unsafe {
+        libc::mmap(
+            null_mut(),
+            size_of::<SharedState>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        error!(err = ?io::Error::last_os_error(), "Map profiling shared memory failed");
+        return;
+    }
+
+    // The anonymous mapping is zero filled, which means no task.
+    let _ = SHARED_STATE.set(unsafe { &*(ptr as *const SharedState) });
+
+    unsafe {
+        ORI_INTERRUPT_FUNCTION = sys::zend_interrupt_function;
+        sys::zend_interrupt_function = Some(interrupt_function);
+    }
+}
+
+/// Write the task into shared memory, only called by worker.
+pub fn set_task(task: Option<&ProfileTask>) {
+    let Some(state) = SHARED_STATE.get() else {
+        return;
+    };
+
+    let mut raw: RawTask = unsafe { std::mem::zeroed() };
+    if let Some(task) = task {
+        if task.task_id.len() > MAX_TASK_ID_LEN {
+            warn!(task_id = %task.task_id, "Profiling task id is too long, ignored");
+            return;
+        }
+        raw.task_id[..task.task_id.len()].copy_from_slice(task.task_id.as_bytes());
+        raw.task_id_len = task.task_id.len();
+        let endpoint_name_len = task.endpoint_name.len().min(MAX_ENDPOINT_NAME_LEN);
+        raw.endpoint_name[..endpoint_name_len]
+            .copy_from_slice(&task.endpoint_name.as_bytes()[..endpoint_name_len]);
+        raw.endpoint_name_len = endpoint_name_len;
+        raw.start_time = task.start_time;
+        raw.end_time = task.end_time;
+        raw.min_duration_threshold = task.min_duration_threshold;
+        raw.dump_period = task.dump_period;
+        raw.max_sampling_count = task.max_sampling_count;
+    }
+
+    state.version.fetch_add(1, Ordering::AcqRel);
+    unsafe {
+        ptr::write_volatile(state.task.get(), raw);
+    }
+    state.started_count.store(0, Ordering::Release);
+    state.version.fetch_add(1, Ordering::Release);
+}
+
+/// Read the task from shared memory.
+pub fn get_task() -> Option<ProfileTask> {
+    let state = SHARED_STATE.get()?;
+    loop {
+        let version = state.version.load(Ordering::Acquire);
+        if version % 2 == 1 {
+            thread::yield_now();
+            continue;
+        }
+        let raw = unsafe { ptr::read_volatile(state.task.get()) };
+        fence(Ordering::Acquire);
+        if state.version.load(Ordering::Relaxed) == version {
+            return raw.to_task();
+        }
+    }
+}
+
+/// Start profiling if the request matches the running task.
+pub fn start_request_profiling(request_id: Option<i64>) {
+    let Some(state) = SHARED_STATE.get() else {
+        return;
+    };
+    let Some(task) = get_task() else {
+        return;
+    };
+    if !task.is_running(now_millis()) {
+        return;
+    }
+
+    let result = RequestContext::try_with_global(request_id, |ctx| {
+        let operation_name = &ctx.entry_span.span_object().operation_name;
+        Ok((operation_name == &task.endpoint_name)
+            .then(|| ctx.tracing_context.trace_segment_id().to_owned()))
+    });
+    let Ok(Some(trace_segment_id)) = result else {
+        return;
+    };
+
+    if state.profiling_count.fetch_add(1, Ordering::AcqRel) >= MAX_PARALLEL {
+        state.profiling_count.fetch_sub(1, Ordering::AcqRel);
+        debug!(task_id = %task.task_id, "Profiling count reach the max parallel count");
+        return;
+    }
+    if state.started_count.fetch_add(1, Ordering::AcqRel) >= task.max_sampling_count {
+        state.profiling_count.fetch_sub(1, Ordering::AcqRel);
+        debug!(task_id = %task.task_id, "Profiling count reach the max sampling count");
+        return;
+    }
+
+    debug!(task_id = %task.task_id, trace_segment_id, "Start profiling request");
+
+    let dump_requested = Arc::new(AtomicBool::new(false));
+    let is_stopped = Arc::new(AtomicBool::new(false));
+    let vm_interrupt = unsafe { addr_of_mut!(eg!(vm_interrupt)) as usize };
+
+    let timer = {
+        let dump_requested = dump_requested.clone();
+        let is_stopped = is_stopped.clone();
+        let min_duration_threshold = Duration::from_millis(task.min_duration_threshold as u64);
+        let dump_period = Duration::from_millis(task.dump_period.max(1) as u64);
+
+        thread::Builder::new()
+            .name("sw: profiling".to_owned())
+            .spawn(move || {
+                let mut deadline = Instant::now() + min_duration_threshold;
+                loop {
+                    let now = Instant::now();
+                    if now < deadline {
+                        thread::park_timeout(deadline - now);
+                    }
+                    if is_stopped.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if Instant::now() < deadline {
+                        continue;
+                    }
+                    dump_requested.store(true, Ordering::Release);
+                    // Both `zend_bool` and `zend_atomic_bool` are one byte.
+                    unsafe {
+                        (*(vm_interrupt as *const AtomicBool)).store(true, Ordering::Release);
+                    }
+                    deadline += dump_period;
+                }
+            })
+    };
+
+    match timer {
+        Ok(timer) => ACTIVE_PROFILE.with(|profile| {
+            *profile.borrow_mut() = Some(ActiveProfile {
+                task_id: task.task_id,
+                trace_segment_id,
+                end_time: task.end_time,
+                sequence: 0,
+                dump_requested,
+                is_stopped,
+                timer,
+            });
+        }),
+        Err(err) => {
+            state.profiling_count.fetch_sub(1, Ordering::AcqRel);
+            error!(?err, "Spawn profiling timer thread failed");
+        }
+    }
+}
+
+/// Stop profiling the request, must be called before the request context is
+/// finished.
+pub fn stop_request_profiling() {
+    let Some(profile) = ACTIVE_PROFILE.with(|profile| profile.borrow_mut().take()) else {
+        return;
+    };
+
+    profile.is_stopped.store(true, Ordering::Release);
+    profile.timer.thread().unpark();
+    if profile.timer.join().is_err() {
+        error!("Join profiling timer thread failed");
+    }
+
+    if let Some(state) = SHARED_STATE.get() {
+        state.profiling_count.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    debug!(
+        task_id = %profile.task_id,
+        sequence = profile.sequence,
+        "Stop profiling request"
+    );
+}
+
+struct ActiveProfile {
+    task_id: String,
+    trace_segment_id: String,
+    end_time: i64,
+    sequence: i32,
+    dump_requested: Arc<AtomicBool>,
+    is_stopped: Arc<AtomicBool>,
+    timer: JoinHandle<()>,
+}
+
+unsafe extern "C" fn interrupt_function(execute_data: *mut sys::zend_execute_data) {
+    if let Err(err) = catch_unwind_result(dump_snapshot) {
+        error!(?err, "Dump profiling snapshot failed");
+    }
+
+    if let Some(f) = ORI_INTERRUPT_FUNCTION {
+        f(execute_data);
+    }
+}
+
+fn dump_snapshot() -> crate::Result<()> {
+    ACTIVE_PROFILE.with(|profile| {
+        let mut profile = profile.borrow_mut();
+        let Some(profile) = profile.as_mut() else {
+            return Ok(());
+        };
+        if !profile.dump_requested.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let time = now_millis();
+        if time >= profile.end_time {
+            return Ok(());
+        }
+
+        let code_signatures = capture_backtrace(MAX_STACK_DEPTH)?;
+
+        let snapshot = ThreadSnapshot {
+            task_id: profile.task_id.clone(),
+            trace_segment_id: profile.trace_segment_id.clone(),
+            time,
+            sequence: profile.sequence,
+            stack: Some(ThreadStack { code_signatures }),
+        };
+        if is_thread_worker_mode() {
+            ThreadReporter::report_snapshot(snapshot);
+        } else {
+            SNAPSHOT_REPORTER.report_snapshot(snapshot);
+        }
+        profile.sequence += 1;
+
+        Ok(())
+    })
+}
+
+#[inline]
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,123 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker side of Configuration Discovery Service, fetches the dynamic
+//! configuration from skywalking server, and shares it with php processes.
+
+use super::reporter_grpc::intercept;
+use crate::{
+    dynamic_config::{self, parse_trace_ignore_paths, DynamicConfig},
+    module::SERVICE_NAME,
+};
+use skywalking::proto::v3::{
+    configuration_discovery_service_client::ConfigurationDiscoveryServiceClient,
+    ConfigurationSyncRequest, KeyStringValuePair,
+};
+use std::time::Duration;
+use tokio::time::interval;
+use tonic::{transport::Channel, Code, Request, Status};
+use tracing::{debug, info, warn};
+
+const CONFIGURATION_DISCOVERY_COMMAND: &str = "ConfigurationDiscoveryCommand";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(20);
+
+const KEY_SERIAL_NUMBER: &str = "SerialNumber";
+const KEY_UUID: &str = "UUID";
+const KEY_SAMPLE_RATE: &str = "agent.sample_rate";
+const KEY_TRACE_IGNORE_PATH: &str = "agent.trace.ignore_path";
+const KEY_SPAN_LIMIT_PER_SEGMENT: &str = "agent.span_limit_per_segment";
+
+type Interceptor = fn(Request<()>) -> Result<Request<()>, Status>;
+
+pub async fn run(channel: Channel) {
+    let mut client =
+        ConfigurationDiscoveryServiceClient::with_interceptor(channel, intercept as Interceptor);
+
+    let mut uuid = String::new();
+    let mut current_config: Option<DynamicConfig> = None;
+    let mut ticker = interval(POLL_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+
+        let request = ConfigurationSyncRequest {
+            service: SERVICE_NAME.clone(),
+            uuid: uuid.clone(),
+        };
+        let commands = match client.fetch_configurations(request).await {
+            Ok(commands) => commands.into_inner().commands,
+            Err(status) if status.code() == Code::Unimplemented => {
+                info!("Configuration discovery service isn't supported by server, stopped");
+                return;
+            }
+            Err(err) => {
+                warn!(?err, "Fetch dynamic configurations failed");
+                continue;
+            }
+        };
+
+        for command in commands {
+            if command.command != CONFIGURATION_DISCOVERY_COMMAND {
+                continue;
+            }
+
+            let config = parse_config(&command.args);
+            if let Some(new_uuid) = get_arg(&command.args, KEY_UUID) {
+                uuid = new_uuid.to_owned();
+            }
+
+            if current_config.as_ref() != Some(&config) {
+                info!(?config, %uuid, "Apply dynamic configuration");
+                dynamic_config::set(Some(&config));
+                current_config = Some(config);
+            }
+        }
+    }
+}
+
+fn get_arg<'a>(args: &'a [KeyStringValuePair], key: &str) -> Option<&'a str> {
+    args.iter()
+        .find(|pair| pair.key == key)
+        .map(|pair| pair.value.as_str())
+}
+
+/// The keys absent or invalid fallback to the ini values, the unknown keys
+/// are ignored.
+fn parse_config(args: &[KeyStringValuePair]) -> DynamicConfig {
+    let mut config = DynamicConfig::from_ini();
+
+    for KeyStringValuePair { key, value } in args {
+        match key.as_str() {
+            KEY_SERIAL_NUMBER | KEY_UUID => {}
+            KEY_SAMPLE_RATE => match value.trim().parse::<f64>() {
+                Ok(rate) if (0. ..=1.).contains(&rate) => config.sample_rate = rate,
+                _ => warn!(%key, %value, "Invalid dynamic sample rate, ignored"),
+            },
+            KEY_TRACE_IGNORE_PATH => {
+                config.trace_ignore_paths = parse_trace_ignore_paths(value);
+            }
+            KEY_SPAN_LIMIT_PER_SEGMENT => match value.trim().parse::<i64>() {
+                Ok(limit) if limit > 0 => config.span_limit_per_segment = limit,
+                _ => warn!(%key, %value, "Invalid dynamic span limit per segment, ignored"),
+            },
+            _ => {
+                debug!(%key, %value, "Unknown dynamic configuration, ignored");
+            }
+        }
+    }
+
+    config
+}
@@ -0,0 +1,222 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Clock skew correction.
+//!
+//! The skywalking server doesn't expose its time in the management protocol,
+//! so the `date` header of the gRPC response is used, which has the second
+//! precision, so the skew less than one second is ignored.
+
+use crate::module::{AUTHENTICATION, SERVICE_INSTANCE, SERVICE_NAME};
+use anyhow::Context;
+use skywalking::{
+    proto::v3::{management_service_client::ManagementServiceClient, Event, InstancePingPkg},
+    reporter::CollectItem,
+};
+use std::{
+    sync::atomic::{AtomicBool, AtomicI64, Ordering},
+    time::{Duration, SystemTime},
+};
+use tokio::time::timeout;
+use tonic::{transport::Channel, Request};
+use tracing::{info, warn};
+
+/// The minimum skew to be corrected, because the precision of server time is
+/// one second.
+const MIN_CORRECTED_SKEW_MILLIS: i64 = 1000;
+
+/// The server not answering the ping shouldn't block the reporter starting.
+const DETECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static IS_CORRECTING: AtomicBool = AtomicBool::new(false);
+
+/// Server time minus local time, in milliseconds.
+static CLOCK_SKEW_MILLIS: AtomicI64 = AtomicI64::new(0);
+
+/// Detect the clock skew by pinging the skywalking server, disable the
+/// correction if failed.
+pub async fn detect(channel: Channel) {
+    match try_detect(channel).await {
+        Ok(skew) => {
+            info!(
+                skew_millis = skew,
+                "Detected clock skew with skywalking server"
+            );
+            if skew.abs() >= MIN_CORRECTED_SKEW_MILLIS {
+                CLOCK_SKEW_MILLIS.store(skew, Ordering::Relaxed);
+                IS_CORRECTING.store(true, Ordering::Relaxed);
+            }
+        }
+        Err(err) => {
+            warn!(
+                ?err,
+                "Get skywalking server time failed, clock skew correction is disabled"
+            );
+        }
+    }
+}
+
+async fn try_detect(channel: Channel) -> anyhow::Result<i64> {
+    let mut client = ManagementServiceClient::new(channel);
+
+    let mut request = Request::new(InstancePingPkg {
+        service: SERVICE_NAME.clone(),
+        service_instance: SERVICE_INSTANCE.clone(),
+        ..Default::default()
+    });
+    if !AUTHENTICATION.is_empty() {
+        request
+            .metadata_mut()
+            .insert("authentication", AUTHENTICATION.parse()?);
+    }
+
+    let begin = now_millis();
+    let response = timeout(DETECT_TIMEOUT, client.keep_alive(request))
+        .await
+        .context("ping skywalking server timeout")??;
+    let end = now_millis();
+
+    let date = response
+        .metadata()
+        .get("date")
+        .context("date header is missing in response")?
+        .to_str()?;
+    let server_time = parse_http_date(date).context("parse date header failed")?;
+
+    // The server time is truncated to seconds, so take the middle of the second.
+    Ok(server_time + 500 - (begin + end) / 2)
+}
+
+/// Correct the span timestamps of segment, and the timestamps of log and
+/// meter, so they still line up with the spans.
+pub fn correct(item: &mut CollectItem) {
+    let Some(skew) = skew() else {
+        return;
+    };
+
+    match item {
+        CollectItem::Trace(segment) => {
+            for span in &mut segment.spans {
+                span.start_time += skew;
+                span.end_time += skew;
+            }
+        }
+        CollectItem::Log(log) => log.timestamp += skew,
+        CollectItem::Meter(meter) => meter.timestamp += skew,
+        _ => {}
+    }
+}
+
+/// Correct the timestamps of event, which is uploaded by the event service
+/// rather than collected.
+pub fn correct_event(event: &mut Event) {
+    if let Some(skew) = skew() {
+        event.start_time += skew;
+        event.end_time += skew;
+    }
+}
+
+fn skew() -> Option<i64> {
+    IS_CORRECTING
+        .load(Ordering::Relaxed)
+        .then(|| CLOCK_SKEW_MILLIS.load(Ordering::Relaxed))
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Parse the IMF-fixdate format, like `Sun, 06 Nov 1994 08:49:37 GMT`, return
+/// the unix timestamp in milliseconds.
+fn parse_http_date(date: &str) -> Option<i64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let mut parts = date.split_whitespace().skip(1);
+    let day = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year = parts.next()?.parse::<i64>().ok()?;
+
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<i64>().ok());
+    let hour = time.next()??;
+    let minute = time.next()??;
+    let second = time.next()??;
+
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    // Days from civil, refer to <http://howardhinnant.github.io/date_algorithms.html>.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(((days * 24 + hour) * 60 + minute) * 60 * 1000 + second * 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_epoch() {
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+    }
+
+    #[test]
+    fn parse_leap_day() {
+        assert_eq!(
+            parse_http_date("Thu, 29 Feb 2024 12:34:56 GMT"),
+            Some(1709210096000)
+        );
+        assert_eq!(
+            parse_http_date("Fri, 31 Dec 1999 23:59:59 GMT"),
+            Some(946684799000)
+        );
+    }
+
+    #[test]
+    fn parse_after_2038() {
+        assert_eq!(
+            parse_http_date("Sun, 01 Jan 2040 00:00:00 GMT"),
+            Some(2208988800000)
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for date in [
+            "",
+            "Thu,",
+            "01 Jan 1970 00:00:00 GMT",
+            "Thu, 01 Foo 1970 00:00:00 GMT",
+            "Thu, 01 Jan 1970 00:00 GMT",
+            "Thu, 01 Jan 1970 00:xx:00 GMT",
+            "Thu, 01 Jan 1970 00:00:00 UTC",
+            "Thu, 01 Jan 1970 00:00:00",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker side of events reporting, uploads the events received from php
+//! processes, and the `Start` and `Shutdown` events of the worker.
+
+use super::{clock_skew, reporter_grpc::intercept};
+use crate::{event::new_event, profiling::now_millis};
+use skywalking::proto::v3::{event_service_client::EventServiceClient, Event};
+use std::{collections::HashMap, process, sync::Mutex, time::Duration};
+use tokio::{sync::mpsc, time::timeout};
+use tonic::{codegen::InterceptedService, transport::Channel, Request, Status};
+use tracing::{debug, warn};
+
+const EVENT_START: &str = "Start";
+
+const EVENT_SHUTDOWN: &str = "Shutdown";
+
+/// The max count of events uploaded in one stream.
+const MAX_EVENT_BATCH: usize = 100;
+
+/// The shutdown event is uploaded before flushing the pending items, so it
+/// shouldn't take much of the shutdown timeout.
+const SHUTDOWN_EVENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Replaced when the worker runtime is rebuilt.
+static EVENT_SENDER: Mutex<Option<mpsc::Sender<Event>>> = Mutex::new(None);
+
+/// Kept for uploading the shutdown event directly, which can't wait in the
+/// queue.
+static EVENT_CLIENT: Mutex<Option<Client>> = Mutex::new(None);
+
+type Interceptor = fn(Request<()>) -> Result<Request<()>, Status>;
+
+type Client = EventServiceClient<InterceptedService<Channel, Interceptor>>;
+
+/// Send the event received from php processes to the uploader.
+pub fn send_event(mut event: Event) {
+    let tx = EVENT_SENDER.lock().ok().and_then(|tx| tx.clone());
+    let Some(tx) = tx else {
+        debug!("Event reporting isn't running, event dropped");
+        return;
+    };
+    clock_skew::correct_event(&mut event);
+    if let Err(err) = tx.try_send(event) {
+        warn!(?err, "Send event failed");
+    }
+}
+
+pub async fn run(channel: Channel) {
+    let client = EventServiceClient::with_interceptor(channel, intercept as Interceptor);
+
+    let (tx, rx) = mpsc::channel(255);
+    match EVENT_SENDER.lock() {
+        Ok(mut sender) => *sender = Some(tx),
+        Err(err) => {
+            warn!(?err, "Get event sender lock failed");
+            return;
+        }
+    }
+    match EVENT_CLIENT.lock() {
+        Ok(mut event_client) => *event_client = Some(client.clone()),
+        Err(err) => warn!(?err, "Get event client lock failed"),
+    }
+
+    send_event(worker_event(EVENT_START, now_millis()));
+    upload_events(client, rx).await;
+}
+
+/// Upload the shutdown event of worker, do nothing if the event reporting
+/// isn't running.
+pub async fn report_shutdown() {
+    let client = EVENT_CLIENT.lock().ok().and_then(|client| client.clone());
+    let Some(mut client) = client else {
+        return;
+    };
+
+    let mut event = worker_event(EVENT_SHUTDOWN, now_millis());
+    clock_skew::correct_event(&mut event);
+    match timeout(
+        SHUTDOWN_EVENT_TIMEOUT,
+        client.collect(tokio_stream::iter([event])),
+    )
+    .await
+    {
+        Ok(Ok(_)) => debug!("Shutdown event uploaded"),
+        Ok(Err(err)) => warn!(?err, "Upload shutdown event failed"),
+        Err(_) => warn!("Upload shutdown event timeout"),
+    }
+}
+
+fn worker_event(name: &str, time: i64) -> Event {
+    let parameters = HashMap::from([
+        (
+            "AGENT_VERSION".to_owned(),
+            env!("CARGO_PKG_VERSION").to_owned(),
+        ),
+        ("WORKER_PID".to_owned(), process::id().to_string()),
+    ]);
+    new_event(
+        name.to_owned(),
+        &format!("{} skywalking php agent worker", name),
+        parameters,
+        false,
+        String::new(),
+        time,
+        time,
+    )
+}
+
+async fn upload_events(mut client: Client, mut rx: mpsc::Receiver<Event>) {
+    while let Some(event) = rx.recv().await {
+        let mut events = vec![event];
+        while events.len() < MAX_EVENT_BATCH {
+            match rx.try_recv() {
+                Ok(event) => events.push(event),
+                Err(_) => break,
+            }
+        }
+
+        debug!(count = events.len(), "Upload events");
+        if let Err(err) = client.collect(tokio_stream::iter(events)).await {
+            warn!(?err, "Upload events failed");
+        }
+    }
+}
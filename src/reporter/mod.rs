@@ -13,18 +13,43 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod cds;
+pub mod clock_skew;
+pub mod event;
+pub mod profile;
+mod proxy;
+mod reporter_diagnostic;
 mod reporter_grpc;
+mod reporter_http;
 mod reporter_kafka;
 
-use crate::module::REPORTER_TYPE;
+use crate::module::{
+    CORRECT_CLOCK_SKEW, DIAGNOSTIC_MODE, ENABLE_EVENT, ENABLE_PROFILING, REPORTER_TYPE,
+};
 use anyhow::bail;
 use skywalking::reporter::{CollectItemConsume, CollectItemProduce};
+use tracing::warn;
 
 pub async fn run_reporter(
     producer: impl CollectItemProduce, consumer: impl CollectItemConsume,
 ) -> anyhow::Result<()> {
+    if *CORRECT_CLOCK_SKEW && REPORTER_TYPE.as_str() != "grpc" {
+        warn!("Clock skew correction is only available for grpc reporter, disabled");
+    }
+    if *ENABLE_PROFILING && REPORTER_TYPE.as_str() != "grpc" {
+        warn!("Profiling is only available for grpc reporter, disabled");
+    }
+    if *ENABLE_EVENT && REPORTER_TYPE.as_str() != "grpc" {
+        warn!("Event reporting is only available for grpc reporter, disabled");
+    }
+
+    if *DIAGNOSTIC_MODE {
+        return reporter_diagnostic::run_reporter(producer, consumer).await;
+    }
+
     match REPORTER_TYPE.as_str() {
         "grpc" => reporter_grpc::run_reporter(producer, consumer).await,
+        "http" => reporter_http::run_reporter(producer, consumer).await,
         #[cfg(feature = "kafka-reporter")]
         "kafka" => reporter_kafka::run_reporter(producer, consumer).await,
         typ => bail!("unknown reporter type, {}", typ),
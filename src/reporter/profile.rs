@@ -0,0 +1,182 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker side of trace profiling, polls the profiling task from skywalking
+//! server, and uploads the snapshots captured by php processes.
+
+use super::reporter_grpc::intercept;
+use crate::{
+    module::{SERVICE_INSTANCE, SERVICE_NAME},
+    profiling::{self, now_millis, ProfileTask},
+};
+use anyhow::Context;
+use skywalking::proto::v3::{
+    profile_task_client::ProfileTaskClient, KeyStringValuePair, ProfileTaskCommandQuery,
+    ProfileTaskFinishReport, ThreadSnapshot,
+};
+use std::{sync::Mutex, time::Duration};
+use tokio::{sync::mpsc, time::interval};
+use tonic::{codegen::InterceptedService, transport::Channel, Request, Status};
+use tracing::{debug, info, warn};
+
+const PROFILE_TASK_COMMAND: &str = "ProfileTaskQuery";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(20);
+
+/// The max count of snapshots uploaded in one stream.
+const MAX_SNAPSHOT_BATCH: usize = 100;
+
+/// Replaced when the worker runtime is rebuilt.
+static SNAPSHOT_SENDER: Mutex<Option<mpsc::Sender<ThreadSnapshot>>> = Mutex::new(None);
+
+type Interceptor = fn(Request<()>) -> Result<Request<()>, Status>;
+
+type Client = ProfileTaskClient<InterceptedService<Channel, Interceptor>>;
+
+/// Send the snapshot received from php processes to the uploader.
+pub fn send_snapshot(snapshot: ThreadSnapshot) {
+    let tx = SNAPSHOT_SENDER.lock().ok().and_then(|tx| tx.clone());
+    let Some(tx) = tx else {
+        debug!("Profiling isn't running, snapshot dropped");
+        return;
+    };
+    if let Err(err) = tx.try_send(snapshot) {
+        warn!(?err, "Send profiling snapshot failed");
+    }
+}
+
+pub async fn run(channel: Channel) {
+    let client = ProfileTaskClient::with_interceptor(channel, intercept as Interceptor);
+
+    let (tx, rx) = mpsc::channel(255);
+    match SNAPSHOT_SENDER.lock() {
+        Ok(mut sender) => *sender = Some(tx),
+        Err(err) => {
+            warn!(?err, "Get snapshot sender lock failed");
+            return;
+        }
+    }
+
+    tokio::spawn(upload_snapshots(client.clone(), rx));
+    poll_tasks(client).await;
+}
+
+async fn poll_tasks(mut client: Client) {
+    let mut last_command_time = 0;
+    let mut current_task: Option<ProfileTask> = None;
+    let mut ticker = interval(POLL_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+
+        let query = ProfileTaskCommandQuery {
+            service: SERVICE_NAME.clone(),
+            service_instance: SERVICE_INSTANCE.clone(),
+            last_command_time,
+        };
+        match client.get_profile_task_commands(query).await {
+            Ok(commands) => {
+                for command in commands.into_inner().commands {
+                    if command.command != PROFILE_TASK_COMMAND {
+                        continue;
+                    }
+                    match parse_task(&command.args) {
+                        Ok((task, create_time)) => {
+                            info!(?task, "Received profiling task");
+                            last_command_time = last_command_time.max(create_time);
+                            profiling::set_task(Some(&task));
+                            current_task = Some(task);
+                        }
+                        Err(err) => {
+                            warn!(?err, "Parse profiling task failed");
+                        }
+                    }
+                }
+            }
+            Err(err) => {
+                warn!(?err, "Get profiling task commands failed");
+            }
+        }
+
+        if let Some(task) = &current_task {
+            if now_millis() >= task.end_time {
+                finish_task(&mut client, task).await;
+                profiling::set_task(None);
+                current_task = None;
+            }
+        }
+    }
+}
+
+async fn finish_task(client: &mut Client, task: &ProfileTask) {
+    info!(task_id = %task.task_id, "Profiling task finished");
+
+    let report = ProfileTaskFinishReport {
+        service: SERVICE_NAME.clone(),
+        service_instance: SERVICE_INSTANCE.clone(),
+        task_id: task.task_id.clone(),
+    };
+    if let Err(err) = client.report_task_finish(report).await {
+        warn!(?err, "Report profiling task finish failed");
+    }
+}
+
+async fn upload_snapshots(mut client: Client, mut rx: mpsc::Receiver<ThreadSnapshot>) {
+    while let Some(snapshot) = rx.recv().await {
+        let mut snapshots = vec![snapshot];
+        while snapshots.len() < MAX_SNAPSHOT_BATCH {
+            match rx.try_recv() {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(_) => break,
+            }
+        }
+
+        debug!(count = snapshots.len(), "Upload profiling snapshots");
+        if let Err(err) = client.collect_snapshot(tokio_stream::iter(snapshots)).await {
+            warn!(?err, "Upload profiling snapshots failed");
+        }
+    }
+}
+
+/// Parse the task, return with the create time.
+fn parse_task(args: &[KeyStringValuePair]) -> anyhow::Result<(ProfileTask, i64)> {
+    let get = |key: &str| {
+        args.iter()
+            .find(|pair| pair.key == key)
+            .map(|pair| pair.value.as_str())
+            .with_context(|| format!("argument {} not exists", key))
+    };
+    let get_i64 = |key: &str| -> anyhow::Result<i64> {
+        get(key)?
+            .parse()
+            .with_context(|| format!("argument {} isn't integer", key))
+    };
+
+    let start_time = get_i64("StartTime")?;
+    // The duration is in minutes.
+    let duration = get_i64("Duration")?;
+
+    let task = ProfileTask {
+        task_id: get("TaskId")?.to_owned(),
+        endpoint_name: get("EndpointName")?.to_owned(),
+        start_time,
+        end_time: start_time + duration * 60 * 1000,
+        min_duration_threshold: get_i64("MinDurationThreshold")?,
+        dump_period: get_i64("DumpPeriod")?,
+        max_sampling_count: get_i64("MaxSamplingCount")?,
+    };
+
+    Ok((task, get_i64("CreateTime")?))
+}
@@ -0,0 +1,306 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Connect to the OAP server through the proxy, configured by
+//! `skywalking_agent.grpc_proxy`, for the networks only allowing egress via
+//! the proxy.
+//!
+//! The HTTP proxy is tunneled by the `CONNECT` method, the SOCKS proxy by the
+//! SOCKS5 protocol. The TLS of the OAP server is negotiated by tonic over the
+//! tunnel, so it's still end to end.
+
+use crate::util::{expand_env_vars, join_host_port};
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{service::Service, Uri};
+use std::{
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    task::{self, Poll},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpStream},
+};
+use tracing::debug;
+use url::Url;
+
+/// Guard against the proxy responding endlessly without the blank line.
+const MAX_HTTP_RESPONSE_HEAD_LEN: usize = 8192;
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_AUTH_NONE: u8 = 0;
+const SOCKS5_AUTH_PASSWORD: u8 = 2;
+const SOCKS5_AUTH_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS5_PASSWORD_AUTH_VERSION: u8 = 1;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+
+#[derive(Clone, Copy)]
+enum ProxyKind {
+    Http,
+    /// The server host is resolved by the proxy if `remote_dns`, the
+    /// `socks5h` scheme, otherwise resolved locally.
+    Socks5 {
+        remote_dns: bool,
+    },
+}
+
+/// The connector of tonic, connecting to the proxy and tunneling to the
+/// requested uri.
+#[derive(Clone)]
+pub struct ProxyConnector {
+    kind: ProxyKind,
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+impl ProxyConnector {
+    /// Parse the proxy url, returns `None` if empty, the `${ENV}` in the
+    /// userinfo is expanded after percent-decoding, so the credentials from
+    /// the environment variables needn't be encoded.
+    pub fn from_url(proxy: &str) -> anyhow::Result<Option<Self>> {
+        let proxy = proxy.trim();
+        if proxy.is_empty() {
+            return Ok(None);
+        }
+
+        let url = Url::parse(proxy).context("illegal proxy url")?;
+        let kind = match url.scheme() {
+            "http" => ProxyKind::Http,
+            "socks5" => ProxyKind::Socks5 { remote_dns: false },
+            "socks5h" => ProxyKind::Socks5 { remote_dns: true },
+            scheme => bail!("unsupported proxy scheme `{}`", scheme),
+        };
+        let host = url.host_str().context("proxy url has no host")?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = url.port_or_known_default().unwrap_or(1080);
+
+        let credentials = if url.username().is_empty() {
+            None
+        } else {
+            let username = expand_env_vars(&percent_decode(url.username())?);
+            let password = expand_env_vars(&percent_decode(url.password().unwrap_or_default())?);
+            Some((username, password))
+        };
+
+        Ok(Some(Self {
+            kind,
+            addr: join_host_port(host, port),
+            credentials,
+        }))
+    }
+
+    /// The address of the proxy, without the credentials, for logging.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    async fn connect(self, uri: Uri) -> anyhow::Result<TcpStream> {
+        let host = uri.host().context("server uri has no host")?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let default_port = if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        };
+        let port = uri.port_u16().unwrap_or(default_port);
+
+        let mut stream = TcpStream::connect(&self.addr)
+            .await
+            .with_context(|| format!("connect to proxy {} failed", self.addr))?;
+        stream.set_nodelay(true)?;
+
+        match self.kind {
+            ProxyKind::Http => self.handshake_http(&mut stream, host, port).await?,
+            ProxyKind::Socks5 { remote_dns } => {
+                self.handshake_socks5(&mut stream, host, port, remote_dns)
+                    .await?
+            }
+        }
+
+        debug!(
+            proxy = %self.addr,
+            host, port, "Tunnel through proxy established"
+        );
+        Ok(stream)
+    }
+
+    async fn handshake_http(
+        &self, stream: &mut TcpStream, host: &str, port: u16,
+    ) -> anyhow::Result<()> {
+        let target = join_host_port(host, port);
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some((username, password)) = &self.credentials {
+            let token = STANDARD.encode(format!("{}:{}", username, password));
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // Read byte by byte, the bytes after the head belong to the tunnel.
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_HTTP_RESPONSE_HEAD_LEN {
+                bail!("response head of proxy is too large");
+            }
+            head.push(stream.read_u8().await?);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        let status_line = head.lines().next().unwrap_or_default();
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok());
+        match status {
+            Some(200..=299) => Ok(()),
+            _ => Err(anyhow!("proxy refused to connect: {}", status_line)),
+        }
+    }
+
+    async fn handshake_socks5(
+        &self, stream: &mut TcpStream, host: &str, port: u16, remote_dns: bool,
+    ) -> anyhow::Result<()> {
+        let methods: &[u8] = if self.credentials.is_some() {
+            &[SOCKS5_AUTH_NONE, SOCKS5_AUTH_PASSWORD]
+        } else {
+            &[SOCKS5_AUTH_NONE]
+        };
+        let mut greeting = vec![SOCKS5_VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS5_VERSION {
+            bail!("proxy isn't socks5, version {}", reply[0]);
+        }
+        match (reply[1], &self.credentials) {
+            (SOCKS5_AUTH_NONE, _) => {}
+            (SOCKS5_AUTH_PASSWORD, Some((username, password))) => {
+                authenticate_socks5(stream, username, password).await?
+            }
+            (SOCKS5_AUTH_NO_ACCEPTABLE, _) => bail!("socks5 proxy has no acceptable auth method"),
+            (method, _) => bail!("socks5 proxy chose unsupported auth method {}", method),
+        }
+
+        let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0];
+        let ip = match host.parse::<IpAddr>() {
+            Ok(ip) => Some(ip),
+            Err(_) if remote_dns => None,
+            Err(_) => Some(
+                lookup_host((host, port))
+                    .await?
+                    .next()
+                    .with_context(|| format!("resolve {} failed", host))?
+                    .ip(),
+            ),
+        };
+        match ip {
+            Some(IpAddr::V4(ip)) => {
+                request.push(SOCKS5_ATYP_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Some(IpAddr::V6(ip)) => {
+                request.push(SOCKS5_ATYP_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            None => {
+                let len = u8::try_from(host.len()).context("server host is too long")?;
+                request.push(SOCKS5_ATYP_DOMAIN);
+                request.push(len);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            bail!("socks5 proxy refused to connect, reply {}", reply[1]);
+        }
+        // Skip the bound address and port.
+        let len = match reply[3] {
+            SOCKS5_ATYP_IPV4 => 4,
+            SOCKS5_ATYP_IPV6 => 16,
+            SOCKS5_ATYP_DOMAIN => stream.read_u8().await? as usize,
+            atyp => bail!("socks5 proxy replied unknown address type {}", atyp),
+        };
+        let mut bound = vec![0u8; len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(())
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Error = anyhow::Error;
+    type Future = Pin<Box<dyn Future<Output = anyhow::Result<TcpStream>> + Send>>;
+    type Response = TcpStream;
+
+    fn poll_ready(&mut self, _cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        Box::pin(self.clone().connect(uri))
+    }
+}
+
+/// The username/password authentication of SOCKS5, RFC 1929.
+async fn authenticate_socks5(
+    stream: &mut TcpStream, username: &str, password: &str,
+) -> anyhow::Result<()> {
+    let username_len = u8::try_from(username.len()).context("proxy username is too long")?;
+    let password_len = u8::try_from(password.len()).context("proxy password is too long")?;
+
+    let mut request = vec![SOCKS5_PASSWORD_AUTH_VERSION, username_len];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password_len);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        bail!("socks5 proxy authentication failed");
+    }
+    Ok(())
+}
+
+/// The userinfo kept by `Url` is percent-encoded.
+fn percent_decode(s: &str) -> anyhow::Result<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(decoded).context("proxy userinfo isn't utf-8")
+}
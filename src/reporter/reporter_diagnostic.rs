@@ -0,0 +1,104 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Log the summaries of the items at info level instead of reporting them,
+//! enabled by `skywalking_agent.diagnostic_mode`, for verifying the spans
+//! without OAP.
+//!
+//! Every span is formatted and logged, which is expensive, so it's only for
+//! debugging, never for production.
+
+use crate::status::WORKER_STATUS;
+use skywalking::{
+    proto::v3::{KeyStringValuePair, SegmentObject},
+    reporter::{CollectItem, CollectItemConsume, CollectItemProduce},
+};
+use tracing::{info, warn};
+
+pub async fn run_reporter(
+    _producer: impl CollectItemProduce, mut consumer: impl CollectItemConsume,
+) -> anyhow::Result<()> {
+    warn!("Diagnostic mode is on, the items are logged instead of reported, only for debugging");
+    WORKER_STATUS.set_connected();
+
+    loop {
+        match consumer.consume().await {
+            Ok(Some(item)) => log_item(&item),
+            Ok(None) => break,
+            Err(err) => warn!(?err, "Consume collect item failed"),
+        }
+    }
+
+    Ok(())
+}
+
+fn log_item(item: &CollectItem) {
+    match item {
+        CollectItem::Trace(segment) => log_segment(segment),
+        CollectItem::Instance(properties) => {
+            info!(
+                service = %properties.service,
+                service_instance = %properties.service_instance,
+                properties = %format_pairs(&properties.properties),
+                "Diagnostic instance properties"
+            );
+        }
+        CollectItem::Ping(ping) => {
+            info!(
+                service = %ping.service,
+                service_instance = %ping.service_instance,
+                "Diagnostic keep alive"
+            );
+        }
+        _ => {
+            info!("Diagnostic item isn't segment, skipped");
+        }
+    }
+}
+
+fn log_segment(segment: &SegmentObject) {
+    info!(
+        trace_id = %segment.trace_id,
+        segment_id = %segment.trace_segment_id,
+        service = %segment.service,
+        service_instance = %segment.service_instance,
+        spans = segment.spans.len(),
+        "Diagnostic segment"
+    );
+
+    for span in &segment.spans {
+        info!(
+            segment_id = %segment.trace_segment_id,
+            span_id = span.span_id,
+            parent_span_id = span.parent_span_id,
+            operation_name = %span.operation_name,
+            span_type = ?span.span_type(),
+            peer = %span.peer,
+            component_id = span.component_id,
+            duration_ms = span.end_time - span.start_time,
+            is_error = span.is_error,
+            tags = %format_pairs(&span.tags),
+            "Diagnostic span"
+        );
+    }
+}
+
+fn format_pairs(pairs: &[KeyStringValuePair]) -> String {
+    pairs
+        .iter()
+        .map(|pair| format!("{}={}", pair.key, pair.value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -13,21 +13,59 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::module::{
-    AUTHENTICATION, ENABLE_TLS, SERVER_ADDR, SSL_CERT_CHAIN_PATH, SSL_KEY_PATH, SSL_TRUSTED_CA_PATH,
+use super::{cds, clock_skew, event, profile, proxy::ProxyConnector};
+use crate::{
+    breaker,
+    module::{
+        AUTHENTICATION, CORRECT_CLOCK_SKEW, ENABLE_EVENT, ENABLE_PROFILING, ENABLE_TLS,
+        GRPC_EXTRA_HEADERS, GRPC_PROXY, KNOWN_SKYWALKING_VERSIONS, RECONNECT_MAX_BACKOFF_MS,
+        SERVER_ADDR, SKYWALKING_VERSION, SSL_CERT_CHAIN_PATH, SSL_KEY_PATH, SSL_TRUSTED_CA_PATH,
+    },
+    status::WORKER_STATUS,
+    util::{expand_env_vars, random_f64, split_host_port},
 };
 use anyhow::anyhow;
+use once_cell::sync::Lazy;
 use skywalking::reporter::{grpc::GrpcReporter, CollectItemConsume, CollectItemProduce};
-use std::time::Duration;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 use tokio::time::sleep;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
-use tracing::{debug, info, warn};
+use tonic::{
+    metadata::{AsciiMetadataKey, AsciiMetadataValue},
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity},
+    Code, Request, Status,
+};
+use tracing::{debug, error, info, warn};
+
+/// The first delay of reconnecting, doubled on every failure until
+/// `skywalking_agent.reconnect_max_backoff_ms`.
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+
+static EXTRA_HEADERS: Lazy<Vec<(AsciiMetadataKey, AsciiMetadataValue)>> =
+    Lazy::new(|| parse_extra_headers(&GRPC_EXTRA_HEADERS));
 
 pub async fn run_reporter(
     producer: impl CollectItemProduce, consumer: impl CollectItemConsume,
 ) -> anyhow::Result<()> {
     let endpoint = create_endpoint(&SERVER_ADDR).await?;
-    let channel = connect(endpoint).await;
+    let proxy = ProxyConnector::from_url(&GRPC_PROXY)?;
+    let channel = connect(endpoint, proxy).await;
+
+    if *CORRECT_CLOCK_SKEW {
+        clock_skew::detect(channel.clone()).await;
+    }
+
+    tokio::spawn(cds::run(channel.clone()));
+
+    if *ENABLE_PROFILING {
+        tokio::spawn(profile::run(channel.clone()));
+    }
+
+    if *ENABLE_EVENT {
+        tokio::spawn(event::run(channel.clone()));
+    }
 
     let mut reporter = GrpcReporter::new_with_pc(channel, producer, consumer);
 
@@ -35,13 +73,26 @@ pub async fn run_reporter(
         reporter = reporter.with_authentication(&*AUTHENTICATION);
     }
 
+    if !EXTRA_HEADERS.is_empty() {
+        reporter = reporter.with_custom_intercept(|mut request| {
+            insert_extra_headers(&mut request);
+            Ok(request)
+        });
+    }
+
     info!("Worker is ready...");
 
     let handle = reporter
         .reporting()
         .await
         .with_status_handle(|message, status| {
-            warn!(?status, "Collect failed: {}", message);
+            WORKER_STATUS.incr_failed();
+            breaker::record_failure();
+            if status.code() == Code::Unimplemented {
+                log_unimplemented_once(message, &status);
+            } else {
+                warn!(?status, "Collect failed: {}", message);
+            }
         })
         .spawn();
 
@@ -52,6 +103,72 @@ pub async fn run_reporter(
     Ok(())
 }
 
+/// The `UNIMPLEMENTED` status means the OAP server doesn't serve the collecting
+/// service, likely an older OAP server, or the receiver isn't enabled. Retrying
+/// won't help, so it's logged only once with the hint, and the requests are
+/// stopped by the circuit breaker.
+fn log_unimplemented_once(message: &str, status: &Status) {
+    static LOGGED: AtomicBool = AtomicBool::new(false);
+    if LOGGED.swap(true, Ordering::Relaxed) {
+        debug!(?status, "Collect failed: {}", message);
+        return;
+    }
+    error!(
+        ?status,
+        skywalking_version = *SKYWALKING_VERSION,
+        "Collect failed: {}, the skywalking server doesn't support the protocol, check that \
+         `skywalking_agent.skywalking_version` matches the major version of OAP server (the known \
+         ones are {:?}, the OAP server before 8 isn't supported), and the receivers are enabled",
+        message,
+        KNOWN_SKYWALKING_VERSIONS,
+    );
+}
+
+/// The interceptor of the gRPC clients other than the reporter.
+pub(super) fn intercept(mut request: Request<()>) -> Result<Request<()>, Status> {
+    if !AUTHENTICATION.is_empty() {
+        if let Ok(authentication) = AUTHENTICATION.parse() {
+            request
+                .metadata_mut()
+                .insert("authentication", authentication);
+        }
+    }
+    insert_extra_headers(&mut request);
+    Ok(request)
+}
+
+fn insert_extra_headers(request: &mut Request<()>) {
+    for (key, value) in &*EXTRA_HEADERS {
+        request.metadata_mut().insert(key.clone(), value.clone());
+    }
+}
+
+/// Parse the `key1:value1,key2:value2`, the illegal headers are skipped.
+fn parse_extra_headers(headers: &str) -> Vec<(AsciiMetadataKey, AsciiMetadataValue)> {
+    headers
+        .split(',')
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .filter_map(|header| {
+            let Some((key, value)) = header.split_once(':') else {
+                error!(header, "Illegal grpc extra header, missing colon");
+                return None;
+            };
+            let key = key.trim();
+            let Ok(key) = AsciiMetadataKey::from_bytes(key.to_ascii_lowercase().as_bytes()) else {
+                error!(key, "Illegal grpc extra header name");
+                return None;
+            };
+            let Ok(value) = expand_env_vars(value.trim()).parse::<AsciiMetadataValue>() else {
+                error!(?key, "Illegal grpc extra header value");
+                return None;
+            };
+            debug!(?key, "Add grpc extra header");
+            Some((key, value))
+        })
+        .collect()
+}
+
 async fn create_endpoint(server_addr: &str) -> anyhow::Result<Endpoint> {
     let scheme = if *ENABLE_TLS { "https" } else { "http" };
 
@@ -68,7 +185,7 @@ async fn create_endpoint(server_addr: &str) -> anyhow::Result<Endpoint> {
     );
 
     if *ENABLE_TLS {
-        let domain_name = server_addr.split(':').next().unwrap_or_default();
+        let (domain_name, _) = split_host_port(server_addr)?;
         debug!(domain_name, "Configure TLS domain");
         let mut tls = ClientTlsConfig::new().domain_name(domain_name);
 
@@ -97,19 +214,56 @@ async fn create_endpoint(server_addr: &str) -> anyhow::Result<Endpoint> {
 }
 
 #[tracing::instrument(skip_all)]
-async fn connect(endpoint: Endpoint) -> Channel {
+async fn connect(endpoint: Endpoint, proxy: Option<ProxyConnector>) -> Channel {
+    let uri = &*endpoint.uri().to_string();
+    let proxy_addr = proxy.as_ref().map(ProxyConnector::addr);
+    let max_backoff = Duration::from_millis(*RECONNECT_MAX_BACKOFF_MS);
+    let mut backoff = INITIAL_RECONNECT_BACKOFF.min(max_backoff);
+    let mut attempts = 0u64;
+
     let channel = loop {
-        match endpoint.connect().await {
+        let result = match &proxy {
+            Some(proxy) => endpoint.connect_with_connector(proxy.clone()).await,
+            None => endpoint.connect().await,
+        };
+        match result {
             Ok(channel) => break channel,
             Err(err) => {
-                warn!(?err, "Connect to skywalking server failed, retry after 10s");
-                sleep(Duration::from_secs(10)).await;
+                attempts += 1;
+                let delay = with_jitter(backoff);
+                if attempts == 1 {
+                    warn!(
+                        ?err,
+                        uri,
+                        proxy = proxy_addr,
+                        "Connect to skywalking server failed, keep retrying"
+                    );
+                }
+                debug!(
+                    ?err,
+                    attempts,
+                    ?delay,
+                    "Reconnect to skywalking server later"
+                );
+                sleep(delay).await;
+                backoff = (backoff * 2).min(max_backoff);
             }
         }
     };
 
-    let uri = &*endpoint.uri().to_string();
-    info!(uri, "Skywalking server connected");
+    info!(
+        uri,
+        proxy = proxy_addr,
+        attempts,
+        "Skywalking server connected"
+    );
+    WORKER_STATUS.set_connected();
 
     channel
 }
+
+/// The equal jitter, the delay is randomly between the half of backoff and the
+/// backoff, to avoid the workers reconnecting at the same time.
+fn with_jitter(backoff: Duration) -> Duration {
+    backoff / 2 + backoff.mul_f64(random_f64() / 2.)
+}
@@ -0,0 +1,215 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Report to the REST receiver of OAP by HTTP/1.1 and JSON, for the networks
+//! not passing the gRPC.
+//!
+//! The items are taken in batches like the gRPC reporter, the segments of one
+//! batch are posted together to `/v3/segments`, the instance properties and
+//! pings to `/v3/management/*`. The meters aren't supported by the REST
+//! receiver, so they are dropped.
+
+use crate::{
+    breaker,
+    channel::Priority,
+    module::{AUTHENTICATION, HTTP_RECEIVER_URL},
+    status::WORKER_STATUS,
+};
+use anyhow::{bail, Context};
+use hyper::{client::HttpConnector, header, Body, Client, Method, Request, Uri};
+use serde_json::{json, Value};
+use skywalking::{
+    proto::v3::{
+        InstancePingPkg, InstanceProperties, KeyStringValuePair, Log, SegmentObject,
+        SegmentReference, SpanObject,
+    },
+    reporter::{CollectItem, CollectItemConsume, CollectItemProduce},
+};
+use tracing::{debug, info, warn};
+
+const SEGMENTS_PATH: &str = "/v3/segments";
+const REPORT_PROPERTIES_PATH: &str = "/v3/management/reportProperties";
+const KEEP_ALIVE_PATH: &str = "/v3/management/keepAlive";
+
+pub async fn run_reporter(
+    _producer: impl CollectItemProduce, mut consumer: impl CollectItemConsume,
+) -> anyhow::Result<()> {
+    let base_url = HTTP_RECEIVER_URL.trim_end_matches('/');
+    let uri = base_url
+        .parse::<Uri>()
+        .context("invalid http receiver url")?;
+    if uri.scheme_str() != Some("http") {
+        bail!(
+            "http receiver url should start with `http://`, {}",
+            base_url
+        );
+    }
+
+    let client = Client::new();
+    WORKER_STATUS.set_connected();
+    info!(base_url, "Worker is ready...");
+
+    loop {
+        let item = match consumer.consume().await {
+            Ok(Some(item)) => item,
+            Ok(None) => break,
+            Err(err) => {
+                warn!(?err, "Consume collect item failed");
+                continue;
+            }
+        };
+
+        let mut segments = Vec::new();
+        handle_item(&client, base_url, item, &mut segments).await;
+        // Drain the rest of the batch filled by the consumer.
+        while let Ok(Some(item)) = consumer.try_consume().await {
+            handle_item(&client, base_url, item, &mut segments).await;
+        }
+
+        if !segments.is_empty() {
+            post(&client, base_url, SEGMENTS_PATH, Value::Array(segments)).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_item(
+    client: &Client<HttpConnector>, base_url: &str, item: CollectItem, segments: &mut Vec<Value>,
+) {
+    match item {
+        CollectItem::Trace(segment) => segments.push(segment_to_json(&segment)),
+        CollectItem::Instance(properties) => {
+            post(
+                client,
+                base_url,
+                REPORT_PROPERTIES_PATH,
+                properties_to_json(&properties),
+            )
+            .await
+        }
+        CollectItem::Ping(ping) => {
+            post(client, base_url, KEEP_ALIVE_PATH, ping_to_json(&ping)).await
+        }
+        _ => {
+            debug!("Collect item isn't supported by http reporter, dropped");
+            WORKER_STATUS.incr_dropped(Priority::of(&item));
+        }
+    }
+}
+
+async fn post(client: &Client<HttpConnector>, base_url: &str, path: &str, body: Value) {
+    if let Err(err) = try_post(client, base_url, path, body).await {
+        WORKER_STATUS.incr_failed();
+        breaker::record_failure();
+        warn!(?err, path, "Collect failed");
+    }
+}
+
+async fn try_post(
+    client: &Client<HttpConnector>, base_url: &str, path: &str, body: Value,
+) -> anyhow::Result<()> {
+    let mut builder = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}{}", base_url, path))
+        .header(header::CONTENT_TYPE, "application/json");
+    if !AUTHENTICATION.is_empty() {
+        builder = builder.header("authentication", &*AUTHENTICATION);
+    }
+    let request = builder.body(Body::from(serde_json::to_vec(&body)?))?;
+
+    let response = client.request(request).await?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("unexpected response status {}", status);
+    }
+    Ok(())
+}
+
+/// The field names are the lower camel case of the proto fields, and the enums
+/// are numbers, which are accepted by the protobuf json parser of OAP.
+fn segment_to_json(segment: &SegmentObject) -> Value {
+    json!({
+        "traceId": segment.trace_id,
+        "traceSegmentId": segment.trace_segment_id,
+        "spans": segment.spans.iter().map(span_to_json).collect::<Vec<_>>(),
+        "service": segment.service,
+        "serviceInstance": segment.service_instance,
+        "isSizeLimited": segment.is_size_limited,
+    })
+}
+
+fn span_to_json(span: &SpanObject) -> Value {
+    json!({
+        "spanId": span.span_id,
+        "parentSpanId": span.parent_span_id,
+        "startTime": span.start_time,
+        "endTime": span.end_time,
+        "refs": span.refs.iter().map(reference_to_json).collect::<Vec<_>>(),
+        "operationName": span.operation_name,
+        "peer": span.peer,
+        "spanType": span.span_type,
+        "spanLayer": span.span_layer,
+        "componentId": span.component_id,
+        "isError": span.is_error,
+        "tags": pairs_to_json(&span.tags),
+        "logs": span.logs.iter().map(log_to_json).collect::<Vec<_>>(),
+        "skipAnalysis": span.skip_analysis,
+    })
+}
+
+fn reference_to_json(reference: &SegmentReference) -> Value {
+    json!({
+        "refType": reference.ref_type,
+        "traceId": reference.trace_id,
+        "parentTraceSegmentId": reference.parent_trace_segment_id,
+        "parentSpanId": reference.parent_span_id,
+        "parentService": reference.parent_service,
+        "parentServiceInstance": reference.parent_service_instance,
+        "parentEndpoint": reference.parent_endpoint,
+        "networkAddressUsedAtPeer": reference.network_address_used_at_peer,
+    })
+}
+
+fn log_to_json(log: &Log) -> Value {
+    json!({
+        "time": log.time,
+        "data": pairs_to_json(&log.data),
+    })
+}
+
+fn properties_to_json(properties: &InstanceProperties) -> Value {
+    json!({
+        "service": properties.service,
+        "serviceInstance": properties.service_instance,
+        "properties": pairs_to_json(&properties.properties),
+        "layer": properties.layer,
+    })
+}
+
+fn ping_to_json(ping: &InstancePingPkg) -> Value {
+    json!({
+        "service": ping.service,
+        "serviceInstance": ping.service_instance,
+        "layer": ping.layer,
+    })
+}
+
+fn pairs_to_json(pairs: &[KeyStringValuePair]) -> Vec<Value> {
+    pairs
+        .iter()
+        .map(|pair| json!({ "key": pair.key, "value": pair.value }))
+        .collect()
+}
@@ -15,13 +15,17 @@
 
 #![cfg(feature = "kafka-reporter")]
 
-use crate::module::{KAFKA_BOOTSTRAP_SERVERS, KAFKA_PRODUCER_CONFIG};
+use crate::{
+    module::{KAFKA_BOOTSTRAP_SERVERS, KAFKA_NAMESPACE, KAFKA_PRODUCER_CONFIG},
+    status::WORKER_STATUS,
+};
 use anyhow::{bail, Context};
 use skywalking::reporter::{
     kafka::{KafkaReportBuilder, RDKafkaClientConfig},
     CollectItemConsume, CollectItemProduce,
 };
 use std::collections::HashMap;
+use tracing::info;
 
 pub async fn run_reporter(
     producer: impl CollectItemProduce, consumer: impl CollectItemConsume,
@@ -36,9 +40,15 @@ pub async fn run_reporter(
         client_config.set(key, value);
     }
 
-    let (_, reporting) = KafkaReportBuilder::new_with_pc(client_config, producer, consumer)
-        .build()
-        .await?;
+    let mut builder = KafkaReportBuilder::new_with_pc(client_config, producer, consumer);
+    let namespace = KAFKA_NAMESPACE.trim();
+    if !namespace.is_empty() {
+        info!(namespace, "Kafka topics are prefixed by namespace");
+        builder = builder.with_namespace(namespace);
+    }
+
+    let (_, reporting) = builder.build().await?;
+    WORKER_STATUS.set_connected();
     let handle = reporting.spawn();
     if let Err(err) = handle.await {
         bail!("wait handle failed: {:?}", err);
@@ -14,28 +14,76 @@
 // limitations under the License.
 
 use crate::{
+    breaker,
     component::COMPONENT_PHP_ID,
-    context::RequestContext,
-    module::{is_enable, INJECT_CONTEXT, SKYWALKING_VERSION},
-    util::{catch_unwind_result, get_sapi_module_name, z_val_to_string},
+    context::{
+        decode_traceparent_header, encode_service_header, is_skip_analysis_header, RequestContext,
+        SW_CORRELATION_HEADER, SW_HEADER, SW_SERVICE_HEADER, SW_X_HEADER, W3C_TRACEPARENT_HEADER,
+    },
+    dynamic_config, execute, meter,
+    module::{
+        is_enable, is_swoole_enabled, AUTO_CORRELATION_REQUEST_ID, CAPTURE_REQUEST_BODY_PATHS,
+        CLIENT_IP_HEADERS, CLI_OPERATION_NAME_MODE, CLI_OPERATION_NAME_MODE_ARGV,
+        CLI_OPERATION_NAME_MODE_SCRIPT_PATH, CORRELATION_REQUEST_ID_KEY,
+        DEFAULT_HTTP_SERVER_COMPONENT, DETERMINISTIC_SAMPLING, INJECT_CONTEXT,
+        INJECT_W3C_TRACEPARENT, QUERY_STRING_REDACT_KEYS, REPORT_FPM_INFO, REPORT_MEMORY,
+        REPORT_QUERY_STRING, RESPONSE_SERVICE_HEADER, SAMPLE_N_PER_3_SECS, SERVICE_INSTANCE,
+        SERVICE_NAME, SKYWALKING_VERSION, TAIL_SAMPLE_LATENCY_MS, TRACE_MAX_BODY_SIZE,
+    },
+    plugin, profiling,
+    tag::{redact_query, sanitize_url_with_keys, TRUNCATED_MARKER},
+    util::{
+        catch_unwind_result, get_sapi_module_name, hash_to_f64, random_f64, truncate_str,
+        z_val_to_string,
+    },
 };
 use anyhow::{anyhow, Context};
 use dashmap::DashMap;
-use once_cell::sync::Lazy;
-use phper::{arrays::ZArr, eg, pg, sg, sys, values::ZVal};
-use skywalking::trace::{propagation::decoder::decode_propagation, span::HandleSpanObject, tracer};
+use once_cell::sync::{Lazy, OnceCell};
+use phper::{arrays::ZArr, eg, functions::call, objects::ZObj, pg, sg, sys, values::ZVal};
+use skywalking::{
+    common::random_generator::RandomGenerator,
+    trace::{propagation::decoder::decode_propagation, span::HandleSpanObject, tracer},
+};
 use std::{
+    ffi::CString,
     panic::AssertUnwindSafe,
+    path::Path,
+    process,
     ptr::null_mut,
-    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Mutex,
+    },
 };
-use tracing::{error, instrument, trace, warn};
+use tracing::{debug, error, instrument, trace, warn};
 use url::Url;
 
 const INJECT_CONTEXT_SERVICE_NAME: &str = "SW_SERVICE_NAME";
 const INJECT_CONTEXT_INSTANCE_NAME: &str = "SW_INSTANCE_NAME";
 const INJECT_CONTEXT_TRACE_ID: &str = "SW_TRACE_ID";
 
+/// The max length of the operation name in `argv` mode.
+const MAX_CLI_OPERATION_NAME_LEN: usize = 256;
+
+const TAG_FPM_POOL: &str = "fpm.pool";
+const TAG_SCRIPT_NAME: &str = "script.name";
+const TAG_PROCESS_ID: &str = "process.id";
+const TAG_HTTP_REQUEST_BODY: &str = "http.request.body";
+const TAG_HTTP_PARAMS: &str = "http.params";
+const TAG_HTTP_CLIENT_IP: &str = "http.client_ip";
+const TAG_FPM_QUEUE_TIME_MS: &str = "fpm.queue_time_ms";
+const TAG_PHP_MEMORY_PEAK_BYTES: &str = "php.memory_peak_bytes";
+const TAG_PHP_MEMORY_LIMIT: &str = "php.memory_limit";
+
+/// The max queue time accepted, the larger one is likely caused by the clock
+/// skew or the wrong unit, and ignored.
+const MAX_QUEUE_TIME_MS: f64 = 3_600_000.;
+
+/// The tags of the serving process, which don't change during the process
+/// lifetime.
+static PROCESS_TAGS: OnceCell<Vec<(&'static str, String)>> = OnceCell::new();
+
 #[instrument(skip_all)]
 pub fn init() {
     if !is_enable() {
@@ -45,6 +93,10 @@ pub fn init() {
         if let Err(err) = catch_unwind_result(request_init_for_fpm) {
             error!(mode = "fpm", ?err, "request init failed");
         }
+    } else if is_cli_script() {
+        if let Err(err) = catch_unwind_result(request_init_for_cli) {
+            error!(mode = "cli", ?err, "request init failed");
+        }
     }
 }
 
@@ -57,9 +109,20 @@ pub fn shutdown() {
         if let Err(err) = catch_unwind_result(request_shutdown_for_fpm) {
             error!(mode = "fpm", ?err, "request shutdown failed");
         }
+    } else if is_cli_script() {
+        if let Err(err) = catch_unwind_result(request_shutdown_for_cli) {
+            error!(mode = "cli", ?err, "request shutdown failed");
+        }
     }
 }
 
+/// The cli script is traced as a whole request, except swoole, which traces
+/// the requests of server.
+#[inline]
+fn is_cli_script() -> bool {
+    get_sapi_module_name().to_bytes() == b"cli" && !is_swoole_enabled()
+}
+
 fn request_init_for_fpm() -> crate::Result<()> {
     jit_initialization();
 
@@ -69,17 +132,149 @@ fn request_init_for_fpm() -> crate::Result<()> {
     let url = get_page_request_url(server)?;
     let method = get_page_request_method(server);
 
+    meter::start_request(None, get_http_operation_name(&method, &url));
+
+    if is_service_header_responded(header.as_deref()) {
+        let service_header = format!(
+            "{}: {}",
+            SW_SERVICE_HEADER,
+            encode_service_header(&SERVICE_NAME, &SERVICE_INSTANCE)
+        );
+        if let Err(err) = call("header", [ZVal::from(service_header)]) {
+            warn!(mode = "fpm", ?err, "respond service header failed");
+        }
+    }
+
+    let sampling = sample(Some(url.path()), header.as_deref());
+    if matches!(sampling, Sampling::Skipped) {
+        RequestContext::set_skipped(None);
+        return Ok(());
+    }
+
     create_request_context(None, header.as_deref(), &method, &url)?;
+    if !apply_sampling(None, sampling)? {
+        return Ok(());
+    }
+    set_skip_analysis_by_header(None, get_page_request_sw_x_header(server).as_deref())?;
+    set_correlation_by_header(
+        None,
+        get_page_request_sw_correlation_header(server).as_deref(),
+    )?;
+    add_client_ip_tag(None, get_page_request_client_ip(server))?;
+    add_queue_time_tag(None, get_page_request_queue_time_ms(server))?;
+
+    add_process_tags(None)?;
+
+    if is_request_body_captured(&url) {
+        if let Err(err) = capture_fpm_request_body() {
+            warn!(mode = "fpm", ?err, "capture request body failed");
+        }
+    }
+
+    profiling::start_request_profiling(None);
 
     inject_server_var_for_fpm()
 }
 
 fn request_shutdown_for_fpm() -> crate::Result<()> {
+    profiling::stop_request_profiling();
+
     let status_code = unsafe { sg!(sapi_headers).http_response_code };
 
+    meter::finish_request(None, status_code >= 400);
+
+    if RequestContext::remove_skipped(None) {
+        plugin::close_request_states(None);
+        return Ok(());
+    }
+
+    add_memory_tags(None)?;
+
     finish_request_context(None, status_code)
 }
 
+fn request_init_for_cli() -> crate::Result<()> {
+    jit_initialization();
+
+    let server = get_page_request_server()?;
+    let operation_name = get_cli_operation_name(server);
+
+    let sampling = sample(None, None);
+    if matches!(sampling, Sampling::Skipped) {
+        RequestContext::set_skipped(None);
+        return Ok(());
+    }
+
+    let ctx = new_request_context(None, &operation_name)?;
+    RequestContext::set_global(None, ctx);
+    if !apply_sampling(None, sampling)? {
+        return Ok(());
+    }
+    set_correlation_by_header(None, None)?;
+
+    add_process_tags(None)?;
+
+    profiling::start_request_profiling(None);
+
+    Ok(())
+}
+
+fn request_shutdown_for_cli() -> crate::Result<()> {
+    profiling::stop_request_profiling();
+
+    if RequestContext::remove_skipped(None) {
+        plugin::close_request_states(None);
+        return Ok(());
+    }
+
+    execute::close_pending_results(None);
+    plugin::close_request_states(None);
+
+    add_memory_tags(None)?;
+
+    let mut ctx = RequestContext::remove_global(None).context("request context not exists")?;
+
+    let exit_status = unsafe { eg!(exit_status) };
+    ctx.entry_span
+        .add_tag("cli.exit_code", &exit_status.to_string());
+    if exit_status != 0 {
+        ctx.entry_span.span_object_mut().is_error = true;
+    }
+
+    ctx.retain_tail_sampling(*TAIL_SAMPLE_LATENCY_MS);
+    ctx.finish();
+
+    Ok(())
+}
+
+fn get_cli_operation_name(server: &ZArr) -> String {
+    let script = server
+        .get("SCRIPT_FILENAME")
+        .and_then(z_val_to_string)
+        .unwrap_or_else(|| "unknown".to_string());
+
+    match CLI_OPERATION_NAME_MODE.as_str() {
+        CLI_OPERATION_NAME_MODE_SCRIPT_PATH => script,
+        CLI_OPERATION_NAME_MODE_ARGV => {
+            let argv = server
+                .get("argv")
+                .and_then(|argv| argv.as_z_arr())
+                .map(|argv| {
+                    argv.iter()
+                        .filter_map(|(_, arg)| z_val_to_string(arg))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or(script);
+            truncate_str(&argv, MAX_CLI_OPERATION_NAME_LEN).to_owned()
+        }
+        _ => Path::new(&script)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(script),
+    }
+}
+
 fn inject_server_var_for_fpm() -> crate::Result<()> {
     if *INJECT_CONTEXT {
         let server = get_mut_page_request_server()?;
@@ -107,11 +302,66 @@ fn get_page_request_header(server: &ZArr) -> Option<String> {
             .and_then(|sw| sw.as_z_str())
             .and_then(|zs| zs.to_str().ok())
             .map(|s| s.to_string())
+            .or_else(|| get_traceparent_header(server.get("HTTP_TRACEPARENT")))
     } else {
         None
     }
 }
 
+fn get_page_request_sw_x_header(server: &ZArr) -> Option<String> {
+    server.get("HTTP_SW8_X").and_then(z_val_to_string)
+}
+
+fn get_page_request_sw_correlation_header(server: &ZArr) -> Option<String> {
+    server.get("HTTP_SW8_CORRELATION").and_then(z_val_to_string)
+}
+
+/// The header `X-Forwarded-For` is `HTTP_X_FORWARDED_FOR` in `$_SERVER`.
+fn get_page_request_client_ip(server: &ZArr) -> Option<String> {
+    get_client_ip(
+        |name| {
+            let key = format!("HTTP_{}", name.to_ascii_uppercase().replace('-', "_"));
+            server.get(&*key).and_then(z_val_to_string)
+        },
+        || server.get("REMOTE_ADDR").and_then(z_val_to_string),
+    )
+}
+
+/// The time the request waited for php-fpm, from the frontend server accepted
+/// it to the `REQUEST_TIME_FLOAT` when php-fpm started it. The accepted time
+/// is passed by the header `X-Request-Start` or `X-Queue-Start`, like
+/// `t=${msec}` of nginx.
+fn get_page_request_queue_time_ms(server: &ZArr) -> Option<i64> {
+    let start_ms = ["HTTP_X_REQUEST_START", "HTTP_X_QUEUE_START"]
+        .into_iter()
+        .find_map(|key| server.get(key).and_then(z_val_to_string))
+        .and_then(|value| parse_request_start_ms(&value))?;
+    let fpm_start_ms = server.get("REQUEST_TIME_FLOAT").and_then(ZVal::as_double)? * 1e3;
+    let queue_time_ms = fpm_start_ms - start_ms;
+    (0. ..=MAX_QUEUE_TIME_MS)
+        .contains(&queue_time_ms)
+        .then(|| queue_time_ms.round() as i64)
+}
+
+/// The value is `t=<timestamp>` or `<timestamp>`, the unit of seconds,
+/// milliseconds or microseconds is guessed by the magnitude.
+fn parse_request_start_ms(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let timestamp = value
+        .strip_prefix("t=")
+        .unwrap_or(value)
+        .parse::<f64>()
+        .ok()
+        .filter(|timestamp| timestamp.is_finite())?;
+    Some(if timestamp > 1e15 {
+        timestamp / 1e3
+    } else if timestamp > 1e12 {
+        timestamp
+    } else {
+        timestamp * 1e3
+    })
+}
+
 fn get_page_request_url(server: &ZArr) -> crate::Result<Url> {
     let scheme = if [Some("1"), Some("on")]
         .contains(&server.get("HTTPS").and_then(z_val_to_string).as_deref())
@@ -174,6 +424,43 @@ fn get_mut_page_request_server<'a>() -> anyhow::Result<&'a mut ZArr> {
 pub const HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME: &str =
     "skywalking_hack_swoole_on_request_please_do_not_use";
 
+pub const HACK_SWOOLE_ON_OPEN_FUNCTION_NAME: &str =
+    "skywalking_hack_swoole_on_open_please_do_not_use";
+
+pub const HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME: &str =
+    "skywalking_hack_swoole_on_message_please_do_not_use";
+
+/// Remove the swoole hack functions registered with the module, so they don't
+/// pollute the global function namespace when swoole isn't enabled. Only
+/// called in module init.
+///
+/// The disabled functions are removed from the function table since PHP 8,
+/// and still defined but not callable before.
+pub fn disable_swoole_hack_functions() {
+    let function_names = [
+        HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME,
+        HACK_SWOOLE_ON_OPEN_FUNCTION_NAME,
+        HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME,
+    ];
+
+    #[cfg(phper_major_version = "8")]
+    {
+        let function_list = CString::new(function_names.join(",")).unwrap();
+        unsafe { sys::zend_disable_functions(function_list.as_ptr()) };
+    }
+
+    #[cfg(not(phper_major_version = "8"))]
+    for function_name in function_names {
+        let name = CString::new(function_name).unwrap();
+        unsafe { sys::zend_disable_function(name.as_ptr() as *mut _, function_name.len()) };
+    }
+}
+
+/// The frame payload larger than it isn't parsed for the metadata.
+const MAX_WEBSOCKET_METADATA_FRAME_SIZE: usize = 64 * 1024;
+
+const WEBSOCKET_OPCODE_TEXT: i64 = 1;
+
 /// Hold the response fd and status code kvs, because I dont't found that
 /// response has the status field, so I hook the response.status method, maybe
 /// there is a better way?
@@ -181,8 +468,36 @@ pub static SWOOLE_RESPONSE_STATUS_MAP: Lazy<DashMap<i64, i32>> = Lazy::new(DashM
 
 pub static ORI_SWOOLE_ON_REQUEST: AtomicPtr<sys::zval> = AtomicPtr::new(null_mut());
 
+pub static ORI_SWOOLE_ON_OPEN: AtomicPtr<sys::zval> = AtomicPtr::new(null_mut());
+
+pub static ORI_SWOOLE_ON_MESSAGE: AtomicPtr<sys::zval> = AtomicPtr::new(null_mut());
+
 pub static IS_SWOOLE: AtomicBool = AtomicBool::new(false);
 
+/// Whether the function is the hacked swoole callback, `onRequest`, `onOpen`
+/// or `onMessage`.
+pub fn is_swoole_hack_function(function_name: &[u8]) -> bool {
+    [
+        HACK_SWOOLE_ON_REQUEST_FUNCTION_NAME,
+        HACK_SWOOLE_ON_OPEN_FUNCTION_NAME,
+        HACK_SWOOLE_ON_MESSAGE_FUNCTION_NAME,
+    ]
+    .iter()
+    .any(|name| function_name == name.as_bytes())
+}
+
+/// The request id of the hacked swoole callbacks is the id of the coroutine
+/// running the callback, rather than the `fd`, so the concurrent callbacks of
+/// one connection, like the websocket frames, don't share the context. The
+/// hooked calls in the callback run in the same coroutine.
+///
+/// The id is `-1` if the coroutine is disabled, the callbacks run one by one
+/// then.
+pub fn get_swoole_request_id() -> crate::Result<i64> {
+    let cid = call(r"Swoole\Coroutine::getCid", [])?;
+    Ok(cid.as_long().context("swoole coroutine id isn't long")?)
+}
+
 /// The function is used by swoole plugin, to surround the callback of on
 /// request.
 pub fn skywalking_hack_swoole_on_request(args: &mut [ZVal]) -> phper::Result<ZVal> {
@@ -193,7 +508,10 @@ pub fn skywalking_hack_swoole_on_request(args: &mut [ZVal]) -> phper::Result<ZVa
     }
     let f = unsafe { ZVal::from_mut_ptr(f) };
 
-    let result = catch_unwind_result(AssertUnwindSafe(|| request_init_for_swoole(&mut args[0])));
+    let result = catch_unwind_result(AssertUnwindSafe(|| {
+        let (request, response) = args.split_at_mut(1);
+        request_init_for_swoole(&mut request[0], &mut response[0])
+    }));
     if let Err(err) = &result {
         error!(mode = "swoole", ?err, "request init failed");
     }
@@ -207,9 +525,9 @@ pub fn skywalking_hack_swoole_on_request(args: &mut [ZVal]) -> phper::Result<ZVa
         );
     }
 
-    if result.is_ok() {
+    if let Ok(request_id) = result {
         if let Err(err) = catch_unwind_result(AssertUnwindSafe(move || {
-            request_shutdown_for_swoole(&mut args[1])
+            request_shutdown_for_swoole(request_id, &mut args[1])
         })) {
             error!(mode = "swoole", ?err, "request shutdown failed");
         }
@@ -218,15 +536,209 @@ pub fn skywalking_hack_swoole_on_request(args: &mut [ZVal]) -> phper::Result<ZVa
     return_value
 }
 
-fn request_init_for_swoole(request: &mut ZVal) -> crate::Result<()> {
+/// The function is used by swoole plugin, to surround the callback of on
+/// open of websocket server.
+pub fn skywalking_hack_swoole_on_open(args: &mut [ZVal]) -> phper::Result<ZVal> {
+    hack_swoole_websocket_callback(&ORI_SWOOLE_ON_OPEN, args, |args| {
+        request_init_for_swoole_websocket_open(&mut args[1])
+    })
+}
+
+/// The function is used by swoole plugin, to surround the callback of on
+/// message of websocket server.
+pub fn skywalking_hack_swoole_on_message(args: &mut [ZVal]) -> phper::Result<ZVal> {
+    hack_swoole_websocket_callback(&ORI_SWOOLE_ON_MESSAGE, args, |args| {
+        request_init_for_swoole_websocket_message(&mut args[1])
+    })
+}
+
+fn hack_swoole_websocket_callback(
+    ori_callback: &AtomicPtr<sys::zval>, args: &mut [ZVal],
+    init: impl FnOnce(&mut [ZVal]) -> crate::Result<i64>,
+) -> phper::Result<ZVal> {
+    let f = ori_callback.load(Ordering::Relaxed);
+    if f.is_null() {
+        error!("Origin swoole websocket handler is null");
+        return Ok(ZVal::from(()));
+    }
+    let f = unsafe { ZVal::from_mut_ptr(f) };
+
+    let result = catch_unwind_result(AssertUnwindSafe(|| init(&mut *args)));
+    if let Err(err) = &result {
+        error!(mode = "swoole", ?err, "websocket request init failed");
+    }
+
+    let return_value = f.call(&mut *args);
+    if let Err(err) = &return_value {
+        error!(
+            mode = "swoole",
+            ?err,
+            "Something wrong when call the origin websocket handler"
+        );
+    }
+
+    if let Ok(request_id) = result {
+        let is_error = return_value.is_err();
+        if let Err(err) = catch_unwind_result(AssertUnwindSafe(|| {
+            request_shutdown_for_swoole_websocket(request_id, is_error)
+        })) {
+            error!(mode = "swoole", ?err, "websocket request shutdown failed");
+        }
+    }
+
+    return_value
+}
+
+fn request_init_for_swoole_websocket_open(request: &mut ZVal) -> crate::Result<i64> {
     let request = request
         .as_mut_z_obj()
         .context("swoole request isn't object")?;
 
-    let fd = request
-        .get_property("fd")
-        .as_long()
-        .context("swoole request fd not exists")?;
+    let request_id = get_swoole_request_id()?;
+
+    let headers = request
+        .get_property("header")
+        .as_z_arr()
+        .context("swoole request header not exists")?;
+
+    let header = get_swoole_request_header(headers);
+
+    let server = request
+        .get_property("server")
+        .as_z_arr()
+        .context("swoole request server not exists")?;
+
+    let url = get_swoole_request_url(server, headers)?;
+
+    if !should_trace(Some(url.path()), header.as_deref()) {
+        RequestContext::set_skipped(Some(request_id));
+        return Ok(request_id);
+    }
+
+    let operation_name = format!("WebSocket:OPEN:{}", url.path());
+    let mut ctx = new_request_context(header.as_deref(), &operation_name)?;
+    ctx.entry_span.add_tag("url", url.to_string());
+
+    RequestContext::set_global(Some(request_id), ctx);
+    set_skip_analysis_by_header(
+        Some(request_id),
+        get_swoole_request_sw_x_header(headers).as_deref(),
+    )?;
+    set_correlation_by_header(
+        Some(request_id),
+        get_swoole_request_sw_correlation_header(headers).as_deref(),
+    )?;
+    add_client_ip_tag(
+        Some(request_id),
+        get_swoole_request_client_ip(server, headers),
+    )?;
+
+    add_process_tags(Some(request_id))?;
+
+    Ok(request_id)
+}
+
+fn request_init_for_swoole_websocket_message(frame: &mut ZVal) -> crate::Result<i64> {
+    let frame = frame.as_z_obj().context("swoole frame isn't object")?;
+
+    let request_id = get_swoole_request_id()?;
+
+    let opcode = frame.get_property("opcode").as_long().unwrap_or_default();
+
+    let metadata = if opcode == WEBSOCKET_OPCODE_TEXT {
+        frame
+            .get_property("data")
+            .as_z_str()
+            .and_then(|data| get_websocket_frame_metadata(data.to_bytes()))
+    } else {
+        None
+    };
+    let (header, command) = metadata.unwrap_or_default();
+
+    if !should_trace(None, header.as_deref()) {
+        RequestContext::set_skipped(Some(request_id));
+        return Ok(request_id);
+    }
+
+    let command = command.unwrap_or_else(|| get_websocket_opcode_name(opcode).to_owned());
+    let operation_name = format!("WebSocket:MESSAGE:{}", command);
+    let mut ctx = new_request_context(header.as_deref(), &operation_name)?;
+    ctx.entry_span
+        .add_tag("websocket.opcode", opcode.to_string());
+
+    RequestContext::set_global(Some(request_id), ctx);
+
+    add_process_tags(Some(request_id))?;
+
+    Ok(request_id)
+}
+
+fn request_shutdown_for_swoole_websocket(request_id: i64, is_error: bool) -> crate::Result<()> {
+    if RequestContext::remove_skipped(Some(request_id)) {
+        plugin::close_request_states(Some(request_id));
+        return Ok(());
+    }
+
+    execute::close_pending_results(Some(request_id));
+    plugin::close_request_states(Some(request_id));
+
+    let RequestContext {
+        tracing_context,
+        mut entry_span,
+    } = RequestContext::remove_global(Some(request_id)).context("request context not exists")?;
+
+    if is_error {
+        entry_span.span_object_mut().is_error = true;
+    }
+
+    drop(entry_span);
+    drop(tracing_context);
+
+    Ok(())
+}
+
+fn get_websocket_opcode_name(opcode: i64) -> &'static str {
+    match opcode {
+        0 => "continuation",
+        WEBSOCKET_OPCODE_TEXT => "text",
+        2 => "binary",
+        8 => "close",
+        9 => "ping",
+        10 => "pong",
+        _ => "unknown",
+    }
+}
+
+/// Get the `sw8` header and the command from the JSON text frame, like
+/// `{"cmd": "chat", "metadata": {"sw8": "..."}}`, the `sw8` can also be
+/// placed in the top level.
+fn get_websocket_frame_metadata(data: &[u8]) -> Option<(Option<String>, Option<String>)> {
+    if data.len() > MAX_WEBSOCKET_METADATA_FRAME_SIZE || !data.starts_with(b"{") {
+        return None;
+    }
+    let payload = serde_json::from_slice::<serde_json::Value>(data).ok()?;
+    let payload = payload.as_object()?;
+
+    let header = payload
+        .get("metadata")
+        .and_then(|metadata| metadata.get(SW_HEADER))
+        .or_else(|| payload.get(SW_HEADER))
+        .and_then(|header| header.as_str())
+        .map(ToOwned::to_owned);
+    let command = ["cmd", "command", "event"]
+        .iter()
+        .find_map(|key| payload.get(*key).and_then(|command| command.as_str()))
+        .map(ToOwned::to_owned);
+
+    Some((header, command))
+}
+
+fn request_init_for_swoole(request: &mut ZVal, response: &mut ZVal) -> crate::Result<i64> {
+    let request = request
+        .as_mut_z_obj()
+        .context("swoole request isn't object")?;
+
+    let request_id = get_swoole_request_id()?;
 
     let headers = request
         .get_property("header")
@@ -243,17 +755,66 @@ fn request_init_for_swoole(request: &mut ZVal) -> crate::Result<()> {
     let method = get_swoole_request_method(server);
     let url = get_swoole_request_url(server, headers)?;
 
-    create_request_context(Some(fd), header.as_deref(), &method, &url)?;
+    meter::start_request(Some(request_id), get_http_operation_name(&method, &url));
+
+    if is_service_header_responded(header.as_deref()) {
+        let result = response
+            .as_mut_z_obj()
+            .context("swoole response isn't object")
+            .and_then(|response| {
+                let service_header = encode_service_header(&SERVICE_NAME, &SERVICE_INSTANCE);
+                Ok(response.call(
+                    "header",
+                    [ZVal::from(SW_SERVICE_HEADER), ZVal::from(service_header)],
+                )?)
+            });
+        if let Err(err) = result {
+            warn!(mode = "swoole", ?err, "respond service header failed");
+        }
+    }
+
+    let sampling = sample(Some(url.path()), header.as_deref());
+    if matches!(sampling, Sampling::Skipped) {
+        RequestContext::set_skipped(Some(request_id));
+        return Ok(request_id);
+    }
+
+    create_request_context(Some(request_id), header.as_deref(), &method, &url)?;
+    if !apply_sampling(Some(request_id), sampling)? {
+        return Ok(request_id);
+    }
+    set_skip_analysis_by_header(
+        Some(request_id),
+        get_swoole_request_sw_x_header(headers).as_deref(),
+    )?;
+    set_correlation_by_header(
+        Some(request_id),
+        get_swoole_request_sw_correlation_header(headers).as_deref(),
+    )?;
+    add_client_ip_tag(
+        Some(request_id),
+        get_swoole_request_client_ip(server, headers),
+    )?;
+
+    add_process_tags(Some(request_id))?;
+
+    if is_request_body_captured(&url) {
+        if let Err(err) = capture_swoole_request_body(request_id, request) {
+            warn!(mode = "swoole", ?err, "capture request body failed");
+        }
+    }
 
     let server = request
         .get_mut_property("server")
         .as_mut_z_arr()
         .context("swoole request server not exists")?;
 
-    inject_server_var_for_swoole(Some(fd), server)
+    inject_server_var_for_swoole(Some(request_id), server)?;
+
+    Ok(request_id)
 }
 
-fn request_shutdown_for_swoole(response: &mut ZVal) -> crate::Result<()> {
+fn request_shutdown_for_swoole(request_id: i64, response: &mut ZVal) -> crate::Result<()> {
     let response = response
         .as_mut_z_obj()
         .context("swoole response isn't object")?;
@@ -263,13 +824,19 @@ fn request_shutdown_for_swoole(response: &mut ZVal) -> crate::Result<()> {
         .as_long()
         .context("swoole request fd not exists")?;
 
-    finish_request_context(
-        Some(fd),
-        SWOOLE_RESPONSE_STATUS_MAP
-            .remove(&fd)
-            .map(|(_, status)| status)
-            .unwrap_or(200),
-    )
+    let status_code = SWOOLE_RESPONSE_STATUS_MAP
+        .remove(&fd)
+        .map(|(_, status)| status)
+        .unwrap_or(200);
+
+    meter::finish_request(Some(request_id), status_code >= 400);
+
+    if RequestContext::remove_skipped(Some(request_id)) {
+        plugin::close_request_states(Some(request_id));
+        return Ok(());
+    }
+
+    finish_request_context(Some(request_id), status_code)
 }
 
 fn inject_server_var_for_swoole(request_id: Option<i64>, server: &mut ZArr) -> crate::Result<()> {
@@ -287,11 +854,46 @@ fn get_swoole_request_header(header: &ZArr) -> Option<String> {
             .and_then(|sw| sw.as_z_str())
             .and_then(|zs| zs.to_str().ok())
             .map(|s| s.to_string())
+            .or_else(|| get_traceparent_header(header.get(W3C_TRACEPARENT_HEADER)))
     } else {
         None
     }
 }
 
+/// Continue the trace from the `traceparent` header if no `sw8`, mapped to the
+/// `sw8` header, enabled by `skywalking_agent.inject_w3c_traceparent`.
+fn get_traceparent_header(traceparent: Option<&ZVal>) -> Option<String> {
+    if !*INJECT_W3C_TRACEPARENT {
+        return None;
+    }
+    let traceparent = traceparent.and_then(z_val_to_string)?;
+    let header = decode_traceparent_header(&traceparent);
+    if header.is_none() {
+        debug!(%traceparent, "Ignore the invalid traceparent header");
+    }
+    header
+}
+
+fn get_swoole_request_sw_x_header(header: &ZArr) -> Option<String> {
+    header.get(SW_X_HEADER).and_then(z_val_to_string)
+}
+
+fn get_swoole_request_sw_correlation_header(header: &ZArr) -> Option<String> {
+    header.get(SW_CORRELATION_HEADER).and_then(z_val_to_string)
+}
+
+/// The header names of swoole request are lowercase.
+fn get_swoole_request_client_ip(server: &ZArr, headers: &ZArr) -> Option<String> {
+    get_client_ip(
+        |name| {
+            headers
+                .get(&*name.to_ascii_lowercase())
+                .and_then(z_val_to_string)
+        },
+        || server.get("remote_addr").and_then(z_val_to_string),
+    )
+}
+
 fn get_swoole_request_url(server: &ZArr, headers: &ZArr) -> crate::Result<Url> {
     let addr = headers
         .get("host")
@@ -325,9 +927,241 @@ fn get_swoole_request_method(server: &ZArr) -> String {
         .unwrap_or_else(|| "UNKNOWN".to_string())
 }
 
+/// The window of `skywalking_agent.sample_n_per_3_secs`.
+const SAMPLE_WINDOW_MILLIS: i64 = 3000;
+
+/// The index of the current sample window and the count of requests sampled
+/// in it by this process.
+static SAMPLE_WINDOW: Mutex<(i64, i64)> = Mutex::new((0, 0));
+
+enum Sampling {
+    Traced,
+    /// Sampled out, but traced to be retained if slow or errored.
+    TailSampling,
+    Skipped,
+    /// Decided by the hash of the trace id against the rate, after the context
+    /// created, when `skywalking_agent.deterministic_sampling` is on.
+    ByTraceId(f64),
+}
+
+/// Decide whether to trace the request by the dynamic config. The ignored path
+/// is never traced, and the request propagated from upstream is always traced,
+/// to keep the trace complete, unless the sample flag of the header is `0`.
+/// The others are sampled by the rate of the first matched endpoint sample
+/// rule, or the global sample rate, then limited by
+/// `skywalking_agent.sample_n_per_3_secs`, and the sampled out requests are
+/// tail sampling if `skywalking_agent.tail_sample_latency_ms` is set.
+fn sample(path: Option<&str>, header: Option<&str>) -> Sampling {
+    // Stop tracing when the reporting is failing, to protect the request latency.
+    if breaker::is_open() {
+        return Sampling::Skipped;
+    }
+    let config = dynamic_config::get();
+    if path.map_or(false, |path| config.is_path_ignored(path)) {
+        return Sampling::Skipped;
+    }
+    if header.map_or(false, |header| !header.starts_with("0-")) {
+        return Sampling::Traced;
+    }
+    let sample_rate = config.sample_rate_of(path);
+    if sample_rate < 1. && *DETERMINISTIC_SAMPLING {
+        Sampling::ByTraceId(sample_rate)
+    } else if (sample_rate >= 1. || random_f64() < sample_rate) && try_sample_in_window() {
+        Sampling::Traced
+    } else if *TAIL_SAMPLE_LATENCY_MS > 0 {
+        Sampling::TailSampling
+    } else {
+        Sampling::Skipped
+    }
+}
+
+/// For the requests without the tail sampling, like the websocket frames,
+/// which are decided before the context created, so there is no trace id to
+/// be hashed.
+fn should_trace(path: Option<&str>, header: Option<&str>) -> bool {
+    match sample(path, header) {
+        Sampling::Traced => true,
+        Sampling::ByTraceId(sample_rate) => random_f64() < sample_rate && try_sample_in_window(),
+        Sampling::TailSampling | Sampling::Skipped => false,
+    }
+}
+
+/// Mark the request context tail sampling if needed, and decide the
+/// deterministic sampling by the trace id of the context. Returns false if
+/// the request turns out not traced, whose context is discarded.
+fn apply_sampling(request_id: Option<i64>, sampling: Sampling) -> crate::Result<bool> {
+    let is_tail_sampling = match sampling {
+        Sampling::TailSampling => true,
+        Sampling::ByTraceId(sample_rate) => {
+            let trace_id = RequestContext::try_with_global_ctx(request_id, |ctx| {
+                Ok(ctx.trace_id().to_owned())
+            })?;
+            if hash_to_f64(&trace_id) < sample_rate && try_sample_in_window() {
+                return Ok(true);
+            }
+            if *TAIL_SAMPLE_LATENCY_MS <= 0 {
+                RequestContext::ignore(request_id);
+                return Ok(false);
+            }
+            true
+        }
+        Sampling::Traced | Sampling::Skipped => false,
+    };
+    if is_tail_sampling {
+        RequestContext::try_with_global(request_id, |ctx| {
+            ctx.set_tail_sampling();
+            Ok(())
+        })?;
+    }
+    Ok(true)
+}
+
+/// Count the request sampled in the current window of 3 seconds, returns false
+/// if `skywalking_agent.sample_n_per_3_secs` is reached. The limit is per
+/// process, so it's multiplied by the count of php-fpm children.
+fn try_sample_in_window() -> bool {
+    if *SAMPLE_N_PER_3_SECS <= 0 {
+        return true;
+    }
+    let window = profiling::now_millis() / SAMPLE_WINDOW_MILLIS;
+    let mut state = match SAMPLE_WINDOW.lock() {
+        Ok(state) => state,
+        Err(err) => {
+            error!(?err, "Get sample window lock failed");
+            return true;
+        }
+    };
+    if state.0 != window {
+        *state = (window, 0);
+    }
+    if state.1 >= *SAMPLE_N_PER_3_SECS {
+        return false;
+    }
+    state.1 += 1;
+    true
+}
+
+/// The `sw8-service` header is only responded to the callers propagating the
+/// `sw8` header, it isn't hidden from the other clients, which can send a
+/// forged `sw8`.
+fn is_service_header_responded(header: Option<&str>) -> bool {
+    *RESPONSE_SERVICE_HEADER && header.is_some()
+}
+
+fn get_http_operation_name(method: &str, url: &Url) -> String {
+    format!("{}:{}", method, url.path())
+}
+
 fn create_request_context(
     request_id: Option<i64>, header: Option<&str>, method: &str, url: &Url,
 ) -> crate::Result<()> {
+    let operation_name = get_http_operation_name(method, url);
+    let mut ctx = new_request_context(header, &operation_name)?;
+
+    let span_object = ctx.entry_span.span_object_mut();
+    span_object.component_id = *DEFAULT_HTTP_SERVER_COMPONENT;
+    // Masked by the same keys of `http.params`, otherwise they leak by the url.
+    span_object.add_tag(
+        "url",
+        sanitize_url_with_keys(url.as_str(), &QUERY_STRING_REDACT_KEYS),
+    );
+    span_object.add_tag("http.method", method);
+    if *REPORT_QUERY_STRING {
+        if let Some(query) = url.query().filter(|query| !query.is_empty()) {
+            span_object.add_tag(
+                TAG_HTTP_PARAMS,
+                redact_query(query, &QUERY_STRING_REDACT_KEYS),
+            );
+        }
+    }
+
+    RequestContext::set_global(request_id, ctx);
+
+    Ok(())
+}
+
+/// Honor the skip analysis of the `sw8-x` header from upstream.
+fn set_skip_analysis_by_header(
+    request_id: Option<i64>, sw_x_header: Option<&str>,
+) -> crate::Result<()> {
+    if sw_x_header.map_or(false, is_skip_analysis_header) {
+        RequestContext::try_with_global(request_id, |ctx| {
+            ctx.set_skip_analysis();
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Find the client ip by the first present header of `client_ip_headers`, the
+/// left-most address of the comma separated list like `X-Forwarded-For` is
+/// the client, fallback to the remote address. Disabled if
+/// `client_ip_headers` is empty.
+fn get_client_ip(
+    get_header: impl Fn(&str) -> Option<String>, get_remote_addr: impl FnOnce() -> Option<String>,
+) -> Option<String> {
+    if CLIENT_IP_HEADERS.is_empty() {
+        return None;
+    }
+    CLIENT_IP_HEADERS
+        .iter()
+        .find_map(|name| {
+            get_header(name)?
+                .split(',')
+                .map(str::trim)
+                .find(|addr| !addr.is_empty())
+                .map(ToOwned::to_owned)
+        })
+        .or_else(get_remote_addr)
+}
+
+fn add_client_ip_tag(request_id: Option<i64>, client_ip: Option<String>) -> crate::Result<()> {
+    if let Some(client_ip) = client_ip {
+        RequestContext::try_with_global(request_id, |ctx| {
+            ctx.entry_span.add_tag(TAG_HTTP_CLIENT_IP, client_ip);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn add_queue_time_tag(request_id: Option<i64>, queue_time_ms: Option<i64>) -> crate::Result<()> {
+    if let Some(queue_time_ms) = queue_time_ms {
+        RequestContext::try_with_global(request_id, |ctx| {
+            ctx.entry_span
+                .add_tag(TAG_FPM_QUEUE_TIME_MS, queue_time_ms.to_string());
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Put the correlation context from the `sw8-correlation` header of upstream,
+/// then generate the request id if `auto_correlation_request_id` is enabled
+/// and upstream didn't pass one.
+fn set_correlation_by_header(
+    request_id: Option<i64>, sw_correlation_header: Option<&str>,
+) -> crate::Result<()> {
+    RequestContext::try_with_global(request_id, |ctx| {
+        if let Some(sw_correlation_header) = sw_correlation_header {
+            ctx.put_correlation_by_header(sw_correlation_header);
+        }
+        if *AUTO_CORRELATION_REQUEST_ID
+            && ctx.get_correlation(&CORRELATION_REQUEST_ID_KEY).is_none()
+        {
+            ctx.put_correlation(
+                CORRELATION_REQUEST_ID_KEY.clone(),
+                RandomGenerator::generate(),
+            );
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn new_request_context(
+    header: Option<&str>, operation_name: &str,
+) -> crate::Result<RequestContext> {
     let propagation = header
         .map(decode_propagation)
         .transpose()
@@ -337,41 +1171,171 @@ fn create_request_context(
 
     let mut ctx = tracer::create_trace_context();
 
-    let operation_name = format!("{}:{}", method, url.path());
     let mut span = match propagation {
-        Some(propagation) => ctx.create_entry_span_with_propagation(&operation_name, &propagation),
-        None => ctx.create_entry_span(&operation_name),
+        Some(propagation) => ctx.create_entry_span_with_propagation(operation_name, &propagation),
+        None => ctx.create_entry_span(operation_name),
     };
+    span.span_object_mut().component_id = COMPONENT_PHP_ID;
 
-    let span_object = span.span_object_mut();
-    span_object.component_id = COMPONENT_PHP_ID;
-    span_object.add_tag("url", url.to_string());
-    span_object.add_tag("http.method", method);
+    Ok(RequestContext {
+        tracing_context: ctx,
+        entry_span: span,
+    })
+}
 
-    RequestContext::set_global(
-        request_id,
-        RequestContext {
-            tracing_context: ctx,
-            entry_span: span,
-        },
-    );
+fn add_process_tags(request_id: Option<i64>) -> crate::Result<()> {
+    if !*REPORT_FPM_INFO {
+        return Ok(());
+    }
 
-    Ok(())
+    let tags = PROCESS_TAGS.get_or_init(get_process_tags);
+
+    Ok(RequestContext::try_with_global(request_id, |ctx| {
+        for (key, value) in tags {
+            ctx.entry_span.add_tag(*key, value);
+        }
+        Ok(())
+    })?)
+}
+
+/// Tag the peak memory usage and the memory limit of the request, enabled by
+/// `skywalking_agent.report_memory`. Only for php-fpm and cli, the memory of
+/// swoole is shared by the requests in the process.
+fn add_memory_tags(request_id: Option<i64>) -> crate::Result<()> {
+    if !*REPORT_MEMORY {
+        return Ok(());
+    }
+
+    // Same as `memory_get_peak_usage(true)`, the parameter is `int` before PHP
+    // 8. The memory limit is in bytes, -1 means unlimited.
+    let memory_peak = unsafe { sys::zend_memory_peak_usage(true.into()) };
+    let memory_limit = unsafe { pg!(memory_limit) };
+
+    Ok(RequestContext::try_with_global(request_id, |ctx| {
+        ctx.entry_span
+            .add_tag(TAG_PHP_MEMORY_PEAK_BYTES, memory_peak.to_string());
+        ctx.entry_span
+            .add_tag(TAG_PHP_MEMORY_LIMIT, memory_limit.to_string());
+        Ok(())
+    })?)
+}
+
+/// Tag the pool name under php-fpm, and the script name under cli.
+fn get_process_tags() -> Vec<(&'static str, String)> {
+    let mut tags = Vec::with_capacity(2);
+
+    if get_sapi_module_name().to_bytes() == b"fpm-fcgi" {
+        if let Some(pool) = get_fpm_pool_name() {
+            tags.push((TAG_FPM_POOL, pool));
+        }
+    } else if let Some(script_name) = get_page_request_server()
+        .ok()
+        .and_then(|server| server.get("SCRIPT_NAME"))
+        .and_then(z_val_to_string)
+    {
+        tags.push((TAG_SCRIPT_NAME, script_name));
+    }
+
+    tags.push((TAG_PROCESS_ID, process::id().to_string()));
+
+    tags
+}
+
+/// The pool name is got from `fpm_get_status()` (since PHP 7.3), fallback to
+/// the pool user.
+fn get_fpm_pool_name() -> Option<String> {
+    let exists = call("function_exists", [ZVal::from("fpm_get_status")])
+        .ok()
+        .and_then(|exists| exists.as_bool())
+        .unwrap_or_default();
+    if exists {
+        let status = call("fpm_get_status", []).ok();
+        let pool = status
+            .as_ref()
+            .and_then(ZVal::as_z_arr)
+            .and_then(|status| status.get("pool"))
+            .and_then(z_val_to_string);
+        if pool.is_some() {
+            return pool;
+        }
+    }
+
+    get_page_request_server()
+        .ok()
+        .and_then(|server| server.get("USER"))
+        .and_then(z_val_to_string)
+}
+
+fn is_request_body_captured(url: &Url) -> bool {
+    let path = url.path();
+    CAPTURE_REQUEST_BODY_PATHS
+        .iter()
+        .any(|pattern| dynamic_config::is_path_matched(pattern, path))
+}
+
+/// Read the raw input, with one more byte to know whether it is truncated.
+fn capture_fpm_request_body() -> crate::Result<()> {
+    let body = call(
+        "file_get_contents",
+        [
+            ZVal::from("php://input"),
+            ZVal::from(false),
+            ZVal::from(()),
+            ZVal::from(0i64),
+            ZVal::from(*TRACE_MAX_BODY_SIZE as i64 + 1),
+        ],
+    )?;
+    match body.as_z_str() {
+        Some(body) => add_request_body_tag(None, body.to_bytes()),
+        None => Ok(()),
+    }
+}
+
+fn capture_swoole_request_body(request_id: i64, request: &mut ZObj) -> crate::Result<()> {
+    let body = request.call("rawContent", [])?;
+    match body.as_z_str() {
+        Some(body) => add_request_body_tag(Some(request_id), body.to_bytes()),
+        None => Ok(()),
+    }
+}
+
+fn add_request_body_tag(request_id: Option<i64>, body: &[u8]) -> crate::Result<()> {
+    if body.is_empty() {
+        return Ok(());
+    }
+
+    let max_size = *TRACE_MAX_BODY_SIZE;
+    let is_truncated = body.len() > max_size;
+    let mut body = String::from_utf8_lossy(&body[..body.len().min(max_size)]).into_owned();
+    // The invalid bytes, like the char split by the max size, are replaced by
+    // the 3 bytes `U+FFFD`, so the converted body may exceed the max size.
+    if is_truncated || body.len() > max_size {
+        let len = truncate_str(&body, max_size).len();
+        body.truncate(len);
+        body.push_str(TRUNCATED_MARKER);
+    }
+
+    Ok(RequestContext::try_with_global(request_id, |ctx| {
+        ctx.entry_span.add_tag(TAG_HTTP_REQUEST_BODY, body);
+        Ok(())
+    })?)
 }
 
 fn finish_request_context(request_id: Option<i64>, status_code: i32) -> crate::Result<()> {
-    let RequestContext {
-        tracing_context,
-        mut entry_span,
-    } = RequestContext::remove_global(request_id).context("request context not exists")?;
+    execute::close_pending_results(request_id);
+    plugin::close_request_states(request_id);
+
+    let mut ctx =
+        RequestContext::remove_global(request_id).context("request context not exists")?;
 
-    entry_span.add_tag("http.status_code", &status_code.to_string());
+    ctx.entry_span
+        .add_tag("http.status_code", &status_code.to_string());
     if status_code >= 400 {
-        entry_span.span_object_mut().is_error = true;
+        ctx.entry_span.span_object_mut().is_error = true;
     }
 
-    drop(entry_span);
-    drop(tracing_context);
+    ctx.retain_tail_sampling(*TAIL_SAMPLE_LATENCY_MS);
+    ctx.finish();
 
     Ok(())
 }
@@ -0,0 +1,147 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Worker status, periodically written to the status file in JSON, so that
+//! the health of worker can be checked without parsing logs.
+
+use crate::{breaker, channel::Priority};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{fs, time::interval};
+use tracing::{debug, error};
+
+/// Interval of writing status file.
+const STATUS_WRITE_INTERVAL: Duration = Duration::from_secs(10);
+
+pub static WORKER_STATUS: Lazy<WorkerStatus> = Lazy::new(WorkerStatus::new);
+
+pub struct WorkerStatus {
+    start_time: Instant,
+    is_connected: AtomicBool,
+    last_report_time: AtomicI64,
+    last_failed_time: AtomicI64,
+    reported_count: AtomicU64,
+    dropped_count: AtomicU64,
+    dropped_core_count: AtomicU64,
+    dropped_low_count: AtomicU64,
+    failed_count: AtomicU64,
+}
+
+impl WorkerStatus {
+    fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            is_connected: AtomicBool::new(false),
+            last_report_time: AtomicI64::new(0),
+            last_failed_time: AtomicI64::new(0),
+            reported_count: AtomicU64::new(0),
+            dropped_count: AtomicU64::new(0),
+            dropped_core_count: AtomicU64::new(0),
+            dropped_low_count: AtomicU64::new(0),
+            failed_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Mark the connection to skywalking server is established.
+    pub fn set_connected(&self) {
+        self.is_connected.store(true, Ordering::Relaxed);
+    }
+
+    /// Record the collect item is handed to the reporter.
+    pub fn incr_reported(&self) {
+        self.reported_count.fetch_add(1, Ordering::Relaxed);
+        self.last_report_time.store(now_secs(), Ordering::Relaxed);
+    }
+
+    /// Record the collect item is dropped before reporting.
+    pub fn incr_dropped(&self, priority: Priority) {
+        self.dropped_count.fetch_add(1, Ordering::Relaxed);
+        match priority {
+            Priority::Core => &self.dropped_core_count,
+            Priority::Low => &self.dropped_low_count,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the reporter get a failed status from skywalking server.
+    pub fn incr_failed(&self) {
+        self.failed_count.fetch_add(1, Ordering::Relaxed);
+        self.last_failed_time.store(now_secs(), Ordering::Relaxed);
+    }
+
+    /// Connected means the connection is established and no report failed in
+    /// the last status write interval.
+    fn is_connected(&self) -> bool {
+        self.is_connected.load(Ordering::Relaxed)
+            && now_secs() - self.last_failed_time.load(Ordering::Relaxed)
+                > STATUS_WRITE_INTERVAL.as_secs() as i64
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "pid": std::process::id(),
+            "connected": self.is_connected(),
+            "last_report_timestamp": self.last_report_time.load(Ordering::Relaxed),
+            "last_failed_timestamp": self.last_failed_time.load(Ordering::Relaxed),
+            "reported_count": self.reported_count.load(Ordering::Relaxed),
+            "dropped_count": self.dropped_count.load(Ordering::Relaxed),
+            "dropped_core_count": self.dropped_core_count.load(Ordering::Relaxed),
+            "dropped_low_count": self.dropped_low_count.load(Ordering::Relaxed),
+            "failed_count": self.failed_count.load(Ordering::Relaxed),
+            "uptime_seconds": self.start_time.elapsed().as_secs(),
+            "circuit_breaker": breaker::status_json(),
+        })
+    }
+}
+
+/// Write the status file periodically, until the worker exits.
+pub async fn run_status_writer(status_file: PathBuf) {
+    let mut interval = interval(STATUS_WRITE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = write_status_file(&status_file).await {
+            error!(?err, ?status_file, "Write status file failed");
+        }
+    }
+}
+
+/// Write to the temporary file and then rename it, to make sure the status
+/// file is written atomically.
+async fn write_status_file(status_file: &Path) -> anyhow::Result<()> {
+    let content = serde_json::to_vec(&WORKER_STATUS.to_json())?;
+
+    let mut tmp_file = OsString::from(status_file.as_os_str());
+    tmp_file.push(".tmp");
+
+    fs::write(&tmp_file, content).await?;
+    fs::rename(&tmp_file, status_file).await?;
+
+    debug!(?status_file, "Status file written");
+
+    Ok(())
+}
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,139 @@
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The harness of unit tests to assert on the segments emitted, without the
+//! worker and the backend.
+//!
+//! Only the code not calling into the php engine can be tested, such as the
+//! spans created in the request context, the ini values are read through
+//! the php engine, so the functions reading them should be split first.
+
+use crate::context::RequestContext;
+use skywalking::{
+    proto::v3::{SegmentObject, SpanObject},
+    reporter::{CollectItem, Report},
+    trace::tracer::Tracer,
+};
+use std::sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc, Mutex,
+};
+
+/// The reporter records the collect items in memory, the segment options
+/// aren't applied.
+#[derive(Clone, Default)]
+pub struct RecordingReporter {
+    items: Arc<Mutex<Vec<CollectItem>>>,
+}
+
+impl RecordingReporter {
+    /// Create the tracer reporting to the new recording reporter.
+    pub fn tracer() -> (Tracer, Self) {
+        let reporter = Self::default();
+        let tracer = Tracer::new("service", "instance", reporter.clone());
+        (tracer, reporter)
+    }
+
+    /// The segments reported, in the order of finishing.
+    pub fn segments(&self) -> Vec<SegmentObject> {
+        self.items
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|item| match item {
+                CollectItem::Trace(segment) => Some((**segment).clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Report for RecordingReporter {
+    fn report(&self, item: CollectItem) {
+        self.items.lock().unwrap().push(item);
+    }
+}
+
+/// The request contexts are global, so every test takes the distinct request
+/// id, to run in parallel.
+pub fn next_request_id() -> Option<i64> {
+    static REQUEST_ID: AtomicI64 = AtomicI64::new(1);
+    Some(REQUEST_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Start the request context with the entry span, as the request hooks do.
+pub fn start_request(tracer: &Tracer, operation_name: &str) -> Option<i64> {
+    let request_id = next_request_id();
+    let mut tracing_context = tracer.create_trace_context();
+    let entry_span = tracing_context.create_entry_span(operation_name);
+    RequestContext::set_global(
+        request_id,
+        RequestContext {
+            tracing_context,
+            entry_span,
+        },
+    );
+    request_id
+}
+
+/// Finish the request context, the segment is reported.
+pub fn finish_request(request_id: Option<i64>) {
+    RequestContext::remove_global(request_id)
+        .expect("request context not exists")
+        .finish();
+}
+
+pub fn find_span<'a>(segment: &'a SegmentObject, operation_name: &str) -> Option<&'a SpanObject> {
+    segment
+        .spans
+        .iter()
+        .find(|span| span.operation_name == operation_name)
+}
+
+pub fn span_tag<'a>(span: &'a SpanObject, key: &str) -> Option<&'a str> {
+    span.tags
+        .iter()
+        .find(|tag| tag.key == key)
+        .map(|tag| tag.value.as_str())
+}
+
+mod tests {
+    use super::*;
+    use skywalking::{proto::v3::SpanType, trace::span::HandleSpanObject};
+
+    #[test]
+    fn record_request_segment() {
+        let (tracer, reporter) = RecordingReporter::tracer();
+        let request_id = start_request(&tracer, "GET:/");
+        RequestContext::try_with_global_ctx(request_id, |ctx| {
+            let mut span = ctx.create_exit_span("GET:/users", "127.0.0.1:8080");
+            span.add_tag("http.method", "GET");
+            Ok(())
+        })
+        .unwrap();
+        assert!(reporter.segments().is_empty());
+
+        finish_request(request_id);
+
+        let segments = reporter.segments();
+        assert_eq!(segments.len(), 1);
+        let entry = find_span(&segments[0], "GET:/").unwrap();
+        assert_eq!(entry.span_type, SpanType::Entry as i32);
+        let exit = find_span(&segments[0], "GET:/users").unwrap();
+        assert_eq!(exit.parent_span_id, entry.span_id);
+        assert_eq!(exit.peer, "127.0.0.1:8080");
+        assert_eq!(span_tag(exit, "http.method"), Some("GET"));
+    }
+}
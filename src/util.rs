@@ -13,14 +13,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::anyhow;
+use crate::module::{BACKTRACE_MAX_DEPTH, BACKTRACE_STRIP_PATHS};
+use anyhow::{anyhow, bail};
 use once_cell::sync::Lazy;
-use phper::{ini::ini_get, sys, values::ZVal};
+use phper::{arrays::ZArr, functions::call, ini::ini_get, objects::ZObj, sys, values::ZVal};
 use std::{
-    ffi::CStr,
+    cell::Cell,
+    env,
+    ffi::{CStr, CString},
+    fmt::Display,
+    io,
+    net::Ipv6Addr,
     os::unix::prelude::OsStrExt,
     panic::{catch_unwind, UnwindSafe},
     path::Path,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use systemstat::{IpAddr, Platform, System};
 
@@ -97,9 +105,444 @@ pub fn change_permission(f: impl AsRef<Path>, mode: libc::mode_t) {
     }
 }
 
+/// Parse the octal permission mode like `0777` or `770`.
+pub fn parse_file_mode(mode: &str) -> Option<libc::mode_t> {
+    let mode = mode.trim();
+    let mode = mode.strip_prefix("0o").unwrap_or(mode);
+    if mode.is_empty() {
+        return None;
+    }
+    libc::mode_t::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+}
+
+/// Truncate the string to the max bytes, at the char boundary.
+pub fn truncate_str(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Check the directory is writable by current process, the error contains the
+/// errno.
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    if unsafe { libc::access(dir.as_ptr(), libc::W_OK | libc::X_OK) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Write the message to the php error log, for the errors that the user must
+/// notice, like the agent is disabled.
+pub fn log_php_error(message: &str) {
+    let Ok(message) = CString::new(format!("[skywalking_agent] {}", message)) else {
+        return;
+    };
+    unsafe {
+        sys::php_log_err_with_severity(message.as_ptr() as *mut _, libc::LOG_ERR);
+    }
+}
+
+/// Split the `host:port` address, the IPv6 host must be bracketed like
+/// `[::1]:11800`, and the brackets are stripped from the returned host.
+pub fn split_host_port(addr: &str) -> anyhow::Result<(&str, Option<u16>)> {
+    let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+        let (host, rest) = rest
+            .split_once(']')
+            .ok_or_else(|| anyhow!("missing `]` of IPv6 address"))?;
+        match rest {
+            "" => (host, None),
+            _ => (
+                host,
+                Some(
+                    rest.strip_prefix(':')
+                        .ok_or_else(|| anyhow!("missing `:` after IPv6 address"))?,
+                ),
+            ),
+        }
+    } else {
+        match addr.split_once(':') {
+            Some((_, port)) if port.contains(':') => {
+                bail!("IPv6 address must be bracketed, like `[::1]:11800`");
+            }
+            Some((host, port)) => (host, Some(port)),
+            None => (addr, None),
+        }
+    };
+
+    if host.is_empty() {
+        bail!("missing host");
+    }
+    let port = port
+        .map(|port| {
+            port.parse::<u16>()
+                .map_err(|_| anyhow!("invalid port `{}`", port))
+        })
+        .transpose()?;
+
+    Ok((host, port))
+}
+
+/// Join the host and port into peer, the IPv6 host is bracketed.
+pub fn join_host_port(host: &str, port: impl Display) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Get the peer from the host which may contain port, like `127.0.0.1`,
+/// `127.0.0.1:3306`, `::1` or `[::1]:3306`, the default port is used if
+/// absent.
+pub fn host_to_peer(host: &str, default_port: impl Display) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        return join_host_port(host, default_port);
+    }
+    match split_host_port(host) {
+        Ok((host, Some(port))) => join_host_port(host, port),
+        Ok((host, None)) => join_host_port(host, default_port),
+        Err(_) => host.to_owned(),
+    }
+}
+
+/// Expand the `${NAME}` with the environment variable, the undefined variable
+/// is expanded to empty string.
+pub fn expand_env_vars(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(begin) = rest.find("${") {
+        let Some(end) = rest[begin..].find('}') else {
+            break;
+        };
+        result.push_str(&rest[..begin]);
+        let name = &rest[begin + 2..begin + end];
+        result.push_str(&env::var(name).unwrap_or_default());
+        rest = &rest[begin + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
 pub fn get_str_ini_with_default(name: &str) -> String {
     ini_get::<Option<&CStr>>(name)
         .and_then(|s| s.to_str().ok())
         .map(ToOwned::to_owned)
         .unwrap_or_default()
 }
+
+/// Generate the random number in `[0, 1)` by xorshift, which is enough for
+/// sampling, and seeded per thread.
+pub fn random_f64() -> f64 {
+    thread_local! {
+        static STATE: Cell<u64> = Cell::new({
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos() as u64)
+                .unwrap_or_default();
+            (nanos ^ ((process::id() as u64) << 32)) | 1
+        });
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+/// Hash the string to the number in `[0, 1)` by FNV-1a, finalized by the
+/// mixer of splitmix64, so the same string always gets the same number, used
+/// by the deterministic sampling.
+pub fn hash_to_f64(s: &str) -> f64 {
+    (hash_to_u64(s, 0) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The same hash as [`hash_to_f64`], the `seed` derives the independent
+/// hashes of the same string.
+pub fn hash_to_u64(s: &str, seed: u64) -> u64 {
+    let mut x = fnv1a_64(s.as_bytes(), seed);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// The 32-bit FNV-1a, which is stable across the agent versions, so the names
+/// derived from it, like the directories of services, stay the same.
+pub fn fnv1a_32(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// The 64-bit FNV-1a, the `seed` is mixed into the offset basis.
+pub fn fnv1a_64(bytes: &[u8], seed: u64) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325 ^ seed, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The `DEBUG_BACKTRACE_IGNORE_ARGS` option of `debug_backtrace()`.
+const DEBUG_BACKTRACE_IGNORE_ARGS: i64 = 2;
+
+/// The max depth of the backtrace, `skywalking_agent.backtrace_max_depth`
+/// lowers the limit of the caller if set.
+pub fn backtrace_max_depth(limit: usize) -> usize {
+    limit_depth(limit, *BACKTRACE_MAX_DEPTH)
+}
+
+/// The configured `max_depth` lowers the `limit`, `0` means unset.
+fn limit_depth(limit: usize, max_depth: usize) -> usize {
+    match max_depth {
+        0 => limit,
+        max_depth => max_depth.min(limit),
+    }
+}
+
+/// The limit passed to `debug_backtrace()`, `0` means unlimited.
+fn capture_limit(max_depth: usize, strip_paths: bool) -> i64 {
+    // The vendor frames are skipped after capturing, so can't limit here.
+    if strip_paths {
+        0
+    } else {
+        max_depth as i64
+    }
+}
+
+/// Capture the code signatures of current stack by `debug_backtrace()`, at
+/// most `limit` frames, see [`format_backtrace`].
+pub fn capture_backtrace(limit: usize) -> crate::Result<Vec<String>> {
+    let max_depth = backtrace_max_depth(limit);
+    let backtrace = call(
+        "debug_backtrace",
+        [
+            ZVal::from(DEBUG_BACKTRACE_IGNORE_ARGS),
+            ZVal::from(capture_limit(max_depth, *BACKTRACE_STRIP_PATHS)),
+        ],
+    )?;
+    Ok(backtrace
+        .as_z_arr()
+        .map(|frames| format_backtrace(frames, max_depth))
+        .unwrap_or_default())
+}
+
+/// Format the frames of `debug_backtrace()` or `Throwable::getTrace()` to the
+/// code signatures like `Foo->bar:12`, the innermost frame is the first, at
+/// most `max_depth` frames.
+///
+/// The frames called from the `vendor` directory are skipped if
+/// `skywalking_agent.backtrace_strip_paths` is enabled.
+pub fn format_backtrace(frames: &ZArr, max_depth: usize) -> Vec<String> {
+    frames
+        .iter()
+        .filter_map(|(_, frame)| frame.as_z_arr())
+        .filter(|frame| !*BACKTRACE_STRIP_PATHS || !is_vendor_frame(frame))
+        .take(max_depth)
+        .map(|frame| {
+            let get = |key: &str| frame.get(key).and_then(z_val_to_string);
+            format_frame(
+                get("function").as_deref(),
+                get("class").as_deref(),
+                get("type").as_deref(),
+                frame.get("line").and_then(ZVal::as_long),
+            )
+        })
+        .collect()
+}
+
+/// The code signature of one frame, like `Foo->bar:12`.
+fn format_frame(
+    function: Option<&str>, class: Option<&str>, typ: Option<&str>, line: Option<i64>,
+) -> String {
+    let function = function.unwrap_or("{main}");
+    let function = match (class, typ) {
+        (Some(class), Some(typ)) => format!("{}{}{}", class, typ, function),
+        _ => function.to_owned(),
+    };
+    match line {
+        Some(line) => format!("{}:{}", function, line),
+        None => function,
+    }
+}
+
+fn is_vendor_frame(frame: &ZArr) -> bool {
+    frame
+        .get("file")
+        .and_then(z_val_to_string)
+        .map(|file| is_vendor_file(&file))
+        .unwrap_or_default()
+}
+
+fn is_vendor_file(file: &str) -> bool {
+    file.contains("/vendor/")
+}
+
+/// The stack of the exception, formatted by [`format_backtrace`] if
+/// `skywalking_agent.backtrace_strip_paths` is enabled, so the absolute paths
+/// aren't reported, otherwise `getTraceAsString()` capped to the max depth.
+pub fn exception_backtrace(ex: &mut ZObj, limit: usize) -> Option<String> {
+    let max_depth = backtrace_max_depth(limit);
+    if *BACKTRACE_STRIP_PATHS {
+        let trace = ex.call("getTrace", []).ok()?;
+        let stack = format_backtrace(trace.as_z_arr()?, max_depth);
+        return Some(stack.join("\n"));
+    }
+
+    let trace = ex.call("getTraceAsString", []).ok()?;
+    let trace = trace.as_z_str()?.to_str().ok()?;
+    Some(trace.lines().take(max_depth).collect::<Vec<_>>().join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_str_short() {
+        assert_eq!(truncate_str("", 0), "");
+        assert_eq!(truncate_str("abc", 3), "abc");
+        assert_eq!(truncate_str("abc", 10), "abc");
+    }
+
+    #[test]
+    fn truncate_str_ascii() {
+        assert_eq!(truncate_str("abcdef", 3), "abc");
+        assert_eq!(truncate_str("abcdef", 0), "");
+    }
+
+    #[test]
+    fn truncate_str_multibyte_boundary() {
+        // "中" is 3 bytes, "é" is 2 bytes and "😀" is 4 bytes.
+        let s = "a中é😀";
+        assert_eq!(truncate_str(s, 1), "a");
+        assert_eq!(truncate_str(s, 2), "a");
+        assert_eq!(truncate_str(s, 3), "a");
+        assert_eq!(truncate_str(s, 4), "a中");
+        assert_eq!(truncate_str(s, 5), "a中");
+        assert_eq!(truncate_str(s, 6), "a中é");
+        assert_eq!(truncate_str(s, 9), "a中é");
+        assert_eq!(truncate_str(s, 10), s);
+    }
+
+    #[test]
+    fn truncate_str_never_exceeds_max_len() {
+        let s = "中".repeat(100);
+        for max_len in 0..=s.len() {
+            let truncated = truncate_str(&s, max_len);
+            assert!(truncated.len() <= max_len);
+            assert!(max_len - truncated.len() < 3);
+        }
+    }
+
+    #[test]
+    fn split_host_port_ipv4_and_hostname() {
+        assert_eq!(
+            split_host_port("127.0.0.1:11800").unwrap(),
+            ("127.0.0.1", Some(11800))
+        );
+        assert_eq!(
+            split_host_port("oap.example.com:11800").unwrap(),
+            ("oap.example.com", Some(11800))
+        );
+    }
+
+    #[test]
+    fn split_host_port_ipv6_brackets() {
+        assert_eq!(
+            split_host_port("[::1]:11800").unwrap(),
+            ("::1", Some(11800))
+        );
+        assert_eq!(
+            split_host_port("[fe80::1%eth0]:80").unwrap(),
+            ("fe80::1%eth0", Some(80))
+        );
+        assert_eq!(split_host_port("[::1]").unwrap(), ("::1", None));
+        assert!(split_host_port("::1:11800").is_err());
+        assert!(split_host_port("[::1:11800").is_err());
+        assert!(split_host_port("[::1]11800").is_err());
+        assert!(split_host_port("[]:11800").is_err());
+    }
+
+    #[test]
+    fn split_host_port_missing_port() {
+        assert_eq!(split_host_port("localhost").unwrap(), ("localhost", None));
+        assert!(split_host_port("localhost:").is_err());
+        assert!(split_host_port("[::1]:").is_err());
+        assert!(split_host_port(":11800").is_err());
+        assert!(split_host_port("").is_err());
+    }
+
+    #[test]
+    fn split_host_port_bad_port() {
+        assert!(split_host_port("localhost:port").is_err());
+        assert!(split_host_port("localhost:65536").is_err());
+        assert!(split_host_port("localhost:-1").is_err());
+        assert!(split_host_port("[::1]:abc").is_err());
+    }
+
+    #[test]
+    fn limit_depth_unset() {
+        assert_eq!(limit_depth(0, 0), 0);
+        assert_eq!(limit_depth(20, 0), 20);
+    }
+
+    #[test]
+    fn limit_depth_lowers_only() {
+        assert_eq!(limit_depth(20, 5), 5);
+        assert_eq!(limit_depth(5, 20), 5);
+        assert_eq!(limit_depth(5, 5), 5);
+    }
+
+    #[test]
+    fn capture_limit_strip_paths() {
+        assert_eq!(capture_limit(10, false), 10);
+        assert_eq!(capture_limit(10, true), 0);
+    }
+
+    #[test]
+    fn format_frame_signature() {
+        assert_eq!(
+            format_frame(Some("bar"), Some("Foo"), Some("->"), Some(12)),
+            "Foo->bar:12"
+        );
+        assert_eq!(
+            format_frame(Some("bar"), Some("Foo"), Some("::"), None),
+            "Foo::bar"
+        );
+        assert_eq!(format_frame(Some("foo"), None, None, Some(3)), "foo:3");
+        assert_eq!(format_frame(None, None, None, Some(7)), "{main}:7");
+    }
+
+    #[test]
+    fn format_frame_class_without_type() {
+        assert_eq!(format_frame(Some("bar"), Some("Foo"), None, None), "bar");
+    }
+
+    #[test]
+    fn vendor_file() {
+        assert!(is_vendor_file("/app/vendor/foo/bar/src/Baz.php"));
+        assert!(!is_vendor_file("/app/src/Vendor.php"));
+        assert!(!is_vendor_file("/app/vendors/Baz.php"));
+        assert!(!is_vendor_file("vendor/Baz.php"));
+    }
+
+    #[test]
+    fn fnv1a_vectors() {
+        assert_eq!(fnv1a_32(b""), 0x811c9dc5);
+        assert_eq!(fnv1a_32(b"a"), 0xe40c292c);
+        assert_eq!(fnv1a_32(b"foobar"), 0xbf9cf968);
+        assert_eq!(fnv1a_64(b"", 0), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_64(b"a", 0), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a_64(b"foobar", 0), 0x85944171f73967e8);
+    }
+}
@@ -14,12 +14,27 @@
 // limitations under the License.
 
 use crate::{
-    channel::{self, TxReporter},
+    breaker,
+    channel::{
+        self, priority_channel, ChannelItem, PriorityReceiver, PrioritySender, TxReporter,
+        WorkerAddr,
+    },
+    context::{
+        apply_segment_options, limit_segment_bytes, limit_segment_spans, propagate_exit_errors,
+        sample_exit_spans,
+    },
+    meter::{self, CustomMeterRecord, EndpointRecord},
     module::{
-        HEARTBEAT_PERIOD, PROPERTIES_REPORT_PERIOD_FACTOR, SERVICE_INSTANCE, SERVICE_NAME,
-        SOCKET_FILE_PATH, WORKER_THREADS,
+        is_thread_worker_mode, reopen_log_file, ENABLE_METER, HEARTBEAT_PERIOD,
+        INSTANCE_PROPERTIES_FILE, IPC_READ_TIMEOUT, MAX_IPC_CONNECTIONS,
+        PROPERTIES_REPORT_PERIOD_FACTOR, REPORT_BATCH_SIZE, REPORT_FLUSH_INTERVAL_MS,
+        REPORT_OS_INFO, SERVICE_INSTANCE, SERVICE_NAME, SOCKET_FILE_MODE, STATUS_FILE, WORKER_ADDR,
+        WORKER_THREADS,
     },
-    reporter::run_reporter,
+    peer::map_peer_services,
+    reporter::{clock_skew, event, profile, run_reporter},
+    status::{run_status_writer, WORKER_STATUS},
+    tag::truncate_tag_values,
     util::change_permission,
 };
 
@@ -27,24 +42,62 @@ use once_cell::sync::Lazy;
 
 use skywalking::{
     management::{instance::Properties, manager::Manager},
-    reporter::{CollectItem, CollectItemConsume},
+    proto::v3::{Event, ThreadSnapshot},
+    reporter::{CollectItem, CollectItemConsume, Report},
 };
 use std::{
-    cmp::Ordering, error::Error, fs, io, marker::PhantomData, num::NonZeroUsize, process::exit,
-    thread::available_parallelism, time::Duration,
+    cmp::Ordering,
+    collections::VecDeque,
+    error::Error,
+    ffi::{c_char, CStr},
+    fs,
+    future::pending,
+    io,
+    marker::PhantomData,
+    mem::forget,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{self, exit},
+    sync::{
+        atomic::{self, AtomicI32},
+        mpsc as std_mpsc, Arc, Mutex,
+    },
+    thread::{self, available_parallelism},
+    time::{Duration, SystemTime},
 };
 
+use crate::{module::AGENT_PID_FILE_PATH, SKYWALKING_AGENT_WORKER_THREADS};
 use fslock::LockFile;
 use tokio::{
-    net::UnixListener,
+    io::AsyncRead,
+    net::{TcpListener, UnixListener},
+    pin,
     runtime::{self, Runtime},
     select,
     signal::unix::{signal, SignalKind},
-    sync::mpsc::{self, error::TrySendError},
+    sync::{mpsc::error::TrySendError, Notify, Semaphore},
+    time::{interval, sleep_until, timeout, Instant, MissedTickBehavior},
 };
 use tonic::async_trait;
 use tracing::{debug, error, info, warn};
-use crate::module::AGENT_PID_FILE_PATH;
+
+/// The interval of checking the pending items when flushing thread worker.
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The waiting time for the holder of the pid file to write its pid.
+const PID_FILE_WRITE_WAITING: Duration = Duration::from_millis(100);
+
+/// The max waiting time of the blocking tasks when the runtime is dropped.
+const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The pid of the forked worker, inherited by the php processes forked later.
+static WORKER_PID: AtomicI32 = AtomicI32::new(0);
+
+/// The max waiting time of flushing the pending items when shutdown.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The interval of checking the modification of `instance_properties_file`.
+const PROPERTIES_FILE_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 pub fn init_worker() {
     let worker_threads = worker_threads();
@@ -62,48 +115,156 @@ pub fn init_worker() {
 
             Ordering::Equal => {
                 // Ensure worker process exits when master process exists.
-                #[cfg(target_os = "linux")]
+                // #[cfg(target_os = "linux")]
                 // libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
 
-                let mut pid_lock =
-                    LockFile::open(&*AGENT_PID_FILE_PATH).unwrap();
-                if !pid_lock.try_lock_with_pid().unwrap() {
-                    println!("process has running...");
-                    return;
-                }
+                // Held until the worker exits.
+                let _pid_lock = lock_pid_file(&AGENT_PID_FILE_PATH);
 
-
-                match fs::metadata(&*SOCKET_FILE_PATH) {
-                    Ok(_) => {
-                        if let Err(err) = fs::remove_file(&*SOCKET_FILE_PATH) {
-                            error!(?err, "Remove socket file failed");
+                let mut worker_threads = worker_threads;
+                loop {
+                    if let WorkerAddr::Unix(socket_file) = &*WORKER_ADDR {
+                        match fs::metadata(socket_file) {
+                            Ok(_) => {
+                                if let Err(err) = fs::remove_file(socket_file) {
+                                    error!(?err, "Remove socket file failed");
+                                }
+                            }
+                            Err(_) => {}
                         }
                     }
-                    Err(_) => {
 
-                    }
-                }
+                    // Run the worker in subprocess.
+                    let rt = new_tokio_runtime(worker_threads);
+                    let result = rt.block_on(start_worker());
+                    rt.shutdown_timeout(RUNTIME_SHUTDOWN_TIMEOUT);
 
-
-                // Run the worker in subprocess.
-                let rt = new_tokio_runtime(worker_threads);
-                match rt.block_on(start_worker()) {
-                    Ok(_) => {
-                        exit(0);
-                    }
-                    Err(err) => {
-                        error!(?err, "worker exit unexpectedly");
-                        exit(1);
+                    match result {
+                        Ok(WorkerExit::Shutdown) => {
+                            exit(0);
+                        }
+                        Ok(WorkerExit::Reload) => {
+                            worker_threads = reload_worker_threads().unwrap_or(worker_threads);
+                            info!(worker_threads, "Rebuild worker runtime");
+                        }
+                        Err(err) => {
+                            error!(?err, "worker exit unexpectedly");
+                            exit(1);
+                        }
                     }
                 }
             }
-            Ordering::Greater => {}
+            Ordering::Greater => {
+                WORKER_PID.store(pid, atomic::Ordering::Relaxed);
+            }
         }
     }
 }
 
+/// Whether the worker is running, the forked worker process is probed by
+/// signal 0, and the thread worker is checked in current process, both are
+/// cheap.
+pub fn is_worker_running() -> bool {
+    if is_thread_worker_mode() {
+        return match THREAD_WORKER.lock() {
+            Ok(worker) => worker
+                .as_ref()
+                .map_or(false, |worker| worker.pid == process::id()),
+            Err(_) => false,
+        };
+    }
+    let pid = WORKER_PID.load(atomic::Ordering::Relaxed);
+    pid > 0 && unsafe { libc::kill(pid, 0) } == 0
+}
+
+/// Lock the pid file with the worker pid, exit if another alive worker holds
+/// it. The lock held by a dead process is reclaimed, the pid file may be left
+/// by a crashed worker, or locked by the process inheriting its fd.
+fn lock_pid_file(pid_file: &Path) -> LockFile {
+    let mut pid_lock = open_pid_file(pid_file);
+    match pid_lock.try_lock_with_pid() {
+        Ok(true) => return pid_lock,
+        Ok(false) => {}
+        Err(err) => {
+            error!(?err, ?pid_file, "Lock agent pid file failed, worker exits");
+            exit(1);
+        }
+    }
+
+    let pid = match read_pid_file(pid_file) {
+        Some(pid) => Some(pid),
+        // The holder may have locked but not written the pid yet.
+        None => {
+            thread::sleep(PID_FILE_WRITE_WAITING);
+            read_pid_file(pid_file)
+        }
+    };
+    if let Some(pid) = pid {
+        if is_process_alive(pid) {
+            info!(pid, ?pid_file, "Worker is already running, exits");
+            exit(0);
+        }
+    }
+
+    warn!(?pid, ?pid_file, "Agent pid file is stale, reclaim it");
+    if let Err(err) = fs::remove_file(pid_file) {
+        error!(
+            ?err,
+            ?pid_file,
+            "Remove stale agent pid file failed, worker exits"
+        );
+        exit(1);
+    }
+
+    let mut pid_lock = open_pid_file(pid_file);
+    match pid_lock.try_lock_with_pid() {
+        Ok(true) => pid_lock,
+        Ok(false) => {
+            info!(
+                ?pid_file,
+                "Agent pid file is reclaimed by another worker, exits"
+            );
+            exit(0);
+        }
+        Err(err) => {
+            error!(?err, ?pid_file, "Lock agent pid file failed, worker exits");
+            exit(1);
+        }
+    }
+}
+
+fn open_pid_file(pid_file: &Path) -> LockFile {
+    match LockFile::open(pid_file) {
+        Ok(pid_lock) => pid_lock,
+        Err(err) => {
+            error!(?err, ?pid_file, "Open agent pid file failed, worker exits");
+            exit(1);
+        }
+    }
+}
+
+/// Returns `None` if the pid file is empty or invalid.
+fn read_pid_file(pid_file: &Path) -> Option<libc::pid_t> {
+    fs::read_to_string(pid_file)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|pid| *pid > 0)
+}
+
+/// The signal 0 only checks the existence, `EPERM` means the process exists
+/// but is owned by another user.
+fn is_process_alive(pid: libc::pid_t) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+    || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
 fn worker_threads() -> usize {
-    let worker_threads = *WORKER_THREADS;
+    resolve_worker_threads(*WORKER_THREADS)
+}
+
+fn resolve_worker_threads(worker_threads: i64) -> usize {
     if worker_threads <= 0 {
         available_parallelism().map(NonZeroUsize::get).unwrap_or(1)
     } else {
@@ -111,6 +272,70 @@ fn worker_threads() -> usize {
     }
 }
 
+/// Re-read `skywalking_agent.worker_threads` from the loaded ini files, the
+/// last assignment wins. Returns `None` if it isn't in the files, like set by
+/// the `-d` option or the php-fpm pool config.
+fn reload_worker_threads() -> Option<usize> {
+    let mut value = None;
+    for file in loaded_ini_files() {
+        let content = match fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(err) => {
+                warn!(?err, ?file, "Read ini file failed");
+                continue;
+            }
+        };
+        for line in content.lines() {
+            let Some((key, v)) = line.split_once('=') else {
+                continue;
+            };
+            if key.trim() == SKYWALKING_AGENT_WORKER_THREADS {
+                let v = v.split(';').next().unwrap_or_default();
+                value = Some(v.trim().trim_matches('"').to_owned());
+            }
+        }
+    }
+
+    match value?.parse() {
+        Ok(worker_threads) => Some(resolve_worker_threads(worker_threads)),
+        Err(err) => {
+            warn!(?err, "Invalid worker_threads in ini file, keep current");
+            None
+        }
+    }
+}
+
+/// The `php.ini` and the additional ini files, in the order of loading.
+fn loaded_ini_files() -> Vec<PathBuf> {
+    extern "C" {
+        static php_ini_opened_path: *mut c_char;
+        static php_ini_scanned_files: *mut c_char;
+    }
+
+    let to_string = |ptr: *mut c_char| {
+        (!ptr.is_null()).then(|| {
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    };
+
+    let mut files = Vec::new();
+    unsafe {
+        files.extend(to_string(php_ini_opened_path).map(PathBuf::from));
+        if let Some(scanned_files) = to_string(php_ini_scanned_files) {
+            files.extend(
+                scanned_files
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|file| !file.is_empty())
+                    .map(PathBuf::from),
+            );
+        }
+    }
+    files
+}
+
 fn new_tokio_runtime(worker_threads: usize) -> Runtime {
     runtime::Builder::new_multi_thread()
         .thread_name("sw: worker")
@@ -120,7 +345,15 @@ fn new_tokio_runtime(worker_threads: usize) -> Runtime {
         .unwrap()
 }
 
-async fn start_worker() -> anyhow::Result<()> {
+/// How the worker runtime exits.
+enum WorkerExit {
+    Shutdown,
+    /// Received `SIGHUP`, the runtime is rebuilt with the reloaded
+    /// `worker_threads`.
+    Reload,
+}
+
+async fn start_worker() -> anyhow::Result<WorkerExit> {
     debug!("Starting worker...");
 
     // Ensure to cleanup resources when worker exits.
@@ -129,106 +362,299 @@ async fn start_worker() -> anyhow::Result<()> {
     // Graceful shutdown signal, put it on the top of program.
     let mut sig_term = signal(SignalKind::terminate())?;
     let mut sig_int = signal(SignalKind::interrupt())?;
+    let mut sig_hup = signal(SignalKind::hangup())?;
+    let mut sig_usr1 = signal(SignalKind::user_defined1())?;
 
-    let socket_file = &*SOCKET_FILE_PATH;
+    let shutdown = Arc::new(Notify::new());
+    let shutdown_ = shutdown.clone();
 
     let fut = async move {
-        debug!(?socket_file, "Bind unix stream");
-        let listener = UnixListener::bind(socket_file)?;
-        change_permission(socket_file, 0o777);
-
-        let (tx, rx) = mpsc::channel::<CollectItem>(255);
+        let (tx, rx) = priority_channel(255);
         let tx_ = tx.clone();
-        tokio::spawn(async move {
-            loop {
-                match listener.accept().await {
-                    Ok((mut stream, _addr)) => {
-                        let tx = tx.clone();
-
-                        tokio::spawn(async move {
-                            debug!("Entering channel_receive loop");
-
-                            loop {
-                                let r = match channel::channel_receive(&mut stream).await {
-                                    Err(err) => match err.downcast_ref::<io::Error>() {
-                                        Some(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
-                                            debug!("Leaving channel_receive loop");
-                                            continue;
-                                        }
-                                        _ => {
-                                            error!(?err, "channel_receive failed");
-                                            continue;
-                                        }
-                                    },
-                                    Ok(i) => i,
-                                };
-
-                                // Try send here, to prevent the ipc blocking caused by the channel
-                                // bursting (too late to report),
-                                // which affects the pool process of php-fpm.
-                                if let Err(err) = tx.try_send(r) {
-                                    error!(?err, "Send collect item failed");
-                                    if !matches!(err, TrySendError::Full(_)) {
-                                        return;
-                                    }
-                                }
+        let connection_permits = Arc::new(Semaphore::new(*MAX_IPC_CONNECTIONS));
+
+        match &*WORKER_ADDR {
+            WorkerAddr::Unix(socket_file) => {
+                debug!(?socket_file, "Bind unix stream");
+                let listener = UnixListener::bind(socket_file)?;
+                let socket_file_mode = SOCKET_FILE_MODE.unwrap_or(0o777);
+                debug!("Change socket file permission to {:o}", socket_file_mode);
+                change_permission(socket_file, socket_file_mode);
+
+                tokio::spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, _addr)) => {
+                                spawn_receive_items(stream, tx.clone(), &connection_permits);
                             }
-                        });
+                            Err(err) => {
+                                error!(?err, "Accept failed");
+                            }
+                        }
                     }
-                    Err(err) => {
-                        error!(?err, "Accept failed");
+                });
+            }
+            WorkerAddr::Tcp(addr) => {
+                debug!(addr, "Bind tcp stream");
+                let listener = TcpListener::bind(addr).await?;
+
+                tokio::spawn(async move {
+                    loop {
+                        match listener.accept().await {
+                            Ok((stream, _addr)) => {
+                                if let Err(err) = stream.set_nodelay(true) {
+                                    warn!(?err, "Set tcp nodelay failed");
+                                }
+                                spawn_receive_items(stream, tx.clone(), &connection_permits);
+                            }
+                            Err(err) => {
+                                error!(?err, "Accept failed");
+                            }
+                        }
                     }
+                });
+            }
+        }
+
+        // Reopen the log file moved by logrotate.
+        tokio::spawn(async move {
+            while sig_usr1.recv().await.is_some() {
+                match reopen_log_file() {
+                    Ok(_) => info!("Log file reopened"),
+                    Err(err) => error!(?err, "Reopen log file failed"),
                 }
             }
         });
 
-        report_properties_and_keep_alive(TxReporter(tx_));
+        if !STATUS_FILE.is_empty() {
+            tokio::spawn(run_status_writer(PathBuf::from(&*STATUS_FILE)));
+        }
+
+        if *ENABLE_METER {
+            tokio::spawn(meter::run(TxReporter(tx_.clone())));
+        }
+
+        // The worker is forked by the master process.
+        report_properties_and_keep_alive(TxReporter(tx_), unsafe { libc::getppid() }, true);
 
         // Run reporter with blocking.
-        run_reporter((), Consumer(rx)).await?;
+        run_reporter((), Consumer::new(rx, shutdown_)).await?;
 
         Ok::<_, anyhow::Error>(())
     };
+    pin!(fut);
+
+    let worker_exit = select! {
+        _ = sig_term.recv() => WorkerExit::Shutdown,
+        _ = sig_int.recv() => WorkerExit::Shutdown,
+        _ = sig_hup.recv() => WorkerExit::Reload,
+        r = &mut fut => {
+            return r.map(|_| WorkerExit::Shutdown);
+        }
+    };
+
+    match worker_exit {
+        WorkerExit::Shutdown => info!("Start to shutdown skywalking reporter"),
+        WorkerExit::Reload => info!("Start to reload worker, flush the pending items first"),
+    }
+
+    event::report_shutdown().await;
 
-    // TODO Do graceful shutdown, and wait 10s then force quit.
-    select! {
-        _ = sig_term.recv() => {}
-        _ = sig_int.recv() => {}
-        r = fut => {
-            r?;
+    // Flush the pending items, and wait 10s then force quit.
+    shutdown.notify_one();
+    if timeout(SHUTDOWN_TIMEOUT, fut).await.is_err() {
+        warn!("Flush pending items timeout, force quit");
+    }
+
+    Ok(worker_exit)
+}
+
+/// Every connection holds a permit until closed, the connection beyond
+/// `skywalking_agent.max_ipc_connections` is rejected.
+fn spawn_receive_items(
+    stream: impl AsyncRead + Unpin + Send + 'static, tx: PrioritySender,
+    connection_permits: &Arc<Semaphore>,
+) {
+    match connection_permits.clone().try_acquire_owned() {
+        Ok(permit) => {
+            tokio::spawn(async move {
+                receive_items(stream, tx).await;
+                drop(permit);
+            });
+        }
+        Err(_) => {
+            warn!(
+                max_ipc_connections = *MAX_IPC_CONNECTIONS,
+                "Too many ipc connections, the new connection is rejected"
+            );
         }
     }
+}
 
-    info!("Start to shutdown skywalking grpc reporter");
+async fn receive_items(mut stream: impl AsyncRead + Unpin, tx: PrioritySender) {
+    debug!("Entering channel_receive loop");
+
+    loop {
+        let item = match channel::channel_receive(&mut stream, *IPC_READ_TIMEOUT).await {
+            Err(err) => match err.downcast_ref::<io::Error>() {
+                Some(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    debug!("Leaving channel_receive loop");
+                    return;
+                }
+                Some(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    debug!("Read frame timeout, close the stalled connection");
+                    return;
+                }
+                Some(_) => {
+                    error!(?err, "channel_receive failed, close the connection");
+                    return;
+                }
+                _ => {
+                    error!(?err, "channel_receive failed");
+                    continue;
+                }
+            },
+            Ok(i) => i,
+        };
+
+        let r = match item {
+            ChannelItem::Collect(item) => item,
+            ChannelItem::ProfileSnapshot(snapshot) => {
+                profile::send_snapshot(snapshot);
+                continue;
+            }
+            ChannelItem::EndpointRecord(record) => {
+                meter::aggregate(record);
+                continue;
+            }
+            ChannelItem::CustomMeterRecord(record) => {
+                meter::aggregate_custom(record);
+                continue;
+            }
+            ChannelItem::Event(event) => {
+                event::send_event(event);
+                continue;
+            }
+        };
+
+        // Try send here, to prevent the ipc blocking caused by the channel
+        // bursting (too late to report),
+        // which affects the pool process of php-fpm.
+        if let Err(err) = tx.try_send(r) {
+            error!(?err, "Send collect item failed");
+            if !matches!(err, TrySendError::Full(_)) {
+                return;
+            }
+        }
+    }
+}
 
-    Ok(())
+struct Consumer {
+    rx: PriorityReceiver,
+    batch: VecDeque<CollectItem>,
+    shutdown: Arc<Notify>,
+    is_shutdown: bool,
 }
 
-struct Consumer(mpsc::Receiver<CollectItem>);
+impl Consumer {
+    fn new(rx: PriorityReceiver, shutdown: Arc<Notify>) -> Self {
+        Self {
+            rx,
+            batch: VecDeque::new(),
+            shutdown,
+            is_shutdown: false,
+        }
+    }
+
+    /// Receive the item, close the receiver when shutdown is notified, so the
+    /// remaining items can be drained.
+    async fn recv(&mut self) -> Option<CollectItem> {
+        if self.is_shutdown {
+            return self.rx.recv().await;
+        }
+        select! {
+            item = self.rx.recv() => item,
+            _ = self.shutdown.notified() => {
+                debug!("Consumer is shutting down, drain the pending items");
+                self.is_shutdown = true;
+                self.rx.close();
+                self.rx.recv().await
+            }
+        }
+    }
+
+    /// Accumulate the items until the batch is full or the flush interval
+    /// elapsed, the reporter will drain the whole batch by `try_consume`.
+    async fn fill_batch(&mut self) {
+        let batch_size = report_batch_size();
+
+        let Some(item) = self.recv().await else {
+            return;
+        };
+        self.batch.push_back(item);
+
+        let deadline = Instant::now() + Duration::from_millis(*REPORT_FLUSH_INTERVAL_MS as u64);
+        while self.batch.len() < batch_size && !self.is_shutdown {
+            select! {
+                item = self.recv() => match item {
+                    Some(item) => self.batch.push_back(item),
+                    None => break,
+                },
+                _ = sleep_until(deadline) => break,
+            }
+        }
+    }
+}
 
 #[async_trait]
 impl CollectItemConsume for Consumer {
     async fn consume(&mut self) -> Result<Option<CollectItem>, Box<dyn Error + Send>> {
-        Ok(self.0.recv().await)
+        if self.batch.is_empty() {
+            if report_batch_size() <= 1 {
+                return Ok(self.recv().await.map(correct_item));
+            }
+            self.fill_batch().await;
+        }
+        Ok(self.batch.pop_front().map(correct_item))
     }
 
     async fn try_consume(&mut self) -> Result<Option<CollectItem>, Box<dyn Error + Send>> {
-        Ok(self.0.try_recv().ok())
+        if let Some(item) = self.batch.pop_front() {
+            return Ok(Some(correct_item(item)));
+        }
+        Ok(self.rx.try_recv().map(correct_item))
     }
 }
 
+fn report_batch_size() -> usize {
+    (*REPORT_BATCH_SIZE).max(1) as usize
+}
+
+fn correct_item(mut item: CollectItem) -> CollectItem {
+    WORKER_STATUS.incr_reported();
+    clock_skew::correct(&mut item);
+    propagate_exit_errors(&mut item);
+    sample_exit_spans(&mut item);
+    map_peer_services(&mut item);
+    limit_segment_spans(&mut item);
+    truncate_tag_values(&mut item);
+    limit_segment_bytes(&mut item);
+    item
+}
+
 #[derive(Default)]
 struct WorkerExitGuard(PhantomData<()>);
 
 impl Drop for WorkerExitGuard {
     fn drop(&mut self) {
-        match Lazy::get(&SOCKET_FILE_PATH) {
-            Some(socket_file) => {
+        match Lazy::get(&WORKER_ADDR) {
+            Some(WorkerAddr::Unix(socket_file)) => {
                 info!(?socket_file, "Remove socket file");
                 if let Err(err) = fs::remove_file(socket_file) {
                     error!(?err, "Remove socket file failed");
                 }
             }
+            Some(WorkerAddr::Tcp(_)) => {}
             None => {
                 warn!("Socket file not created");
             }
@@ -236,21 +662,308 @@ impl Drop for WorkerExitGuard {
     }
 }
 
-fn report_properties_and_keep_alive(reporter: TxReporter) {
+fn report_properties_and_keep_alive(
+    reporter: TxReporter, process_no: libc::pid_t, refresh_on_signal: bool,
+) {
     let manager = Manager::new(&*SERVICE_NAME, &*SERVICE_INSTANCE, reporter);
 
+    // The properties are recomputed every time reported.
     manager.report_and_keep_alive(
-        || {
-            let mut props = Properties::new();
-            props.insert_os_info();
-            props.update(Properties::KEY_LANGUAGE, "php");
-            props.update(Properties::KEY_PROCESS_NO, unsafe {
-                libc::getppid().to_string()
-            });
-            debug!(?props, "Report instance properties");
-            props
-        },
+        move || instance_properties(process_no),
         Duration::from_secs(*HEARTBEAT_PERIOD as u64),
         *PROPERTIES_REPORT_PERIOD_FACTOR as usize,
     );
+
+    tokio::spawn(refresh_properties(manager, process_no, refresh_on_signal));
+}
+
+fn instance_properties(process_no: libc::pid_t) -> Properties {
+    let mut props = Properties::new();
+    // The hostname, IPs and OS name.
+    if *REPORT_OS_INFO {
+        props.insert_os_info();
+    }
+    if !INSTANCE_PROPERTIES_FILE.is_empty() {
+        match fs::read_to_string(&*INSTANCE_PROPERTIES_FILE) {
+            Ok(content) => {
+                for (key, value) in parse_properties(&content) {
+                    props.update(key, value);
+                }
+            }
+            Err(err) => {
+                warn!(
+                    ?err,
+                    file = &*INSTANCE_PROPERTIES_FILE,
+                    "Read instance properties file failed"
+                );
+            }
+        }
+    }
+    props.update(Properties::KEY_LANGUAGE, "php");
+    props.update(Properties::KEY_PROCESS_NO, process_no.to_string());
+    debug!(?props, "Report instance properties");
+    props
+}
+
+/// The `key=value` lines, the blank lines and the lines starting with `#` are
+/// skipped.
+fn parse_properties(content: &str) -> impl Iterator<Item = (&str, &str)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Some((key.trim(), value.trim())),
+            _ => {
+                warn!(line, "Instance property should be `key=value`, ignored");
+                None
+            }
+        })
+}
+
+/// Report the instance properties at once, without waiting for the
+/// `properties_report_period_factor` heartbeats, when the
+/// `instance_properties_file` is modified, or `SIGUSR2` is received by the
+/// forked worker. The thread worker doesn't take over the signals of php.
+async fn refresh_properties(manager: Manager, process_no: libc::pid_t, refresh_on_signal: bool) {
+    let mut sig_usr2 = if refresh_on_signal {
+        match signal(SignalKind::user_defined2()) {
+            Ok(sig) => Some(sig),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "Listen signal for refreshing instance properties failed"
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if sig_usr2.is_none() && INSTANCE_PROPERTIES_FILE.is_empty() {
+        return;
+    }
+
+    let mut modified = properties_file_modified();
+    let mut poll = interval(PROPERTIES_FILE_POLL_INTERVAL);
+    poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        select! {
+            Some(_) = async {
+                match &mut sig_usr2 {
+                    Some(sig) => sig.recv().await,
+                    None => pending().await,
+                }
+            } => {
+                info!("Signal received, refresh instance properties");
+            }
+            _ = poll.tick(), if !INSTANCE_PROPERTIES_FILE.is_empty() => {
+                let current = properties_file_modified();
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                info!(
+                    file = &*INSTANCE_PROPERTIES_FILE,
+                    "Instance properties file changed, refresh"
+                );
+            }
+        }
+        manager.report_properties(instance_properties(process_no));
+    }
+}
+
+/// The `None` means the file is missing or unreadable, the change between it
+/// and the modification time is also treated as modified.
+fn properties_file_modified() -> Option<SystemTime> {
+    fs::metadata(&*INSTANCE_PROPERTIES_FILE)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The worker running in the background thread of current php process, used
+/// when `worker_mode` is `thread`.
+struct ThreadWorker {
+    pid: u32,
+    tx: PrioritySender,
+    shutdown: Arc<Notify>,
+    done_rx: std_mpsc::Receiver<()>,
+}
+
+static THREAD_WORKER: Mutex<Option<ThreadWorker>> = Mutex::new(None);
+
+/// Report the items to the worker in the background thread, without the
+/// socket.
+pub struct ThreadReporter;
+
+impl ThreadReporter {
+    /// Run the closure with the sender of thread worker, start the worker if
+    /// it isn't started in current process.
+    fn with_sender<T>(f: impl FnOnce(&PrioritySender) -> T) -> Option<T> {
+        let mut worker = match THREAD_WORKER.lock() {
+            Ok(worker) => worker,
+            Err(err) => {
+                error!(?err, "Get thread worker lock failed");
+                return None;
+            }
+        };
+
+        // The worker is inherited from the parent process after fork, but the
+        // thread isn't, so start a new one.
+        let pid = process::id();
+        if worker.as_ref().map(|w| w.pid != pid).unwrap_or_default() {
+            forget(worker.take());
+        }
+        if worker.is_none() {
+            match spawn_thread_worker(pid) {
+                Ok(w) => *worker = Some(w),
+                Err(err) => {
+                    error!(?err, "Start thread worker failed");
+                    return None;
+                }
+            }
+        }
+
+        worker.as_ref().map(|worker| f(&worker.tx))
+    }
+
+    /// Aggregate the endpoint record in current process.
+    pub fn report_endpoint_record(record: EndpointRecord) {
+        if Self::with_sender(|_| ()).is_some() {
+            meter::aggregate(record);
+        }
+    }
+
+    /// Aggregate the custom meter record in current process.
+    pub fn report_custom_meter_record(record: CustomMeterRecord) {
+        if Self::with_sender(|_| ()).is_some() {
+            meter::aggregate_custom(record);
+        }
+    }
+
+    /// Send the profiling snapshot to the uploader in current process.
+    pub fn report_snapshot(snapshot: ThreadSnapshot) {
+        if Self::with_sender(|_| ()).is_some() {
+            profile::send_snapshot(snapshot);
+        }
+    }
+
+    /// Send the event to the uploader in current process.
+    pub fn report_event(event: Event) {
+        if Self::with_sender(|_| ()).is_some() {
+            event::send_event(event);
+        }
+    }
+
+    /// Wait until the items in channel are taken by the worker thread, returns
+    /// false if timeout. The worker isn't started if not exists.
+    pub fn flush(wait: Duration) -> bool {
+        let tx = match THREAD_WORKER.lock() {
+            Ok(worker) => match &*worker {
+                Some(worker) if worker.pid == process::id() => worker.tx.clone(),
+                _ => return true,
+            },
+            Err(err) => {
+                error!(?err, "Get thread worker lock failed");
+                return false;
+            }
+        };
+
+        let deadline = std::time::Instant::now() + wait;
+        while tx.pending() > 0 {
+            if std::time::Instant::now() >= deadline {
+                debug!(pending = tx.pending(), "Flush thread worker timeout");
+                return false;
+            }
+            thread::sleep(FLUSH_POLL_INTERVAL);
+        }
+        true
+    }
+}
+
+impl Report for ThreadReporter {
+    fn report(&self, mut item: CollectItem) {
+        if !apply_segment_options(&mut item) {
+            debug!("Segment discarded");
+            return;
+        }
+        if breaker::is_open() {
+            debug!("Circuit breaker is open, collect item dropped");
+            return;
+        }
+        Self::with_sender(|tx| {
+            if let Err(err) = tx.try_send(item) {
+                error!(?err, "Send collect item failed");
+            }
+        });
+    }
+}
+
+fn spawn_thread_worker(pid: u32) -> anyhow::Result<ThreadWorker> {
+    debug!(pid, "Starting thread worker...");
+
+    let worker_threads = worker_threads();
+    let (tx, rx) = priority_channel(255);
+    let shutdown = Arc::new(Notify::new());
+    let shutdown_ = shutdown.clone();
+    let (done_tx, done_rx) = std_mpsc::channel();
+    let tx_ = tx.clone();
+
+    thread::Builder::new()
+        .name("sw: worker".to_owned())
+        .spawn(move || {
+            let rt = new_tokio_runtime(worker_threads);
+            let result = rt.block_on(async move {
+                if *ENABLE_METER {
+                    tokio::spawn(meter::run(TxReporter(tx_.clone())));
+                }
+                report_properties_and_keep_alive(TxReporter(tx_), pid as libc::pid_t, false);
+                run_reporter((), Consumer::new(rx, shutdown_)).await
+            });
+            if let Err(err) = result {
+                error!(?err, "thread worker exit unexpectedly");
+            }
+            let _ = done_tx.send(());
+        })?;
+
+    Ok(ThreadWorker {
+        pid,
+        tx,
+        shutdown,
+        done_rx,
+    })
+}
+
+/// Flush the pending items of thread worker, wait until flushed or timeout.
+pub fn shutdown_thread_worker() {
+    let worker = match THREAD_WORKER.lock() {
+        Ok(mut worker) => worker.take(),
+        Err(err) => {
+            error!(?err, "Get thread worker lock failed");
+            return;
+        }
+    };
+    let Some(worker) = worker else {
+        return;
+    };
+    if worker.pid != process::id() {
+        forget(worker);
+        return;
+    }
+
+    info!("Start to shutdown skywalking thread worker");
+
+    let ThreadWorker {
+        tx,
+        shutdown,
+        done_rx,
+        ..
+    } = worker;
+    drop(tx);
+    shutdown.notify_one();
+    if done_rx.recv_timeout(SHUTDOWN_TIMEOUT).is_err() {
+        warn!("Flush pending items timeout, force quit");
+    }
 }
@@ -23,7 +23,7 @@ use axum::{
     Extension, Router,
 };
 use futures_util::future::join_all;
-use libc::{kill, pid_t, SIGTERM};
+use libc::{kill, pid_t, SIGQUIT, SIGTERM};
 use once_cell::sync::Lazy;
 use std::{
     env,
@@ -50,6 +50,7 @@ pub const PROXY_SERVER_1_ADDRESS: &str = "127.0.0.1:9011";
 pub const PROXY_SERVER_2_ADDRESS: &str = "127.0.0.1:9012";
 pub const FPM_SERVER_1_ADDRESS: &str = "127.0.0.1:9001";
 pub const FPM_SERVER_2_ADDRESS: &str = "127.0.0.1:9002";
+pub const FPM_SERVER_3_ADDRESS: &str = "127.0.0.1:9003";
 pub const SWOOLE_SERVER_1_ADDRESS: &str = "127.0.0.1:9501";
 pub const SWOOLE_SERVER_2_ADDRESS: &str = "127.0.0.1:9502";
 pub const COLLECTOR_GRPC_ADDRESS: &str = "127.0.0.1:19876";
@@ -274,7 +275,7 @@ fn create_request_uri(script_name: &str, query: &str) -> String {
 }
 
 #[instrument]
-fn setup_php_fpm(index: usize, fpm_addr: &str) -> Child {
+pub fn setup_php_fpm(index: usize, fpm_addr: &str) -> Child {
     let php_fpm = env::var("PHP_FPM_BIN").unwrap_or_else(|_| "php-fpm".to_string());
     let args = [
         &php_fpm,
@@ -309,6 +310,8 @@ fn setup_php_fpm(index: usize, fpm_addr: &str) -> Child {
             "skywalking_agent.enable_zend_observer={}",
             *ENABLE_ZEND_OBSERVER
         ),
+        "-d",
+        "skywalking_agent.route_operation_name=On",
     ];
     info!(cmd = args.join(" "), "start command");
     let child = Command::new(&args[0])
@@ -369,10 +372,20 @@ fn setup_php_swoole(index: usize) -> Child {
     child
 }
 
-async fn kill_command(mut child: Child) -> io::Result<ExitStatus> {
+async fn kill_command(child: Child) -> io::Result<ExitStatus> {
+    signal_command(child, SIGTERM).await
+}
+
+/// Quit gracefully, php-fpm passes `SIGQUIT` to the children, so the module
+/// shutdown of them is run.
+pub async fn quit_command(child: Child) -> io::Result<ExitStatus> {
+    signal_command(child, SIGQUIT).await
+}
+
+async fn signal_command(mut child: Child, signal: i32) -> io::Result<ExitStatus> {
     if let Some(id) = child.id() {
         unsafe {
-            kill(id as pid_t, SIGTERM);
+            kill(id as pid_t, signal);
         }
     }
     child.wait().await
@@ -16,8 +16,8 @@
 mod common;
 
 use crate::common::{
-    COLLECTOR_HTTP_ADDRESS, HTTP_CLIENT, PROXY_SERVER_1_ADDRESS, SWOOLE_SERVER_1_ADDRESS,
-    SWOOLE_SERVER_2_ADDRESS,
+    COLLECTOR_HTTP_ADDRESS, FPM_SERVER_3_ADDRESS, HTTP_CLIENT, PROXY_SERVER_1_ADDRESS,
+    SWOOLE_SERVER_1_ADDRESS, SWOOLE_SERVER_2_ADDRESS,
 };
 use reqwest::{header::CONTENT_TYPE, RequestBuilder, StatusCode};
 use std::{
@@ -53,10 +53,30 @@ async fn e2e() {
     }
 }
 
+/// The children of the static pool are initialized but never serve a request,
+/// they should quit gracefully without connecting to the worker.
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn fpm_idle_children() {
+    let log_file = "/tmp/fpm-skywalking-agent.3.log";
+    let _ = fs::remove_file(log_file).await;
+
+    let child = common::setup_php_fpm(3, FPM_SERVER_3_ADDRESS);
+    sleep(Duration::from_secs(3)).await;
+
+    let status = common::quit_command(child).await.unwrap();
+    assert!(status.success());
+
+    let log = fs::read_to_string(log_file).await.unwrap();
+    assert!(!log.contains("panicked"));
+    assert!(!log.contains("channel send"));
+    assert!(!log.contains("Close the channel to worker"));
+}
+
 async fn run_e2e() {
     request_fpm_curl().await;
     request_fpm_curl_multi().await;
     request_fpm_pdo().await;
+    request_fpm_exception().await;
     request_fpm_predis().await;
     request_fpm_mysqli().await;
     request_fpm_memcached().await;
@@ -64,6 +84,8 @@ async fn run_e2e() {
     request_fpm_rabbitmq().await;
     request_fpm_mongodb().await;
     request_fpm_memcache().await;
+    request_fpm_http_stream().await;
+    request_fpm_laravel_route().await;
     request_swoole_curl().await;
     request_swoole_2_curl().await;
     request_swoole_2_pdo().await;
@@ -104,6 +126,14 @@ async fn request_fpm_pdo() {
     .await;
 }
 
+async fn request_fpm_exception() {
+    request_common(
+        HTTP_CLIENT.get(format!("http://{}/exception.php", PROXY_SERVER_1_ADDRESS)),
+        "ok",
+    )
+    .await;
+}
+
 async fn request_fpm_mysqli() {
     request_common(
         HTTP_CLIENT.get(format!("http://{}/mysqli.php", PROXY_SERVER_1_ADDRESS)),
@@ -166,6 +196,25 @@ async fn request_fpm_memcache() {
     .await;
 }
 
+async fn request_fpm_http_stream() {
+    request_common(
+        HTTP_CLIENT.get(format!("http://{}/http-stream.php", PROXY_SERVER_1_ADDRESS)),
+        "ok",
+    )
+    .await;
+}
+
+async fn request_fpm_laravel_route() {
+    request_common(
+        HTTP_CLIENT.get(format!(
+            "http://{}/laravel-route.php",
+            PROXY_SERVER_1_ADDRESS
+        )),
+        "ok",
+    )
+    .await;
+}
+
 async fn request_swoole_curl() {
     request_common(
         HTTP_CLIENT.get(format!("http://{}/curl", SWOOLE_SERVER_1_ADDRESS)),
@@ -79,4 +79,27 @@ require_once dirname(__DIR__) . "/vendor/autoload.php";
     Assert::same($output, "ok");
 }
 
+{
+    $ch = curl_init();
+    curl_setopt($ch, CURLOPT_URL, "http://127.0.0.1:9011/index.php?proxy=1");
+    curl_setopt($ch, CURLOPT_PROXY, "127.0.0.1");
+    curl_setopt($ch, CURLOPT_PROXYPORT, 1);
+    curl_setopt($ch, CURLOPT_TIMEOUT, 10);
+    curl_setopt($ch, CURLOPT_RETURNTRANSFER, 1);
+    curl_exec($ch);
+    curl_close($ch);
+}
+
+{
+    $ch = curl_init();
+    curl_setopt_array($ch, [
+        CURLOPT_URL => "http://localhost/index.php?unix_socket=1",
+        CURLOPT_UNIX_SOCKET_PATH => "/tmp/skywalking-not-exists.sock",
+        CURLOPT_TIMEOUT => 10,
+        CURLOPT_RETURNTRANSFER => 1,
+    ]);
+    curl_exec($ch);
+    curl_close($ch);
+}
+
 echo "ok";
@@ -0,0 +1,35 @@
+<?php
+
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use Webmozart\Assert\Assert;
+
+require_once dirname(__DIR__) . "/vendor/autoload.php";
+
+{
+    $fp = fopen("http://127.0.0.1:9011/index.php?stream=1", "r");
+    $output = stream_get_contents($fp);
+    fclose($fp);
+    Assert::same($output, "ok");
+}
+
+{
+    // Local files shouldn't be traced.
+    $content = file_get_contents(__FILE__);
+    Assert::notEmpty($content);
+}
+
+echo "ok";
@@ -0,0 +1,67 @@
+<?php
+
+// Licensed to the Apache Software Foundation (ASF) under one or more
+// contributor license agreements.  See the NOTICE file distributed with
+// this work for additional information regarding copyright ownership.
+// The ASF licenses this file to You under the Apache License, Version 2.0
+// (the "License"); you may not use this file except in compliance with
+// the License.  You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The minimal stubs of the Laravel router, only the methods called by the
+// route plugin are implemented.
+
+namespace Illuminate\Routing {
+    class Route
+    {
+        private $uri;
+
+        public function __construct(string $uri)
+        {
+            $this->uri = $uri;
+        }
+
+        public function uri(): string
+        {
+            return $this->uri;
+        }
+    }
+
+    class Router
+    {
+        public function findRoute($request): Route
+        {
+            return new Route('orders/{id}');
+        }
+    }
+}
+
+namespace Illuminate\Http {
+    class Request
+    {
+        public function getMethod(): string
+        {
+            return 'GET';
+        }
+    }
+}
+
+namespace {
+    use Illuminate\Http\Request;
+    use Illuminate\Routing\Router;
+    use Webmozart\Assert\Assert;
+
+    require_once dirname(__DIR__) . "/vendor/autoload.php";
+
+    $route = (new Router())->findRoute(new Request());
+    Assert::same($route->uri(), 'orders/{id}');
+
+    echo "ok";
+}